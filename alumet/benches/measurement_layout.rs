@@ -0,0 +1,61 @@
+//! Compares the two layouts of `MeasurementBuffer`: `BufferLayout::Rows` (array of structs)
+//! and `BufferLayout::Columns` (struct of arrays), when filling a buffer and when reading
+//! the fields that a columnar output (e.g. Parquet) serializes.
+
+use alumet::measurement::{BufferLayout, MeasurementBuffer, MeasurementPoint, Timestamp, WrappedMeasurementValue};
+use alumet::metrics::RawMetricId;
+use alumet::resources::{Resource, ResourceConsumer};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+/// Number of different metrics in the buffer.
+const METRICS: u64 = 50;
+
+fn buffer(layout: BufferLayout, len: u64) -> MeasurementBuffer {
+    let timestamp = Timestamp::now();
+    let mut buf = MeasurementBuffer::with_layout(layout, len as usize);
+    buf.extend_from_points((0..len).map(|i| {
+        MeasurementPoint::new_untyped(
+            timestamp,
+            RawMetricId::from_u64(i % METRICS),
+            Resource::CpuPackage { id: (i % 4) as u32 },
+            ResourceConsumer::LocalMachine,
+            WrappedMeasurementValue::U64(i),
+        )
+    }));
+    buf
+}
+
+/// Reads the metrics and the values of the points, like an output that writes one column at a time.
+fn scan(buf: &MeasurementBuffer) -> (u64, f64) {
+    match buf.as_columns() {
+        Some(columns) => {
+            let metrics = columns.metrics().iter().map(|m| m.as_u64()).sum();
+            let values = columns.values().iter().map(|v| v.as_f64()).sum();
+            (metrics, values)
+        }
+        None => {
+            let metrics = buf.iter().map(|m| m.metric.as_u64()).sum();
+            let values = buf.iter().map(|m| m.value.as_f64()).sum();
+            (metrics, values)
+        }
+    }
+}
+
+fn measurement_layout(c: &mut Criterion) {
+    let mut group = c.benchmark_group("measurement_layout");
+    for len in [100, 1000, 10000] {
+        for (name, layout) in [("rows", BufferLayout::Rows), ("columns", BufferLayout::Columns)] {
+            group.bench_with_input(BenchmarkId::new(format!("fill_{name}"), len), &len, |b, &len| {
+                b.iter(|| buffer(black_box(layout), len))
+            });
+            let buf = buffer(layout, len);
+            group.bench_with_input(BenchmarkId::new(format!("scan_{name}"), len), &buf, |b, buf| {
+                b.iter(|| scan(black_box(buf)))
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, measurement_layout);
+criterion_main!(benches);
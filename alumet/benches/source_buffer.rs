@@ -0,0 +1,45 @@
+//! Compares the buffers of the managed sources with and without pre-allocation,
+//! see [`Agent::points_per_poll`](alumet::agent::Agent::points_per_poll).
+
+use alumet::measurement::{MeasurementBuffer, MeasurementPoint, Timestamp, WrappedMeasurementValue};
+use alumet::metrics::RawMetricId;
+use alumet::resources::{Resource, ResourceConsumer};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+/// Number of polls between two flushes.
+const FLUSH_ROUNDS: usize = 5;
+
+/// Fills the buffer like a source that produces `points_per_poll` points on each of the `FLUSH_ROUNDS` polls.
+fn fill(mut buffer: MeasurementBuffer, points_per_poll: usize) -> MeasurementBuffer {
+    let timestamp = Timestamp::now();
+    for _ in 0..FLUSH_ROUNDS {
+        for i in 0..points_per_poll {
+            buffer.push(MeasurementPoint::new_untyped(
+                timestamp,
+                RawMetricId::from_u64(0),
+                Resource::CpuPackage { id: i as u32 },
+                ResourceConsumer::LocalMachine,
+                WrappedMeasurementValue::U64(i as u64),
+            ));
+        }
+    }
+    buffer
+}
+
+fn source_buffer(c: &mut Criterion) {
+    let mut group = c.benchmark_group("source_buffer");
+    for points_per_poll in [4, 64, 1024] {
+        group.bench_with_input(
+            BenchmarkId::new("no_hint", points_per_poll),
+            &points_per_poll,
+            |b, &n| b.iter(|| black_box(fill(MeasurementBuffer::with_capacity(FLUSH_ROUNDS), n))),
+        );
+        group.bench_with_input(BenchmarkId::new("hint", points_per_poll), &points_per_poll, |b, &n| {
+            b.iter(|| black_box(fill(MeasurementBuffer::with_capacity(FLUSH_ROUNDS * n), n)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, source_buffer);
+criterion_main!(benches);
@@ -0,0 +1,64 @@
+//! Compares a transform that scans the whole buffer with a transform that declares its input metrics
+//! (see `Transform::input_metrics`), to which the pipeline only gives the measurements of these metrics.
+
+use alumet::measurement::{MeasurementBuffer, MeasurementPoint, Timestamp, WrappedMeasurementValue};
+use alumet::metrics::RawMetricId;
+use alumet::resources::{Resource, ResourceConsumer};
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+
+/// Number of different metrics in the buffer.
+const METRICS: u64 = 50;
+
+/// The metric that the transform applies to.
+const INPUT_METRIC: u64 = 1;
+
+fn buffer(len: u64) -> MeasurementBuffer {
+    let timestamp = Timestamp::now();
+    let points: Vec<_> = (0..len)
+        .map(|i| {
+            MeasurementPoint::new_untyped(
+                timestamp,
+                RawMetricId::from_u64(i % METRICS),
+                Resource::LocalMachine,
+                ResourceConsumer::LocalMachine,
+                WrappedMeasurementValue::U64(i),
+            )
+        })
+        .collect();
+    MeasurementBuffer::from(points)
+}
+
+/// Doubles the values of the input metric, like a transform that does not declare its inputs.
+fn double(measurements: &mut MeasurementBuffer) {
+    let input = RawMetricId::from_u64(INPUT_METRIC);
+    for m in measurements.iter_mut() {
+        if m.metric == input {
+            m.value = WrappedMeasurementValue::U64(m.value.as_u64().unwrap() * 2);
+        }
+    }
+}
+
+fn transform_dispatch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("transform_dispatch");
+    let input = RawMetricId::from_u64(INPUT_METRIC);
+    for len in [100, 1000, 10000] {
+        group.bench_with_input(BenchmarkId::new("whole_buffer", len), &len, |b, &len| {
+            b.iter_batched_ref(|| buffer(len), |buf| double(black_box(buf)), BatchSize::SmallInput)
+        });
+        group.bench_with_input(BenchmarkId::new("input_metrics", len), &len, |b, &len| {
+            b.iter_batched_ref(
+                || buffer(len),
+                |buf| {
+                    let (mut selected, mask) = buf.take_matching(|m| m.metric == input);
+                    double(black_box(&mut selected));
+                    buf.put_back(selected, &mask);
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, transform_dispatch);
+criterion_main!(benches);
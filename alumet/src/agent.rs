@@ -1,18 +1,28 @@
 //! Helpers for creating a measurement agent.
 
 use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
     path::{Path, PathBuf},
+    sync::Arc,
     time::Duration,
 };
 
 use crate::{
+    config::{self, UnknownKeysPolicy},
+    logging,
+    measurement::AttributeValue,
+    metrics::DuplicateMetricPolicy,
     pipeline::{
         self,
+        branches::BranchConfig,
         builder::PipelineBuilder,
+        probe::StartupProbe,
         runtime::{IdlePipeline, RunningPipeline},
         trigger::TriggerConstraints,
     },
-    plugin::{AlumetStart, ConfigTable, Plugin, PluginMetadata},
+    plugin::{rust::InvalidConfig, AlumetStart, ConfigTable, Plugin, PluginMetadata},
+    time::{Clock, SystemClock},
 };
 
 /// Easy-to-use skeleton for building a measurement application based on
@@ -78,8 +88,33 @@ pub struct AgentBuilder {
     f_after_operation_begin: fn(&mut RunningPipeline),
     allow_no_metrics: bool,
     source_constraints: TriggerConstraints,
+    global_attributes: Vec<(Cow<'static, str>, AttributeValue)>,
+    measure_pipeline_overhead: bool,
+    emit_agent_info: bool,
+    unknown_config_keys: UnknownKeysPolicy,
+    clock: Arc<dyn Clock>,
+    last_value_cache: Option<Duration>,
+    sequence_numbers: bool,
+    points_per_poll: Option<usize>,
+    duplicate_metrics: DuplicateMetricPolicy,
+    branches: BTreeMap<String, BranchConfig>,
+    cpu_affinity: Option<Vec<usize>>,
+    startup_probe: StartupProbe,
+    effective_config_path: Option<PathBuf>,
 }
 
+/// Key of the attribute that identifies the node (machine) on which Alumet runs.
+///
+/// Applications that aggregate the measurements of multiple nodes should set it with
+/// [`Agent::add_global_attribute`]. Outputs can use this key to export it as a label, or tag.
+pub const NODE_ID_ATTRIBUTE: &str = "node_id";
+
+/// Key of the configuration of a plugin that sets its log level, for instance `log_level = "debug"`.
+///
+/// It is removed from the configuration before the plugin is initialized. If it is missing, the global level
+/// of the application applies. See the [`logging`] module.
+pub const LOG_LEVEL_KEY: &str = "log_level";
+
 enum AgentConfigSource {
     Value(toml::Table),
     FilePath(std::path::PathBuf),
@@ -88,12 +123,16 @@ enum AgentConfigSource {
 pub struct AgentConfig {
     plugins_table: toml::Table,
     app_table: Option<toml::Table>,
+    /// The whole configuration, as loaded, to build the effective configuration.
+    loaded: toml::Table,
 }
 
 impl TryFrom<toml::Table> for AgentConfig {
     type Error = anyhow::Error;
 
     fn try_from(mut global_config: toml::Table) -> Result<Self, Self::Error> {
+        let loaded = global_config.clone();
+
         // Extract the plugins' configurations.
         let plugins_table = match global_config.remove("plugins") {
             Some(toml::Value::Table(t)) => Ok(t),
@@ -110,6 +149,7 @@ impl TryFrom<toml::Table> for AgentConfig {
         Ok(AgentConfig {
             plugins_table,
             app_table,
+            loaded,
         })
     }
 }
@@ -152,12 +192,15 @@ impl AgentConfig {
 impl Agent {
     pub fn load_config(&mut self) -> anyhow::Result<AgentConfig> {
         // Load the global config, from a file or from a value, depending on the agent's settings.
-        let global_config = match self.settings.config.take().unwrap() {
+        let mut global_config = match self.settings.config.take().unwrap() {
             AgentConfigSource::Value(config) => config,
             AgentConfigSource::FilePath(path) => {
                 load_config_from_file(&self.settings.plugins, &path, &self.settings.default_app_config)?
             }
         };
+
+        // Replace the references to environment variables, such as `${VAR}`.
+        config::substitute_env_variables(&mut global_config).context(InvalidConfig)?;
         log::debug!("Global configuration: {global_config:?}");
 
         // Wrap the config in AgentConfig and check its structure.
@@ -176,18 +219,27 @@ impl Agent {
     /// with callbacks such as [`AgentBuilder::after_plugin_init`].
     #[must_use = "To keep Alumet running, call RunningAgent::wait_for_shutdown."]
     pub fn start(self, mut config: AgentConfig) -> anyhow::Result<RunningAgent> {
+        // Export the effective configuration, for auditing.
+        if let Some(path) = &self.settings.effective_config_path {
+            self.write_effective_config(&config, path)?;
+        }
+
+        // Order the plugins according to their dependencies.
+        let plugins = sort_by_dependencies(self.settings.plugins).context("invalid plugin dependencies")?;
+        for (a, b, target) in shared_log_targets(&plugins) {
+            log::warn!("Plugins {a} and {b} have the same log target {target}: the log_level of one also applies to the other.");
+        }
+
         // Initialization phase.
         log::info!("Initializing the plugins...");
 
         // initialize the plugins with the config
-        let mut initialized_plugins: Vec<Box<dyn Plugin>> = self
-            .settings
-            .plugins
+        let mut initialized_plugins: Vec<Box<dyn Plugin>> = plugins
             .into_iter()
             .map(|plugin| -> anyhow::Result<Box<dyn Plugin>> {
                 let name = plugin.name.clone();
                 let version = plugin.version.clone();
-                initialize_with_config(&mut config, plugin)
+                initialize_with_config(&mut config, plugin, self.settings.unknown_config_keys)
                     .with_context(|| format!("Plugin failed to initialize: {} v{}", name, version))
             })
             .collect::<anyhow::Result<Vec<_>>>()?;
@@ -204,6 +256,16 @@ impl Agent {
         let mut pipeline_builder = pipeline::builder::PipelineBuilder::new();
         pipeline_builder.source_constraints = self.settings.source_constraints;
         pipeline_builder.allow_no_metrics = self.settings.allow_no_metrics;
+        pipeline_builder.global_attributes = self.settings.global_attributes;
+        pipeline_builder.measure_overhead = self.settings.measure_pipeline_overhead;
+        pipeline_builder.clock = self.settings.clock;
+        pipeline_builder.last_value_cache = self.settings.last_value_cache;
+        pipeline_builder.sequence_numbers = self.settings.sequence_numbers;
+        pipeline_builder.points_per_poll = self.settings.points_per_poll;
+        pipeline_builder.metrics.duplicates = self.settings.duplicate_metrics;
+        pipeline_builder.branches = self.settings.branches;
+        pipeline_builder.cpu_affinity = self.settings.cpu_affinity;
+        pipeline_builder.startup_probe = self.settings.startup_probe;
 
         for plugin in initialized_plugins.iter_mut() {
             log::debug!("Starting plugin {} v{}", plugin.name(), plugin.version());
@@ -215,6 +277,13 @@ impl Agent {
                 .start(&mut start_struct)
                 .with_context(|| format!("Plugin failed to start: {} v{}", plugin.name(), plugin.version()))?;
         }
+        if self.settings.emit_agent_info {
+            let plugins = initialized_plugins
+                .iter()
+                .map(|p| (p.name().to_owned(), p.version().to_owned()))
+                .collect();
+            pipeline::info::add_info_source(&mut pipeline_builder, plugins);
+        }
         print_stats(&pipeline_builder, &initialized_plugins);
         (self.settings.f_after_plugin_start)(&pipeline_builder);
 
@@ -263,25 +332,59 @@ impl Agent {
         build_default_config(&self.settings.plugins, &self.settings.default_app_config)
     }
 
+    /// Builds a default configuration like [`default_config`](Self::default_config), as a TOML string
+    /// in which the configuration of each plugin is documented by its [schema](crate::config::ConfigSchema).
+    pub fn commented_default_config(&self) -> anyhow::Result<String> {
+        build_commented_default_config(&self.settings.plugins, &self.settings.default_app_config)
+    }
+
     /// Builds and saves a default configuration by combining:
     /// - the default agent config (which is set by [`AgentBuilder::default_app_config`])
     /// - the default config of each plugin (which are set by [`AgentBuilder::new`])
     ///
     /// This can be used to provide a command line option that (re)generates the configuration file.
     pub fn write_default_config(&self) -> anyhow::Result<()> {
-        let default_config = self.default_config()?;
+        let default_config = self.commented_default_config()?;
         match self.settings.config.as_ref().unwrap() {
             AgentConfigSource::Value(_) => Err(anyhow!(
                 "write_default_config() only works if the Agent is built with config_path()"
             )),
             AgentConfigSource::FilePath(path) => {
-                std::fs::write(path, default_config.to_string())
+                std::fs::write(path, default_config)
                     .with_context(|| format!("writing default config to {}", path.display()))?;
                 Ok(())
             }
         }
     }
 
+    /// Builds the effective configuration of the agent, that is, the loaded configuration
+    /// where the missing keys are filled with the default values of [`default_config`](Self::default_config).
+    ///
+    /// The sensitive values are redacted, see [Effective configuration](crate::config#effective-configuration).
+    pub fn effective_config(&self, config: &AgentConfig) -> anyhow::Result<toml::Table> {
+        let settings = &self.settings;
+        build_effective_config(&settings.plugins, &settings.default_app_config, &config.loaded)
+    }
+
+    /// Writes the [effective configuration](Self::effective_config) to a TOML file.
+    pub fn write_effective_config(&self, config: &AgentConfig, path: &Path) -> anyhow::Result<()> {
+        let effective = self.effective_config(config)?;
+        let mut content = String::from("# Effective configuration of Alumet. The sensitive values are redacted.\n\n");
+        content.push_str(&toml::to_string(&effective)?);
+        std::fs::write(path, content).with_context(|| format!("writing effective config to {}", path.display()))?;
+        log::info!("Effective configuration written to {}", path.display());
+        Ok(())
+    }
+
+    /// Sets the file to which the effective configuration is written when the agent starts (none by default).
+    ///
+    /// The effective configuration is the configuration that the agent uses, with the default values and without
+    /// the references to environment variables. It can be loaded again, but its sensitive values are redacted,
+    /// see [Effective configuration](crate::config#effective-configuration).
+    pub fn effective_config_path(&mut self, path: Option<PathBuf>) {
+        self.settings.effective_config_path = path;
+    }
+
     /// Sets the maximum interval between two updates of the commands processed by
     /// each measurement [`Source`](crate::pipeline::Source).
     ///
@@ -290,9 +393,119 @@ impl Agent {
     pub fn sources_max_update_interval(&mut self, max_update_interval: Duration) {
         self.settings.source_constraints.max_update_interval = max_update_interval;
     }
+
+    /// Adds an attribute to all the measurement points produced by the pipeline.
+    ///
+    /// The attribute is attached after the transforms, before the outputs.
+    /// It does not replace the attributes that have the same key and are already set by the sources.
+    /// See also [`NODE_ID_ATTRIBUTE`].
+    pub fn add_global_attribute(&mut self, key: impl Into<Cow<'static, str>>, value: AttributeValue) {
+        self.settings.global_attributes.push((key.into(), value));
+    }
+
+    /// Enables or disables the measurement of the overhead of the pipeline (disabled by default).
+    ///
+    /// When enabled, the pipeline produces measurements of the time spent in the sources,
+    /// transforms and outputs. See [`pipeline::overhead`](crate::pipeline::overhead) for the list of metrics.
+    pub fn measure_pipeline_overhead(&mut self, enabled: bool) {
+        self.settings.measure_pipeline_overhead = enabled;
+    }
+
+    /// Enables or disables the metadata about the agent (disabled by default).
+    ///
+    /// When enabled, the pipeline produces, once, measurements that describe the version of Alumet
+    /// and the plugins that are running. See [`pipeline::info`](crate::pipeline::info) for the list of metrics.
+    pub fn emit_agent_info(&mut self, enabled: bool) {
+        self.settings.emit_agent_info = enabled;
+    }
+
+    /// Sets what to do when the configuration of a plugin contains keys that the plugin does not use.
+    ///
+    /// By default, a warning is logged. Use [`UnknownKeysPolicy::Error`] to make the plugin fail to initialize.
+    pub fn unknown_config_keys(&mut self, policy: UnknownKeysPolicy) {
+        self.settings.unknown_config_keys = policy;
+    }
+
+    /// Sets the clock of the pipeline (the system clock by default).
+    ///
+    /// This is useful to test the pipeline with a [`MockClock`](crate::time::MockClock).
+    pub fn clock(&mut self, clock: Arc<dyn Clock>) {
+        self.settings.clock = clock;
+    }
+
+    /// Enables or disables the cache of the last value of each series (disabled by default).
+    ///
+    /// When enabled, the outputs can query the last values in [`OutputContext::last_values`](crate::pipeline::OutputContext::last_values).
+    /// The series that have not been updated for `max_age` are evicted, see [`pipeline::cache`](crate::pipeline::cache).
+    pub fn last_value_cache(&mut self, max_age: Option<Duration>) {
+        self.settings.last_value_cache = max_age;
+    }
+
+    /// Enables or disables the numbering of the buffers sent to the outputs (disabled by default).
+    ///
+    /// When enabled, the outputs can read the number of each buffer in
+    /// [`OutputContext::sequence_number`](crate::pipeline::OutputContext::sequence_number),
+    /// for instance to detect missing or reordered buffers downstream.
+    pub fn sequence_numbers(&mut self, enabled: bool) {
+        self.settings.sequence_numbers = enabled;
+    }
+
+    /// Sets the expected number of measurement points produced by each poll of a source (unknown by default).
+    ///
+    /// The buffers of the sources are pre-allocated according to this hint, which avoids reallocating them
+    /// while they are filled, on machines with many RAPL domains, CPU sockets or GPUs.
+    /// Without a hint, the buffers start small and adapt to the number of points of the previous flush.
+    /// The pre-allocation is capped, so that an unreasonable hint does not allocate a huge buffer.
+    pub fn points_per_poll(&mut self, hint: Option<usize>) {
+        self.settings.points_per_poll = hint;
+    }
+
+    /// Sets what to do when several plugins register a metric with the same name.
+    ///
+    /// By default, the registrations that have the same type and unit share the same metric id,
+    /// see [`DuplicateMetricPolicy`].
+    pub fn duplicate_metrics(&mut self, policy: DuplicateMetricPolicy) {
+        self.settings.duplicate_metrics = policy;
+    }
+
+    /// Sets the named processing branches of the pipeline (none by default).
+    ///
+    /// Each branch applies some transforms to the measurements of one output only, instead of all the outputs,
+    /// see [`branches`](crate::pipeline::branches).
+    pub fn branches(&mut self, branches: BTreeMap<String, BranchConfig>) {
+        self.settings.branches = branches;
+    }
+
+    /// Restricts the threads of the measurement pipeline to some CPUs (Linux only, unrestricted by default).
+    ///
+    /// The managed sources are polled by these threads: pinning them avoids migrations between cores, and keeps
+    /// the measurement from perturbing the cores that are measured. The CPUs can be parsed from a list such as
+    /// `"0-3,8"` with [`parse_cpu_list`](crate::pipeline::threading::parse_cpu_list).
+    /// If the affinity cannot be applied, an error is logged and the threads run on any CPU.
+    pub fn cpu_affinity(&mut self, cpus: Option<Vec<usize>>) {
+        self.settings.cpu_affinity = cpus;
+    }
+
+    /// Sets whether each managed source is polled once when the pipeline is built, and what to do
+    /// if this trial poll fails (disabled by default).
+    ///
+    /// This catches the misconfigured sources when the agent starts. A poll that succeeds without producing
+    /// any measurement is not a failure, see [`pipeline::probe`](crate::pipeline::probe).
+    pub fn startup_probe(&mut self, policy: StartupProbe) {
+        self.settings.startup_probe = policy;
+    }
 }
 
 impl RunningAgent {
+    /// Lets the measurement pipeline run for `duration`, then stops it and stops the plugins.
+    ///
+    /// The last measurements are flushed to the outputs before the plugins are stopped,
+    /// see [`RunningPipeline::run_for`].
+    pub fn run_for(mut self, duration: Duration) -> anyhow::Result<()> {
+        self.pipeline.shutdown_after(duration);
+        self.wait_for_shutdown()
+    }
+
     /// Waits until the measurement pipeline stops, then stops the plugins.
     ///
     /// If an element of the pipeline returns an error or panicks, the other elements are aborted and an error is returned.
@@ -345,7 +558,8 @@ fn load_config_from_file(
                 std::io::ErrorKind::NotFound => {
                     // the file does not exist, create the default config and save it
                     let default_config = build_default_config(plugins, default_agent_config)?;
-                    std::fs::write(path, default_config.to_string())
+                    let commented = build_commented_default_config(plugins, default_agent_config)?;
+                    std::fs::write(path, commented)
                         .with_context(|| format!("writing default config to {}", path.display()))?;
                     log::info!("Default configuration written to {}", path.display());
                     Ok(default_config)
@@ -380,13 +594,163 @@ fn build_default_config(plugins: &[PluginMetadata], default_agent_config: &toml:
     Ok(default_config)
 }
 
+/// Builds the effective configuration by merging the loaded configuration into the default configuration,
+/// then redacts the sensitive values.
+fn build_effective_config(
+    plugins: &[PluginMetadata],
+    default_agent_config: &toml::Table,
+    loaded: &toml::Table,
+) -> anyhow::Result<toml::Table> {
+    let mut effective = build_default_config(plugins, default_agent_config)?;
+    config::merge_tables(&mut effective, loaded.clone());
+    config::redact_sensitive(&mut effective, &[]);
+
+    // The schemas of the plugins can mark other keys as sensitive.
+    if let Some(toml::Value::Table(plugins_config)) = effective.get_mut("plugins") {
+        for plugin in plugins {
+            let (Some(schema), Some(toml::Value::Table(plugin_config))) =
+                ((plugin.config_schema)(), plugins_config.get_mut(&plugin.name))
+            else {
+                continue;
+            };
+            let sensitive: Vec<&str> = schema
+                .entries()
+                .iter()
+                .filter(|e| e.sensitive)
+                .map(|e| e.key.as_str())
+                .collect();
+            config::redact_sensitive(plugin_config, &sensitive);
+        }
+    }
+    Ok(effective)
+}
+
+/// Builds the same configuration as [`build_default_config`], as a TOML string with comments
+/// that describe the configuration keys of the plugins.
+fn build_commented_default_config(
+    plugins: &[PluginMetadata],
+    default_agent_config: &toml::Table,
+) -> anyhow::Result<String> {
+    let mut out = toml::to_string(default_agent_config)?;
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    out.push_str("[plugins]\n");
+    for plugin in plugins {
+        let values = (plugin.default_config)()?;
+        let schema = (plugin.config_schema)();
+        if values.is_none() && schema.is_none() {
+            continue;
+        }
+        let values = values.map(|c| c.0).unwrap_or_default();
+        out.push('\n');
+        config::write_commented_table(&mut out, &format!("plugins.{}", plugin.name), &values, schema.as_ref());
+    }
+    Ok(out)
+}
+
+/// Sorts the plugins so that each plugin comes after all its dependencies.
+///
+/// Plugins that do not depend on each other keep their original order.
+/// Returns an error if a dependency is missing or if there is a dependency cycle.
+fn sort_by_dependencies(plugins: Vec<PluginMetadata>) -> anyhow::Result<Vec<PluginMetadata>> {
+    #[derive(Clone, Copy, PartialEq)]
+    enum Mark {
+        Unvisited,
+        InProgress,
+        Done,
+    }
+
+    fn visit(
+        i: usize,
+        plugins: &[PluginMetadata],
+        indices: &HashMap<&str, usize>,
+        marks: &mut [Mark],
+        stack: &mut Vec<usize>,
+        order: &mut Vec<usize>,
+    ) -> anyhow::Result<()> {
+        match marks[i] {
+            Mark::Done => return Ok(()),
+            Mark::InProgress => {
+                let start = stack.iter().position(|&j| j == i).unwrap();
+                let cycle = stack[start..]
+                    .iter()
+                    .chain(std::iter::once(&i))
+                    .map(|&j| plugins[j].name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(" -> ");
+                return Err(anyhow!("dependency cycle detected: {cycle}"));
+            }
+            Mark::Unvisited => (),
+        }
+        marks[i] = Mark::InProgress;
+        stack.push(i);
+        for dep in &plugins[i].dependencies {
+            let j = *indices.get(dep.as_str()).with_context(|| {
+                format!("plugin {} depends on plugin {dep}, which is not enabled", plugins[i].name)
+            })?;
+            visit(j, plugins, indices, marks, stack, order)?;
+        }
+        stack.pop();
+        marks[i] = Mark::Done;
+        order.push(i);
+        Ok(())
+    }
+
+    let indices: HashMap<&str, usize> = plugins.iter().enumerate().map(|(i, p)| (p.name.as_str(), i)).collect();
+    let mut marks = vec![Mark::Unvisited; plugins.len()];
+    let mut order = Vec::with_capacity(plugins.len());
+    for i in 0..plugins.len() {
+        visit(i, &plugins, &indices, &mut marks, &mut Vec::new(), &mut order)?;
+    }
+
+    let mut plugins: Vec<Option<PluginMetadata>> = plugins.into_iter().map(Some).collect();
+    Ok(order.into_iter().map(|i| plugins[i].take().unwrap()).collect())
+}
+
+/// Returns the pairs of plugins that have the same log target, with this target.
+fn shared_log_targets(plugins: &[PluginMetadata]) -> Vec<(&str, &str, &str)> {
+    let mut targets: HashMap<&str, &str> = HashMap::new();
+    let mut shared = Vec::new();
+    for plugin in plugins {
+        if let Some(target) = &plugin.log_target {
+            if let Some(other) = targets.insert(target.as_str(), plugin.name.as_str()) {
+                shared.push((other, plugin.name.as_str(), target.as_str()));
+            }
+        }
+    }
+    shared
+}
+
 /// Finds the configuration of a plugin in the global config, and initialize the plugin.
-fn initialize_with_config(agent_config: &mut AgentConfig, plugin: PluginMetadata) -> anyhow::Result<Box<dyn Plugin>> {
+fn initialize_with_config(
+    agent_config: &mut AgentConfig,
+    plugin: PluginMetadata,
+    unknown_keys: UnknownKeysPolicy,
+) -> anyhow::Result<Box<dyn Plugin>> {
     let name = &plugin.name;
-    let plugin_config = agent_config.take_plugin_config(name)?;
+    let mut plugin_config = agent_config.take_plugin_config(name)?;
+
+    // The log level is handled by the agent, the plugin does not see it.
+    if let Some(level) = plugin_config.remove(LOG_LEVEL_KEY) {
+        let level = match level {
+            toml::Value::String(level) => logging::parse_level(&level),
+            other => Err(anyhow!("{LOG_LEVEL_KEY} must be a string, not a {}", other.type_str())),
+        }
+        .context(InvalidConfig)?;
+        match &plugin.log_target {
+            Some(target) if logging::set_plugin_level(target, level) => {
+                log::debug!("Log level of plugin {name} (target {target}): {level}");
+            }
+            Some(_) => {
+                log::warn!("The log_level of plugin {name} is ignored, because the application does not support it.")
+            }
+            None => log::warn!("The log_level of plugin {name} is ignored, because its log records are not forwarded."),
+        }
+    }
 
     log::debug!("Initializing plugin {name} with config {plugin_config:?}");
-    (plugin.init)(ConfigTable(plugin_config))
+    (plugin.init)(ConfigTable::from(plugin_config).with_unknown_keys_policy(name, unknown_keys))
 }
 
 /// Prints some statistics after the plugin start-up phase.
@@ -450,6 +814,19 @@ impl AgentBuilder {
             f_after_operation_begin: |_| (),
             allow_no_metrics: false,
             source_constraints: TriggerConstraints::default(),
+            global_attributes: Vec::new(),
+            measure_pipeline_overhead: false,
+            emit_agent_info: false,
+            unknown_config_keys: UnknownKeysPolicy::default(),
+            clock: Arc::new(SystemClock),
+            last_value_cache: None,
+            sequence_numbers: false,
+            points_per_poll: None,
+            duplicate_metrics: DuplicateMetricPolicy::default(),
+            branches: BTreeMap::new(),
+            cpu_affinity: None,
+            startup_probe: StartupProbe::default(),
+            effective_config_path: None,
         }
     }
 
@@ -570,10 +947,12 @@ pub use static_plugins;
 
 #[cfg(test)]
 mod tests {
+    use anyhow::anyhow;
     use serde::Serialize;
 
-    use crate::plugin::rust::{serialize_config, AlumetPlugin};
-    use crate::plugin::{AlumetStart, ConfigTable};
+    use crate::config::{ConfigSchema, ConfigValueType, UnknownKeysPolicy};
+    use crate::plugin::rust::{serialize_config, AlumetPlugin, InvalidConfig};
+    use crate::plugin::{AlumetStart, ConfigTable, PluginMetadata};
 
     #[test]
     fn parse_config_file() {
@@ -632,6 +1011,147 @@ mod tests {
         );
     }
 
+    #[test]
+    fn commented_default_config() {
+        let plugins = static_plugins![MyPlugin];
+        let mut agent_config = toml::Table::new();
+        agent_config.insert(String::from("key"), toml::Value::String(String::from("value")));
+
+        let commented = super::build_commented_default_config(&plugins, &agent_config).unwrap();
+        assert!(commented.contains("# How many items to count.\n# type: integer\ncount = 42\n"));
+        assert!(commented.contains("# Optional limit.\n# type: integer\n# limit = 10\n"));
+        assert!(commented.contains("list = [\"default-item\"]"));
+
+        // same values as the uncommented config
+        let expected = super::build_default_config(&plugins, &agent_config).unwrap();
+        assert_eq!(commented.parse::<toml::Table>().unwrap(), expected);
+    }
+
+    #[test]
+    fn effective_config() {
+        let mut plugins = static_plugins![MyPlugin];
+        let mut metadata = metadata_with_deps("db", &[]);
+        metadata.default_config = Box::new(|| {
+            let config: toml::Table = "url = 'http://localhost'\ndsn = ''".parse().unwrap();
+            Ok(Some(ConfigTable::from(config)))
+        });
+        metadata.config_schema = Box::new(|| {
+            let schema = ConfigSchema::new()
+                .entry("url", ConfigValueType::String, "Address of the database.")
+                .entry("dsn", ConfigValueType::String, "Connection string.")
+                .mark_sensitive("dsn");
+            Some(schema)
+        });
+        plugins.push(metadata);
+
+        let loaded: toml::Table = toml::toml! {
+            key = "value"
+            [plugins.name]
+            count = 1
+            [plugins.db]
+            dsn = "user:pass@db"
+            api_token = "abcd"
+        };
+        let defaults = toml::toml! { period = "1s" };
+        let effective = super::build_effective_config(&plugins, &defaults, &loaded).unwrap();
+        let expected: toml::Table = toml::toml! {
+            period = "1s"
+            key = "value"
+            [plugins.name]
+            list = ["default-item"]
+            count = 1
+            [plugins.db]
+            url = "http://localhost"
+            dsn = "<redacted>"
+            api_token = "<redacted>"
+        };
+        assert_eq!(effective, expected);
+
+        // it can be loaded again
+        let written = toml::to_string(&effective).unwrap();
+        assert_eq!(written.parse::<toml::Table>().unwrap(), effective);
+    }
+
+    #[test]
+    fn plugin_dependencies_order() {
+        let plugins = vec![
+            metadata_with_deps("c", &["a", "b"]),
+            metadata_with_deps("a", &[]),
+            metadata_with_deps("b", &["a"]),
+            metadata_with_deps("d", &[]),
+        ];
+        let sorted = super::sort_by_dependencies(plugins).unwrap();
+        let names: Vec<&str> = sorted.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn plugin_dependencies_cycle() {
+        let plugins = vec![
+            metadata_with_deps("a", &["b"]),
+            metadata_with_deps("b", &["c"]),
+            metadata_with_deps("c", &["a"]),
+        ];
+        let err = super::sort_by_dependencies(plugins).err().expect("cycle should be detected");
+        assert!(err.to_string().contains("a -> b -> c -> a"), "wrong error: {err}");
+    }
+
+    #[test]
+    fn plugin_dependencies_missing() {
+        let plugins = vec![metadata_with_deps("a", &["nope"])];
+        assert!(super::sort_by_dependencies(plugins).is_err());
+    }
+
+    #[test]
+    fn plugin_log_level() {
+        assert_eq!(
+            PluginMetadata::from_static::<MyPlugin>().log_target.as_deref(),
+            Some("alumet")
+        );
+
+        // two plugins of the same crate have the same target, unless they override it
+        let mut plugins = vec![metadata_with_deps("a", &[]), metadata_with_deps("b", &[])];
+        plugins[1].log_target = Some(String::from("a"));
+        assert_eq!(super::shared_log_targets(&plugins), vec![("a", "b", "a")]);
+        plugins[1].log_target = None;
+        assert!(super::shared_log_targets(&plugins).is_empty());
+
+        // log_level is written as a TOML value
+        let init = |log_level: &str| {
+            let mut metadata = metadata_with_deps("a", &[]);
+            metadata.init = Box::new(|config| {
+                // the plugin must not see the log level
+                assert!(!config.0.contains_key(super::LOG_LEVEL_KEY));
+                Err(anyhow!("plugin initialized"))
+            });
+            let global_config: toml::Table = format!("[plugins.a]\nlog_level = {log_level}").parse().unwrap();
+            let mut config = super::AgentConfig::try_from(global_config).unwrap();
+            super::initialize_with_config(&mut config, metadata, UnknownKeysPolicy::Error)
+                .err()
+                .unwrap()
+        };
+        let err = init("'debug'");
+        assert_eq!(err.to_string(), "plugin initialized");
+        let err = init("'verbose'");
+        assert!(err.is::<InvalidConfig>(), "unexpected error {err:?}");
+        let err = init("3");
+        assert!(err.is::<InvalidConfig>(), "unexpected error {err:?}");
+    }
+
+    fn metadata_with_deps(name: &str, dependencies: &[&str]) -> PluginMetadata {
+        PluginMetadata {
+            name: name.to_owned(),
+            version: String::from("0.0.1"),
+            init: Box::new(|_| Err(anyhow::anyhow!("plugins are not initialized in this test"))),
+            default_config: Box::new(|| Ok(None)),
+            dependencies: dependencies.iter().map(|d| (*d).to_owned()).collect(),
+            config_required: true,
+            config_schema: Box::new(|| None),
+            commands: Box::new(Vec::new),
+            log_target: Some(name.to_owned()),
+        }
+    }
+
     struct MyPlugin;
     impl AlumetPlugin for MyPlugin {
         fn name() -> &'static str {
@@ -658,6 +1178,13 @@ mod tests {
             let config = serialize_config(MyPluginConfig::default())?;
             Ok(Some(config))
         }
+
+        fn config_schema() -> Option<ConfigSchema> {
+            let schema = ConfigSchema::new()
+                .entry("count", ConfigValueType::Integer, "How many items to count.")
+                .optional_entry("limit", ConfigValueType::Integer, 10, "Optional limit.");
+            Some(schema)
+        }
     }
 
     #[derive(Serialize)]
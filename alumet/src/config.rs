@@ -0,0 +1,957 @@
+//! Configuration utilities.
+//!
+//! ## Environment variables
+//!
+//! String values of the configuration can refer to environment variables with the `${VAR}` syntax.
+//! The reference is replaced by the value of the variable when the configuration is loaded.
+//! For instance:
+//! ```toml
+//! endpoint = "${OTLP_ENDPOINT}"
+//! ```
+//!
+//! A default value can be provided with `${VAR:-default}`. It is used when the variable
+//! is not defined, or is empty. If there is no default value and the variable is not defined,
+//! loading the configuration fails.
+//!
+//! To write a literal `${`, escape it with an additional dollar: `$${` becomes `${`.
+//! A `$` that is not followed by `{` is left untouched.
+//!
+//! Only string values are interpolated, keys are never modified.
+//!
+//! ## Durations
+//!
+//! Durations can be written as strings made of integers followed by a unit, for instance
+//! `"500ms"`, `"2s"` or `"1m 30s"`. The syntax is the one of the [`humantime`] crate, which is also
+//! used by the plugins that rely on `humantime_serde`. The common units are `ns`, `us`, `ms`, `s`,
+//! `m` (or `min`), `h` and `d`. Beware: `M` means months, not minutes.
+//! A bare integer, such as `500`, is a number of milliseconds.
+//! See [`parse_duration`] and [`ConfigTable::get_duration`](crate::plugin::ConfigTable::get_duration).
+//!
+//! ## Unknown keys
+//!
+//! When a plugin deserializes its configuration with [`deserialize_config`](crate::plugin::rust::deserialize_config),
+//! the keys that it does not use are detected, because they are probably typos.
+//! By default, they are reported with a warning. The agent can be made stricter with
+//! [`Agent::unknown_config_keys`](crate::agent::Agent::unknown_config_keys), see [`UnknownKeysPolicy`].
+//!
+//! ## Schema
+//!
+//! A plugin can describe the keys of its configuration with a [`ConfigSchema`], returned by
+//! [`AlumetPlugin::config_schema`](crate::plugin::rust::AlumetPlugin::config_schema).
+//! The schema is used to document the default configuration file, see
+//! [`Agent::commented_default_config`](crate::agent::Agent::commented_default_config):
+//! each key is preceded by its description and type, and the optional keys that are not in
+//! the default configuration are written as comments.
+//!
+//! ## Effective configuration
+//!
+//! For auditing, the agent can write the configuration that it actually uses to a file, when it starts,
+//! see [`Agent::effective_config_path`](crate::agent::Agent::effective_config_path). This configuration
+//! is the configuration file, where the missing keys are filled with the default values and the environment
+//! variables are replaced. It is valid TOML, which can be loaded again.
+//!
+//! The sensitive values, such as passwords, are replaced by [`REDACTED`]. A key is sensitive if
+//! it is marked as such in the schema of the plugin (see [`ConfigSchema::mark_sensitive`]), or if its name
+//! contains one of the words of [`SENSITIVE_KEY_WORDS`], for instance `token` or `password`.
+
+use std::{cell::RefCell, fmt, time::Duration};
+
+use serde::de::{self, DeserializeSeed, Deserializer, IntoDeserializer, MapAccess, SeqAccess, Visitor};
+
+/// Replaces the references to environment variables in every string value of the table, recursively.
+///
+/// See the [module documentation](self) for the syntax.
+pub fn substitute_env_variables(table: &mut toml::Table) -> Result<(), InterpolationError> {
+    substitute_in_table(table, "", &|name| std::env::var(name).ok())
+}
+
+fn substitute_in_table(
+    table: &mut toml::Table,
+    path: &str,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> Result<(), InterpolationError> {
+    for (key, value) in table.iter_mut() {
+        let path = if path.is_empty() {
+            key.to_owned()
+        } else {
+            format!("{path}.{key}")
+        };
+        substitute_in_value(value, &path, lookup)?;
+    }
+    Ok(())
+}
+
+fn substitute_in_value(
+    value: &mut toml::Value,
+    path: &str,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> Result<(), InterpolationError> {
+    match value {
+        toml::Value::String(s) => {
+            if s.contains('$') {
+                *s = interpolate(s, path, lookup)?;
+            }
+        }
+        toml::Value::Array(array) => {
+            for (i, v) in array.iter_mut().enumerate() {
+                substitute_in_value(v, &format!("{path}[{i}]"), lookup)?;
+            }
+        }
+        toml::Value::Table(t) => substitute_in_table(t, path, lookup)?,
+        _ => (),
+    }
+    Ok(())
+}
+
+/// Interpolates the variables in `input`, using `lookup` to get their values.
+fn interpolate(input: &str, path: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Result<String, InterpolationError> {
+    let mut res = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(i) = rest.find('$') {
+        res.push_str(&rest[..i]);
+        let after_dollar = &rest[i + 1..];
+        if let Some(escaped) = after_dollar.strip_prefix("${") {
+            // `$${` is an escaped `${`
+            res.push_str("${");
+            rest = escaped;
+        } else if let Some(expr_start) = after_dollar.strip_prefix('{') {
+            let end = expr_start.find('}').ok_or_else(|| InterpolationError::Unterminated {
+                key: path.to_owned(),
+            })?;
+            let expr = &expr_start[..end];
+            let value = match expr.split_once(":-") {
+                Some((name, default)) => lookup(name)
+                    .filter(|v| !v.is_empty())
+                    .unwrap_or_else(|| default.to_owned()),
+                None => lookup(expr).ok_or_else(|| InterpolationError::UndefinedVariable {
+                    key: path.to_owned(),
+                    variable: expr.to_owned(),
+                })?,
+            };
+            res.push_str(&value);
+            rest = &expr_start[end + 1..];
+        } else {
+            // lone dollar, keep it
+            res.push('$');
+            rest = after_dollar;
+        }
+    }
+    res.push_str(rest);
+    Ok(res)
+}
+
+/// Error that can occur when substituting environment variables in the configuration.
+#[derive(Debug)]
+pub enum InterpolationError {
+    /// The variable is not defined and no default value has been provided.
+    UndefinedVariable { key: String, variable: String },
+    /// A `${` has no matching `}`.
+    Unterminated { key: String },
+}
+
+impl fmt::Display for InterpolationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InterpolationError::UndefinedVariable { key, variable } => write!(
+                f,
+                "environment variable {variable} is not defined (used in config key {key}), use ${{{variable}:-default}} to provide a default value"
+            ),
+            InterpolationError::Unterminated { key } => {
+                write!(f, "unterminated variable reference in config key {key}: missing '}}'")
+            }
+        }
+    }
+}
+
+impl std::error::Error for InterpolationError {}
+
+/// Parses a human-friendly duration, such as `"500ms"`, `"2s"` or `"1m 30s"`.
+///
+/// This is [`humantime::parse_duration`], which is also used by the plugins that deserialize their durations
+/// with `humantime_serde`: the core and the plugins accept the same syntax.
+/// See the [module documentation](self) for more details.
+pub fn parse_duration(input: &str) -> Result<Duration, DurationError> {
+    humantime::parse_duration(input)
+}
+
+/// Error that can occur when parsing a duration, see [`parse_duration`].
+pub use humantime::DurationError;
+
+/// Serializes and deserializes a [`Duration`] in the human-friendly format of [`parse_duration`].
+///
+/// Like [`ConfigTable::get_duration`](crate::plugin::ConfigTable::get_duration), an integer is accepted
+/// as a number of milliseconds. Use it with `#[serde(with = "alumet::config::serde_duration")]`.
+pub mod serde_duration {
+    use std::time::Duration;
+
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        let s = if duration.subsec_nanos() == 0 {
+            format!("{}s", duration.as_secs())
+        } else if duration.subsec_nanos() % 1_000_000 == 0 {
+            format!("{}ms", duration.as_millis())
+        } else {
+            format!("{}ns", duration.as_nanos())
+        };
+        serializer.serialize_str(&s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Millis(u64),
+            Text(String),
+        }
+        match Repr::deserialize(deserializer)? {
+            Repr::Millis(ms) => Ok(Duration::from_millis(ms)),
+            Repr::Text(s) => humantime::parse_duration(&s).map_err(de::Error::custom),
+        }
+    }
+}
+
+/// Error that can occur when reading a value of a [`ConfigTable`](crate::plugin::ConfigTable).
+#[derive(Debug)]
+pub enum ConfigValueError {
+    /// The value does not have the expected type.
+    WrongType { key: String, expected: &'static str },
+    /// The value is not a valid duration.
+    InvalidDuration { key: String, source: DurationError },
+    /// The value is a negative number of milliseconds.
+    NegativeDuration { key: String, millis: i64 },
+}
+
+impl fmt::Display for ConfigValueError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigValueError::WrongType { key, expected } => {
+                write!(f, "invalid value for config key {key}: expected {expected}")
+            }
+            ConfigValueError::InvalidDuration { key, source } => {
+                write!(f, "invalid duration for config key {key}: {source}")
+            }
+            ConfigValueError::NegativeDuration { key, millis } => {
+                write!(f, "invalid duration for config key {key}: {millis}ms is negative")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigValueError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigValueError::WrongType { .. } | ConfigValueError::NegativeDuration { .. } => None,
+            ConfigValueError::InvalidDuration { source, .. } => Some(source),
+        }
+    }
+}
+
+/// Description of the configuration keys of a plugin.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigSchema {
+    entries: Vec<ConfigEntry>,
+}
+
+/// Description of a configuration key.
+#[derive(Debug, Clone)]
+pub struct ConfigEntry {
+    /// The key, for instance `poll_interval`.
+    pub key: String,
+    /// The type of the value.
+    pub value_type: ConfigValueType,
+    /// The value to suggest when the key is not in the default configuration, if any (see [`ConfigSchema::optional_entry`]).
+    pub default: Option<toml::Value>,
+    /// What the key does.
+    pub description: String,
+    /// Whether the value is a secret, which must not be written to the effective configuration.
+    pub sensitive: bool,
+}
+
+/// Type of a configuration value, as documented in a [`ConfigSchema`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigValueType {
+    String,
+    Integer,
+    Float,
+    Boolean,
+    /// A duration, written as a string such as `"1s"` (see [Durations](self#durations)).
+    Duration,
+    Array,
+    Table,
+}
+
+impl fmt::Display for ConfigValueType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ConfigValueType::String => "string",
+            ConfigValueType::Integer => "integer",
+            ConfigValueType::Float => "float",
+            ConfigValueType::Boolean => "boolean",
+            ConfigValueType::Duration => "duration",
+            ConfigValueType::Array => "array",
+            ConfigValueType::Table => "table",
+        })
+    }
+}
+
+impl ConfigSchema {
+    /// Creates an empty schema.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a key to the schema.
+    pub fn entry(
+        mut self,
+        key: impl Into<String>,
+        value_type: ConfigValueType,
+        description: impl Into<String>,
+    ) -> Self {
+        self.entries.push(ConfigEntry {
+            key: key.into(),
+            value_type,
+            default: None,
+            description: description.into(),
+            sensitive: false,
+        });
+        self
+    }
+
+    /// Adds an optional key to the schema, with a value to suggest.
+    ///
+    /// The suggested value is only written (as a comment) when the key is missing from the default configuration
+    /// of the plugin, which takes precedence.
+    pub fn optional_entry(
+        mut self,
+        key: impl Into<String>,
+        value_type: ConfigValueType,
+        suggested: impl Into<toml::Value>,
+        description: impl Into<String>,
+    ) -> Self {
+        self.entries.push(ConfigEntry {
+            key: key.into(),
+            value_type,
+            default: Some(suggested.into()),
+            description: description.into(),
+            sensitive: false,
+        });
+        self
+    }
+
+    /// Marks a key of the schema as sensitive: its value is redacted in the effective configuration.
+    ///
+    /// The key must have been added before, with [`entry`](Self::entry) or [`optional_entry`](Self::optional_entry).
+    pub fn mark_sensitive(mut self, key: &str) -> Self {
+        match self.entries.iter_mut().find(|e| e.key == key) {
+            Some(entry) => entry.sensitive = true,
+            None => log::warn!("Cannot mark {key} as sensitive: it is not in the schema."),
+        }
+        self
+    }
+
+    /// The keys of the schema, in the order in which they have been added.
+    pub fn entries(&self) -> &[ConfigEntry] {
+        &self.entries
+    }
+
+    /// Finds the description of a key.
+    pub fn get(&self, key: &str) -> Option<&ConfigEntry> {
+        self.entries.iter().find(|e| e.key == key)
+    }
+}
+
+/// Replaces the sensitive values in the effective configuration.
+pub const REDACTED: &str = "<redacted>";
+
+/// The keys that contain one of these words (ignoring the case) are always sensitive.
+pub const SENSITIVE_KEY_WORDS: [&str; 6] = ["password", "secret", "token", "api_key", "api-key", "authorization"];
+
+/// Returns true if the name of `key` indicates that its value is sensitive, see [`SENSITIVE_KEY_WORDS`].
+pub fn is_sensitive_key(key: &str) -> bool {
+    let key = key.to_ascii_lowercase();
+    SENSITIVE_KEY_WORDS.iter().any(|word| key.contains(word))
+}
+
+/// Merges `overrides` into `base`, recursively: the values of `overrides` replace the values of `base`,
+/// except for the tables, which are merged.
+pub(crate) fn merge_tables(base: &mut toml::Table, overrides: toml::Table) {
+    for (key, value) in overrides {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base_table)), toml::Value::Table(table)) => merge_tables(base_table, table),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Replaces the sensitive values of the table by [`REDACTED`], recursively.
+///
+/// The values of the keys of `sensitive_keys`, at the top level of the table, are replaced,
+/// in addition to the keys whose name is sensitive (see [`is_sensitive_key`]) at any level.
+pub(crate) fn redact_sensitive(table: &mut toml::Table, sensitive_keys: &[&str]) {
+    for (key, value) in table.iter_mut() {
+        if sensitive_keys.contains(&key.as_str()) || is_sensitive_key(key) {
+            *value = toml::Value::String(String::from(REDACTED));
+            continue;
+        }
+        match value {
+            toml::Value::Table(t) => redact_sensitive(t, &[]),
+            toml::Value::Array(values) => {
+                for v in values {
+                    if let toml::Value::Table(t) = v {
+                        redact_sensitive(t, &[]);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Writes a TOML table named `header`, with the `values` of the default configuration,
+/// documented by `schema`.
+///
+/// The keys of the schema come first, in the order of the schema, followed by the undocumented keys.
+pub(crate) fn write_commented_table(
+    out: &mut String,
+    header: &str,
+    values: &toml::Table,
+    schema: Option<&ConfigSchema>,
+) {
+    use std::fmt::Write;
+
+    fn key_repr(key: &str) -> String {
+        if !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            key.to_owned()
+        } else {
+            toml::Value::String(key.to_owned()).to_string()
+        }
+    }
+
+    writeln!(out, "[{header}]").unwrap();
+    let documented = schema.map(|s| s.entries()).unwrap_or_default();
+    for entry in documented {
+        for line in entry.description.lines() {
+            writeln!(out, "# {line}").unwrap();
+        }
+        writeln!(out, "# type: {}", entry.value_type).unwrap();
+        let key = key_repr(&entry.key);
+        match (values.get(&entry.key), &entry.default) {
+            (Some(value), _) => writeln!(out, "{key} = {value}").unwrap(),
+            (None, Some(default)) => writeln!(out, "# {key} = {default}").unwrap(),
+            (None, None) => writeln!(out, "# {key} =").unwrap(),
+        }
+        writeln!(out).unwrap();
+    }
+    for (key, value) in values {
+        if !documented.iter().any(|e| &e.key == key) {
+            writeln!(out, "{} = {value}", key_repr(key)).unwrap();
+        }
+    }
+}
+
+/// What to do when the configuration of a plugin contains keys that the plugin does not use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnknownKeysPolicy {
+    /// Log a warning and continue.
+    #[default]
+    Warn,
+    /// Fail to initialize the plugin.
+    Error,
+}
+
+/// The plugin whose configuration is checked, and the policy to apply to its unknown keys.
+///
+/// It is attached to the [`ConfigTable`](crate::plugin::ConfigTable) given to the plugin by the agent.
+#[derive(Debug, Clone)]
+pub(crate) struct ConfigCheck {
+    pub plugin: String,
+    pub policy: UnknownKeysPolicy,
+}
+
+/// Reports the keys that have not been used by a plugin, according to the [`UnknownKeysPolicy`] of `check`.
+///
+/// Without `check`, a warning is logged.
+pub(crate) fn report_unknown_keys(keys: &[String], check: Option<&ConfigCheck>) -> anyhow::Result<()> {
+    if keys.is_empty() {
+        return Ok(());
+    }
+    let keys = keys.join(", ");
+    let (plugin, policy) = match check {
+        Some(check) => (format!(" of plugin {}", check.plugin), check.policy),
+        None => (String::new(), UnknownKeysPolicy::default()),
+    };
+    match policy {
+        UnknownKeysPolicy::Warn => {
+            log::warn!("Unknown keys in the configuration{plugin}, they will be ignored (is there a typo?): {keys}");
+            Ok(())
+        }
+        UnknownKeysPolicy::Error => Err(anyhow::anyhow!("unknown keys in the configuration{plugin}: {keys}")),
+    }
+}
+
+/// Deserializes a table and returns the keys that have not been used, as dotted paths such as `a.b` or `list[0].c`.
+///
+/// A key is unused when the deserialized type ignores it, for instance because a struct has no field with this name.
+/// The content of untagged enums and flattened fields is considered to be used.
+pub fn deserialize_tracked<'de, T: de::Deserialize<'de>>(
+    table: toml::Table,
+) -> Result<(T, Vec<String>), toml::de::Error> {
+    let unused = RefCell::new(Vec::new());
+    let value = T::deserialize(TrackedValue {
+        value: toml::Value::Table(table),
+        path: String::new(),
+        unused: &unused,
+    })?;
+    Ok((value, unused.into_inner()))
+}
+
+/// Deserializer of toml values that records the values that are ignored.
+struct TrackedValue<'a> {
+    value: toml::Value,
+    path: String,
+    unused: &'a RefCell<Vec<String>>,
+}
+
+struct TrackedMap<'a> {
+    iter: toml::map::IntoIter,
+    next_value: Option<(String, toml::Value)>,
+    path: String,
+    unused: &'a RefCell<Vec<String>>,
+}
+
+struct TrackedSeq<'a> {
+    iter: std::iter::Enumerate<std::vec::IntoIter<toml::Value>>,
+    path: String,
+    unused: &'a RefCell<Vec<String>>,
+}
+
+impl<'de, 'a> Deserializer<'de> for TrackedValue<'a> {
+    type Error = toml::de::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.value {
+            toml::Value::String(s) => visitor.visit_string(s),
+            toml::Value::Integer(i) => visitor.visit_i64(i),
+            toml::Value::Float(f) => visitor.visit_f64(f),
+            toml::Value::Boolean(b) => visitor.visit_bool(b),
+            toml::Value::Array(array) => visitor.visit_seq(TrackedSeq {
+                iter: array.into_iter().enumerate(),
+                path: self.path,
+                unused: self.unused,
+            }),
+            toml::Value::Table(table) => visitor.visit_map(TrackedMap {
+                iter: table.into_iter(),
+                next_value: None,
+                path: self.path,
+                unused: self.unused,
+            }),
+            datetime @ toml::Value::Datetime(_) => datetime.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        // there is no null value in toml
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        match self.value {
+            toml::Value::String(s) => visitor.visit_enum(IntoDeserializer::<toml::de::Error>::into_deserializer(s)),
+            // enums with data are not tracked
+            other => other.deserialize_enum(name, variants, visitor),
+        }
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.unused.borrow_mut().push(self.path);
+        visitor.visit_unit()
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct identifier
+    }
+}
+
+impl<'de, 'a> MapAccess<'de> for TrackedMap<'a> {
+    type Error = toml::de::Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, Self::Error> {
+        match self.iter.next() {
+            Some((key, value)) => {
+                let key_deserializer: de::value::StrDeserializer<'_, toml::de::Error> =
+                    key.as_str().into_deserializer();
+                let res = seed.deserialize(key_deserializer)?;
+                self.next_value = Some((key, value));
+                Ok(Some(res))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Self::Error> {
+        let (key, value) = self.next_value.take().expect("next_value_seed must be called after next_key_seed");
+        let path = if self.path.is_empty() {
+            key
+        } else {
+            format!("{}.{key}", self.path)
+        };
+        seed.deserialize(TrackedValue {
+            value,
+            path,
+            unused: self.unused,
+        })
+    }
+}
+
+impl<'de, 'a> SeqAccess<'de> for TrackedSeq<'a> {
+    type Error = toml::de::Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, Self::Error> {
+        match self.iter.next() {
+            Some((i, value)) => {
+                let path = format!("{}[{i}]", self.path);
+                seed.deserialize(TrackedValue {
+                    value,
+                    path,
+                    unused: self.unused,
+                })
+                .map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde::Deserialize;
+
+    use super::{
+        deserialize_tracked, interpolate, merge_tables, parse_duration, redact_sensitive, substitute_in_table,
+        write_commented_table, ConfigSchema, ConfigValueError, ConfigValueType, InterpolationError, UnknownKeysPolicy,
+        REDACTED,
+    };
+    use crate::plugin::{rust::deserialize_config, ConfigTable};
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "HOST" => Some(String::from("localhost")),
+            "PORT" => Some(String::from("4317")),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn interpolate_variables() {
+        let res = interpolate("http://${HOST}:${PORT}/v1", "k", &lookup).unwrap();
+        assert_eq!(res, "http://localhost:4317/v1");
+        assert_eq!(interpolate("no variable", "k", &lookup).unwrap(), "no variable");
+        assert_eq!(interpolate("${HOST}", "k", &lookup).unwrap(), "localhost");
+    }
+
+    #[test]
+    fn interpolate_default() {
+        assert_eq!(interpolate("${NOPE:-abcd}", "k", &lookup).unwrap(), "abcd");
+        assert_eq!(interpolate("${EMPTY:-abcd}", "k", &lookup).unwrap(), "abcd");
+        assert_eq!(interpolate("${HOST:-abcd}", "k", &lookup).unwrap(), "localhost");
+        assert_eq!(interpolate("${NOPE:-}", "k", &lookup).unwrap(), "");
+    }
+
+    #[test]
+    fn interpolate_escape() {
+        assert_eq!(interpolate("$${HOST}", "k", &lookup).unwrap(), "${HOST}");
+        assert_eq!(interpolate("cost: 5$", "k", &lookup).unwrap(), "cost: 5$");
+        assert_eq!(interpolate("$HOST", "k", &lookup).unwrap(), "$HOST");
+    }
+
+    #[test]
+    fn interpolate_errors() {
+        assert!(matches!(
+            interpolate("${NOPE}", "k", &lookup),
+            Err(InterpolationError::UndefinedVariable { key, variable }) if key == "k" && variable == "NOPE"
+        ));
+        assert!(matches!(
+            interpolate("${HOST", "k", &lookup),
+            Err(InterpolationError::Unterminated { .. })
+        ));
+    }
+
+    #[test]
+    fn substitute_strings_only() {
+        let mut table: toml::Table = r#"
+            count = 12
+            endpoint = "${HOST}"
+            [plugins.a]
+            list = ["${PORT}", "x"]
+        "#
+        .parse()
+        .unwrap();
+        substitute_in_table(&mut table, "", &lookup).unwrap();
+
+        let expected: toml::Table = r#"
+            count = 12
+            endpoint = "localhost"
+            [plugins.a]
+            list = ["4317", "x"]
+        "#
+        .parse()
+        .unwrap();
+        assert_eq!(table, expected);
+
+        let mut table: toml::Table = r#"
+            [plugins.a]
+            list = ["${NOPE}"]
+        "#
+        .parse()
+        .unwrap();
+        let err = substitute_in_table(&mut table, "", &lookup).unwrap_err();
+        assert!(matches!(err, InterpolationError::UndefinedVariable { key, .. } if key == "plugins.a.list[0]"));
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("2s"), Ok(Duration::from_secs(2)));
+        assert_eq!(parse_duration("1m30s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("1h 15min"), Ok(Duration::from_secs(4500)));
+        assert_eq!(parse_duration("10us"), Ok(Duration::from_micros(10)));
+        assert_eq!(parse_duration("3ns"), Ok(Duration::from_nanos(3)));
+        assert_eq!(parse_duration("1d"), Ok(Duration::from_secs(86400)));
+        // same syntax as the plugins that use humantime_serde
+        assert_eq!(parse_duration("1h 30m"), humantime::parse_duration("1h 30m"));
+    }
+
+    #[test]
+    fn duration_errors() {
+        for invalid in ["", "500", "1s500", "5 parsecs", "-5s", "1.5s", "ms", "99999999999999999999s"] {
+            assert!(parse_duration(invalid).is_err(), "{invalid:?} should be rejected");
+        }
+    }
+
+    #[test]
+    fn config_table_duration() {
+        let table: toml::Table = r#"
+            a = "250ms"
+            b = 1500
+            c = "1.5s"
+            d = -1
+            e = true
+        "#
+        .parse()
+        .unwrap();
+        let table = ConfigTable::from(table);
+        assert_eq!(table.get_duration("a").unwrap(), Some(Duration::from_millis(250)));
+        assert_eq!(table.get_duration("b").unwrap(), Some(Duration::from_millis(1500)));
+        assert_eq!(table.get_duration("missing").unwrap(), None);
+        assert!(matches!(
+            table.get_duration("c"),
+            Err(ConfigValueError::InvalidDuration { key, .. }) if key == "c"
+        ));
+        assert!(matches!(
+            table.get_duration("d"),
+            Err(ConfigValueError::NegativeDuration { key, millis: -1 }) if key == "d"
+        ));
+        assert!(matches!(
+            table.get_duration("e"),
+            Err(ConfigValueError::WrongType { key, .. }) if key == "e"
+        ));
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Config {
+        name: String,
+        #[serde(default)]
+        limit: Option<u32>,
+        mode: Mode,
+        nested: Nested,
+        list: Vec<Nested>,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Nested {
+        x: f64,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    #[serde(rename_all = "snake_case")]
+    enum Mode {
+        Fast,
+        Slow,
+    }
+
+    const CONFIG_WITH_TYPOS: &str = r#"
+        name = "test"
+        limt = 5
+        mode = "slow"
+        nested = { x = 1, y = 2 }
+        list = [{ x = 0.5 }, { x = 1.5, z = true }]
+    "#;
+
+    #[test]
+    fn unknown_keys() {
+        let table: toml::Table = CONFIG_WITH_TYPOS.parse().unwrap();
+        let (config, unused) = deserialize_tracked::<Config>(table).unwrap();
+        assert_eq!(
+            config,
+            Config {
+                name: String::from("test"),
+                limit: None,
+                mode: Mode::Slow,
+                nested: Nested { x: 1.0 },
+                list: vec![Nested { x: 0.5 }, Nested { x: 1.5 }],
+            }
+        );
+        assert_eq!(unused, vec!["limt", "nested.y", "list[1].z"]);
+
+        let table: toml::Table = "name = 'a'\nlimit = 1\nmode = 'fast'\nnested.x = 0\nlist = []".parse().unwrap();
+        let (config, unused) = deserialize_tracked::<Config>(table).unwrap();
+        assert_eq!(config.limit, Some(1));
+        assert!(unused.is_empty());
+    }
+
+    #[test]
+    fn unknown_keys_policy() {
+        let table = ConfigTable::from(CONFIG_WITH_TYPOS.parse::<toml::Table>().unwrap());
+        assert!(deserialize_config::<Config>(table.clone()).is_ok());
+        let res = deserialize_config::<Config>(table.clone().with_unknown_keys_policy("test", UnknownKeysPolicy::Warn));
+        assert!(res.is_ok());
+        let res = deserialize_config::<Config>(table.with_unknown_keys_policy("test", UnknownKeysPolicy::Error));
+        let err = res.unwrap_err();
+        assert!(format!("{err:#}").contains("unknown keys in the configuration of plugin test: limt, nested.y, list[1].z"));
+    }
+
+    #[test]
+    fn commented_table() {
+        let schema = ConfigSchema::new()
+            .entry(
+                "poll_interval",
+                ConfigValueType::Duration,
+                "Interval between two measurements.",
+            )
+            .optional_entry(
+                "rescan",
+                ConfigValueType::Duration,
+                "1m",
+                "If set, rescan the devices.\nDisabled by default.",
+            )
+            .entry("devices", ConfigValueType::Array, "Devices to monitor.");
+        let values: toml::Table = toml::from_str(
+            r#"
+            poll_interval = "1s"
+            "undocumented key" = { a = 1 }
+            "#,
+        )
+        .unwrap();
+
+        let mut out = String::new();
+        write_commented_table(&mut out, "plugins.test", &values, Some(&schema));
+        let expected = r#"[plugins.test]
+# Interval between two measurements.
+# type: duration
+poll_interval = "1s"
+
+# If set, rescan the devices.
+# Disabled by default.
+# type: duration
+# rescan = "1m"
+
+# Devices to monitor.
+# type: array
+# devices =
+
+"undocumented key" = { a = 1 }
+"#;
+        assert_eq!(out, expected);
+
+        // the result is valid TOML, with the same values
+        let parsed: toml::Table = toml::from_str(&out).unwrap();
+        assert_eq!(parsed["plugins"]["test"].as_table().unwrap(), &values);
+    }
+
+    #[test]
+    fn merge_and_redact() {
+        let mut base: toml::Table = toml::toml! {
+            interval = "1s"
+            password = "default"
+            [server]
+            host = "localhost"
+            port = 8086
+        };
+        let overrides: toml::Table = toml::toml! {
+            interval = "5s"
+            api_token = "abcd"
+            [server]
+            host = "example.org"
+            [[server.headers]]
+            name = "X-Custom"
+            Authorization = "Bearer xyz"
+        };
+        merge_tables(&mut base, overrides);
+        assert_eq!(base["interval"].as_str(), Some("5s"));
+        assert_eq!(base["server"]["host"].as_str(), Some("example.org"));
+        assert_eq!(base["server"]["port"].as_integer(), Some(8086));
+
+        redact_sensitive(&mut base, &["interval"]);
+        assert_eq!(base["interval"].as_str(), Some(REDACTED));
+        assert_eq!(base["password"].as_str(), Some(REDACTED));
+        assert_eq!(base["api_token"].as_str(), Some(REDACTED));
+        let header = &base["server"]["headers"][0];
+        assert_eq!(header["Authorization"].as_str(), Some(REDACTED));
+        assert_eq!(header["name"].as_str(), Some("X-Custom"));
+        assert_eq!(base["server"]["host"].as_str(), Some("example.org"));
+
+        // the schema can mark a key that does not look sensitive
+        let schema = ConfigSchema::new()
+            .entry("dsn", ConfigValueType::String, "Connection string.")
+            .mark_sensitive("dsn");
+        assert!(schema.get("dsn").unwrap().sensitive);
+    }
+
+    #[test]
+    fn serde_duration() {
+        #[derive(serde::Serialize, Deserialize, Debug, PartialEq)]
+        struct Config {
+            #[serde(with = "super::serde_duration")]
+            timeout: Duration,
+        }
+        let parse = |s: &str| toml::from_str::<Config>(s).map(|c| c.timeout);
+        assert_eq!(parse("timeout = \"1m30s\"").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse("timeout = 250").unwrap(), Duration::from_millis(250));
+        assert!(parse("timeout = \"3 parsecs\"").is_err());
+
+        for (duration, text) in [
+            (Duration::from_secs(2), "2s"),
+            (Duration::from_millis(1500), "1500ms"),
+            (Duration::from_nanos(10), "10ns"),
+        ] {
+            let serialized = toml::to_string(&Config { timeout: duration }).unwrap();
+            assert_eq!(serialized.trim(), format!("timeout = \"{text}\""));
+            assert_eq!(parse(&serialized).unwrap(), duration);
+        }
+    }
+}
@@ -25,6 +25,18 @@ pub extern "C" fn metric_name<'a>(metric: RawMetricId, ctx: &'a FfiOutputContext
     AStr::from(name)
 }
 
+/// Returns the sequence number of the buffer that is being written,
+/// or null if the sequence numbers are disabled.
+///
+/// The pointer is only valid during the call to the output's `write` function.
+#[no_mangle]
+pub extern "C" fn output_sequence_number(ctx: &FfiOutputContext) -> *const u64 {
+    match &unsafe { &*ctx.inner }.sequence_number {
+        Some(n) => n,
+        None => std::ptr::null(),
+    }
+}
+
 // ====== MeasurementPoint ffi ======
 
 #[no_mangle]
@@ -32,6 +32,7 @@ pub mod resources;
 pub mod units;
 pub mod string;
 pub mod time;
+pub mod value;
 
 // ====== Function types ======
 pub type PluginInitFn = extern "C" fn(config: *const toml::Table) -> *mut c_void;
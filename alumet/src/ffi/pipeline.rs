@@ -1,8 +1,13 @@
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use anyhow::anyhow;
 use libc::c_void;
 
 use super::{DropFn, FfiOutputContext, OutputWriteFn, SourcePollFn, TransformApplyFn};
 use crate::{
-    measurement::{MeasurementAccumulator, MeasurementBuffer},
+    measurement::{MeasurementAccumulator, MeasurementBuffer, Timestamp},
     pipeline::{self, OutputContext},
 };
 
@@ -34,6 +39,95 @@ impl pipeline::Source for FfiSource {
         Ok(())
     }
 }
+
+/// A [`FfiSource`] whose polls run in a dedicated thread, with a timeout.
+///
+/// Foreign code cannot be cancelled safely. If a poll exceeds the timeout, the source is marked
+/// as unhealthy and the poll returns a fatal error: the pipeline stops scheduling the source,
+/// but keeps running. The thread that runs the hung poll is leaked. If the poll ever returns,
+/// the thread drops the source (calling its `drop_fn`) and exits.
+pub(crate) struct WatchdogFfiSource {
+    timeout: Duration,
+    /// Sends poll requests to the worker thread. Dropping it stops the thread.
+    requests: Option<mpsc::Sender<Timestamp>>,
+    /// Receives the measurements produced by the worker thread.
+    results: mpsc::Receiver<MeasurementBuffer>,
+    worker: Option<JoinHandle<()>>,
+    healthy: bool,
+}
+
+impl WatchdogFfiSource {
+    pub fn new(source: FfiSource, timeout: Duration) -> std::io::Result<Self> {
+        let (req_tx, req_rx) = mpsc::channel::<Timestamp>();
+        let (res_tx, res_rx) = mpsc::channel();
+        let worker = std::thread::Builder::new()
+            .name(String::from("ffi-source-poll"))
+            .spawn(move || {
+                let mut source = source;
+                for timestamp in req_rx {
+                    let mut buffer = MeasurementBuffer::new();
+                    (source.poll_fn)(source.data, &mut buffer.as_accumulator(), timestamp.into());
+                    if res_tx.send(buffer).is_err() {
+                        break; // the watchdog has been dropped
+                    }
+                }
+                // the source is dropped here, in the worker thread
+            })?;
+        Ok(Self {
+            timeout,
+            requests: Some(req_tx),
+            results: res_rx,
+            worker: Some(worker),
+            healthy: true,
+        })
+    }
+}
+
+impl pipeline::Source for WatchdogFfiSource {
+    fn poll(&mut self, into: &mut MeasurementAccumulator, time: Timestamp) -> Result<(), pipeline::PollError> {
+        if !self.healthy {
+            return Err(pipeline::PollError::Fatal(anyhow!("the source is unhealthy, a previous poll hung")));
+        }
+        let requests = self.requests.as_ref().unwrap();
+        if requests.send(time).is_err() {
+            return Err(pipeline::PollError::Fatal(anyhow!("the poll thread has stopped unexpectedly")));
+        }
+        match self.results.recv_timeout(self.timeout) {
+            Ok(mut measurements) => {
+                into.0.append(&mut measurements);
+                Ok(())
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                self.healthy = false;
+                log::error!(
+                    "A poll of a foreign source exceeded the timeout of {:?}: the source is now unhealthy and will not be polled anymore. The thread that runs it is leaked.",
+                    self.timeout
+                );
+                Err(pipeline::PollError::Fatal(anyhow!(
+                    "poll timeout exceeded ({:?})",
+                    self.timeout
+                )))
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err(pipeline::PollError::Fatal(anyhow!(
+                "the poll thread has stopped unexpectedly (panic in the source?)"
+            ))),
+        }
+    }
+}
+
+impl Drop for WatchdogFfiSource {
+    fn drop(&mut self) {
+        // stop the worker thread
+        self.requests.take();
+        if let Some(worker) = self.worker.take() {
+            if self.healthy {
+                let _ = worker.join();
+            }
+            // else: the thread is stuck in a poll, leak it
+        }
+    }
+}
+
 impl pipeline::Transform for FfiTransform {
     fn apply(&mut self, on: &mut MeasurementBuffer) -> Result<(), pipeline::TransformError> {
         (self.apply_fn)(self.data, on);
@@ -69,3 +163,73 @@ impl Drop for FfiOutput {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    use libc::c_void;
+
+    use super::{FfiSource, WatchdogFfiSource};
+    use crate::ffi::time::Timestamp as FfiTimestamp;
+    use crate::measurement::{MeasurementAccumulator, MeasurementBuffer, MeasurementPoint, Timestamp};
+    use crate::metrics::RawMetricId;
+    use crate::pipeline::{PollError, Source};
+    use crate::resources::{Resource, ResourceConsumer};
+
+    extern "C" fn fast_poll(_data: *mut c_void, buffer: *mut MeasurementAccumulator, timestamp: FfiTimestamp) {
+        let buffer = unsafe { &mut *buffer };
+        buffer.push(MeasurementPoint::new_untyped(
+            timestamp.into(),
+            RawMetricId(0),
+            Resource::LocalMachine,
+            ResourceConsumer::LocalMachine,
+            crate::measurement::WrappedMeasurementValue::U64(1),
+        ));
+    }
+
+    /// Blocks until the test sends a message to the channel, whose receiver is `data`.
+    extern "C" fn hung_poll(data: *mut c_void, _buffer: *mut MeasurementAccumulator, _timestamp: FfiTimestamp) {
+        let release = unsafe { &*(data as *const mpsc::Receiver<()>) };
+        let _ = release.recv();
+    }
+
+    unsafe extern "C" fn drop_receiver(data: *mut c_void) {
+        drop(Box::from_raw(data as *mut mpsc::Receiver<()>));
+    }
+
+    #[test]
+    fn watchdog() {
+        let timeout = Duration::from_millis(50);
+        let source = FfiSource {
+            data: std::ptr::null_mut(),
+            poll_fn: fast_poll,
+            drop_fn: None,
+        };
+        let mut source = WatchdogFfiSource::new(source, timeout).unwrap();
+        let mut buf = MeasurementBuffer::new();
+        source.poll(&mut buf.as_accumulator(), Timestamp::now()).unwrap();
+        source.poll(&mut buf.as_accumulator(), Timestamp::now()).unwrap();
+        assert_eq!(buf.len(), 2);
+
+        // The poll hangs until `release` is used, which only happens at the end of the test:
+        // if the watchdog waited for the poll, the test would never finish.
+        let (release, release_rx) = mpsc::channel::<()>();
+        let source = FfiSource {
+            data: Box::into_raw(Box::new(release_rx)) as *mut c_void,
+            poll_fn: hung_poll,
+            drop_fn: Some(drop_receiver),
+        };
+        let mut source = WatchdogFfiSource::new(source, timeout).unwrap();
+        let res = source.poll(&mut buf.as_accumulator(), Timestamp::now());
+        assert!(matches!(res, Err(PollError::Fatal(_))));
+        // unhealthy: not polled anymore
+        let res = source.poll(&mut buf.as_accumulator(), Timestamp::now());
+        assert!(matches!(res, Err(PollError::Fatal(_))));
+        // dropping the source does not wait for the hung thread
+        drop(source);
+        // let the leaked thread finish, it drops the receiver
+        release.send(()).unwrap();
+    }
+}
@@ -7,7 +7,7 @@ use crate::metrics::RawMetricId;
 use crate::pipeline::trigger;
 use crate::{plugin::AlumetStart, units::Unit};
 
-use super::pipeline::{FfiOutput, FfiTransform};
+use super::pipeline::{FfiOutput, FfiTransform, WatchdogFfiSource};
 use super::time::TimeDuration;
 use super::units::FfiUnit;
 use super::{pipeline::FfiSource, string::AStr, NullableDropFn, SourcePollFn};
@@ -69,6 +69,52 @@ pub extern "C" fn alumet_add_source(
             .unwrap(),
     );
 }
+
+/// Adds a source whose polls are guarded by a watchdog.
+///
+/// Each poll runs in a dedicated thread. If a poll takes more than `poll_timeout`,
+/// the source is considered unhealthy and is not polled anymore, but the pipeline keeps running.
+/// Because foreign code cannot be cancelled, the thread of the hung poll is leaked.
+///
+/// Returns `false` if the source cannot be added, for instance because the thread cannot be spawned.
+/// In that case, the error is logged and `source_data` is dropped with `source_drop_fn`.
+#[no_mangle]
+pub extern "C" fn alumet_add_source_with_timeout(
+    alumet: &mut AlumetStart,
+    source_data: *mut c_void,
+    poll_interval: TimeDuration,
+    flush_interval: TimeDuration,
+    poll_timeout: TimeDuration,
+    source_poll_fn: SourcePollFn,
+    source_drop_fn: NullableDropFn,
+) -> bool {
+    let source = FfiSource {
+        data: source_data,
+        poll_fn: source_poll_fn,
+        drop_fn: source_drop_fn,
+    };
+    let trigger = match trigger::builder::time_interval(poll_interval.into())
+        .flush_interval(flush_interval.into())
+        .build()
+    {
+        Ok(trigger) => trigger,
+        Err(e) => {
+            log::error!("Cannot add the foreign source, invalid trigger: {e}");
+            return false;
+        }
+    };
+    match WatchdogFfiSource::new(source, poll_timeout.into()) {
+        Ok(source) => {
+            alumet.add_source(Box::new(source), trigger);
+            true
+        }
+        Err(e) => {
+            log::error!("Cannot add the foreign source, failed to spawn the poll thread: {e}");
+            false
+        }
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn alumet_add_transform(
     alumet: &mut AlumetStart,
@@ -83,6 +129,7 @@ pub extern "C" fn alumet_add_transform(
     });
     alumet.add_transform(transform);
 }
+
 #[no_mangle]
 pub extern "C" fn alumet_add_output(
     alumet: &mut AlumetStart,
@@ -0,0 +1,280 @@
+//! Versioned representation of the values exchanged with the plugins through the FFI.
+//!
+//! [`FfiValue`] is a tagged union: a discriminant, followed by the value. Its layout is part of the ABI
+//! of the plugins, it only changes with a new [`FFI_VALUE_LAYOUT_VERSION`]. In C, it is equivalent to:
+//!
+//! ```c
+//! typedef enum FfiValue_Tag {
+//!   FfiValue_F64 = 0,
+//!   FfiValue_U64 = 1,
+//!   FfiValue_Bool = 2,
+//!   FfiValue_String = 3,
+//! } FfiValue_Tag; // stored as an uint32_t
+//!
+//! typedef struct FfiValue {
+//!   uint32_t tag;
+//!   union {
+//!     double f64;
+//!     uint64_t u64;
+//!     bool bool_;
+//!     AString string;
+//!   };
+//! } FfiValue;
+//! ```
+//!
+//! New kinds of values are added at the end, with a new discriminant, and the existing discriminants never change.
+//!
+//! The values are created with the constructors `alumet_measurement_f64`, `alumet_measurement_u64`,
+//! `alumet_measurement_bool` and `alumet_measurement_str`. A value is consumed by the function that it is given to.
+//! A value that is not given to any function must be freed with [`alumet_value_free`], because a string value
+//! owns its memory.
+//!
+//! The measurement values are numbers: a measurement point accepts `F64` and `U64` values only, but the attributes
+//! accept every kind of value.
+
+use std::borrow::Cow;
+
+use crate::{
+    measurement::{AttributeValue, MeasurementPoint, WrappedMeasurementValue},
+    metrics::RawMetricId,
+    resources::{Resource, ResourceConsumer},
+};
+
+use super::{
+    resources::{FfiConsumerId, FfiResourceId},
+    string::{AStr, AString},
+    time::Timestamp,
+};
+
+/// Version of the layout of [`FfiValue`].
+///
+/// It is incremented on every incompatible change of the layout, so that a plugin can check that it has been
+/// compiled for the same layout as the agent, with [`alumet_value_layout_version`].
+pub const FFI_VALUE_LAYOUT_VERSION: u32 = 1;
+
+/// A value given to, or obtained from, a plugin. See the [module documentation](self) for the layout.
+#[repr(C, u32)]
+#[derive(Debug, PartialEq)]
+pub enum FfiValue {
+    F64(f64) = 0,
+    U64(u64) = 1,
+    Bool(bool) = 2,
+    String(AString) = 3,
+}
+
+impl Clone for FfiValue {
+    fn clone(&self) -> Self {
+        match self {
+            FfiValue::F64(x) => FfiValue::F64(*x),
+            FfiValue::U64(x) => FfiValue::U64(*x),
+            FfiValue::Bool(b) => FfiValue::Bool(*b),
+            // copy the string, because the derived clone of AString would copy its pointer, and free it twice
+            FfiValue::String(s) => FfiValue::String(AString::from(s.as_str())),
+        }
+    }
+}
+
+impl From<&WrappedMeasurementValue> for FfiValue {
+    fn from(value: &WrappedMeasurementValue) -> Self {
+        match value {
+            WrappedMeasurementValue::F64(x) => FfiValue::F64(*x),
+            WrappedMeasurementValue::U64(x) => FfiValue::U64(*x),
+        }
+    }
+}
+
+impl TryFrom<FfiValue> for WrappedMeasurementValue {
+    type Error = FfiValue;
+
+    /// Converts the value to a measurement value, or gives it back if it is not a number.
+    fn try_from(value: FfiValue) -> Result<Self, Self::Error> {
+        match value {
+            FfiValue::F64(x) => Ok(WrappedMeasurementValue::F64(x)),
+            FfiValue::U64(x) => Ok(WrappedMeasurementValue::U64(x)),
+            other => Err(other),
+        }
+    }
+}
+
+impl From<&AttributeValue> for FfiValue {
+    fn from(value: &AttributeValue) -> Self {
+        match value {
+            AttributeValue::F64(x) => FfiValue::F64(*x),
+            AttributeValue::U64(x) => FfiValue::U64(*x),
+            AttributeValue::Bool(b) => FfiValue::Bool(*b),
+            AttributeValue::Str(s) => FfiValue::String(AString::from(*s)),
+            AttributeValue::String(s) => FfiValue::String(AString::from(s)),
+        }
+    }
+}
+
+impl From<FfiValue> for AttributeValue {
+    fn from(value: FfiValue) -> Self {
+        match value {
+            FfiValue::F64(x) => AttributeValue::F64(x),
+            FfiValue::U64(x) => AttributeValue::U64(x),
+            FfiValue::Bool(b) => AttributeValue::Bool(b),
+            FfiValue::String(s) => AttributeValue::String(s.to_string()),
+        }
+    }
+}
+
+// ====== Constructors ======
+
+/// Returns the version of the layout of the values, see [`FFI_VALUE_LAYOUT_VERSION`].
+#[no_mangle]
+pub extern "C" fn alumet_value_layout_version() -> u32 {
+    FFI_VALUE_LAYOUT_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn alumet_measurement_f64(value: f64) -> FfiValue {
+    FfiValue::F64(value)
+}
+
+#[no_mangle]
+pub extern "C" fn alumet_measurement_u64(value: u64) -> FfiValue {
+    FfiValue::U64(value)
+}
+
+#[no_mangle]
+pub extern "C" fn alumet_measurement_bool(value: bool) -> FfiValue {
+    FfiValue::Bool(value)
+}
+
+/// Creates a string value, which contains a copy of `value`.
+#[no_mangle]
+pub extern "C" fn alumet_measurement_str(value: AStr) -> FfiValue {
+    FfiValue::String(AString::from(value.as_str()))
+}
+
+/// Frees a value that has not been given to any function.
+#[no_mangle]
+pub extern "C" fn alumet_value_free(value: FfiValue) {
+    drop(value);
+}
+
+// ====== MeasurementPoint ======
+
+/// Creates a measurement point with the given value, which is consumed.
+///
+/// Returns null if the value is not a number, because the measurement values are numbers.
+#[no_mangle]
+pub extern "C" fn mpoint_new_value(
+    timestamp: Timestamp,
+    metric: RawMetricId,
+    resource: FfiResourceId,
+    consumer: FfiConsumerId,
+    value: FfiValue,
+) -> *mut MeasurementPoint {
+    let value = match WrappedMeasurementValue::try_from(value) {
+        Ok(value) => value,
+        Err(value) => {
+            log::error!("Invalid measurement value {value:?}: a measurement value must be a number.");
+            return std::ptr::null_mut();
+        }
+    };
+    let resource = Resource::from(resource);
+    let consumer = ResourceConsumer::from(consumer);
+    let p = MeasurementPoint::new_untyped(timestamp.into(), metric, resource, consumer, value);
+    Box::into_raw(Box::new(p))
+}
+
+/// Adds an attribute to the point. The value is consumed.
+#[no_mangle]
+pub extern "C" fn mpoint_attr_value(point: *mut MeasurementPoint, key: AStr, value: FfiValue) {
+    let point = unsafe { &mut *point };
+    let key = Cow::Owned(key.to_string());
+    point.add_attr(key, AttributeValue::from(value));
+}
+
+/// Returns the value of the point. The returned value must be freed with [`alumet_value_free`].
+#[no_mangle]
+pub extern "C" fn mpoint_tagged_value(point: &MeasurementPoint) -> FfiValue {
+    FfiValue::from(&point.value)
+}
+
+// ====== Tests ======
+
+#[cfg(test)]
+mod tests {
+    use std::mem::{align_of, size_of};
+
+    use super::{
+        alumet_measurement_bool, alumet_measurement_f64, alumet_measurement_str, alumet_measurement_u64,
+        mpoint_attr_value, mpoint_new_value, mpoint_tagged_value, FfiValue,
+    };
+    use crate::ffi::resources::{FfiConsumerId, FfiResourceId};
+    use crate::ffi::string::{AStr, AString};
+    use crate::ffi::time::Timestamp as FfiTimestamp;
+    use crate::measurement::{AttributeValue, MeasurementPoint, Timestamp};
+    use crate::metrics::RawMetricId;
+    use crate::resources::{Resource, ResourceConsumer};
+
+    /// Mimics a plugin that receives a value and gives it back, through the C calling convention.
+    extern "C" fn mock_c_identity(value: FfiValue) -> FfiValue {
+        value
+    }
+
+    /// Mimics a plugin that reads the tag of a value, as a C plugin would do.
+    extern "C" fn mock_c_tag(value: *const FfiValue) -> u32 {
+        unsafe { *(value as *const u32) }
+    }
+
+    #[test]
+    fn memory_layout() {
+        // tag (padded to 8 bytes), then the largest variant: AString
+        assert_eq!(size_of::<FfiValue>(), 8 + size_of::<AString>());
+        assert_eq!(align_of::<FfiValue>(), 8);
+        let tag = |v: FfiValue| mock_c_tag(&v);
+        assert_eq!(tag(FfiValue::F64(0.0)), 0);
+        assert_eq!(tag(FfiValue::U64(0)), 1);
+        assert_eq!(tag(FfiValue::Bool(false)), 2);
+        assert_eq!(tag(FfiValue::String(AString::from("a"))), 3);
+    }
+
+    #[test]
+    fn round_trip() {
+        let f: extern "C" fn(FfiValue) -> FfiValue = mock_c_identity;
+        assert_eq!(f(alumet_measurement_f64(12.5)), FfiValue::F64(12.5));
+        assert_eq!(f(alumet_measurement_u64(u64::MAX)), FfiValue::U64(u64::MAX));
+        assert_eq!(f(alumet_measurement_bool(true)), FfiValue::Bool(true));
+        let s = f(alumet_measurement_str(AStr::from("package-0")));
+        assert_eq!(s, FfiValue::String(AString::from("package-0")));
+
+        // through a measurement point
+        let timestamp = FfiTimestamp::from(Timestamp::now());
+        let resource = FfiResourceId::from(Resource::LocalMachine);
+        let consumer = FfiConsumerId::from(ResourceConsumer::LocalMachine);
+        let point = mpoint_new_value(timestamp, RawMetricId(1), resource, consumer, f(FfiValue::F64(2.5)));
+        assert!(!point.is_null());
+        mpoint_attr_value(point, AStr::from("domain"), f(s));
+        mpoint_attr_value(point, AStr::from("enabled"), f(FfiValue::Bool(true)));
+        let point = unsafe { Box::from_raw(point) };
+        assert_eq!(mpoint_tagged_value(&point), FfiValue::F64(2.5));
+        let attr = |key: &str| point.attributes().find(|(k, _)| *k == key).map(|(_, v)| v.clone());
+        assert!(matches!(attr("domain"), Some(AttributeValue::String(s)) if s == "package-0"));
+        assert!(matches!(attr("enabled"), Some(AttributeValue::Bool(true))));
+    }
+
+    #[test]
+    fn not_a_measurement_value() {
+        let timestamp = FfiTimestamp::from(Timestamp::now());
+        let resource = FfiResourceId::from(Resource::LocalMachine);
+        let consumer = FfiConsumerId::from(ResourceConsumer::LocalMachine);
+        let value = FfiValue::String(AString::from("not a number"));
+        let point: *mut MeasurementPoint = mpoint_new_value(timestamp, RawMetricId(1), resource, consumer, value);
+        assert!(point.is_null());
+    }
+
+    #[test]
+    fn clone_string() {
+        let value = FfiValue::String(AString::from("package-0"));
+        let copy = value.clone();
+        let FfiValue::String(s) = &value else { unreachable!() };
+        let FfiValue::String(c) = &copy else { unreachable!() };
+        assert_ne!(s.as_str().as_ptr(), c.as_str().as_ptr());
+        drop(value);
+        assert_eq!(copy, FfiValue::String(AString::from("package-0")));
+    }
+}
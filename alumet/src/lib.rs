@@ -12,11 +12,14 @@
 //! are provided by [plugins](plugin).
 
 pub mod agent;
+pub mod config;
+pub mod logging;
 pub mod measurement;
 pub mod metrics;
 pub mod pipeline;
 pub mod plugin;
 pub mod resources;
+pub mod time;
 pub mod units;
 
 #[cfg(feature = "dynamic")]
@@ -0,0 +1,205 @@
+//! Log levels per plugin.
+//!
+//! By default, the log level of the whole application is set by the logger of the application,
+//! for instance with the `RUST_LOG` environment variable. The configuration of each plugin can override it
+//! with a `log_level` key, which the agent applies with [`set_plugin_level`] when it initializes the plugin.
+//!
+//! To support this, the application must install a [`PluginLogger`] with [`init`] instead of its logger.
+//! The plugin logger needs two loggers: the usual one, which applies the global filter of the application,
+//! and an unfiltered one, which is used for the plugins that have their own level.
+//!
+//! With the `env_logger` feature, [`init_env_logger`] installs a plugin logger based on `env_logger`.
+//!
+//! ## Example
+//! ```ignore
+//! let filtered = env_logger::Builder::from_env(Env::default().default_filter_or("info")).build();
+//! let unfiltered = env_logger::Builder::new().filter_level(log::LevelFilter::Trace).build();
+//! let global_level = filtered.filter();
+//! alumet::logging::init(Box::new(filtered), Box::new(unfiltered), global_level).unwrap();
+//! ```
+
+use std::sync::{OnceLock, RwLock};
+
+use log::{LevelFilter, Log, Metadata, Record, SetLoggerError};
+
+/// The plugin logger, if it has been installed.
+static PLUGIN_LOGGER: OnceLock<&'static PluginLogger> = OnceLock::new();
+
+/// A logger that applies a different level to the targets of some plugins.
+pub struct PluginLogger {
+    /// Logger that applies the global filter, used for the targets without a specific level.
+    filtered: Box<dyn Log>,
+    /// Logger that accepts every record, used for the targets with a specific level.
+    unfiltered: Box<dyn Log>,
+    /// The most verbose level of `filtered`.
+    global_level: LevelFilter,
+    /// Level of each target prefix, usually the name of the crate of a plugin.
+    levels: RwLock<Vec<(String, LevelFilter)>>,
+}
+
+/// Installs the plugin logger as the global logger.
+///
+/// `global_level` must be the most verbose level that `filtered` accepts.
+pub fn init(filtered: Box<dyn Log>, unfiltered: Box<dyn Log>, global_level: LevelFilter) -> Result<(), SetLoggerError> {
+    let logger: &'static PluginLogger = Box::leak(Box::new(PluginLogger::new(filtered, unfiltered, global_level)));
+    log::set_logger(logger)?;
+    log::set_max_level(global_level);
+    let _ = PLUGIN_LOGGER.set(logger);
+    Ok(())
+}
+
+/// Installs a plugin logger based on `env_logger`, as the global logger.
+///
+/// The global level is set by the `RUST_LOG` environment variable, `info` by default.
+#[cfg(feature = "env_logger")]
+pub fn init_env_logger() -> Result<(), SetLoggerError> {
+    let env = env_logger::Env::default().default_filter_or("info");
+    let filtered = env_logger::Builder::from_env(env).build();
+    let unfiltered = env_logger::Builder::new().filter_level(LevelFilter::Trace).build();
+    let global_level = filtered.filter();
+    init(Box::new(filtered), Box::new(unfiltered), global_level)
+}
+
+/// Sets the log level of the records whose target is `target` or one of its submodules.
+///
+/// Returns `false` if the application has not installed the plugin logger with [`init`],
+/// in which case the level cannot be applied.
+pub fn set_plugin_level(target: &str, level: LevelFilter) -> bool {
+    match PLUGIN_LOGGER.get() {
+        Some(logger) => {
+            logger.set_level(target, level);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Parses a log level, such as `"debug"` or `"off"` (case-insensitive).
+pub fn parse_level(level: &str) -> anyhow::Result<LevelFilter> {
+    level.parse().map_err(|_| {
+        anyhow::anyhow!("invalid log level '{level}', expected one of: off, error, warn, info, debug, trace")
+    })
+}
+
+impl PluginLogger {
+    fn new(filtered: Box<dyn Log>, unfiltered: Box<dyn Log>, global_level: LevelFilter) -> Self {
+        Self {
+            filtered,
+            unfiltered,
+            global_level,
+            levels: RwLock::new(Vec::new()),
+        }
+    }
+
+    fn set_level(&self, target: &str, level: LevelFilter) {
+        let mut levels = self.levels.write().unwrap();
+        match levels.iter_mut().find(|(t, _)| t == target) {
+            Some((_, l)) => *l = level,
+            None => levels.push((target.to_owned(), level)),
+        }
+        // the log macros skip the records above the max level, it must allow the most verbose plugin
+        let max = levels.iter().map(|(_, l)| *l).fold(self.global_level, LevelFilter::max);
+        log::set_max_level(max);
+    }
+
+    /// Returns the level of the target, if it has a specific one.
+    fn level_of(&self, target: &str) -> Option<LevelFilter> {
+        let levels = self.levels.read().unwrap();
+        levels
+            .iter()
+            .find(|(prefix, _)| match target.strip_prefix(prefix.as_str()) {
+                Some(rest) => rest.is_empty() || rest.starts_with("::"),
+                None => false,
+            })
+            .map(|(_, level)| *level)
+    }
+}
+
+impl Log for PluginLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        match self.level_of(metadata.target()) {
+            Some(level) => metadata.level() <= level,
+            None => self.filtered.enabled(metadata),
+        }
+    }
+
+    fn log(&self, record: &Record) {
+        match self.level_of(record.target()) {
+            Some(level) if record.level() <= level => self.unfiltered.log(record),
+            Some(_) => (),
+            None => self.filtered.log(record),
+        }
+    }
+
+    fn flush(&self) {
+        self.filtered.flush();
+        self.unfiltered.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use log::{Level, LevelFilter, Log, Metadata, Record};
+
+    use super::{parse_level, PluginLogger};
+
+    /// Records the messages that it accepts.
+    struct TestLogger {
+        level: LevelFilter,
+        messages: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Log for TestLogger {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            metadata.level() <= self.level
+        }
+
+        fn log(&self, record: &Record) {
+            if self.enabled(record.metadata()) {
+                self.messages.lock().unwrap().push(format!("{}", record.args()));
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn plugin_levels() {
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let filtered = TestLogger {
+            level: LevelFilter::Info,
+            messages: messages.clone(),
+        };
+        let unfiltered = TestLogger {
+            level: LevelFilter::Trace,
+            messages: messages.clone(),
+        };
+        let logger = PluginLogger::new(Box::new(filtered), Box::new(unfiltered), LevelFilter::Info);
+        logger.set_level("plugin_rapl", LevelFilter::Debug);
+        logger.set_level("plugin_csv", LevelFilter::Error);
+
+        let log = |target: &str, level: Level, msg: &str| {
+            logger.log(
+                &Record::builder()
+                    .target(target)
+                    .level(level)
+                    .args(format_args!("{msg}"))
+                    .build(),
+            );
+        };
+        log("plugin_rapl::powercap", Level::Debug, "rapl debug");
+        log("plugin_rapl", Level::Trace, "rapl trace");
+        log("plugin_rapl_extra", Level::Debug, "other crate debug");
+        log("plugin_csv", Level::Warn, "csv warn");
+        log("plugin_csv::output", Level::Error, "csv error");
+        log("alumet::agent", Level::Info, "agent info");
+        log("alumet::agent", Level::Debug, "agent debug");
+        assert_eq!(*messages.lock().unwrap(), vec!["rapl debug", "csv error", "agent info"]);
+
+        assert_eq!(parse_level("DEBUG").unwrap(), LevelFilter::Debug);
+        assert_eq!(parse_level("off").unwrap(), LevelFilter::Off);
+        assert!(parse_level("verbose").is_err());
+    }
+}
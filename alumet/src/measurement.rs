@@ -29,7 +29,7 @@ use core::fmt;
 use std::borrow::Cow;
 use fxhash::FxBuildHasher;
 use smallvec::SmallVec;
-use std::{collections::HashMap, fmt::Display, time::SystemTime};
+use std::{collections::HashMap, fmt::Display, sync::OnceLock, time::SystemTime};
 
 use crate::resources::ResourceConsumer;
 
@@ -129,6 +129,11 @@ impl MeasurementPoint {
         self.attributes.iter().map(|(k, _v)| k.as_ref())
     }
 
+    /// Keeps only the attributes for which `f` returns true.
+    pub fn retain_attributes<F: FnMut(&str, &AttributeValue) -> bool>(&mut self, mut f: F) {
+        self.attributes.retain(|(k, v)| f(k, v));
+    }
+
     pub(crate) fn add_attr(&mut self, key: Cow<'static, str>, value: AttributeValue) {
         self.attributes.push((key, value));
     }
@@ -242,6 +247,34 @@ impl WrappedMeasurementValue {
             WrappedMeasurementValue::U64(_) => WrappedMeasurementType::U64,
         }
     }
+
+    /// Returns the value as a `f64`, whatever its type.
+    ///
+    /// The conversion from `U64` is lossy for values above 2^53: they are rounded
+    /// to the nearest representable `f64`.
+    pub fn as_f64(&self) -> f64 {
+        match self {
+            WrappedMeasurementValue::F64(v) => *v,
+            WrappedMeasurementValue::U64(v) => *v as f64,
+        }
+    }
+
+    /// Returns the value as a `u64`, whatever its type.
+    ///
+    /// The fractional part of `F64` values is truncated. Returns `None` if the value is
+    /// negative, NaN, or too large to fit in a `u64`.
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            WrappedMeasurementValue::F64(v) => {
+                if *v >= 0.0 && *v < u64::MAX as f64 {
+                    Some(*v as u64)
+                } else {
+                    None
+                }
+            }
+            WrappedMeasurementValue::U64(v) => Some(*v),
+        }
+    }
 }
 
 /// An attribute value of any supported attribute type.
@@ -258,6 +291,26 @@ pub enum AttributeValue {
     String(String),
 }
 
+impl AttributeValue {
+    /// Returns true if the values are equal. A `Str` is equal to a `String` with the same content.
+    fn same_as(&self, other: &AttributeValue) -> bool {
+        match (self, other) {
+            (AttributeValue::F64(a), AttributeValue::F64(b)) => a == b,
+            (AttributeValue::U64(a), AttributeValue::U64(b)) => a == b,
+            (AttributeValue::Bool(a), AttributeValue::Bool(b)) => a == b,
+            _ => matches!((self.as_str(), other.as_str()), (Some(a), Some(b)) if a == b),
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            AttributeValue::Str(s) => Some(s),
+            AttributeValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
 impl Display for AttributeValue {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -300,67 +353,370 @@ impl From<&'static str> for AttributeValue {
     }
 }
 
+/// The memory layout of a [`MeasurementBuffer`], chosen when the buffer is constructed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BufferLayout {
+    /// The points are stored one after another, in a `Vec<MeasurementPoint>` ("array of structs").
+    ///
+    /// This is the default layout, which suits the sources and the transforms.
+    #[default]
+    Rows,
+    /// Each field of the points is stored in its own vector ("struct of arrays"), see [`MeasurementColumns`].
+    ///
+    /// This layout speeds up the outputs that write columnar formats such as Parquet or Arrow.
+    /// Iterating on the points of such a buffer builds them once, and keeps them until the buffer is modified.
+    Columns,
+}
+
 /// A `MeasurementBuffer` stores measured data points.
 /// Unlike a [`MeasurementAccumulator`], the buffer allows to modify the measurements.
+///
+/// The points are stored according to a [`BufferLayout`], which does not change the API of the buffer.
 #[derive(Clone)]
 pub struct MeasurementBuffer {
-    points: Vec<MeasurementPoint>,
+    storage: Storage,
+}
+
+#[derive(Clone)]
+enum Storage {
+    Rows(Vec<MeasurementPoint>),
+    Columns {
+        columns: MeasurementColumns,
+        /// The points built from the columns, on the first iteration.
+        /// Reset when the columns change.
+        rows: OnceLock<Vec<MeasurementPoint>>,
+    },
 }
 
 impl MeasurementBuffer {
     /// Constructs a new buffer.
     pub fn new() -> MeasurementBuffer {
-        MeasurementBuffer { points: Vec::new() }
+        MeasurementBuffer {
+            storage: Storage::Rows(Vec::new()),
+        }
     }
 
     /// Constructs a new buffer with at least the specified capacity (allocated on construction).
     pub fn with_capacity(capacity: usize) -> MeasurementBuffer {
-        MeasurementBuffer {
-            points: Vec::with_capacity(capacity),
+        MeasurementBuffer::with_layout(BufferLayout::Rows, capacity)
+    }
+
+    /// Constructs a new buffer with the given layout and at least the specified capacity.
+    pub fn with_layout(layout: BufferLayout, capacity: usize) -> MeasurementBuffer {
+        let storage = match layout {
+            BufferLayout::Rows => Storage::Rows(Vec::with_capacity(capacity)),
+            BufferLayout::Columns => Storage::Columns {
+                columns: MeasurementColumns::with_capacity(capacity),
+                rows: OnceLock::new(),
+            },
+        };
+        MeasurementBuffer { storage }
+    }
+
+    /// Returns the memory layout of the buffer.
+    pub fn layout(&self) -> BufferLayout {
+        match &self.storage {
+            Storage::Rows(_) => BufferLayout::Rows,
+            Storage::Columns { .. } => BufferLayout::Columns,
         }
     }
-    
+
+    /// Returns the columns of the buffer if its layout is [`BufferLayout::Columns`], `None` otherwise.
+    pub fn as_columns(&self) -> Option<&MeasurementColumns> {
+        match &self.storage {
+            Storage::Rows(_) => None,
+            Storage::Columns { columns, .. } => Some(columns),
+        }
+    }
+
     /// Returns true if this buffer is empty.
     pub fn is_empty(&self) -> bool {
-        self.points.is_empty()
+        self.len() == 0
     }
 
     /// Returns the number of measurement points in the buffer.
     pub fn len(&self) -> usize {
-        self.points.len()
+        match &self.storage {
+            Storage::Rows(points) => points.len(),
+            Storage::Columns { columns, .. } => columns.len(),
+        }
+    }
+
+    /// Returns the number of measurement points that the buffer can hold without reallocating.
+    pub fn capacity(&self) -> usize {
+        match &self.storage {
+            Storage::Rows(points) => points.capacity(),
+            Storage::Columns { columns, .. } => columns.capacity(),
+        }
     }
 
     /// Reserves capacity for at least `additional` more elements.
     /// See [`Vec::reserve`].
     pub fn reserve(&mut self, additional: usize) {
-        self.points.reserve(additional);
+        match &mut self.storage {
+            Storage::Rows(points) => points.reserve(additional),
+            Storage::Columns { columns, .. } => columns.reserve(additional),
+        }
     }
 
     /// Adds a measurement to the buffer.
     /// The measurement points are *not* automatically deduplicated by the buffer.
     pub fn push(&mut self, point: MeasurementPoint) {
-        self.points.push(point);
+        match &mut self.storage {
+            Storage::Rows(points) => points.push(point),
+            Storage::Columns { columns, rows } => {
+                columns.push(point);
+                rows.take();
+            }
+        }
+    }
+
+    /// Moves all the measurements of `other` into this buffer, leaving `other` empty.
+    ///
+    /// The points are moved, not cloned. After the merge, the points of `other` come
+    /// after the points that were already in this buffer (concatenation order).
+    pub fn append(&mut self, other: &mut MeasurementBuffer) {
+        match (&mut self.storage, &mut other.storage) {
+            (Storage::Rows(points), Storage::Rows(other_points)) => points.append(other_points),
+            _ => {
+                let points = other.take_points();
+                self.extend_from_points(points);
+            }
+        }
+    }
+
+    /// Adds multiple measurements to the buffer, in the order of the iterator.
+    ///
+    /// Like [`push`](Self::push), the points are *not* deduplicated.
+    pub fn extend_from_points(&mut self, points: impl IntoIterator<Item = MeasurementPoint>) {
+        let points = points.into_iter();
+        self.reserve(points.size_hint().0);
+        match &mut self.storage {
+            Storage::Rows(rows) => rows.extend(points),
+            Storage::Columns { columns, rows } => {
+                points.for_each(|p| columns.push(p));
+                rows.take();
+            }
+        }
+    }
+
+    /// Retains only the measurements for which `f` returns true, and removes the others.
+    /// The order of the remaining measurements is preserved.
+    pub fn retain(&mut self, f: impl FnMut(&mut MeasurementPoint) -> bool) {
+        self.modify_points(|points| points.retain_mut(f));
+    }
+
+    /// Removes the measurements for which `f` returns true, and returns them in a new buffer,
+    /// with a mask of their positions (`true` for the removed measurements).
+    /// The order of the measurements is preserved in both buffers, and the new buffer has the same layout.
+    ///
+    /// The mask allows to restore the original order with [`put_back`](Self::put_back).
+    pub fn take_matching(&mut self, mut f: impl FnMut(&MeasurementPoint) -> bool) -> (MeasurementBuffer, Vec<bool>) {
+        let layout = self.layout();
+        let mut mask = Vec::with_capacity(self.len());
+        let matching = self.modify_points(|points| {
+            let (matching, others) = std::mem::take(points).into_iter().partition(|m| {
+                let selected = f(m);
+                mask.push(selected);
+                selected
+            });
+            *points = others;
+            matching
+        });
+        (MeasurementBuffer::from_points(layout, matching), mask)
+    }
+
+    /// Puts back the measurements taken by [`take_matching`](Self::take_matching), at their original positions.
+    ///
+    /// If there are fewer measurements than before (some have been removed), they fill the first positions.
+    /// If there are more (some have been added), the additional measurements are put at the end of the buffer.
+    pub fn put_back(&mut self, mut taken: MeasurementBuffer, mask: &[bool]) {
+        let taken = taken.take_points();
+        self.modify_points(|points| {
+            let mut others = std::mem::take(points).into_iter();
+            let mut taken = taken.into_iter();
+            let mut merged = Vec::with_capacity(others.len() + taken.len());
+            for &selected in mask {
+                merged.extend(if selected { taken.next() } else { others.next() });
+            }
+            merged.extend(others);
+            merged.extend(taken);
+            *points = merged;
+        });
     }
 
     /// Clears the buffer, removing all the measurements.
     pub fn clear(&mut self) {
-        self.points.clear();
+        match &mut self.storage {
+            Storage::Rows(points) => points.clear(),
+            Storage::Columns { columns, rows } => {
+                columns.clear();
+                rows.take();
+            }
+        }
     }
 
     /// Creates an iterator on the buffer's content.
     pub fn iter(&self) -> impl Iterator<Item = &MeasurementPoint> {
-        self.points.iter()
+        self.points().iter()
     }
 
     /// Creates an iterator that allows to modify the measurements.
+    ///
+    /// The points are modified in place, hence a buffer with the [`BufferLayout::Columns`] layout
+    /// is converted to [`BufferLayout::Rows`].
     pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut MeasurementPoint> {
-        self.points.iter_mut()
+        self.points_mut().iter_mut()
+    }
+
+    /// Groups the measurements by metric, for the outputs that write the points of each metric together.
+    ///
+    /// The groups are in the order of the first measurement of each metric, and the measurements keep their order
+    /// within each group. The points are borrowed, not copied, and the buffer is not modified.
+    pub fn group_by_metric(&self) -> impl Iterator<Item = (RawMetricId, Vec<&MeasurementPoint>)> {
+        let mut groups: Vec<(RawMetricId, Vec<&MeasurementPoint>)> = Vec::new();
+        let mut index: HashMap<RawMetricId, usize, FxBuildHasher> = HashMap::default();
+        for point in self.points() {
+            let i = *index.entry(point.metric).or_insert_with(|| {
+                groups.push((point.metric, Vec::new()));
+                groups.len() - 1
+            });
+            groups[i].1.push(point);
+        }
+        groups.into_iter()
+    }
+
+    /// Reorders the measurements so that the points of each metric are contiguous, sorted by metric id.
+    ///
+    /// The sort is stable: the measurements keep their order within each metric. The points are moved, not cloned.
+    pub fn sort_by_metric(&mut self) {
+        self.modify_points(|points| points.sort_by_key(|p| p.metric.0));
     }
 
     /// Returns a `MeasurementAccumulator` that will push all measurements to this buffer.
     pub fn as_accumulator(&mut self) -> MeasurementAccumulator {
         MeasurementAccumulator(self)
     }
+
+    /// Merges the duplicate measurements by summing their values, and returns the number of removed points.
+    ///
+    /// Two measurements are duplicates if they have exactly the same timestamp (there is no time bucket), metric,
+    /// resource, consumer and attributes (in any order). This happens when several sources measure the same thing,
+    /// and prevents the outputs from counting it twice.
+    /// The merged point takes the place of the first duplicate. If the duplicates do not have the same type of
+    /// value, the sum is a `F64`. The sum of `U64` values saturates at `u64::MAX`.
+    pub fn dedup_sum(&mut self) -> usize {
+        self.dedup_with(|kept, dup| {
+            kept.value = match (&kept.value, dup.value) {
+                (WrappedMeasurementValue::U64(a), WrappedMeasurementValue::U64(b)) => {
+                    WrappedMeasurementValue::U64(a.saturating_add(b))
+                }
+                (a, b) => WrappedMeasurementValue::F64(a.as_f64() + b.as_f64()),
+            };
+        })
+    }
+
+    /// Merges the duplicate measurements by keeping the last one, and returns the number of removed points.
+    ///
+    /// The duplicates are defined like in [`dedup_sum`](Self::dedup_sum). The last duplicate takes the place
+    /// of the first one, so that the order of the other measurements is preserved.
+    pub fn dedup_last(&mut self) -> usize {
+        self.dedup_with(|kept, dup| *kept = dup)
+    }
+
+    fn dedup_with(&mut self, mut merge: impl FnMut(&mut MeasurementPoint, MeasurementPoint)) -> usize {
+        self.modify_points(|points| {
+            let len = points.len();
+            let mut kept: Vec<MeasurementPoint> = Vec::with_capacity(len);
+            // the points with the same timestamp and metric, which are usually few, are compared one by one
+            let mut index: HashMap<(SystemTime, RawMetricId), SmallVec<[usize; 1]>, FxBuildHasher> =
+                HashMap::default();
+            for point in points.drain(..) {
+                let candidates = index.entry((point.timestamp.0, point.metric)).or_default();
+                match candidates.iter().find(|&&i| is_same_series(&kept[i], &point)) {
+                    Some(&i) => merge(&mut kept[i], point),
+                    None => {
+                        candidates.push(kept.len());
+                        kept.push(point);
+                    }
+                }
+            }
+            *points = kept;
+            len - points.len()
+        })
+    }
+
+    fn from_points(layout: BufferLayout, points: Vec<MeasurementPoint>) -> MeasurementBuffer {
+        let storage = match layout {
+            BufferLayout::Rows => Storage::Rows(points),
+            BufferLayout::Columns => Storage::Columns {
+                columns: MeasurementColumns::from_points(points),
+                rows: OnceLock::new(),
+            },
+        };
+        MeasurementBuffer { storage }
+    }
+
+    /// Returns the points, built from the columns on the first call if needed.
+    fn points(&self) -> &Vec<MeasurementPoint> {
+        match &self.storage {
+            Storage::Rows(points) => points,
+            Storage::Columns { columns, rows } => rows.get_or_init(|| columns.to_points()),
+        }
+    }
+
+    /// Returns the points, converting the buffer to the [`BufferLayout::Rows`] layout.
+    fn points_mut(&mut self) -> &mut Vec<MeasurementPoint> {
+        if let Storage::Columns { .. } = self.storage {
+            self.storage = Storage::Rows(self.take_points());
+        }
+        match &mut self.storage {
+            Storage::Rows(points) => points,
+            Storage::Columns { .. } => unreachable!("the buffer has just been converted to rows"),
+        }
+    }
+
+    /// Moves the points out of the buffer, leaving it empty.
+    fn take_points(&mut self) -> Vec<MeasurementPoint> {
+        match &mut self.storage {
+            Storage::Rows(points) => std::mem::take(points),
+            Storage::Columns { columns, rows } => match rows.take() {
+                Some(points) => {
+                    columns.clear();
+                    points
+                }
+                None => std::mem::take(columns).into_points(),
+            },
+        }
+    }
+
+    /// Applies `f` to the points of the buffer, keeping its layout.
+    fn modify_points<R>(&mut self, f: impl FnOnce(&mut Vec<MeasurementPoint>) -> R) -> R {
+        match &mut self.storage {
+            Storage::Rows(points) => f(points),
+            Storage::Columns { columns, rows } => {
+                let mut points = match rows.take() {
+                    Some(points) => points,
+                    None => std::mem::take(columns).into_points(),
+                };
+                let res = f(&mut points);
+                *columns = MeasurementColumns::from_points(points);
+                res
+            }
+        }
+    }
+}
+
+/// Returns true if the two points have the same resource, consumer and attributes (in any order).
+fn is_same_series(a: &MeasurementPoint, b: &MeasurementPoint) -> bool {
+    a.resource == b.resource
+        && a.consumer == b.consumer
+        && a.attributes.len() == b.attributes.len()
+        && a.attributes.iter().all(|(key, value)| {
+            b.attributes
+                .iter()
+                .any(|(other_key, other_value)| key == other_key && value.same_as(other_value))
+        })
 }
 
 impl<'a> IntoIterator for &'a MeasurementBuffer {
@@ -368,26 +724,175 @@ impl<'a> IntoIterator for &'a MeasurementBuffer {
     type IntoIter = std::slice::Iter<'a, MeasurementPoint>;
 
     fn into_iter(self) -> Self::IntoIter {
-        self.points.iter()
+        self.points().iter()
     }
 }
 
 impl std::fmt::Debug for MeasurementBuffer {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("MeasurementBuffer")
-            .field("len", &self.points.len())
+            .field("len", &self.len())
+            .field("layout", &self.layout())
             .finish()
     }
 }
 
 impl From<Vec<MeasurementPoint>> for MeasurementBuffer {
     fn from(value: Vec<MeasurementPoint>) -> Self {
-        MeasurementBuffer { points: value }
+        MeasurementBuffer {
+            storage: Storage::Rows(value),
+        }
+    }
+}
+
+/// Attributes of a single measurement point, as stored in [`MeasurementPoint`].
+type PointAttributes = SmallVec<[(Cow<'static, str>, AttributeValue); 4]>;
+
+/// The columns of a [`MeasurementBuffer`] with the [`BufferLayout::Columns`] layout ("struct of arrays").
+///
+/// Columnar formats such as Apache Arrow or Parquet expect one contiguous array per field:
+/// outputs can serialize each column without walking through every point.
+/// The `i`-th element of each column belongs to the `i`-th point.
+#[derive(Clone, Default)]
+pub struct MeasurementColumns {
+    timestamps: Vec<Timestamp>,
+    metrics: Vec<RawMetricId>,
+    values: Vec<WrappedMeasurementValue>,
+    resources: Vec<Resource>,
+    consumers: Vec<ResourceConsumer>,
+    attributes: Vec<PointAttributes>,
+}
+
+impl MeasurementColumns {
+    /// Returns the number of measurement points, that is, the length of each column.
+    pub fn len(&self) -> usize {
+        self.timestamps.len()
+    }
+
+    /// Returns true if there is no measurement point.
+    pub fn is_empty(&self) -> bool {
+        self.timestamps.is_empty()
+    }
+
+    /// The timestamps of the points.
+    pub fn timestamps(&self) -> &[Timestamp] {
+        &self.timestamps
+    }
+
+    /// The metrics of the points.
+    pub fn metrics(&self) -> &[RawMetricId] {
+        &self.metrics
+    }
+
+    /// The measured values.
+    pub fn values(&self) -> &[WrappedMeasurementValue] {
+        &self.values
+    }
+
+    /// The resources of the points.
+    pub fn resources(&self) -> &[Resource] {
+        &self.resources
+    }
+
+    /// The resource consumers of the points.
+    pub fn consumers(&self) -> &[ResourceConsumer] {
+        &self.consumers
+    }
+
+    /// Iterates on the attributes of the `i`-th point.
+    ///
+    /// Panics if `i` is out of bounds.
+    pub fn attributes(&self, i: usize) -> impl Iterator<Item = (&str, &AttributeValue)> {
+        self.attributes[i].iter().map(|(k, v)| (k.as_ref(), v))
+    }
+
+    fn with_capacity(capacity: usize) -> Self {
+        Self {
+            timestamps: Vec::with_capacity(capacity),
+            metrics: Vec::with_capacity(capacity),
+            values: Vec::with_capacity(capacity),
+            resources: Vec::with_capacity(capacity),
+            consumers: Vec::with_capacity(capacity),
+            attributes: Vec::with_capacity(capacity),
+        }
+    }
+
+    fn from_points(points: Vec<MeasurementPoint>) -> Self {
+        let mut columns = Self::with_capacity(points.len());
+        points.into_iter().for_each(|p| columns.push(p));
+        columns
+    }
+
+    fn capacity(&self) -> usize {
+        self.timestamps.capacity()
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        self.timestamps.reserve(additional);
+        self.metrics.reserve(additional);
+        self.values.reserve(additional);
+        self.resources.reserve(additional);
+        self.consumers.reserve(additional);
+        self.attributes.reserve(additional);
+    }
+
+    fn push(&mut self, point: MeasurementPoint) {
+        self.timestamps.push(point.timestamp);
+        self.metrics.push(point.metric);
+        self.values.push(point.value);
+        self.resources.push(point.resource);
+        self.consumers.push(point.consumer);
+        self.attributes.push(point.attributes);
+    }
+
+    fn clear(&mut self) {
+        self.timestamps.clear();
+        self.metrics.clear();
+        self.values.clear();
+        self.resources.clear();
+        self.consumers.clear();
+        self.attributes.clear();
+    }
+
+    /// Builds the points, cloning the content of the columns.
+    fn to_points(&self) -> Vec<MeasurementPoint> {
+        (0..self.len())
+            .map(|i| MeasurementPoint {
+                metric: self.metrics[i],
+                timestamp: self.timestamps[i],
+                value: self.values[i].clone(),
+                resource: self.resources[i].clone(),
+                consumer: self.consumers[i].clone(),
+                attributes: self.attributes[i].clone(),
+            })
+            .collect()
+    }
+
+    fn into_points(self) -> Vec<MeasurementPoint> {
+        self.timestamps
+            .into_iter()
+            .zip(self.metrics)
+            .zip(self.values)
+            .zip(self.resources)
+            .zip(self.consumers)
+            .zip(self.attributes)
+            .map(
+                |(((((timestamp, metric), value), resource), consumer), attributes)| MeasurementPoint {
+                    metric,
+                    timestamp,
+                    value,
+                    resource,
+                    consumer,
+                    attributes,
+                },
+            )
+            .collect()
     }
 }
 
 /// An accumulator stores measured data points.
 /// Unlike a [`MeasurementBuffer`], the accumulator only allows to [`push`](MeasurementAccumulator::push) new points, not to modify them.
+/// In particular, the points keep their own timestamps, which can differ within one poll.
 pub struct MeasurementAccumulator<'a>(&'a mut MeasurementBuffer);
 
 impl<'a> MeasurementAccumulator<'a> {
@@ -396,4 +901,222 @@ impl<'a> MeasurementAccumulator<'a> {
     pub fn push(&mut self, point: MeasurementPoint) {
         self.0.push(point)
     }
+
+    /// Adds multiple measurements to this accumulator, in the order of the iterator.
+    ///
+    /// The capacity is reserved once, based on the size hint of the iterator,
+    /// which is faster than calling [`push`](Self::push) for each point.
+    pub fn push_many(&mut self, points: impl IntoIterator<Item = MeasurementPoint>) {
+        self.0.extend_from_points(points)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::marker::PhantomData;
+
+    use crate::metrics::{RawMetricId, TypedMetricId};
+    use crate::resources::{Resource, ResourceConsumer};
+
+    use super::{BufferLayout, MeasurementBuffer, MeasurementPoint, Timestamp, WrappedMeasurementValue};
+
+    fn point(value: u64) -> MeasurementPoint {
+        let metric: TypedMetricId<u64> = TypedMetricId(RawMetricId(0), PhantomData);
+        MeasurementPoint::new(
+            Timestamp::now(),
+            metric,
+            Resource::LocalMachine,
+            ResourceConsumer::LocalMachine,
+            value,
+        )
+    }
+
+    fn values(buf: &MeasurementBuffer) -> Vec<u64> {
+        buf.iter()
+            .map(|p| match p.value {
+                WrappedMeasurementValue::U64(v) => v,
+                WrappedMeasurementValue::F64(_) => panic!("unexpected F64 value"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn append_buffers() {
+        let mut a = MeasurementBuffer::from(vec![point(1), point(2)]);
+        let mut b = MeasurementBuffer::from(vec![point(3)]);
+        a.append(&mut b);
+        assert!(b.is_empty());
+        assert_eq!(values(&a), vec![1, 2, 3]);
+
+        a.extend_from_points(vec![point(4), point(5)]);
+        assert_eq!(values(&a), vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn take_and_put_back() {
+        let mut buf = MeasurementBuffer::from((1..=5).map(point).collect::<Vec<_>>());
+        let (mut even, mask) = buf.take_matching(|m| m.value.as_u64().unwrap() % 2 == 0);
+        assert_eq!(values(&even), vec![2, 4]);
+        assert_eq!(values(&buf), vec![1, 3, 5]);
+        assert_eq!(mask, vec![false, true, false, true, false]);
+
+        // the measurements are put back at their place
+        for m in even.iter_mut() {
+            m.value = WrappedMeasurementValue::U64(m.value.as_u64().unwrap() * 10);
+        }
+        let mut same = buf.clone();
+        same.put_back(even, &mask);
+        assert_eq!(values(&same), vec![1, 20, 3, 40, 5]);
+
+        // one measurement removed and two added
+        let mut buf2 = buf.clone();
+        buf2.put_back(MeasurementBuffer::from(vec![point(6), point(7), point(8)]), &mask);
+        assert_eq!(values(&buf2), vec![1, 6, 3, 7, 5, 8]);
+        buf.put_back(MeasurementBuffer::from(vec![point(6)]), &mask);
+        assert_eq!(values(&buf), vec![1, 6, 3, 5]);
+    }
+
+    #[test]
+    fn accumulator_push_many() {
+        let mut buf = MeasurementBuffer::from(vec![point(1)]);
+        let mut acc = buf.as_accumulator();
+        acc.push_many((2..=4).map(point));
+        acc.push(point(5));
+        acc.push_many(Vec::new());
+        assert_eq!(values(&buf), vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn group_by_metric() {
+        let point_of = |metric, value| {
+            let mut p = point(value);
+            p.metric = RawMetricId(metric);
+            p
+        };
+        let mut buf = MeasurementBuffer::from(vec![
+            point_of(2, 1),
+            point_of(0, 2),
+            point_of(2, 3),
+            point_of(1, 4),
+            point_of(0, 5),
+        ]);
+        let groups: Vec<(RawMetricId, Vec<u64>)> = buf
+            .group_by_metric()
+            .map(|(metric, points)| (metric, points.iter().map(|p| p.value.as_f64() as u64).collect()))
+            .collect();
+        assert_eq!(
+            groups,
+            vec![
+                (RawMetricId(2), vec![1, 3]),
+                (RawMetricId(0), vec![2, 5]),
+                (RawMetricId(1), vec![4]),
+            ]
+        );
+        // the points are borrowed from the buffer
+        let (_, points) = buf.group_by_metric().next().unwrap();
+        assert!(std::ptr::eq(points[0], buf.iter().next().unwrap()));
+        assert_eq!(MeasurementBuffer::new().group_by_metric().count(), 0);
+
+        buf.sort_by_metric();
+        assert_eq!(values(&buf), vec![2, 5, 4, 1, 3]);
+    }
+
+    #[test]
+    fn dedup() {
+        let t = Timestamp::now();
+        let at = |timestamp: Timestamp, metric: usize, pkg: u32, value: u64| {
+            MeasurementPoint::new_untyped(
+                timestamp,
+                RawMetricId(metric),
+                Resource::CpuPackage { id: pkg },
+                ResourceConsumer::LocalMachine,
+                WrappedMeasurementValue::U64(value),
+            )
+        };
+        let later = Timestamp(t.0 + std::time::Duration::from_nanos(1));
+        let points = vec![
+            at(t, 0, 0, 1),
+            at(t, 0, 1, 2),                                // other resource
+            at(t, 1, 0, 3),                                // other metric
+            at(later, 0, 0, 4),                            // other timestamp, even very close
+            at(t, 0, 0, 5).with_attr("domain", "package"), // other attributes
+            at(t, 0, 0, 6),
+            at(t, 0, 0, 7).with_attr("domain", String::from("package")),
+            at(t, 0, 0, 8),
+        ];
+
+        let mut buf = MeasurementBuffer::from(points.clone());
+        assert_eq!(buf.dedup_sum(), 3);
+        assert_eq!(values(&buf), vec![15, 2, 3, 4, 12]);
+
+        let mut buf = MeasurementBuffer::from(points);
+        assert_eq!(buf.dedup_last(), 3);
+        assert_eq!(values(&buf), vec![8, 2, 3, 4, 7]);
+        assert_eq!(buf.dedup_last(), 0);
+
+        // mixed types are summed as floats
+        let mut buf = MeasurementBuffer::from(vec![at(t, 0, 0, 1), at(t, 0, 0, 2)]);
+        buf.iter_mut().nth(1).unwrap().value = WrappedMeasurementValue::F64(0.5);
+        assert_eq!(buf.dedup_sum(), 1);
+        assert_eq!(buf.iter().next().unwrap().value.as_f64(), 1.5);
+    }
+
+    #[test]
+    fn columns_layout() {
+        let mut buf = MeasurementBuffer::with_layout(BufferLayout::Columns, 2);
+        assert_eq!(buf.layout(), BufferLayout::Columns);
+        buf.push(point(1));
+        buf.extend_from_points(vec![point(2).with_attr("key", 42_u64), point(3)]);
+        assert_eq!(values(&buf), vec![1, 2, 3]);
+
+        let columns = buf.as_columns().unwrap();
+        assert_eq!(columns.len(), 3);
+        assert_eq!(columns.timestamps().len(), 3);
+        assert_eq!(columns.attributes(0).count(), 0);
+        assert_eq!(columns.attributes(1).map(|(k, _)| k).collect::<Vec<_>>(), vec!["key"]);
+        let column_values: Vec<u64> = columns.values().iter().map(|v| v.as_u64().unwrap()).collect();
+        assert_eq!(column_values, vec![1, 2, 3]);
+        assert!(MeasurementBuffer::new().as_columns().is_none());
+
+        // the points built for the iteration are updated when the columns change
+        buf.push(point(4));
+        assert_eq!(values(&buf), vec![1, 2, 3, 4]);
+
+        // the layout is kept by the operations that do not modify the points in place
+        let (odd, mask) = buf.take_matching(|m| m.value.as_u64().unwrap() % 2 == 1);
+        assert_eq!(odd.layout(), BufferLayout::Columns);
+        assert_eq!(values(&odd), vec![1, 3]);
+        buf.put_back(odd, &mask);
+        buf.retain(|m| m.value.as_u64().unwrap() != 3);
+        assert_eq!(buf.layout(), BufferLayout::Columns);
+        assert_eq!(values(&buf), vec![1, 2, 4]);
+        assert_eq!(buf.iter().nth(1).unwrap().attributes_len(), 1);
+
+        let mut rows = MeasurementBuffer::from(vec![point(5)]);
+        buf.append(&mut rows);
+        assert!(rows.is_empty());
+        assert_eq!(buf.layout(), BufferLayout::Columns);
+        assert_eq!(values(&buf), vec![1, 2, 4, 5]);
+
+        // modifying the points in place converts the buffer to rows
+        for m in buf.iter_mut() {
+            m.value = WrappedMeasurementValue::U64(m.value.as_u64().unwrap() * 10);
+        }
+        assert_eq!(buf.layout(), BufferLayout::Rows);
+        assert_eq!(values(&buf), vec![10, 20, 40, 50]);
+    }
+
+    #[test]
+    fn value_conversions() {
+        assert_eq!(WrappedMeasurementValue::U64(12).as_f64(), 12.0);
+        assert_eq!(WrappedMeasurementValue::F64(1.5).as_f64(), 1.5);
+        // lossy above 2^53
+        assert_eq!(WrappedMeasurementValue::U64((1 << 53) + 1).as_f64(), (1u64 << 53) as f64);
+
+        assert_eq!(WrappedMeasurementValue::U64(u64::MAX).as_u64(), Some(u64::MAX));
+        assert_eq!(WrappedMeasurementValue::F64(42.9).as_u64(), Some(42));
+        assert_eq!(WrappedMeasurementValue::F64(-1.0).as_u64(), None);
+        assert_eq!(WrappedMeasurementValue::F64(f64::NAN).as_u64(), None);
+        assert_eq!(WrappedMeasurementValue::F64(1e30).as_u64(), None);
+    }
 }
@@ -22,6 +22,9 @@
 //! or [`AlumetStart::create_metric_untyped`](crate::plugin::AlumetStart::create_metric).
 //! You can then pass the id around.
 //!
+//! If several plugins register a metric with the same name, type and unit, they share the same id
+//! (see [`DuplicateMetricPolicy`]). Registering a metric with the same name but a different type or unit fails.
+//!
 //! ### Example
 //!
 //! ```no_run
@@ -47,7 +50,7 @@ use std::marker::PhantomData;
 use crate::pipeline::OutputContext;
 
 use super::measurement::{MeasurementType, WrappedMeasurementType};
-use super::units::PrefixedUnit;
+use super::units::{PrefixedUnit, UnitRegistry};
 
 /// The complete definition of a metric.
 ///
@@ -86,6 +89,22 @@ pub trait MetricId {
 pub struct MetricRegistry {
     pub(crate) metrics_by_id: HashMap<RawMetricId, Metric>,
     pub(crate) metrics_by_name: HashMap<String, RawMetricId>,
+    pub(crate) units: UnitRegistry,
+    /// Name of the plugin that created each metric, if any.
+    pub(crate) plugins: HashMap<RawMetricId, String>,
+    /// What to do when a metric is registered twice.
+    pub(crate) duplicates: DuplicateMetricPolicy,
+}
+
+/// What to do when a metric is registered with the name of an existing metric.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicateMetricPolicy {
+    /// If the two metrics have the same type and unit, return the id of the existing metric.
+    /// Otherwise, fail.
+    #[default]
+    Coalesce,
+    /// Always fail.
+    Strict,
 }
 
 /// A metric id without a generic type information.
@@ -170,6 +189,9 @@ impl MetricRegistry {
         MetricRegistry {
             metrics_by_id: HashMap::new(),
             metrics_by_name: HashMap::new(),
+            units: UnitRegistry::new(),
+            plugins: HashMap::new(),
+            duplicates: DuplicateMetricPolicy::default(),
         }
     }
 
@@ -183,6 +205,19 @@ impl MetricRegistry {
         self.metrics_by_name.get(name).and_then(|id| self.metrics_by_id.get(id))
     }
 
+    /// Finds the id of the metric that has the given name.
+    pub fn id_with_name(&self, name: &str) -> Option<RawMetricId> {
+        self.metrics_by_name.get(name).copied()
+    }
+
+    /// Returns the name of the plugin that created the metric with the given id.
+    ///
+    /// Returns `None` if the metric does not exist, or if it has not been created by a plugin
+    /// (for instance, the metrics of the agent itself).
+    pub fn plugin_of<M: MetricId>(&self, id: &M) -> Option<&str> {
+        self.plugins.get(&id.untyped_id()).map(|p| p.as_str())
+    }
+
     /// The number of metrics in the registry.
     pub fn len(&self) -> usize {
         self.metrics_by_id.len()
@@ -192,6 +227,11 @@ impl MetricRegistry {
         self.metrics_by_id.is_empty()
     }
 
+    /// The custom units that can be used by the metrics.
+    pub fn units(&self) -> &UnitRegistry {
+        &self.units
+    }
+
     /// An iterator on the registered metrics.
     pub fn iter(&self) -> MetricIter<'_> {
         // return new iterator
@@ -202,13 +242,23 @@ impl MetricRegistry {
 
     /// Registers a new metric in this registry.
     ///
-    /// A new id is generated and returned.
+    /// A new id is generated and returned. If a metric with the same name already exists,
+    /// the [`DuplicateMetricPolicy`] of the registry applies.
     pub(crate) fn register(&mut self, m: Metric) -> Result<RawMetricId, MetricCreationError> {
         let name = &m.name;
-        if let Some(_name_conflict) = self.metrics_by_name.get(name) {
-            return Err(MetricCreationError::new(format!(
-                "A metric with this name already exist: {name}"
-            )));
+        if let Some(existing_id) = self.metrics_by_name.get(name) {
+            let existing = &self.metrics_by_id[existing_id];
+            let compatible = existing.value_type == m.value_type && existing.unit == m.unit;
+            return match self.duplicates {
+                DuplicateMetricPolicy::Coalesce if compatible => Ok(*existing_id),
+                DuplicateMetricPolicy::Coalesce => Err(MetricCreationError::new(format!(
+                    "A metric with this name already exist, with a different type or unit: {name} ({:?} in {})",
+                    existing.value_type, existing.unit
+                ))),
+                DuplicateMetricPolicy::Strict => Err(MetricCreationError::new(format!(
+                    "A metric with this name already exist: {name}"
+                ))),
+            };
         }
         let id = RawMetricId(self.metrics_by_name.len());
         self.metrics_by_name.insert(name.clone(), id);
@@ -216,6 +266,15 @@ impl MetricRegistry {
         Ok(id)
     }
 
+    /// Registers a new metric that is created by the given plugin.
+    ///
+    /// If the metric is coalesced with an existing one, it keeps the plugin that created it first.
+    pub(crate) fn register_from_plugin(&mut self, m: Metric, plugin: &str) -> Result<RawMetricId, MetricCreationError> {
+        let id = self.register(m)?;
+        self.plugins.entry(id).or_insert_with(|| plugin.to_owned());
+        Ok(id)
+    }
+
     fn deduplicated_name(&self, requested_name: &str, resolution_suffix: &str) -> String {
         if let Some(_conflict) = self.metrics_by_name.get(requested_name) {
             let mut name = format!("{requested_name}_{resolution_suffix}");
@@ -305,7 +364,7 @@ impl fmt::Display for MetricCreationError {
 mod tests {
     use crate::{measurement::WrappedMeasurementType, metrics::Metric, units::Unit};
 
-    use super::MetricRegistry;
+    use super::{DuplicateMetricPolicy, MetricRegistry};
 
     #[test]
     fn no_duplicate_metrics() {
@@ -330,6 +389,56 @@ mod tests {
         assert_eq!(metrics.len(), 1);
     }
 
+    #[test]
+    fn metric_plugin() {
+        let mut metrics = MetricRegistry::new();
+        let metric = |name: &str| Metric {
+            name: name.to_owned(),
+            description: "".to_owned(),
+            value_type: WrappedMeasurementType::F64,
+            unit: Unit::Watt.into(),
+        };
+        let from_rapl = metrics.register_from_plugin(metric("rapl_power"), "rapl").unwrap();
+        let internal = metrics.register(metric("internal")).unwrap();
+        assert_eq!(metrics.plugin_of(&from_rapl), Some("rapl"));
+        assert_eq!(metrics.plugin_of(&internal), None);
+        metrics.duplicates = DuplicateMetricPolicy::Strict;
+        metrics.register_from_plugin(metric("rapl_power"), "other").unwrap_err();
+        assert_eq!(metrics.plugin_of(&from_rapl), Some("rapl"));
+    }
+
+    #[test]
+    fn coalesce_duplicate_metrics() {
+        let mut metrics = MetricRegistry::new();
+        let metric = |description: &str, unit: Unit| Metric {
+            name: "gpu_power".to_owned(),
+            description: description.to_owned(),
+            value_type: WrappedMeasurementType::F64,
+            unit: unit.into(),
+        };
+        let first = metrics
+            .register_from_plugin(metric("power", Unit::Watt), "nvidia")
+            .unwrap();
+        let second = metrics
+            .register_from_plugin(metric("power of the GPU", Unit::Watt), "other")
+            .unwrap();
+        assert_eq!(first, second);
+        assert_eq!(metrics.len(), 1);
+        assert_eq!(metrics.plugin_of(&second), Some("nvidia"));
+        assert_eq!(metrics.with_id(&second).unwrap().description, "power");
+
+        // incompatible unit
+        metrics.register(metric("power", Unit::Joule)).unwrap_err();
+        // incompatible type
+        metrics
+            .register(Metric {
+                value_type: WrappedMeasurementType::U64,
+                ..metric("power", Unit::Watt)
+            })
+            .unwrap_err();
+        assert_eq!(metrics.len(), 1);
+    }
+
     #[test]
     fn metric_registry() {
         let mut metrics = MetricRegistry::new();
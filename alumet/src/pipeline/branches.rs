@@ -0,0 +1,334 @@
+//! Named processing branches: transforms that apply to the measurements of one output only.
+//!
+//! By default, every transform applies to the measurements of every output. A branch moves some transforms
+//! out of this shared step, and applies them to a copy of the measurements, just before one output.
+//! This allows to send the same metric to several outputs with a different post-processing,
+//! for instance the raw values to a file and the smoothed values to a database.
+//!
+//! ```text
+//!                                   ┌─> branch "raw":      ────────────────────> csv output
+//! sources ─> shared transforms ─────┤
+//!                                   └─> branch "smoothed": smoothing transform ─> prometheus output
+//! ```
+//!
+//! The branches are declared in the configuration of the agent, in the `pipelines` table.
+//! The elements are referred to by name, such as `"csv/output-0"`, or by the name of the plugin that
+//! registered them, such as `"csv"`. A plugin name refers to all the transforms of the plugin,
+//! in the order of their registration, but it must refer to exactly one output.
+//!
+//! ```toml
+//! [pipelines.raw]
+//! output = "csv"
+//!
+//! [pipelines.smoothed]
+//! output = "prometheus"
+//! transforms = ["smoothing"]
+//! ```
+//!
+//! Each transform can be used by one branch at most, and each output by one branch at most.
+//! The outputs that are not in a branch, like the transforms, keep receiving the measurements of the shared step.
+//!
+//! ## Limitations
+//! The transforms of a branch run in the task of its output, not in the transform step. Therefore:
+//! - they are always enabled: the [`TransformCmd`](super::runtime::TransformCmd) sent through the control handle
+//!   only apply to the shared transforms;
+//! - their time is not part of `alumet_transform_duration`, but of the `alumet_write_duration` of the output
+//!   (see the [`overhead`](super::overhead) module).
+
+use std::collections::BTreeMap;
+
+use anyhow::anyhow;
+
+use crate::measurement::{MeasurementBuffer, WrappedMeasurementType};
+
+use super::builder::{OutputBuilder, TransformBuilder};
+use super::transforms::TransformChain;
+use super::{Output, OutputContext, Transform, TransformError, WriteError};
+
+/// Configuration of a branch, see the [module documentation](self).
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct BranchConfig {
+    /// The output at the end of the branch.
+    pub output: String,
+    /// The transforms of the branch, in order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transforms: Vec<String>,
+}
+
+/// An output that applies a chain of transforms to a copy of the measurements before writing them.
+pub struct BranchOutput {
+    name: String,
+    chain: TransformChain,
+    inner: Box<dyn Output>,
+}
+
+impl BranchOutput {
+    pub fn new(name: impl Into<String>, chain: TransformChain, inner: Box<dyn Output>) -> Self {
+        Self {
+            name: name.into(),
+            chain,
+            inner,
+        }
+    }
+}
+
+impl Output for BranchOutput {
+    fn write(&mut self, measurements: &MeasurementBuffer, ctx: &OutputContext) -> Result<(), WriteError> {
+        if self.chain.is_empty() {
+            return self.inner.write(measurements, ctx);
+        }
+        // The other branches read the same buffer, modify a copy of it.
+        let mut measurements = measurements.clone();
+        match self.chain.apply(&mut measurements) {
+            Ok(()) => (),
+            Err(TransformError::UnexpectedInput(e)) => {
+                log::error!("Branch {} received unexpected measurements: {e:#}", self.name);
+            }
+            Err(TransformError::Fatal(e)) => {
+                return Err(WriteError::Fatal(
+                    e.context(format!("fatal error in branch {}", self.name)),
+                ));
+            }
+        }
+        if measurements.is_empty() {
+            return Ok(());
+        }
+        self.inner.write(&measurements, ctx)
+    }
+
+    fn supported_value_kinds(&self) -> &'static [WrappedMeasurementType] {
+        self.inner.supported_value_kinds()
+    }
+
+    fn finalize(&mut self) -> anyhow::Result<()> {
+        self.inner.finalize()
+    }
+
+    fn stop(&mut self) -> anyhow::Result<()> {
+        self.inner.stop()
+    }
+}
+
+/// Moves the transforms of each branch into the builder of its output.
+///
+/// The remaining `transforms` are the shared ones, which apply to every output.
+pub(super) fn apply_branches(
+    branches: &BTreeMap<String, BranchConfig>,
+    transforms: &mut Vec<TransformBuilder>,
+    outputs: &mut Vec<OutputBuilder>,
+) -> anyhow::Result<()> {
+    let mut transform_owners: Vec<Option<&str>> = vec![None; transforms.len()];
+    let mut output_owners: Vec<Option<&str>> = vec![None; outputs.len()];
+    let mut resolved: Vec<(&str, usize, Vec<usize>)> = Vec::with_capacity(branches.len());
+
+    for (name, branch) in branches {
+        let matching: Vec<usize> = (0..outputs.len())
+            .filter(|i| outputs[*i].name == branch.output || outputs[*i].plugin == branch.output)
+            .collect();
+        let output = match matching[..] {
+            [i] => i,
+            [] => return Err(anyhow!("branch {name}: output {} not found", branch.output)),
+            _ => {
+                return Err(anyhow!(
+                    "branch {name}: {} refers to several outputs, use the name of an output instead, for instance {}",
+                    branch.output,
+                    outputs[matching[0]].name
+                ))
+            }
+        };
+        if let Some(other) = output_owners[output].replace(name) {
+            return Err(anyhow!(
+                "branch {name}: output {} is already used by branch {other}",
+                branch.output
+            ));
+        }
+
+        let mut chain = Vec::new();
+        for reference in &branch.transforms {
+            let matching: Vec<usize> = (0..transforms.len())
+                .filter(|i| &transforms[*i].name == reference || &transforms[*i].plugin == reference)
+                .collect();
+            if matching.is_empty() {
+                return Err(anyhow!("branch {name}: transform {reference} not found"));
+            }
+            for i in matching {
+                if let Some(other) = transform_owners[i].replace(name) {
+                    return Err(anyhow!(
+                        "branch {name}: transform {} is already used by branch {other}",
+                        transforms[i].name
+                    ));
+                }
+                chain.push(i);
+            }
+        }
+        resolved.push((name, output, chain));
+    }
+
+    // Take the transforms of the branches out of the shared ones, without changing the order of the others.
+    let mut taken: Vec<Option<TransformBuilder>> = Vec::with_capacity(transforms.len());
+    let mut shared = Vec::with_capacity(transforms.len());
+    for (transform, owner) in transforms.drain(..).zip(&transform_owners) {
+        match owner {
+            Some(_) => taken.push(Some(transform)),
+            None => {
+                taken.push(None);
+                shared.push(transform);
+            }
+        }
+    }
+    *transforms = shared;
+
+    // Wrap the output of each branch, without changing the order of the outputs.
+    let mut builders: Vec<Option<OutputBuilder>> = outputs.drain(..).map(Some).collect();
+    for (name, output, chain) in resolved {
+        let chain: Vec<TransformBuilder> = chain.into_iter().map(|i| taken[i].take().unwrap()).collect();
+        let builder = builders[output].take().unwrap();
+        let names: Vec<&str> = chain.iter().map(|t| t.name.as_str()).collect();
+        log::info!("Branch {name}: {names:?} -> {}", builder.name);
+
+        let build_output = builder.build;
+        let name = name.to_owned();
+        builders[output] = Some(OutputBuilder {
+            name: builder.name,
+            plugin: builder.plugin,
+            build: Box::new(move |pending| {
+                let inner = build_output(pending)?;
+                let chain = chain
+                    .into_iter()
+                    .fold(TransformChain::new(), |chain, t| chain.with(t.name, (t.build)(pending)));
+                Ok(Box::new(BranchOutput::new(name, chain, inner)))
+            }),
+        });
+    }
+    *outputs = builders.into_iter().flatten().collect();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::sync::{Arc, Mutex};
+
+    use tokio::sync::broadcast;
+
+    use crate::measurement::{MeasurementBuffer, MeasurementPoint, Timestamp, WrappedMeasurementValue};
+    use crate::metrics::{MetricRegistry, RawMetricId};
+    use crate::pipeline::builder::{OutputBuilder, PendingPipelineContext, TransformBuilder};
+    use crate::pipeline::{Output, OutputContext, Transform, TransformError, WriteError};
+    use crate::resources::{Resource, ResourceConsumer};
+
+    use super::{apply_branches, BranchConfig};
+
+    /// Multiplies the values by a factor.
+    struct Scale(f64);
+
+    impl Transform for Scale {
+        fn apply(&mut self, measurements: &mut MeasurementBuffer) -> Result<(), TransformError> {
+            for m in measurements.iter_mut() {
+                m.value = WrappedMeasurementValue::F64(m.value.as_f64() * self.0);
+            }
+            Ok(())
+        }
+    }
+
+    struct ValuesOutput(Arc<Mutex<Vec<f64>>>);
+
+    impl Output for ValuesOutput {
+        fn write(&mut self, measurements: &MeasurementBuffer, _ctx: &OutputContext) -> Result<(), WriteError> {
+            let mut values = self.0.lock().unwrap();
+            values.extend(measurements.iter().map(|m| m.value.as_f64()));
+            Ok(())
+        }
+    }
+
+    fn transform(name: &str, factor: f64) -> TransformBuilder {
+        TransformBuilder {
+            name: format!("{name}/transform-0"),
+            plugin: name.to_owned(),
+            build: Box::new(move |_| Box::new(Scale(factor))),
+        }
+    }
+
+    fn output(name: &str, values: &Arc<Mutex<Vec<f64>>>) -> OutputBuilder {
+        let values = values.clone();
+        OutputBuilder {
+            name: format!("{name}/output-0"),
+            plugin: name.to_owned(),
+            build: Box::new(move |_| Ok(Box::new(ValuesOutput(values)))),
+        }
+    }
+
+    fn branch(output: &str, transforms: &[&str]) -> BranchConfig {
+        BranchConfig {
+            output: output.to_owned(),
+            transforms: transforms.iter().map(|t| t.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn two_branches() {
+        let (raw, smoothed) = (Arc::new(Mutex::new(Vec::new())), Arc::new(Mutex::new(Vec::new())));
+        let mut transforms = vec![transform("shared", 10.0), transform("smoothing", 0.5)];
+        let mut outputs = vec![output("csv", &raw), output("prometheus", &smoothed)];
+        let branches = BTreeMap::from([
+            (String::from("raw"), branch("csv", &[])),
+            (String::from("smoothed"), branch("prometheus/output-0", &["smoothing"])),
+        ]);
+        apply_branches(&branches, &mut transforms, &mut outputs).unwrap();
+
+        // the transform of the branch is no longer shared
+        let shared: Vec<&str> = transforms.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(shared, vec!["shared/transform-0"]);
+
+        // both outputs receive the same buffer, only the second one applies the transform
+        let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let to_output = broadcast::Sender::new(1);
+        let pending = PendingPipelineContext {
+            to_output: &to_output,
+            rt_handle: rt.handle(),
+        };
+        let mut outputs: Vec<Box<dyn Output>> = outputs.into_iter().map(|o| (o.build)(&pending).unwrap()).collect();
+        let ctx = OutputContext::new(MetricRegistry::new());
+        let point = |value| {
+            MeasurementPoint::new_untyped(
+                Timestamp::now(),
+                RawMetricId(0),
+                Resource::LocalMachine,
+                ResourceConsumer::LocalMachine,
+                WrappedMeasurementValue::F64(value),
+            )
+        };
+        let buf = MeasurementBuffer::from(vec![point(2.0), point(4.0)]);
+        for output in &mut outputs {
+            output.write(&buf, &ctx).unwrap();
+        }
+        assert_eq!(*raw.lock().unwrap(), vec![2.0, 4.0]);
+        assert_eq!(*smoothed.lock().unwrap(), vec![1.0, 2.0]);
+    }
+
+    #[test]
+    fn invalid_branches() {
+        let values = Arc::new(Mutex::new(Vec::new()));
+        let check = |branches: Vec<(&str, BranchConfig)>| {
+            let mut transforms = vec![transform("smoothing", 0.5)];
+            let mut outputs = vec![output("csv", &values), output("csv", &values)];
+            outputs[1].name = String::from("csv/output-1");
+            let branches = branches.into_iter().map(|(n, b)| (n.to_owned(), b)).collect();
+            apply_branches(&branches, &mut transforms, &mut outputs).map(|_| ())
+        };
+        assert!(check(vec![("a", branch("csv/output-0", &["smoothing"]))]).is_ok());
+        // unknown elements
+        assert!(check(vec![("a", branch("influxdb", &[]))]).is_err());
+        assert!(check(vec![("a", branch("csv/output-0", &["rate"]))]).is_err());
+        // ambiguous output
+        assert!(check(vec![("a", branch("csv", &[]))]).is_err());
+        // elements used twice
+        let reused = vec![("a", branch("csv/output-0", &[])), ("b", branch("csv/output-0", &[]))];
+        assert!(check(reused).is_err());
+        let twice = vec![
+            ("a", branch("csv/output-0", &["smoothing"])),
+            ("b", branch("csv/output-1", &["smoothing"])),
+        ];
+        assert!(check(twice).is_err());
+    }
+}
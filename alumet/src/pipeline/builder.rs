@@ -1,25 +1,34 @@
 use core::fmt;
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
 use std::future::Future;
 use std::io::{self, ErrorKind};
 use std::path::PathBuf;
 use std::pin::Pin;
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use anyhow::Context;
+use fxhash::FxHashSet;
 
 use tokio::runtime::Runtime;
 use tokio::sync::{broadcast, mpsc};
 use tokio_util::sync::CancellationToken;
 
+use crate::measurement::AttributeValue;
 use crate::metrics::{Metric, MetricRegistry, RawMetricId};
+use crate::time::{self, Clock};
 use crate::{
     measurement::MeasurementBuffer,
     pipeline::{Output, Source, Transform},
 };
 
-use super::runtime::{self, IdlePipeline, OutputMsg};
+use super::branches::{self, BranchConfig};
+use super::cache::LastValueCache;
+use super::overhead::OverheadMetrics;
+use super::probe::{self, StartupProbe};
+use super::runtime::{self, IdlePipeline, OutputMsg, SourceHandle};
 use super::trigger::{TriggerConstraints, TriggerSpec};
 
 /// A builder of measurement pipeline.
@@ -35,8 +44,37 @@ pub struct PipelineBuilder {
     pub(crate) metrics: MetricRegistry,
     pub(crate) allow_no_metrics: bool,
 
+    /// Attributes attached to all the measurement points, after the transforms.
+    pub(crate) global_attributes: Vec<(Cow<'static, str>, AttributeValue)>,
+
+    /// Whether to measure the overhead of the pipeline, see [`overhead`](super::overhead).
+    pub(crate) measure_overhead: bool,
+
     pub(crate) normal_worker_threads: Option<usize>,
     pub(crate) priority_worker_threads: Option<usize>,
+
+    /// Source of the timestamps of the managed sources, see [`time`](crate::time).
+    pub(crate) clock: Arc<dyn Clock>,
+
+    /// Maximum age of the values in the [`LastValueCache`], if the cache is enabled.
+    pub(crate) last_value_cache: Option<Duration>,
+
+    /// Whether to number the buffers sent to the outputs, see [`super::OutputContext::sequence_number`].
+    pub(crate) sequence_numbers: bool,
+
+    /// Expected number of measurement points produced by each poll of a managed source.
+    /// It is used to pre-allocate the buffers of the sources, see [`Agent::points_per_poll`](crate::agent::Agent::points_per_poll).
+    pub(crate) points_per_poll: Option<usize>,
+
+    /// Named branches, which apply some transforms to the measurements of one output only,
+    /// see [`branches`](super::branches).
+    pub(crate) branches: BTreeMap<String, BranchConfig>,
+
+    /// CPUs on which the worker threads of the pipeline run, if restricted.
+    pub(crate) cpu_affinity: Option<Vec<usize>>,
+
+    /// Whether to poll each managed source once when the pipeline is built, see [`probe`](super::probe).
+    pub(crate) startup_probe: StartupProbe,
 }
 
 pub type SourceBuildFn = dyn FnOnce(&PendingPipelineContext) -> Box<dyn Source>;
@@ -49,6 +87,7 @@ pub type AutonomousSourceBuildFn = dyn FnOnce(
 pub struct ManagedSourceBuilder {
     pub name: String,
     pub plugin: String,
+    pub handle: SourceHandle,
     pub trigger: TriggerSpec,
     pub build: Box<SourceBuildFn>,
 }
@@ -73,8 +112,8 @@ pub struct OutputBuilder {
 
 /// Information about a pipeline that is being built.
 pub struct PendingPipelineContext<'a> {
-    to_output: &'a broadcast::Sender<runtime::OutputMsg>,
-    rt_handle: &'a tokio::runtime::Handle,
+    pub(super) to_output: &'a broadcast::Sender<runtime::OutputMsg>,
+    pub(super) rt_handle: &'a tokio::runtime::Handle,
 }
 
 impl<'a> PendingPipelineContext<'a> {
@@ -128,6 +167,8 @@ pub(super) struct ConfiguredSource {
     pub name: String,
     /// Name of the plugin that registered the source.
     pub plugin_name: String,
+    /// Handle that identifies the source.
+    pub handle: SourceHandle,
     /// How to trigger this source.
     pub trigger_provider: TriggerSpec,
 }
@@ -146,6 +187,8 @@ pub(super) struct ConfiguredTransform {
     pub name: String,
     /// Name of the plugin that registered the source.
     pub plugin_name: String,
+    /// The metrics that the transform applies to, if it has declared them, see [`Transform::input_metrics`].
+    pub input_metrics: Option<FxHashSet<RawMetricId>>,
 }
 /// An output that is ready to run.
 pub(super) struct ConfiguredOutput {
@@ -178,6 +221,8 @@ pub enum ElementType {
 pub enum InvalidReason {
     NoSource,
     NoOutput,
+    /// A branch refers to elements that do not exist, or that are used by another branch.
+    Branch(anyhow::Error),
 }
 
 impl fmt::Display for InvalidReason {
@@ -185,6 +230,7 @@ impl fmt::Display for InvalidReason {
         match self {
             InvalidReason::NoSource => write!(f, "no Source"),
             InvalidReason::NoOutput => write!(f, "no Output"),
+            InvalidReason::Branch(err) => write!(f, "{err}"),
         }
     }
 }
@@ -220,12 +266,26 @@ impl PipelineBuilder {
             autonomous_sources: Vec::new(),
             metrics: MetricRegistry::new(),
             allow_no_metrics: false,
+            global_attributes: Vec::new(),
+            measure_overhead: false,
             normal_worker_threads: None,
             priority_worker_threads: None,
             source_constraints: TriggerConstraints::default(),
+            clock: time::system_clock(),
+            last_value_cache: None,
+            sequence_numbers: false,
+            points_per_poll: None,
+            branches: BTreeMap::new(),
+            cpu_affinity: None,
+            startup_probe: StartupProbe::default(),
         }
     }
 
+    /// Returns the clock of the pipeline.
+    pub fn clock(&self) -> Arc<dyn Clock> {
+        self.clock.clone()
+    }
+
     pub fn source_count(&self) -> usize {
         self.sources.len() + self.autonomous_sources.len()
     }
@@ -246,7 +306,7 @@ impl PipelineBuilder {
         self.metrics.iter()
     }
 
-    pub fn build(self) -> Result<IdlePipeline, PipelineBuildError> {
+    pub fn build(mut self) -> Result<IdlePipeline, PipelineBuildError> {
         // Check some conditions.
         if self.metrics.is_empty() && !self.allow_no_metrics {
             log::warn!("No metrics have been registered, have you loaded the right plugins?")
@@ -259,6 +319,20 @@ impl PipelineBuilder {
             return Err(PipelineBuildError::Invalid(InvalidReason::NoSource));
         }
 
+        // Move the transforms of the branches to their outputs, the other transforms are shared by all the outputs.
+        branches::apply_branches(&self.branches, &mut self.transforms, &mut self.outputs)
+            .map_err(|err| PipelineBuildError::Invalid(InvalidReason::Branch(err)))?;
+
+        // Register the internal metrics, after the check above (it concerns the metrics of the plugins).
+        let overhead = self
+            .measure_overhead
+            .then(|| OverheadMetrics::register(&mut self.metrics));
+
+        if let Some(cpus) = &self.cpu_affinity {
+            let cpus = super::threading::format_cpu_list(cpus);
+            log::info!("The threads of the pipeline will run on CPUs {cpus}.");
+        }
+
         // Create the normal runtime, the priority one is initialized on demand.
         let rt_normal: Runtime = self.build_normal_runtime()?;
         let rt_priority: Option<Runtime> = self.build_priority_runtime()?;
@@ -272,7 +346,7 @@ impl PipelineBuilder {
         let out_tx = broadcast::Sender::<OutputMsg>::new(256);
 
         // Create the pipeline elements.
-        let sources: Vec<ConfiguredSource> = self
+        let mut sources: Vec<ConfiguredSource> = self
             .sources
             .into_iter()
             .map(|builder| {
@@ -301,11 +375,26 @@ impl PipelineBuilder {
                     source,
                     name,
                     plugin_name: builder.plugin,
+                    handle: builder.handle,
                     trigger_provider: trigger,
                 }
             })
             .collect();
 
+        for src in sources.iter() {
+            let declared = src.source.declared_metrics();
+            if !declared.is_empty() {
+                let names = probe::metric_names(&self.metrics, &declared);
+                log::debug!("Source {} declares the metrics: {names}", src.name);
+            }
+        }
+
+        if self.startup_probe != StartupProbe::Disabled {
+            let _guard = rt_normal.enter();
+            let clock = self.clock.as_ref();
+            probe::probe_sources(&mut sources, self.startup_probe, clock, &self.metrics, &in_tx)?;
+        }
+
         let pending = PendingPipelineContext {
             to_output: &out_tx,
             rt_handle: rt_normal.handle(),
@@ -315,10 +404,12 @@ impl PipelineBuilder {
             .into_iter()
             .map(|builder| {
                 let transform = (builder.build)(&pending);
+                let input_metrics = transform.input_metrics().map(FxHashSet::from_iter);
                 ConfiguredTransform {
                     transform,
                     name: builder.name,
                     plugin_name: builder.plugin,
+                    input_metrics,
                 }
             })
             .collect();
@@ -338,13 +429,15 @@ impl PipelineBuilder {
             .collect();
         let outputs = outputs?;
 
-        // Create the autonomous sources
+        // Create the autonomous sources.
+        // Their measurements go through a separate channel, so that they can be discarded while the pipeline is paused.
+        let (autonomous_tx, autonomous_rx) = mpsc::channel::<MeasurementBuffer>(256);
         let autonomous_shutdown_token = CancellationToken::new();
         let autonomous_sources: Vec<_> = self
             .autonomous_sources
             .into_iter()
             .map(|builder| {
-                let data_tx = in_tx.clone();
+                let data_tx = autonomous_tx.clone();
                 let name = builder.name;
                 // This token will be cancelled when the global token gets cancelled (Alumet is shutting down).
                 // It can also be cancelled on its own, in which case only this source will be stopped.
@@ -361,7 +454,14 @@ impl PipelineBuilder {
             autonomous_sources,
             autonomous_shutdown_token,
             metrics: self.metrics,
+            last_values: self.last_value_cache.map(|max_age| LastValueCache::new(self.clock.clone(), max_age)),
+            sequence_numbers: self.sequence_numbers,
+            points_per_poll: self.points_per_poll,
+            clock: self.clock,
+            global_attributes: self.global_attributes,
+            overhead,
             from_sources: (in_tx, in_rx),
+            from_autonomous_sources: autonomous_rx,
             to_outputs: out_tx,
             rt_normal,
             rt_priority,
@@ -378,6 +478,9 @@ impl PipelineBuilder {
         if let Some(n) = self.normal_worker_threads {
             builder.worker_threads(n);
         }
+        if let Some(cpus) = self.cpu_affinity.clone() {
+            builder.on_thread_start(move || super::threading::pin_current_thread(&cpus));
+        }
         builder.build()
     }
 
@@ -398,11 +501,15 @@ impl PipelineBuilder {
             // but it will be unusable. To avoid that, we store the error here and don't return Some(runtime).
             static THREAD_START_FAILURE: Mutex<Option<io::Error>> = Mutex::new(None);
 
+            let cpu_affinity = self.cpu_affinity.clone();
             let mut builder = tokio::runtime::Builder::new_multi_thread();
             builder
                 .enable_all()
                 .worker_threads(n_rt_sources)
-                .on_thread_start(|| {
+                .on_thread_start(move || {
+                    if let Some(cpus) = &cpu_affinity {
+                        super::threading::pin_current_thread(cpus);
+                    }
                     if let Err(e) = super::threading::increase_thread_priority() {
                         let mut failure = THREAD_START_FAILURE.lock().unwrap();
                         if failure.is_none() {
@@ -0,0 +1,162 @@
+//! Last value of each series, shared by the outputs.
+//!
+//! Some outputs only need the current value of each metric, for instance to expose gauges on a dashboard.
+//! Instead of tracking it in every output, they can query the [`LastValueCache`] of the pipeline,
+//! which is available in the [`OutputContext`](super::OutputContext) when enabled with
+//! [`Agent::last_value_cache`](crate::agent::Agent::last_value_cache).
+//!
+//! The cache is disabled by default, because it keeps one measurement per metric and resource.
+//!
+//! ## Eviction
+//! A series (metric and resource) that has not received any measurement for `max_age`,
+//! according to the [`Clock`] of the pipeline, is stale: it is no longer returned by the cache,
+//! and it is removed from memory on the next update of the cache.
+//! This prevents the cache from growing indefinitely when the resources change over time.
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use crate::measurement::{MeasurementBuffer, Timestamp, WrappedMeasurementValue};
+use crate::metrics::RawMetricId;
+use crate::resources::Resource;
+use crate::time::Clock;
+
+/// The last measured value of each metric and resource.
+///
+/// The clones of a `LastValueCache` share the same data.
+#[derive(Clone)]
+pub struct LastValueCache {
+    clock: Arc<dyn Clock>,
+    max_age: Duration,
+    /// Last value of each resource, by metric.
+    /// There are usually few resources per metric, a linear search is enough.
+    series: Arc<RwLock<HashMap<RawMetricId, Vec<LastValue>>>>,
+}
+
+/// The last measurement of a series.
+#[derive(Debug, Clone)]
+pub struct LastValue {
+    pub resource: Resource,
+    pub timestamp: Timestamp,
+    pub value: WrappedMeasurementValue,
+}
+
+impl LastValueCache {
+    pub(crate) fn new(clock: Arc<dyn Clock>, max_age: Duration) -> Self {
+        Self {
+            clock,
+            max_age,
+            series: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// Returns the last value of `metric` for `resource`, unless it is stale.
+    pub fn get(&self, metric: RawMetricId, resource: &Resource) -> Option<LastValue> {
+        let now = self.now();
+        let series = self.series.read().unwrap();
+        let last = series.get(&metric)?.iter().find(|v| &v.resource == resource)?;
+        self.is_fresh(last, now).then(|| last.clone())
+    }
+
+    /// Returns the last values of `metric` for every resource, except the stale ones.
+    pub fn get_all(&self, metric: RawMetricId) -> Vec<LastValue> {
+        let now = self.now();
+        let series = self.series.read().unwrap();
+        match series.get(&metric) {
+            Some(values) => values.iter().filter(|v| self.is_fresh(v, now)).cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Updates the cache with new measurements, and removes the stale series.
+    pub(crate) fn update(&self, measurements: &MeasurementBuffer) {
+        let now = self.now();
+        let mut series = self.series.write().unwrap();
+        for m in measurements.iter() {
+            let values = series.entry(m.metric).or_default();
+            match values.iter_mut().find(|v| v.resource == m.resource) {
+                Some(last) => {
+                    // don't replace a value by an older one
+                    if SystemTime::from(m.timestamp) >= SystemTime::from(last.timestamp) {
+                        last.timestamp = m.timestamp;
+                        last.value = m.value.clone();
+                    }
+                }
+                None => values.push(LastValue {
+                    resource: m.resource.clone(),
+                    timestamp: m.timestamp,
+                    value: m.value.clone(),
+                }),
+            }
+        }
+        for values in series.values_mut() {
+            values.retain(|v| self.is_fresh(v, now));
+        }
+        series.retain(|_, values| !values.is_empty());
+    }
+
+    fn now(&self) -> SystemTime {
+        SystemTime::from(self.clock.now())
+    }
+
+    fn is_fresh(&self, value: &LastValue, now: SystemTime) -> bool {
+        match now.duration_since(SystemTime::from(value.timestamp)) {
+            Ok(age) => age <= self.max_age,
+            Err(_) => true, // in the future
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime};
+
+    use crate::measurement::{MeasurementBuffer, MeasurementPoint, Timestamp, WrappedMeasurementValue};
+    use crate::metrics::RawMetricId;
+    use crate::resources::{Resource, ResourceConsumer};
+    use crate::time::MockClock;
+
+    use super::LastValueCache;
+
+    #[test]
+    fn last_values_and_eviction() {
+        let t0 = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        let clock = MockClock::new(t0);
+        let cache = LastValueCache::new(Arc::new(clock.clone()), Duration::from_secs(10));
+        let point = |secs: u64, pkg: u32, value: u64| {
+            MeasurementPoint::new_untyped(
+                Timestamp::from(t0 + Duration::from_secs(secs)),
+                RawMetricId(0),
+                Resource::CpuPackage { id: pkg },
+                ResourceConsumer::LocalMachine,
+                WrappedMeasurementValue::U64(value),
+            )
+        };
+        let value = |pkg: u32| {
+            cache
+                .get(RawMetricId(0), &Resource::CpuPackage { id: pkg })
+                .map(|v| v.value.as_u64().unwrap())
+        };
+
+        cache.update(&MeasurementBuffer::from(vec![point(0, 0, 1), point(0, 1, 10)]));
+        assert_eq!(value(0), Some(1));
+        assert_eq!(value(1), Some(10));
+        assert!(cache.get(RawMetricId(1), &Resource::CpuPackage { id: 0 }).is_none());
+
+        // the last value wins, older values are ignored
+        clock.advance(Duration::from_secs(8));
+        cache.update(&MeasurementBuffer::from(vec![point(8, 0, 3), point(5, 0, 2)]));
+        assert_eq!(value(0), Some(3));
+        assert_eq!(cache.get_all(RawMetricId(0)).len(), 2);
+
+        // package 1 becomes stale
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(value(0), Some(3));
+        assert_eq!(value(1), None);
+        assert_eq!(cache.get_all(RawMetricId(0)).len(), 1);
+        cache.update(&MeasurementBuffer::new());
+        assert_eq!(cache.series.read().unwrap()[&RawMetricId(0)].len(), 1);
+    }
+}
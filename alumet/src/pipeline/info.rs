@@ -0,0 +1,157 @@
+//! Metadata about the agent, for the provenance of the measurements.
+//!
+//! When enabled with [`Agent::emit_agent_info`](crate::agent::Agent::emit_agent_info),
+//! the pipeline contains a source that produces the following measurements once, when the pipeline starts:
+//!
+//! - `alumet_agent_info`: one point of value 1, with the attributes `alumet_version`,
+//!   `os` and `arch` (the operating system and CPU architecture of the machine), and `hostname`
+//!   (the name of the machine, omitted if it cannot be obtained).
+//! - `alumet_plugin_info`: one point of value 1 per plugin, with the attributes
+//!   `plugin` (name of the plugin) and `plugin_version`.
+//!
+//! Since the measurements only contain numbers, the metadata is stored in the attributes.
+//! The node on which the agent runs is identified by the global attribute
+//! [`NODE_ID_ATTRIBUTE`](crate::agent::NODE_ID_ATTRIBUTE), if set.
+
+use std::marker::PhantomData;
+
+use anyhow::anyhow;
+
+use crate::measurement::{
+    AttributeValue, MeasurementBuffer, MeasurementPoint, Timestamp, WrappedMeasurementType,
+};
+use crate::metrics::{Metric, MetricRegistry, TypedMetricId};
+use crate::plugin::util::system_hostname;
+use crate::plugin::version::Version;
+use crate::resources::{Resource, ResourceConsumer};
+use crate::units::Unit;
+
+use super::builder::{AutonomousSourceBuilder, PipelineBuilder};
+
+pub const AGENT_INFO_METRIC: &str = "alumet_agent_info";
+pub const PLUGIN_INFO_METRIC: &str = "alumet_plugin_info";
+
+/// The metadata of the agent, which is sent once to the pipeline.
+pub(crate) struct AgentInfo {
+    agent_metric: TypedMetricId<u64>,
+    plugin_metric: TypedMetricId<u64>,
+    /// Name and version of each plugin.
+    plugins: Vec<(String, String)>,
+    /// Name of the machine, if it can be obtained.
+    hostname: Option<String>,
+}
+
+impl AgentInfo {
+    /// Registers the info metrics and gathers the metadata.
+    pub fn new(registry: &mut MetricRegistry, plugins: Vec<(String, String)>) -> Self {
+        let mut register = |name: &str, description: &str| {
+            let m = Metric {
+                name: name.to_owned(),
+                description: description.to_owned(),
+                value_type: WrappedMeasurementType::U64,
+                unit: Unit::Unity.into(),
+            };
+            TypedMetricId(registry.register_infallible(m, "alumet"), PhantomData)
+        };
+        let hostname = match system_hostname() {
+            Ok(name) => Some(name),
+            Err(e) => {
+                log::warn!("Unable to get the hostname, it will be missing from {AGENT_INFO_METRIC}: {e}");
+                None
+            }
+        };
+        Self {
+            agent_metric: register(AGENT_INFO_METRIC, "Information about the Alumet agent, in the attributes."),
+            plugin_metric: register(PLUGIN_INFO_METRIC, "Information about a plugin, in the attributes."),
+            plugins,
+            hostname,
+        }
+    }
+
+    /// Returns the measurements that describe the agent.
+    fn measurements(&self, timestamp: Timestamp) -> MeasurementBuffer {
+        let point = |metric| {
+            MeasurementPoint::new(
+                timestamp,
+                metric,
+                Resource::LocalMachine,
+                ResourceConsumer::LocalMachine,
+                1u64,
+            )
+        };
+        let mut agent = point(self.agent_metric)
+            .with_attr("alumet_version", AttributeValue::String(Version::alumet().to_string()))
+            .with_attr("os", std::env::consts::OS)
+            .with_attr("arch", std::env::consts::ARCH);
+        if let Some(hostname) = &self.hostname {
+            agent = agent.with_attr("hostname", AttributeValue::String(hostname.clone()));
+        }
+        let mut buffer = MeasurementBuffer::with_capacity(1 + self.plugins.len());
+        buffer.push(agent);
+        for (name, version) in &self.plugins {
+            buffer.push(
+                point(self.plugin_metric)
+                    .with_attr("plugin", AttributeValue::String(name.clone()))
+                    .with_attr("plugin_version", AttributeValue::String(version.clone())),
+            );
+        }
+        buffer
+    }
+}
+
+/// Adds a source that sends the metadata of the agent once, when the pipeline starts.
+///
+/// It is an autonomous source that stops right after sending the measurements.
+pub(crate) fn add_info_source(builder: &mut PipelineBuilder, plugins: Vec<(String, String)>) {
+    let info = AgentInfo::new(&mut builder.metrics, plugins);
+    let name = builder.namegen.deduplicate(String::from("alumet/info"), false);
+    builder.autonomous_sources.push(AutonomousSourceBuilder {
+        name,
+        plugin: String::from("alumet"),
+        build: Box::new(|_: &_, _, tx| {
+            Box::pin(async move {
+                let measurements = info.measurements(Timestamp::now());
+                tx.send(measurements)
+                    .await
+                    .map_err(|_| anyhow!("the pipeline has stopped before receiving the agent info"))
+            })
+        }),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::measurement::{AttributeValue, Timestamp};
+    use crate::metrics::MetricRegistry;
+
+    use super::AgentInfo;
+
+    #[test]
+    fn info_measurements() {
+        let mut registry = MetricRegistry::new();
+        let plugins = vec![
+            (String::from("rapl"), String::from("0.1.0")),
+            (String::from("csv"), String::from("0.2.0")),
+        ];
+        let info = AgentInfo::new(&mut registry, plugins);
+        assert_eq!(registry.len(), 2);
+
+        let buf = info.measurements(Timestamp::now());
+        assert_eq!(buf.len(), 3);
+        let plugin_names: Vec<_> = buf
+            .iter()
+            .filter(|p| p.metric == info.plugin_metric.0)
+            .flat_map(|p| p.attributes().filter(|(k, _)| *k == "plugin").map(|(_, v)| v.clone()))
+            .map(|v| match v {
+                AttributeValue::String(name) => name,
+                other => panic!("unexpected attribute value {other:?}"),
+            })
+            .collect();
+        assert_eq!(plugin_names, vec!["rapl", "csv"]);
+
+        let agent = buf.iter().find(|p| p.metric == info.agent_metric.0).unwrap();
+        let keys: Vec<&str> = agent.attributes().map(|(k, _)| k).collect();
+        assert!(keys.contains(&"alumet_version"));
+        assert_eq!(keys.contains(&"hostname"), info.hostname.is_some());
+    }
+}
@@ -2,34 +2,147 @@
 
 use std::fmt;
 
-use crate::{measurement::{MeasurementAccumulator, MeasurementBuffer, Timestamp}, metrics::MetricRegistry};
+use crate::{measurement::{MeasurementAccumulator, MeasurementBuffer, Timestamp, WrappedMeasurementType}, metrics::{MetricId, MetricRegistry, RawMetricId}};
 
 pub mod runtime;
 pub mod builder;
-mod threading;
+pub mod threading;
 mod scoped;
 pub mod trigger;
+pub mod transforms;
+pub mod outputs;
+pub mod cache;
+pub mod overhead;
+pub mod info;
+pub mod branches;
+pub mod probe;
 
 /// Produces measurements related to some metrics.
 pub trait Source: Send {
     /// Polls the source for new measurements.
+    ///
+    /// ## Timestamps
+    /// `timestamp` is the time of the poll, given by the clock of the pipeline. It is a suggestion:
+    /// the pipeline keeps the timestamp of each [`MeasurementPoint`](crate::measurement::MeasurementPoint)
+    /// as it is, and never replaces it by the time of the poll. A source that reads its values at different times
+    /// within one poll can give each point the time of its own read, for instance with [`Timestamp::now`].
+    /// Such timestamps do not follow the clock of the pipeline, which is a mock clock in some tests.
     fn poll(&mut self, measurements: &mut MeasurementAccumulator, timestamp: Timestamp) -> Result<(), PollError>;
+
+    /// Stops the source.
+    ///
+    /// This method is called by the pipeline when the source is removed or when the pipeline stops,
+    /// after the last call to [`poll`](Source::poll) and before the source is dropped.
+    /// It allows the source to release its resources deterministically and to report errors,
+    /// which `Drop` cannot do.
+    ///
+    /// The default implementation does nothing.
+    fn stop(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Returns the metrics that the source produces, if it declares them.
+    ///
+    /// The declaration is informative: it is logged when the pipeline is built, and the
+    /// [startup probe](probe) checks that the source does not produce other metrics.
+    /// A source may produce only some of its declared metrics, for instance because a device is missing.
+    ///
+    /// The default implementation returns an empty list, which means that the metrics are not declared.
+    fn declared_metrics(&self) -> Vec<RawMetricId> {
+        Vec::new()
+    }
 }
 
 /// Transforms measurements.
 pub trait Transform: Send {
     /// Applies the transform on the measurements.
     fn apply(&mut self, measurements: &mut MeasurementBuffer) -> Result<(), TransformError>;
+
+    /// Returns the metrics that the transform applies to, or `None` if it needs to see every measurement.
+    ///
+    /// When the transform declares its metrics, the pipeline only gives the measurements of these metrics
+    /// to [`apply`](Transform::apply), and does not call it at all if the buffer contains none of them.
+    /// The measurements that `apply` keeps are put back at their place in the buffer, and the measurements
+    /// that it adds are put at the end, as if the transform had been applied to the whole buffer.
+    /// This saves a scan of the whole buffer in the transforms that only deal with a few metrics.
+    ///
+    /// The pipeline calls this method once, when it starts. The default implementation returns `None`.
+    fn input_metrics(&self) -> Option<Vec<RawMetricId>> {
+        None
+    }
 }
 
 /// Exports measurements to an external entity, like a file or a database.
 pub trait Output: Send {
     /// Writes the measurements to the output.
     fn write(&mut self, measurements: &MeasurementBuffer, ctx: &OutputContext) -> Result<(), WriteError>;
+
+    /// Returns the kinds of values that the output can write.
+    ///
+    /// Before calling [`write`](Self::write), the output stage converts the values of the other kinds when the
+    /// conversion is exact, and skips the measurements that cannot be converted (see [`outputs::ValueKindFilter`]).
+    /// By default, every kind is supported.
+    fn supported_value_kinds(&self) -> &'static [WrappedMeasurementType] {
+        &[WrappedMeasurementType::F64, WrappedMeasurementType::U64]
+    }
+
+    /// Finalizes the output at the clean end of the stream of measurements.
+    ///
+    /// This method is called once, when the output stops normally, for instance when the pipeline is shut down
+    /// at the end of [`run_for`](runtime::RunningPipeline::run_for), after the last call to [`write`](Self::write).
+    /// It allows the output to write what can only be written at the end of the data, like the footer or the index
+    /// of a file. It is not called when the output stops because of an error.
+    ///
+    /// ## Ordering
+    /// `finalize` (on a clean end only), then [`stop`](Self::stop), then the output is dropped.
+    ///
+    /// The default implementation does nothing.
+    fn finalize(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Stops the output.
+    ///
+    /// This method is called by the pipeline when the output stops, whether the stream of measurements has ended
+    /// normally or because of an error, after the last call to [`write`](Self::write) and [`finalize`](Self::finalize),
+    /// and before the output is dropped. Like [`Source::stop`], it allows the output to release its resources
+    /// deterministically, for instance to close a socket, and to report errors, which `Drop` cannot do.
+    ///
+    /// The default implementation does nothing.
+    fn stop(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
 }
 
 pub struct OutputContext {
     pub metrics: MetricRegistry,
+    /// Last value of each series, if enabled (see [`cache`]).
+    pub last_values: Option<cache::LastValueCache>,
+    /// Sequence number of the buffer that is being written, if enabled.
+    ///
+    /// The transform step numbers the buffers that it sends to the outputs, starting at 0.
+    /// Every output sees the same number for the same buffer, and a gap means that the output
+    /// has missed some buffers (because it was lagging behind). The counter wraps around to 0
+    /// after `u64::MAX`, which in practice never happens.
+    pub sequence_number: Option<u64>,
+}
+
+impl OutputContext {
+    /// Creates a context with the given metrics, without last values and sequence number.
+    pub fn new(metrics: MetricRegistry) -> Self {
+        Self {
+            metrics,
+            last_values: None,
+            sequence_number: None,
+        }
+    }
+
+    /// Returns the name of the plugin that created the metric, if any.
+    ///
+    /// This allows outputs to label the measurements with the plugin that produced them.
+    pub fn metric_plugin<M: MetricId>(&self, id: &M) -> Option<&str> {
+        self.metrics.plugin_of(id)
+    }
 }
 
 // ====== Errors ======
@@ -74,11 +187,28 @@ pub enum WriteError {
 impl fmt::Display for PollError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            PollError::Fatal(e) => write!(f, "fatal error in Source::poll: {e}"),
-            PollError::CanRetry(e) => write!(f, "polling failed (but could work later): {e}"),
+            PollError::Fatal(e) => write!(f, "fatal error in Source::poll: {e:#}"),
+            PollError::CanRetry(e) => write!(f, "polling failed (but could work later): {e:#}"),
         }
     }
 }
+
+impl PollError {
+    /// Returns the underlying error, for instance the [`std::io::Error`] that has been
+    /// converted to a `PollError` by the `?` operator.
+    ///
+    /// `PollError` cannot implement [`std::error::Error`], because it would conflict with the
+    /// conversion from any error type, which `?` relies on. This method provides the same information
+    /// as [`std::error::Error::source`]. The rest of the chain can be obtained from the returned error.
+    pub fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        let err = match self {
+            PollError::Fatal(e) => e,
+            PollError::CanRetry(e) => e,
+        };
+        Some(&**err)
+    }
+}
+
 impl fmt::Display for TransformError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -136,3 +266,33 @@ impl<T, E: Into<anyhow::Error>> WriteRetry<T> for Result<T, E> {
         self.map_err(|e| WriteError::CanRetry(e.into()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use anyhow::Context;
+
+    use super::{PollError, PollRetry};
+
+    fn read_missing_file() -> Result<Vec<u8>, PollError> {
+        Ok(std::fs::read("/this/file/does/not/exist")?)
+    }
+
+    #[test]
+    fn poll_error_source() {
+        let err = read_missing_file().unwrap_err();
+        assert!(matches!(err, PollError::Fatal(_)));
+        let io_err = err.source().unwrap().downcast_ref::<io::Error>().unwrap();
+        assert_eq!(io_err.kind(), io::ErrorKind::NotFound);
+
+        // with some context, the parsing error is the next element of the chain
+        let res: Result<u32, PollError> = "abc".parse::<u32>().context("invalid counter").retry_poll();
+        let err = res.unwrap_err();
+        assert!(matches!(err, PollError::CanRetry(_)));
+        let source = err.source().unwrap();
+        assert_eq!(source.to_string(), "invalid counter");
+        assert!(source.source().unwrap().is::<std::num::ParseIntError>());
+        assert!(err.to_string().ends_with("invalid counter: invalid digit found in string"));
+    }
+}
@@ -0,0 +1,536 @@
+//! Generic outputs that can be used by any plugin.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+
+use crate::measurement::{MeasurementBuffer, Timestamp, WrappedMeasurementType, WrappedMeasurementValue};
+use crate::metrics::RawMetricId;
+use crate::resources::{Resource, ResourceConsumer};
+
+use super::{Output, OutputContext, WriteError};
+
+/// How the values of a metric accumulate over time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MetricKind {
+    /// Each value is the quantity measured since the previous measurement,
+    /// for instance the energy consumed since the previous poll, as measured by the RAPL plugin.
+    #[default]
+    Delta,
+    /// Each value is the total quantity since an arbitrary origin, for instance the value of a hardware counter.
+    Cumulative,
+}
+
+/// Keeps the recent measurements of some metrics, in order to compute their total over a period,
+/// for instance the energy consumed by each CPU package since a given time.
+///
+/// The output is moved to the pipeline, use the [`Totals`] returned by [`totals`](Self::totals) to query it.
+/// The measurements are grouped by metric and resource (the consumers are not distinguished).
+/// Only the measurements of the last `retention` period are kept.
+pub struct TotalsOutput {
+    retention: Duration,
+    state: Arc<Mutex<TotalsState>>,
+}
+
+/// Handle to query the totals computed by a [`TotalsOutput`].
+#[derive(Clone)]
+pub struct Totals {
+    state: Arc<Mutex<TotalsState>>,
+}
+
+#[derive(Default)]
+struct TotalsState {
+    kinds: HashMap<RawMetricId, MetricKind>,
+    /// Measurements of each resource, by metric.
+    /// There are usually few resources per metric, a linear search is enough.
+    series: HashMap<RawMetricId, Vec<(Resource, VecDeque<(SystemTime, f64)>)>>,
+}
+
+impl TotalsOutput {
+    /// Creates an output that applies to no metric, use [`with_metric`](Self::with_metric) to add some.
+    pub fn new(retention: Duration) -> Self {
+        Self {
+            retention,
+            state: Arc::new(Mutex::new(TotalsState::default())),
+        }
+    }
+
+    /// Keeps the measurements of `metric`, whose values accumulate according to `kind`.
+    pub fn with_metric(self, metric: RawMetricId, kind: MetricKind) -> Self {
+        self.state.lock().unwrap().kinds.insert(metric, kind);
+        self
+    }
+
+    /// Returns a handle to query the totals.
+    pub fn totals(&self) -> Totals {
+        Totals {
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl Output for TotalsOutput {
+    fn write(&mut self, measurements: &MeasurementBuffer, _ctx: &OutputContext) -> Result<(), WriteError> {
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        let mut latest: Option<SystemTime> = None;
+        for m in measurements.iter() {
+            if !state.kinds.contains_key(&m.metric) {
+                continue;
+            }
+            let series = state.series.entry(m.metric).or_default();
+            let points = match series.iter_mut().find(|(r, _)| r == &m.resource) {
+                Some((_, points)) => points,
+                None => {
+                    series.push((m.resource.clone(), VecDeque::new()));
+                    &mut series.last_mut().unwrap().1
+                }
+            };
+            let t = m.timestamp.0;
+            points.push_back((t, m.value.as_f64()));
+            latest = latest.max(Some(t));
+        }
+
+        // forget the measurements that are too old
+        if let Some(cutoff) = latest.and_then(|t| t.checked_sub(self.retention)) {
+            for points in state.series.values_mut().flatten().map(|(_, points)| points) {
+                while points.front().is_some_and(|(t, _)| *t < cutoff) {
+                    points.pop_front();
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Totals {
+    /// Returns the total of `metric` for `resource` since `since`, or `None` if there is no measurement
+    /// of this metric and resource.
+    ///
+    /// For [`MetricKind::Delta`] metrics, this is the sum of the values measured after `since`.
+    /// For [`MetricKind::Cumulative`] metrics, this is the difference between the last value and the last value
+    /// measured at or before `since` (or the first value, if there is none). A value that is lower than the previous
+    /// one is considered to be a reset of the counter to zero.
+    ///
+    /// If `since` is older than the retention period of the output, the total only covers the retention period.
+    pub fn total_since(&self, metric: RawMetricId, resource: &Resource, since: Timestamp) -> Option<f64> {
+        let state = self.state.lock().unwrap();
+        let kind = state.kinds.get(&metric)?;
+        let (_, points) = state.series.get(&metric)?.iter().find(|(r, _)| r == resource)?;
+        let since = since.0;
+        match kind {
+            MetricKind::Delta => Some(points.iter().filter(|(t, _)| *t > since).map(|(_, v)| v).sum()),
+            MetricKind::Cumulative => {
+                // the baseline is the last point at or before `since`, or the first point
+                let n_before = points.iter().take_while(|(t, _)| *t <= since).count();
+                let mut values = points.iter().skip(n_before.saturating_sub(1)).map(|(_, v)| *v);
+                let mut prev = values.next()?;
+                let mut total = 0.0;
+                for v in values {
+                    total += if v >= prev { v - prev } else { v };
+                    prev = v;
+                }
+                Some(total)
+            }
+        }
+    }
+}
+
+/// Keeps the recent measurements in memory, in order to query them while the pipeline is running,
+/// for instance to answer "what is the current power of the GPU?" in an application that embeds Alumet.
+///
+/// The output is moved to the pipeline, use the [`RecentMeasurements`] returned by [`handle`](Self::handle)
+/// to query it. The measurements are grouped by metric and resource (the consumers are not distinguished),
+/// and their attributes are not kept. To keep some metrics only, wrap the output in a
+/// [`MetricFilter`](crate::plugin::util::MetricFilter).
+///
+/// ## Memory
+/// The measurements that are older than `max_age`, compared to the newest measurement written to the output,
+/// are forgotten, and so are the series that have no measurement left. With [`with_max_points`](Self::with_max_points),
+/// each series also keeps this number of measurements at most, the newest ones.
+///
+/// ## Concurrency
+/// The handle can be cloned and sent to other threads. The measurements are protected by a [`RwLock`]:
+/// the output takes the write lock once per buffer, and the queries take the read lock, so that they don't block
+/// each other. A query never sees a buffer that is partially written, and returns copies of the measurements,
+/// which do not hold the lock.
+pub struct RecentMeasurementsOutput {
+    max_age: Duration,
+    max_points: Option<usize>,
+    series: Arc<RwLock<RecentSeries>>,
+}
+
+/// Measurements of each resource, by metric, from the oldest to the newest.
+/// There are usually few resources per metric, a linear search is enough.
+type RecentSeries = HashMap<RawMetricId, Vec<(Resource, VecDeque<RecentValue>)>>;
+
+/// Handle to query the measurements kept by a [`RecentMeasurementsOutput`].
+#[derive(Clone)]
+pub struct RecentMeasurements {
+    series: Arc<RwLock<RecentSeries>>,
+}
+
+/// A measurement kept by a [`RecentMeasurementsOutput`].
+#[derive(Debug, Clone)]
+pub struct RecentValue {
+    pub timestamp: Timestamp,
+    pub consumer: ResourceConsumer,
+    pub value: WrappedMeasurementValue,
+}
+
+impl RecentMeasurementsOutput {
+    /// Creates an output that keeps the measurements of the last `max_age` period.
+    pub fn new(max_age: Duration) -> Self {
+        Self {
+            max_age,
+            max_points: None,
+            series: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// Keeps at most `max_points` measurements (at least 1) in each series, in addition to the limit of age.
+    pub fn with_max_points(mut self, max_points: usize) -> Self {
+        self.max_points = Some(max_points.max(1));
+        self
+    }
+
+    /// Returns a handle to query the measurements.
+    pub fn handle(&self) -> RecentMeasurements {
+        RecentMeasurements {
+            series: self.series.clone(),
+        }
+    }
+}
+
+impl Output for RecentMeasurementsOutput {
+    fn write(&mut self, measurements: &MeasurementBuffer, _ctx: &OutputContext) -> Result<(), WriteError> {
+        let mut series = self.series.write().unwrap();
+        let mut latest: Option<SystemTime> = None;
+        for m in measurements.iter() {
+            let resources = series.entry(m.metric).or_default();
+            let points = match resources.iter_mut().find(|(r, _)| r == &m.resource) {
+                Some((_, points)) => points,
+                None => {
+                    resources.push((m.resource.clone(), VecDeque::new()));
+                    &mut resources.last_mut().unwrap().1
+                }
+            };
+            let t = m.timestamp.0;
+            let value = RecentValue {
+                timestamp: m.timestamp,
+                consumer: m.consumer.clone(),
+                value: m.value.clone(),
+            };
+            // keep the points sorted, even if they arrive out of order
+            let i = points.partition_point(|p| p.timestamp.0 <= t);
+            points.insert(i, value);
+            if let Some(max_points) = self.max_points {
+                while points.len() > max_points {
+                    points.pop_front();
+                }
+            }
+            latest = latest.max(Some(t));
+        }
+
+        // forget the measurements that are too old, and the series that become empty
+        if let Some(cutoff) = latest.and_then(|t| t.checked_sub(self.max_age)) {
+            for resources in series.values_mut() {
+                for (_, points) in resources.iter_mut() {
+                    while points.front().is_some_and(|p| p.timestamp.0 < cutoff) {
+                        points.pop_front();
+                    }
+                }
+                resources.retain(|(_, points)| !points.is_empty());
+            }
+            series.retain(|_, resources| !resources.is_empty());
+        }
+        Ok(())
+    }
+}
+
+impl RecentMeasurements {
+    /// Returns the newest measurement of `metric` for `resource`, if any.
+    pub fn latest(&self, metric: RawMetricId, resource: &Resource) -> Option<RecentValue> {
+        self.with_points(metric, resource, |points| points.back().cloned()).flatten()
+    }
+
+    /// Returns the measurements of `metric` for `resource` that are more recent than `since`,
+    /// from the oldest to the newest.
+    pub fn range(&self, metric: RawMetricId, resource: &Resource, since: Timestamp) -> Vec<RecentValue> {
+        let since = since.0;
+        self.with_points(metric, resource, |points| {
+            let start = points.partition_point(|p| p.timestamp.0 <= since);
+            points.range(start..).cloned().collect()
+        })
+        .unwrap_or_default()
+    }
+
+    fn with_points<R>(
+        &self,
+        metric: RawMetricId,
+        resource: &Resource,
+        f: impl FnOnce(&VecDeque<RecentValue>) -> R,
+    ) -> Option<R> {
+        let series = self.series.read().unwrap();
+        let (_, points) = series.get(&metric)?.iter().find(|(r, _)| r == resource)?;
+        Some(f(points))
+    }
+}
+
+/// Adapts the measurements to the kinds of values that an output supports (see [`Output::supported_value_kinds`]).
+///
+/// A value of an unsupported kind is converted when the conversion is exact: an `U64` becomes an `F64`
+/// if it is smaller than 2^53, and an `F64` becomes an `U64` if it is a non-negative integer.
+/// Otherwise, the measurement is skipped: the first skip of the output is logged as a warning,
+/// and the number of skipped measurements is counted.
+pub struct ValueKindFilter {
+    supported: &'static [WrappedMeasurementType],
+    skipped: u64,
+}
+
+impl ValueKindFilter {
+    pub fn new(supported: &'static [WrappedMeasurementType]) -> Self {
+        Self { supported, skipped: 0 }
+    }
+
+    /// Returns true if every kind of value is supported, in which case the measurements are left untouched.
+    pub fn supports_all(&self) -> bool {
+        [WrappedMeasurementType::F64, WrappedMeasurementType::U64]
+            .iter()
+            .all(|kind| self.supported.contains(kind))
+    }
+
+    /// Returns the number of measurements that have been skipped so far.
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+
+    /// Converts or removes the values that `output_name` does not support.
+    pub fn apply(&mut self, measurements: &mut MeasurementBuffer, output_name: &str) {
+        if self.supports_all() {
+            return;
+        }
+        let len_before = measurements.len();
+        let supported = self.supported;
+        measurements.retain(|m| {
+            if supported.contains(&m.value.measurement_type()) {
+                return true;
+            }
+            match convert_value(&m.value, supported) {
+                Some(converted) => {
+                    m.value = converted;
+                    true
+                }
+                None => false,
+            }
+        });
+        let skipped = (len_before - measurements.len()) as u64;
+        if skipped > 0 {
+            if self.skipped == 0 {
+                log::warn!(
+                    "Output {output_name} only supports the values of kind {:?}, the measurements that cannot be converted are skipped.",
+                    self.supported
+                );
+            }
+            self.skipped += skipped;
+            let total = self.skipped;
+            log::debug!("Output {output_name}: {skipped} measurements skipped ({total} in total).");
+        }
+    }
+}
+
+/// Converts a value to one of the `supported` kinds, if the conversion is exact.
+fn convert_value(
+    value: &WrappedMeasurementValue,
+    supported: &[WrappedMeasurementType],
+) -> Option<WrappedMeasurementValue> {
+    const MAX_EXACT_F64: u64 = 1 << f64::MANTISSA_DIGITS;
+    match value {
+        WrappedMeasurementValue::U64(v) if supported.contains(&WrappedMeasurementType::F64) && *v <= MAX_EXACT_F64 => {
+            Some(WrappedMeasurementValue::F64(*v as f64))
+        }
+        WrappedMeasurementValue::F64(v)
+            if supported.contains(&WrappedMeasurementType::U64)
+                && v.fract() == 0.0
+                && *v >= 0.0
+                && *v < u64::MAX as f64 =>
+        {
+            Some(WrappedMeasurementValue::U64(*v as u64))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use crate::measurement::{MeasurementBuffer, MeasurementPoint, Timestamp, WrappedMeasurementValue};
+    use crate::metrics::{MetricRegistry, RawMetricId};
+    use crate::pipeline::{Output, OutputContext};
+    use crate::resources::{Resource, ResourceConsumer};
+
+    use super::{MetricKind, RecentMeasurementsOutput, TotalsOutput, ValueKindFilter};
+    use crate::measurement::WrappedMeasurementType;
+
+    const DELTA: RawMetricId = RawMetricId(0);
+    const COUNTER: RawMetricId = RawMetricId(1);
+
+    fn at(secs: u64) -> Timestamp {
+        Timestamp(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    fn point(metric: RawMetricId, socket: u32, secs: u64, value: f64) -> MeasurementPoint {
+        MeasurementPoint::new_untyped(
+            at(secs),
+            metric,
+            Resource::CpuPackage { id: socket },
+            ResourceConsumer::LocalMachine,
+            WrappedMeasurementValue::F64(value),
+        )
+    }
+
+    fn write(output: &mut dyn Output, points: Vec<MeasurementPoint>) {
+        let ctx = OutputContext::new(MetricRegistry::new());
+        output.write(&MeasurementBuffer::from(points), &ctx).unwrap();
+    }
+
+    #[test]
+    fn delta_and_cumulative() {
+        let mut output = TotalsOutput::new(Duration::from_secs(3600))
+            .with_metric(DELTA, MetricKind::Delta)
+            .with_metric(COUNTER, MetricKind::Cumulative);
+        let totals = output.totals();
+        let pkg0 = Resource::CpuPackage { id: 0 };
+        let pkg1 = Resource::CpuPackage { id: 1 };
+
+        write(
+            &mut output,
+            vec![
+                point(DELTA, 0, 1, 1.5),
+                point(DELTA, 1, 1, 10.0),
+                point(COUNTER, 0, 1, 100.0),
+                point(RawMetricId(2), 0, 1, 1000.0),
+            ],
+        );
+        write(
+            &mut output,
+            vec![
+                point(DELTA, 0, 2, 2.0),
+                point(DELTA, 0, 3, 0.5),
+                point(COUNTER, 0, 2, 130.0),
+                point(COUNTER, 0, 3, 150.0),
+            ],
+        );
+
+        assert_eq!(totals.total_since(DELTA, &pkg0, at(0)), Some(4.0));
+        assert_eq!(totals.total_since(DELTA, &pkg0, at(1)), Some(2.5));
+        assert_eq!(totals.total_since(DELTA, &pkg0, at(3)), Some(0.0));
+        assert_eq!(totals.total_since(DELTA, &pkg1, at(0)), Some(10.0));
+        assert_eq!(totals.total_since(COUNTER, &pkg0, at(0)), Some(50.0));
+        assert_eq!(totals.total_since(COUNTER, &pkg0, at(2)), Some(20.0));
+        assert_eq!(totals.total_since(COUNTER, &pkg1, at(0)), None);
+        assert_eq!(totals.total_since(RawMetricId(2), &pkg0, at(0)), None);
+
+        // the counter is reset
+        write(&mut output, vec![point(COUNTER, 0, 4, 5.0), point(COUNTER, 0, 5, 8.0)]);
+        assert_eq!(totals.total_since(COUNTER, &pkg0, at(2)), Some(20.0 + 5.0 + 3.0));
+    }
+
+    #[test]
+    fn retention() {
+        let mut output = TotalsOutput::new(Duration::from_secs(10)).with_metric(DELTA, MetricKind::Delta);
+        let totals = output.totals();
+        let pkg0 = Resource::CpuPackage { id: 0 };
+
+        write(&mut output, (0..30).map(|t| point(DELTA, 0, t, 1.0)).collect());
+        // only the points from t=19 to t=29 are kept
+        assert_eq!(totals.total_since(DELTA, &pkg0, at(0)), Some(11.0));
+        assert_eq!(totals.total_since(DELTA, &pkg0, at(25)), Some(4.0));
+    }
+
+    #[test]
+    fn recent_measurements() {
+        let mut output = RecentMeasurementsOutput::new(Duration::from_secs(10)).with_max_points(5);
+        let recent = output.handle();
+        let pkg0 = Resource::CpuPackage { id: 0 };
+        let pkg1 = Resource::CpuPackage { id: 1 };
+        let values = |points: Vec<super::RecentValue>| points.iter().map(|p| p.value.as_f64()).collect::<Vec<_>>();
+
+        assert!(recent.latest(DELTA, &pkg0).is_none());
+        // out of order
+        let unordered = vec![
+            point(DELTA, 0, 2, 2.0),
+            point(DELTA, 0, 1, 1.0),
+            point(DELTA, 1, 1, 10.0),
+        ];
+        write(&mut output, unordered);
+        write(&mut output, vec![point(DELTA, 0, 3, 3.0)]);
+        assert_eq!(recent.latest(DELTA, &pkg0).unwrap().value.as_f64(), 3.0);
+        assert_eq!(recent.latest(DELTA, &pkg1).unwrap().timestamp, at(1));
+        assert_eq!(values(recent.range(DELTA, &pkg0, at(0))), vec![1.0, 2.0, 3.0]);
+        assert_eq!(values(recent.range(DELTA, &pkg0, at(2))), vec![3.0]);
+        assert!(recent.range(COUNTER, &pkg0, at(0)).is_empty());
+
+        // at most 5 points per series
+        write(&mut output, (4..=8).map(|t| point(DELTA, 0, t, t as f64)).collect());
+        assert_eq!(values(recent.range(DELTA, &pkg0, at(0))), vec![4.0, 5.0, 6.0, 7.0, 8.0]);
+
+        // too old: package 1 is forgotten
+        write(&mut output, vec![point(DELTA, 0, 15, 15.0)]);
+        let kept = values(recent.range(DELTA, &pkg0, at(0)));
+        assert_eq!(kept, vec![5.0, 6.0, 7.0, 8.0, 15.0]);
+        assert!(recent.latest(DELTA, &pkg1).is_none());
+        assert_eq!(output.series.read().unwrap()[&DELTA].len(), 1);
+    }
+
+    #[test]
+    fn unsupported_value_kinds() {
+        let value_point = |value| {
+            MeasurementPoint::new_untyped(
+                at(1),
+                DELTA,
+                Resource::LocalMachine,
+                ResourceConsumer::LocalMachine,
+                value,
+            )
+        };
+        let buffer = || {
+            MeasurementBuffer::from(vec![
+                value_point(WrappedMeasurementValue::F64(1.5)),
+                value_point(WrappedMeasurementValue::U64(42)),
+                value_point(WrappedMeasurementValue::F64(12.0)),
+                value_point(WrappedMeasurementValue::U64(u64::MAX)),
+                value_point(WrappedMeasurementValue::F64(-3.0)),
+            ])
+        };
+        let values = |buf: &MeasurementBuffer| buf.iter().map(|m| m.value.as_f64()).collect::<Vec<_>>();
+        let kinds = |buf: &MeasurementBuffer| buf.iter().map(|m| m.value.measurement_type()).collect::<Vec<_>>();
+
+        // everything is supported: nothing changes
+        let mut filter = ValueKindFilter::new(&[WrappedMeasurementType::U64, WrappedMeasurementType::F64]);
+        assert!(filter.supports_all());
+        let mut buf = buffer();
+        filter.apply(&mut buf, "test");
+        assert_eq!(buf.len(), 5);
+        assert_eq!(filter.skipped(), 0);
+
+        // only floats: the small integers are converted
+        let mut filter = ValueKindFilter::new(&[WrappedMeasurementType::F64]);
+        let mut buf = buffer();
+        filter.apply(&mut buf, "test");
+        assert_eq!(values(&buf), vec![1.5, 42.0, 12.0, -3.0]);
+        assert_eq!(kinds(&buf), vec![WrappedMeasurementType::F64; 4]);
+        assert_eq!(filter.skipped(), 1);
+
+        // only integers: the non-negative integral floats are converted, and the skips accumulate
+        let mut filter = ValueKindFilter::new(&[WrappedMeasurementType::U64]);
+        for _ in 0..2 {
+            let mut buf = buffer();
+            filter.apply(&mut buf, "test");
+            assert_eq!(values(&buf), vec![42.0, 12.0, u64::MAX as f64]);
+            assert_eq!(kinds(&buf), vec![WrappedMeasurementType::U64; 3]);
+        }
+        assert_eq!(filter.skipped(), 4);
+    }
+}
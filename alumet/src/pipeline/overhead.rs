@@ -0,0 +1,266 @@
+//! Measurement of the overhead of the pipeline itself.
+//!
+//! When enabled with [`Agent::measure_pipeline_overhead`](crate::agent::Agent::measure_pipeline_overhead),
+//! the pipeline registers the following metrics, whose values are durations in nanoseconds:
+//!
+//! - `alumet_poll_duration`: time spent in [`Source::poll`](super::Source::poll),
+//!   one point per poll, with the attribute `source` (name of the source).
+//! - `alumet_transform_duration`: time spent applying all the transforms to a buffer, one point per buffer.
+//! - `alumet_write_duration`: time spent in [`Output::write`](super::Output::write) since the previous
+//!   report, one point per output and per buffer, with the attribute `output` (name of the output).
+//!
+//! It also registers `source_actual_interval_seconds`, the interval that is actually achieved between two polls
+//! of a managed source, in seconds, with the attribute `source`. Because of the scheduling delays, it can differ
+//! from the interval of the trigger: a larger value means that the polling is falling behind. The value is an
+//! exponentially weighted moving average of the elapsed times, reported after each poll (except the first one).
+//!
+//! The consumer of these measurements is the Alumet process.
+//!
+//! Measuring the overhead only requires to read the monotonic clock around the pipeline steps.
+//! The durations of the outputs are accumulated in atomic counters, and reported by the transform step,
+//! which avoids a feedback loop (writing the overhead measurements would produce new overhead measurements).
+
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::measurement::{AttributeValue, MeasurementBuffer, MeasurementPoint, Timestamp};
+use crate::metrics::{Metric, MetricRegistry, TypedMetricId};
+use crate::resources::{Resource, ResourceConsumer};
+use crate::units::{PrefixedUnit, Unit};
+
+use super::builder::ConfiguredOutput;
+
+pub const POLL_DURATION_METRIC: &str = "alumet_poll_duration";
+pub const TRANSFORM_DURATION_METRIC: &str = "alumet_transform_duration";
+pub const WRITE_DURATION_METRIC: &str = "alumet_write_duration";
+pub const ACTUAL_INTERVAL_METRIC: &str = "source_actual_interval_seconds";
+
+/// Weight of the last elapsed time in the moving average of [`ActualInterval`].
+const INTERVAL_EWMA_WEIGHT: f64 = 0.2;
+
+/// The metrics that measure the overhead of the pipeline.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct OverheadMetrics {
+    pub poll: TypedMetricId<u64>,
+    pub transform: TypedMetricId<u64>,
+    pub write: TypedMetricId<u64>,
+    pub actual_interval: TypedMetricId<f64>,
+}
+
+impl OverheadMetrics {
+    /// Registers the overhead metrics.
+    pub fn register(registry: &mut MetricRegistry) -> Self {
+        let mut register = |name: &str, description: &str| {
+            let m = Metric {
+                name: name.to_owned(),
+                description: description.to_owned(),
+                value_type: crate::measurement::WrappedMeasurementType::U64,
+                unit: PrefixedUnit::nano(Unit::Second),
+            };
+            TypedMetricId(registry.register_infallible(m, "alumet"), PhantomData)
+        };
+        Self {
+            poll: register(POLL_DURATION_METRIC, "Time spent polling a source."),
+            transform: register(
+                TRANSFORM_DURATION_METRIC,
+                "Time spent applying the transforms to a measurement buffer.",
+            ),
+            write: register(WRITE_DURATION_METRIC, "Time spent writing measurements in an output."),
+            actual_interval: TypedMetricId(
+                registry.register_infallible(
+                    Metric {
+                        name: ACTUAL_INTERVAL_METRIC.to_owned(),
+                        description: String::from("Moving average of the time elapsed between two polls of a source."),
+                        value_type: crate::measurement::WrappedMeasurementType::F64,
+                        unit: PrefixedUnit::from(Unit::Second),
+                    },
+                    "alumet",
+                ),
+                PhantomData,
+            ),
+        }
+    }
+}
+
+/// Moving average of the time elapsed between two polls of a source.
+#[derive(Debug, Default)]
+pub(crate) struct ActualInterval {
+    last_poll: Option<Instant>,
+    /// Average interval, in seconds.
+    average: Option<f64>,
+}
+
+impl ActualInterval {
+    /// Records a poll that started at `now`, and returns the updated average interval in seconds,
+    /// or `None` if this is the first poll.
+    pub fn update(&mut self, now: Instant) -> Option<f64> {
+        let elapsed = now.duration_since(self.last_poll.replace(now)?).as_secs_f64();
+        let average = match self.average {
+            Some(avg) => avg + INTERVAL_EWMA_WEIGHT * (elapsed - avg),
+            None => elapsed,
+        };
+        self.average = Some(average);
+        Some(average)
+    }
+
+    /// Forgets the previous poll, for instance when the source is paused, so that the pause does not count.
+    pub fn restart(&mut self) {
+        self.last_poll = None;
+    }
+}
+
+/// Overhead state of the transform step, which reports the durations of the transforms and of the outputs.
+pub(crate) struct TransformOverhead {
+    metrics: OverheadMetrics,
+    /// Accumulated write duration of each output, in nanoseconds, by output name.
+    write_durations: Vec<(String, Arc<AtomicU64>)>,
+}
+
+impl TransformOverhead {
+    /// Creates the overhead state of the transforms, and a write counter for each output.
+    pub fn new(metrics: OverheadMetrics, outputs: &[ConfiguredOutput]) -> Self {
+        let write_durations = outputs
+            .iter()
+            .map(|o| (o.name.clone(), Arc::new(AtomicU64::new(0))))
+            .collect();
+        Self {
+            metrics,
+            write_durations,
+        }
+    }
+
+    /// Returns the write counter of the `i`-th output.
+    pub fn write_counter(&self, i: usize) -> Arc<AtomicU64> {
+        self.write_durations[i].1.clone()
+    }
+
+    /// Pushes the duration of the transforms, and the write durations accumulated since the previous call.
+    pub fn push_measurements(&self, measurements: &mut MeasurementBuffer, transform_duration: Duration) {
+        let timestamp = Timestamp::now();
+        measurements.push(MeasurementPoint::new(
+            timestamp,
+            self.metrics.transform,
+            Resource::LocalMachine,
+            self_consumer(),
+            duration_nanos(transform_duration),
+        ));
+        for (output_name, counter) in &self.write_durations {
+            let nanos = counter.swap(0, Ordering::Relaxed);
+            if nanos > 0 {
+                measurements.push(
+                    MeasurementPoint::new(
+                        timestamp,
+                        self.metrics.write,
+                        Resource::LocalMachine,
+                        self_consumer(),
+                        nanos,
+                    )
+                    .with_attr("output", AttributeValue::String(output_name.clone())),
+                );
+            }
+        }
+    }
+}
+
+/// Returns a measurement point that contains the duration of a poll.
+pub(crate) fn poll_measurement(
+    metric: TypedMetricId<u64>,
+    timestamp: Timestamp,
+    source_name: &str,
+    duration: Duration,
+) -> MeasurementPoint {
+    MeasurementPoint::new(
+        timestamp,
+        metric,
+        Resource::LocalMachine,
+        self_consumer(),
+        duration_nanos(duration),
+    )
+    .with_attr("source", AttributeValue::String(source_name.to_owned()))
+}
+
+/// Returns a measurement point that contains the average interval between the polls of a source.
+pub(crate) fn interval_measurement(
+    metric: TypedMetricId<f64>,
+    timestamp: Timestamp,
+    source_name: &str,
+    interval_secs: f64,
+) -> MeasurementPoint {
+    MeasurementPoint::new(
+        timestamp,
+        metric,
+        Resource::LocalMachine,
+        self_consumer(),
+        interval_secs,
+    )
+    .with_attr("source", AttributeValue::String(source_name.to_owned()))
+}
+
+/// Adds `duration` to the write counter of an output.
+pub(crate) fn add_write_duration(counter: &AtomicU64, duration: Duration) {
+    counter.fetch_add(duration_nanos(duration), Ordering::Relaxed);
+}
+
+fn self_consumer() -> ResourceConsumer {
+    ResourceConsumer::Process {
+        pid: std::process::id(),
+    }
+}
+
+fn duration_nanos(d: Duration) -> u64 {
+    u64::try_from(d.as_nanos()).unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::measurement::MeasurementBuffer;
+    use crate::metrics::MetricRegistry;
+
+    use super::{ActualInterval, OverheadMetrics, TransformOverhead};
+
+    #[test]
+    fn write_durations_are_reset() {
+        let mut registry = MetricRegistry::new();
+        let metrics = OverheadMetrics::register(&mut registry);
+        assert_eq!(registry.len(), 4);
+
+        let overhead = TransformOverhead {
+            metrics,
+            write_durations: vec![(String::from("out"), Default::default())],
+        };
+        super::add_write_duration(&overhead.write_counter(0), Duration::from_nanos(40));
+        super::add_write_duration(&overhead.write_counter(0), Duration::from_nanos(2));
+
+        let mut buf = MeasurementBuffer::new();
+        overhead.push_measurements(&mut buf, Duration::from_nanos(10));
+        let values: Vec<_> = buf.iter().map(|p| (p.metric, p.value.as_u64())).collect();
+        assert_eq!(values, vec![(metrics.transform.0, Some(10)), (metrics.write.0, Some(42))]);
+
+        // the write durations have been reported, they are not reported again
+        let mut buf = MeasurementBuffer::new();
+        overhead.push_measurements(&mut buf, Duration::from_nanos(5));
+        assert_eq!(buf.len(), 1);
+    }
+
+    #[test]
+    fn actual_interval_average() {
+        let t0 = Instant::now();
+        let at = |millis| t0 + Duration::from_millis(millis);
+        let mut interval = ActualInterval::default();
+        assert_eq!(interval.update(at(0)), None);
+        assert_eq!(interval.update(at(100)), Some(0.1));
+        // one late poll moves the average by 20% of the difference
+        let avg = interval.update(at(300)).unwrap();
+        assert!((avg - 0.12).abs() < 1e-9, "{avg}");
+
+        // the pause is not counted
+        interval.restart();
+        assert_eq!(interval.update(at(10_000)), None);
+        let avg = interval.update(at(10_100)).unwrap();
+        assert!((avg - 0.116).abs() < 1e-9, "{avg}");
+    }
+}
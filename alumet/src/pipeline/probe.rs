@@ -0,0 +1,286 @@
+//! Startup probe: a trial poll of every managed source, when the pipeline is built.
+//!
+//! A misconfigured source, for instance a source that reads a file that does not exist or that requires
+//! a permission that the agent lacks, usually fails on its first poll. The probe catches this failure when
+//! the agent starts, instead of after the deployment. It is enabled with
+//! [`Agent::startup_probe`](crate::agent::Agent::startup_probe).
+//!
+//! A poll that succeeds without producing any measurement is not a failure: the sources that compute the
+//! difference between two readings of a counter, like the RAPL source, have nothing to report on their first poll.
+//!
+//! The measurements produced by the probe are not discarded, they are sent to the pipeline before the first
+//! regular poll. The autonomous sources are not probed, because they are not polled by the pipeline.
+//!
+//! If a source [declares its metrics](Source::declared_metrics), the probe also checks that it does not produce
+//! any other metric, and logs a warning otherwise (with any policy except `Disabled`).
+
+use fxhash::FxHashSet;
+use tokio::sync::mpsc;
+
+use crate::measurement::{MeasurementBuffer, Timestamp};
+use crate::metrics::{MetricRegistry, RawMetricId};
+use crate::time::Clock;
+
+use super::builder::{ConfiguredSource, ElementType, PipelineBuildError};
+use super::{PollError, Source};
+
+/// Whether to probe the sources when the pipeline is built, and what to do when a probe fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StartupProbe {
+    /// Don't probe the sources.
+    #[default]
+    Disabled,
+    /// Log a warning for each source that fails, and continue.
+    ///
+    /// The sources that fail with [`PollError::Fatal`] are removed from the pipeline, like they would be
+    /// on their first regular poll. The other sources are kept.
+    Warn,
+    /// Fail to build the pipeline if a source fails.
+    Error,
+}
+
+/// The outcome of the trial poll of a source.
+#[derive(Debug)]
+pub enum ProbeOutcome {
+    /// The poll succeeded and produced this number of measurements.
+    Measured(usize),
+    /// The poll succeeded but produced no measurement, which is normal for some sources.
+    NoData,
+    /// The poll failed.
+    Failed(PollError),
+}
+
+/// Polls `source` once, and returns the outcome with the measurements that have been produced.
+pub fn probe_source(source: &mut dyn Source, timestamp: Timestamp) -> (ProbeOutcome, MeasurementBuffer) {
+    let mut buffer = MeasurementBuffer::new();
+    let outcome = match source.poll(&mut buffer.as_accumulator(), timestamp) {
+        Ok(()) if buffer.is_empty() => ProbeOutcome::NoData,
+        Ok(()) => ProbeOutcome::Measured(buffer.len()),
+        Err(e) => ProbeOutcome::Failed(e),
+    };
+    (outcome, buffer)
+}
+
+/// Returns the metrics of `measurements` that `source` does not declare, without duplicates.
+///
+/// Returns an empty list if the source does not declare its metrics.
+pub fn undeclared_metrics(source: &dyn Source, measurements: &MeasurementBuffer) -> Vec<RawMetricId> {
+    let declared: FxHashSet<RawMetricId> = source.declared_metrics().into_iter().collect();
+    if declared.is_empty() {
+        return Vec::new();
+    }
+    let mut seen = FxHashSet::default();
+    measurements
+        .iter()
+        .map(|m| m.metric)
+        .filter(|id| !declared.contains(id) && seen.insert(*id))
+        .collect()
+}
+
+/// Probes the sources, and sends their measurements to `tx`.
+///
+/// Must be called in the context of the runtime of the pipeline, because some sources rely on it.
+pub(super) fn probe_sources(
+    sources: &mut Vec<ConfiguredSource>,
+    policy: StartupProbe,
+    clock: &dyn Clock,
+    metrics: &MetricRegistry,
+    tx: &mpsc::Sender<MeasurementBuffer>,
+) -> Result<(), PipelineBuildError> {
+    log::info!("Probing {} sources...", sources.len());
+    let mut first_failure = None;
+    let mut kept = Vec::with_capacity(sources.len());
+    for mut src in sources.drain(..) {
+        let name = &src.name;
+        let (outcome, measurements) = probe_source(src.source.as_mut(), clock.now());
+        match outcome {
+            ProbeOutcome::Measured(n) => log::debug!("Probe of {name} succeeded with {n} measurements."),
+            ProbeOutcome::NoData => log::debug!("Probe of {name} succeeded without any measurement."),
+            ProbeOutcome::Failed(err) => {
+                let (err, fatal) = match err {
+                    PollError::Fatal(e) => (e, true),
+                    PollError::CanRetry(e) => (e, false),
+                };
+                let err = err.context(format!("startup probe of {name} failed"));
+                if policy == StartupProbe::Error {
+                    log::error!("{err:#}");
+                    first_failure.get_or_insert((err, src.plugin_name.clone()));
+                } else if fatal {
+                    log::warn!("{err:#}. The source is removed from the pipeline.");
+                    if let Err(stop_err) = src.source.stop() {
+                        log::error!("Error while stopping {name}: {stop_err:?}");
+                    }
+                    continue;
+                } else {
+                    log::warn!("{err:#}. The source will be polled again when the pipeline starts.");
+                }
+            }
+        }
+        let undeclared = undeclared_metrics(src.source.as_ref(), &measurements);
+        if !undeclared.is_empty() {
+            let names = metric_names(metrics, &undeclared);
+            log::warn!("Source {name} produces metrics that it does not declare: {names}");
+        }
+        if !measurements.is_empty() {
+            if let Err(e) = tx.try_send(measurements) {
+                log::error!("Could not send the measurements of the probe of {name}: {e}");
+            }
+        }
+        kept.push(src);
+    }
+    *sources = kept;
+    match first_failure {
+        Some((err, plugin)) => Err(PipelineBuildError::ElementBuild(err, ElementType::Source, plugin)),
+        None => Ok(()),
+    }
+}
+
+/// Returns the names of the metrics, separated by commas. The metrics that are not registered are written as ids.
+pub(super) fn metric_names(metrics: &MetricRegistry, ids: &[RawMetricId]) -> String {
+    let names: Vec<String> = ids
+        .iter()
+        .map(|id| match metrics.with_id(id) {
+            Some(metric) => metric.name.clone(),
+            None => format!("{id:?}"),
+        })
+        .collect();
+    names.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::marker::PhantomData;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use anyhow::anyhow;
+    use tokio::sync::mpsc;
+
+    use crate::measurement::{MeasurementAccumulator, MeasurementBuffer, MeasurementPoint, Timestamp};
+    use crate::metrics::{MetricRegistry, RawMetricId, TypedMetricId};
+    use crate::pipeline::builder::ConfiguredSource;
+    use crate::pipeline::runtime::SourceHandle;
+    use crate::pipeline::trigger::TriggerSpec;
+    use crate::pipeline::{PollError, Source};
+    use crate::resources::{Resource, ResourceConsumer};
+    use crate::time::MockClock;
+
+    use super::{probe_source, probe_sources, undeclared_metrics, ProbeOutcome, StartupProbe};
+
+    enum TestSource {
+        Measuring,
+        /// Like a counter, which has nothing to report on the first poll.
+        Counter(bool),
+        Retry,
+        Fatal,
+    }
+
+    impl Source for TestSource {
+        fn poll(&mut self, measurements: &mut MeasurementAccumulator, timestamp: Timestamp) -> Result<(), PollError> {
+            let metric: TypedMetricId<u64> = TypedMetricId(RawMetricId(1), PhantomData);
+            let consumer = ResourceConsumer::LocalMachine;
+            let point = MeasurementPoint::new(timestamp, metric, Resource::LocalMachine, consumer, 1_u64);
+            match self {
+                TestSource::Measuring => measurements.push(point),
+                TestSource::Counter(first) if *first => *first = false,
+                TestSource::Counter(_) => measurements.push(point),
+                TestSource::Retry => return Err(PollError::CanRetry(anyhow!("device busy"))),
+                TestSource::Fatal => return Err(PollError::Fatal(anyhow!("no such file"))),
+            }
+            Ok(())
+        }
+    }
+
+    /// Declares some metrics, and produces a point of the metric 1.
+    struct DeclaringSource(Vec<RawMetricId>);
+
+    impl Source for DeclaringSource {
+        fn poll(&mut self, measurements: &mut MeasurementAccumulator, timestamp: Timestamp) -> Result<(), PollError> {
+            TestSource::Measuring.poll(measurements, timestamp)
+        }
+
+        fn declared_metrics(&self) -> Vec<RawMetricId> {
+            self.0.clone()
+        }
+    }
+
+    fn configured(name: &str, source: TestSource) -> ConfiguredSource {
+        ConfiguredSource {
+            source: Box::new(source),
+            name: name.to_owned(),
+            plugin_name: String::from("test"),
+            handle: SourceHandle::new(),
+            trigger_provider: TriggerSpec::at_interval(Duration::from_secs(1)),
+        }
+    }
+
+    #[test]
+    fn outcomes() {
+        let t = Timestamp::now();
+        let (outcome, buf) = probe_source(&mut TestSource::Measuring, t);
+        assert!(matches!(outcome, ProbeOutcome::Measured(1)));
+        assert_eq!(buf.len(), 1);
+
+        let mut counter = TestSource::Counter(true);
+        assert!(matches!(probe_source(&mut counter, t).0, ProbeOutcome::NoData));
+        assert!(matches!(probe_source(&mut counter, t).0, ProbeOutcome::Measured(1)));
+
+        let (outcome, buf) = probe_source(&mut TestSource::Retry, t);
+        assert!(matches!(outcome, ProbeOutcome::Failed(PollError::CanRetry(_))));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn declared_metrics() {
+        let t = Timestamp::now();
+        let mut buf = MeasurementBuffer::new();
+        TestSource::Measuring.poll(&mut buf.as_accumulator(), t).unwrap();
+        TestSource::Measuring.poll(&mut buf.as_accumulator(), t).unwrap();
+
+        // not declared: nothing to check
+        assert!(undeclared_metrics(&TestSource::Measuring, &buf).is_empty());
+        // declared
+        let source = DeclaringSource(vec![RawMetricId(1), RawMetricId(2)]);
+        assert!(undeclared_metrics(&source, &buf).is_empty());
+        // declared, but not the metric that is produced
+        let source = DeclaringSource(vec![RawMetricId(2)]);
+        assert_eq!(undeclared_metrics(&source, &buf), vec![RawMetricId(1)]);
+        let (outcome, buf) = probe_source(&mut DeclaringSource(vec![RawMetricId(2)]), t);
+        assert!(matches!(outcome, ProbeOutcome::Measured(1)));
+        assert_eq!(buf.len(), 1);
+    }
+
+    #[test]
+    fn policies() {
+        let clock = MockClock::new(UNIX_EPOCH);
+        let metrics = MetricRegistry::new();
+        let (tx, mut rx) = mpsc::channel(16);
+        let all_sources = || {
+            vec![
+                configured("measuring", TestSource::Measuring),
+                configured("counter", TestSource::Counter(true)),
+                configured("retry", TestSource::Retry),
+                configured("fatal", TestSource::Fatal),
+            ]
+        };
+
+        // warn: the fatal source is removed, the others are kept
+        let mut sources = all_sources();
+        probe_sources(&mut sources, StartupProbe::Warn, &clock, &metrics, &tx).unwrap();
+        let names: Vec<&str> = sources.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["measuring", "counter", "retry"]);
+        // only the source that has measured something has sent a buffer
+        assert_eq!(rx.try_recv().unwrap().len(), 1);
+        assert!(rx.try_recv().is_err());
+
+        // error: the first failure is returned
+        let mut sources = all_sources();
+        let err = probe_sources(&mut sources, StartupProbe::Error, &clock, &metrics, &tx).unwrap_err();
+        assert!(err.to_string().contains("startup probe of retry failed"), "{err}");
+
+        // no data is not a failure
+        let mut sources = vec![configured("counter", TestSource::Counter(true))];
+        probe_sources(&mut sources, StartupProbe::Error, &clock, &metrics, &tx).unwrap();
+        assert_eq!(sources.len(), 1);
+    }
+}
@@ -1,12 +1,14 @@
 //! Implementation of the measurement pipeline.
 
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::ops::BitOrAssign;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, Context};
+use fxhash::FxHashSet;
 
 use tokio::sync::mpsc::error::TrySendError;
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
@@ -16,19 +18,23 @@ use tokio::time::timeout;
 use tokio::{runtime::Runtime, sync::watch};
 use tokio_util::sync::CancellationToken;
 
-use crate::measurement::Timestamp;
+use crate::measurement::AttributeValue;
 use crate::metrics::{Metric, RawMetricId};
+use crate::pipeline::cache::LastValueCache;
+use crate::pipeline::outputs::ValueKindFilter;
+use crate::pipeline::overhead::{self, ActualInterval, OverheadMetrics, TransformOverhead};
 use crate::pipeline::scoped;
 use crate::pipeline::trigger::TriggerReason;
+use crate::time::Clock;
 use crate::{
     measurement::MeasurementBuffer,
     metrics::MetricRegistry,
-    pipeline::{Output, Source},
+    pipeline::{Output, Source, Transform},
 };
 
 use super::builder;
 use super::builder::{ConfiguredTransform, ElementType};
-use super::trigger::{Trigger, TriggerSpec};
+use super::trigger::{self, Trigger, TriggerSpec};
 use super::{OutputContext, PollError, TransformError, WriteError};
 
 /// A measurement pipeline that has not been started yet.
@@ -49,9 +55,30 @@ pub struct IdlePipeline {
     // registries
     pub(super) metrics: MetricRegistry,
 
+    /// Source of the timestamps of the managed sources.
+    pub(super) clock: Arc<dyn Clock>,
+
+    /// Last value of each series, if enabled.
+    pub(super) last_values: Option<LastValueCache>,
+
+    /// Whether to number the buffers sent to the outputs.
+    pub(super) sequence_numbers: bool,
+
+    /// Expected number of points per poll of a source, used to pre-allocate the buffers.
+    pub(super) points_per_poll: Option<usize>,
+
+    /// Attributes attached to all the measurement points.
+    pub(super) global_attributes: Vec<(Cow<'static, str>, AttributeValue)>,
+
+    /// Metrics that measure the overhead of the pipeline, if enabled.
+    pub(super) overhead: Option<OverheadMetrics>,
+
     /// Channel: source -> transforms
     pub(super) from_sources: (mpsc::Sender<MeasurementBuffer>, mpsc::Receiver<MeasurementBuffer>),
 
+    /// Channel: autonomous sources -> transforms (through [`forward_autonomous_measurements`])
+    pub(super) from_autonomous_sources: mpsc::Receiver<MeasurementBuffer>,
+
     /// Broadcast queue to outputs
     pub(super) to_outputs: broadcast::Sender<OutputMsg>,
 }
@@ -64,7 +91,12 @@ enum ControlMessage {
         plugin_name: String,
         source: Box<dyn Source>,
         trigger: TriggerSpec,
+        handle: SourceHandle,
     },
+    RemoveSource(SourceHandle),
+    SetSourceTrigger(SourceHandle, TriggerSpec),
+    Pause,
+    Resume,
     ModifySource(ElementCommand<SourceCmd>),
     ModifyTransform(ElementCommand<TransformCmd>),
     ModifyOutput(ElementCommand<OutputCmd>),
@@ -106,7 +138,7 @@ struct PipelineControllerState {
     global_shutdown_send: UnboundedSender<()>,
 
     // Senders to keep the receivers alive and to send commands.
-    source_command_senders_by_plugin: HashMap<String, Vec<watch::Sender<SourceCmd>>>,
+    source_command_senders_by_plugin: HashMap<String, Vec<(SourceHandle, watch::Sender<SourceCmd>)>>,
     output_command_senders_by_plugin: HashMap<String, Vec<watch::Sender<OutputCmd>>>,
 
     /// Currently active transforms.
@@ -136,6 +168,18 @@ struct PipelineModifierState {
 
     /// Handle to the tokio runtime with "normal" threads.
     rt_normal: tokio::runtime::Handle,
+
+    /// Metrics of the overhead of the pipeline, if it is measured.
+    overhead_metrics: Option<OverheadMetrics>,
+
+    /// Expected number of points per poll of a source, if known.
+    points_per_poll: Option<usize>,
+
+    /// Whether the pipeline is paused, shared by all the managed sources.
+    paused: Arc<AtomicBool>,
+
+    /// Source of the timestamps of the managed sources.
+    clock: Arc<dyn Clock>,
 }
 
 #[derive(Clone)]
@@ -146,6 +190,23 @@ pub struct ControlHandle {
     tx: mpsc::Sender<ControlMessage>,
 }
 
+/// Error returned by the [`ControlHandle`] when a message cannot be sent to the pipeline.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ControlError {
+    /// The buffer of control messages is full. The message can be sent again later.
+    BufferFull,
+}
+
+impl std::fmt::Display for ControlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ControlError::BufferFull => write!(f, "the buffer of control messages is full"),
+        }
+    }
+}
+
+impl std::error::Error for ControlError {}
+
 impl IdlePipeline {
     pub fn metric_count(&self) -> usize {
         self.metrics.len()
@@ -171,8 +232,11 @@ impl IdlePipeline {
         // Start the tasks, starting at the end of the pipeline (to avoid filling the buffers too quickly).
         let (in_tx, in_rx) = self.from_sources;
 
+        // The write durations of the outputs are reported by the transform step.
+        let transform_overhead = self.overhead.map(|m| TransformOverhead::new(m, &self.outputs));
+
         // 1. Outputs
-        for out in self.outputs {
+        for (i, out) in self.outputs.into_iter().enumerate() {
             let msg_rx = self.to_outputs.subscribe();
             let (command_tx, command_rx) = watch::channel(OutputCmd::Run);
             let ctx = OutputContext {
@@ -180,6 +244,8 @@ impl IdlePipeline {
                 // This allows fast, uncontended access to the registry, and avoids a global state (no Arc<Mutex<...>>).
                 // The cost is a duplication of the registry (increased memory use) in the case where multiple outputs exist.
                 metrics: self.metrics.clone(),
+                last_values: self.last_values.clone(),
+                sequence_number: None,
             };
 
             // Store command_tx so that we can accept commands later (commands can target the outputs of a specific plugin).
@@ -189,7 +255,8 @@ impl IdlePipeline {
                 .push(command_tx);
 
             // Spawn the task in the JoinSet.
-            let task = run_output_from_broadcast(out.name, out.output, msg_rx, command_rx, ctx);
+            let write_overhead = transform_overhead.as_ref().map(|o| o.write_counter(i));
+            let task = run_output_from_broadcast(out.name, out.output, msg_rx, command_rx, ctx, write_overhead);
             output_set.spawn_on(task, self.rt_normal.handle());
         }
 
@@ -202,10 +269,29 @@ impl IdlePipeline {
                 .or_default()
                 .bitor_assign(mask);
         }
-        let transforms_task = run_transforms(self.transforms, in_rx, self.to_outputs, active_transforms.clone());
+        let settings = TransformStepSettings {
+            global_attributes: self.global_attributes,
+            overhead: transform_overhead,
+            last_values: self.last_values,
+            sequence_numbers: self.sequence_numbers,
+        };
+        let transforms_task = run_transforms(
+            self.transforms,
+            in_rx,
+            self.to_outputs,
+            active_transforms.clone(),
+            settings,
+        );
         transform_set.spawn_on(transforms_task, self.rt_normal.handle());
 
         // 3. Managed sources
+        let paused = Arc::new(AtomicBool::new(false));
+        let source_settings = SourceTaskSettings {
+            overhead: self.overhead,
+            points_per_poll: self.points_per_poll,
+            pipeline_paused: paused.clone(),
+            clock: self.clock.clone(),
+        };
         for src in self.sources {
             let data_tx = in_tx.clone();
             let runtime = match src.trigger_provider.realtime_priority {
@@ -216,13 +302,17 @@ impl IdlePipeline {
             source_command_senders_by_plugin
                 .entry(src.plugin_name)
                 .or_default()
-                .push(command_tx);
+                .push((src.handle, command_tx));
 
-            let task = run_source(src.name, src.source, data_tx, command_rx);
+            let task = run_source(src.name, src.source, data_tx, command_rx, source_settings.clone());
             source_set.spawn_on(task, runtime.handle());
         }
 
         // 4. Autonomous sources
+        if !self.autonomous_sources.is_empty() {
+            let task = forward_autonomous_measurements(self.from_autonomous_sources, in_tx.clone(), paused.clone());
+            source_set.spawn_on(task, self.rt_normal.handle());
+        }
         for src in self.autonomous_sources {
             let task = async move {
                 src.source
@@ -259,6 +349,10 @@ impl IdlePipeline {
                 join_sets,
                 in_tx,
                 rt_normal: self.rt_normal.handle().clone(),
+                overhead_metrics: self.overhead,
+                points_per_poll: self.points_per_poll,
+                paused,
+                clock: self.clock,
             },
         };
         let control_handle = ControlHandle { tx: control_tx };
@@ -277,6 +371,25 @@ impl IdlePipeline {
     }
 }
 
+/// Identifies a managed source of the pipeline, in order to remove it later.
+///
+/// Returned by [`AlumetStart::add_source`](crate::plugin::AlumetStart::add_source)
+/// and [`ControlHandle::add_source`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SourceHandle {
+    id: u64,
+}
+
+impl SourceHandle {
+    /// Creates a new handle, different from all the other handles.
+    pub(crate) fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+}
+
 /// Stores [`JoinSet`]s for all the tasks of the pipeline
 /// that correspond to an element (source, transform, output).
 struct ElementJoinSets {
@@ -293,12 +406,63 @@ pub enum SourceCmd {
     SetTrigger(Option<TriggerSpec>),
 }
 
+/// Forwards the measurements of the autonomous sources to the transforms,
+/// except while the pipeline is paused, in which case they are discarded.
+async fn forward_autonomous_measurements(
+    mut rx: mpsc::Receiver<MeasurementBuffer>,
+    tx: mpsc::Sender<MeasurementBuffer>,
+    pipeline_paused: Arc<AtomicBool>,
+) -> anyhow::Result<()> {
+    while let Some(measurements) = rx.recv().await {
+        if pipeline_paused.load(Ordering::Relaxed) {
+            log::trace!("Pipeline paused, discarding {} measurements.", measurements.len());
+            continue;
+        }
+        tx.send(measurements)
+            .await
+            .context("could not forward the measurements of the autonomous sources to the transforms")?;
+    }
+    Ok(())
+}
+
+/// Settings of a managed source task, shared by all the managed sources of the pipeline.
+#[derive(Clone)]
+struct SourceTaskSettings {
+    /// Metrics of the overhead of the pipeline, if it is measured.
+    overhead: Option<OverheadMetrics>,
+    /// Expected number of points per poll of a source, if known.
+    points_per_poll: Option<usize>,
+    /// Whether the pipeline is paused.
+    pipeline_paused: Arc<AtomicBool>,
+    /// Source of the timestamps of the polls.
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for SourceTaskSettings {
+    fn default() -> Self {
+        Self {
+            overhead: None,
+            points_per_poll: None,
+            pipeline_paused: Default::default(),
+            clock: crate::time::system_clock(),
+        }
+    }
+}
+
 async fn run_source(
     source_name: String,
     mut source: Box<dyn Source>,
     tx: mpsc::Sender<MeasurementBuffer>,
     mut commands: watch::Receiver<SourceCmd>,
+    settings: SourceTaskSettings,
 ) -> anyhow::Result<()> {
+    let SourceTaskSettings {
+        overhead,
+        points_per_poll,
+        pipeline_paused,
+        clock,
+    } = settings;
+
     /// Takes the [`Trigger`] from the option and initializes it.
     fn init_trigger(
         trigger_spec: &mut Option<TriggerSpec>,
@@ -328,8 +492,13 @@ async fn run_source(
     };
 
     // Store measurements in this buffer, and replace it every `flush_rounds` rounds.
-    // For now, we don't know how many measurements the source will produce, so we allocate 1 per round.
-    let mut buffer = MeasurementBuffer::with_capacity(trigger.config.flush_rounds);
+    // Unless the pipeline has been given a hint, we don't know how many measurements the source will produce,
+    // so we allocate 1 per round.
+    let mut min_capacity = source_buffer_capacity(trigger.config.flush_rounds, points_per_poll);
+    let mut buffer = MeasurementBuffer::with_capacity(min_capacity);
+
+    // Average interval between two polls, reported with the overhead.
+    let mut actual_interval = ActualInterval::default();
 
     // main loop
     let mut i = 1usize;
@@ -340,9 +509,26 @@ async fn run_source(
         let reason = trigger.next().await.with_context(|| source_name.clone())?;
 
         let update = match reason {
+            TriggerReason::Triggered if pipeline_paused.load(Ordering::Relaxed) => {
+                // The pipeline is paused: don't poll, but send the measurements of the previous polls now,
+                // instead of delaying them until the pipeline resumes.
+                if !buffer.is_empty() {
+                    let prev_length = buffer.len();
+                    let capacity = prev_length.max(min_capacity);
+                    let flushed = std::mem::replace(&mut buffer, MeasurementBuffer::with_capacity(capacity));
+                    if let Err(e) = tx.try_send(flushed) {
+                        log::error!("{source_name} failed to flush its measurements before pausing: {e}");
+                    }
+                }
+                // the next poll after the pause should not be compared to the last poll before it
+                actual_interval.restart();
+                // check the commands on every round, so that the source can be stopped while paused
+                true
+            }
             TriggerReason::Triggered => {
                 // poll the source
-                let timestamp = Timestamp::now();
+                let timestamp = clock.now();
+                let poll_start = overhead.map(|metrics| (metrics, Instant::now()));
                 match source.poll(&mut buffer.as_accumulator(), timestamp) {
                     Ok(()) => (),
                     Err(PollError::CanRetry(e)) => {
@@ -350,9 +536,24 @@ async fn run_source(
                     }
                     Err(PollError::Fatal(e)) => {
                         log::error!("Fatal error when polling {source_name} (will stop running): {e:?}");
+                        if let Err(stop_err) = source.stop() {
+                            log::error!("Error while stopping {source_name}: {stop_err:?}");
+                        }
                         return Err(e.context(format!("fatal error when polling {source_name}")));
                     }
                 };
+                if let Some((metrics, start)) = poll_start {
+                    buffer.push(overhead::poll_measurement(
+                        metrics.poll,
+                        timestamp,
+                        &source_name,
+                        start.elapsed(),
+                    ));
+                    if let Some(secs) = actual_interval.update(start) {
+                        let metric = metrics.actual_interval;
+                        buffer.push(overhead::interval_measurement(metric, timestamp, &source_name, secs));
+                    }
+                }
 
                 // Flush the measurements, not on every round for performance reasons.
                 // This is done _after_ polling, to ensure that we poll at least once before flushing, even if flush_rounds is 1.
@@ -367,7 +568,7 @@ async fn run_source(
                         Ok(()) => {
                             // buffer has been sent, create a new one
                             log::debug!("{source_name} flushed {prev_length} measurements");
-                            MeasurementBuffer::with_capacity(prev_length)
+                            MeasurementBuffer::with_capacity(prev_length.max(min_capacity))
                         }
                         Err(TrySendError::Closed(_buf)) => {
                             // the channel Receiver has been closed
@@ -383,8 +584,9 @@ async fn run_source(
                     };
                 }
 
-                // only update on some rounds, for performance reasons.
-                let update = (i % trigger.config.update_rounds) == 0;
+                // only update on some rounds, for performance reasons,
+                // except when the trigger changes: the new trigger applies on the next tick.
+                let update = (i % trigger.config.update_rounds) == 0 || has_new_trigger(&commands);
 
                 // increase i
                 i = i.wrapping_add(1);
@@ -440,6 +642,7 @@ async fn run_source(
                             let remaining_rounds = trigger.config.flush_rounds;
                             let hint_additional_elems = remaining_rounds * prev_length / prev_flush_rounds;
                             buffer.reserve(hint_additional_elems);
+                            min_capacity = source_buffer_capacity(trigger.config.flush_rounds, points_per_poll);
 
                             // don't be stuck here
                             if !paused {
@@ -456,23 +659,67 @@ async fn run_source(
             }
         }
     }
+    source.stop().with_context(|| format!("error while stopping {source_name}"))?;
     Ok(())
 }
 
+/// Maximum number of points that are pre-allocated in the buffer of a managed source.
+///
+/// This protects the sources against an unreasonable `points_per_poll` hint.
+/// The buffers can still grow beyond this size if the sources produce more points.
+const MAX_PREALLOCATED_POINTS: usize = 1 << 16;
+
+/// Returns the initial capacity of the buffer of a managed source that flushes every `flush_rounds` polls.
+fn source_buffer_capacity(flush_rounds: usize, points_per_poll: Option<usize>) -> usize {
+    flush_rounds
+        .saturating_mul(points_per_poll.unwrap_or(1).max(1))
+        .min(MAX_PREALLOCATED_POINTS)
+}
+
+/// Returns true if the latest command, which has not been seen yet, replaces the trigger of the source.
+///
+/// This only checks the version of the channel in the common case, hence it can be called on every poll.
+fn has_new_trigger(commands: &watch::Receiver<SourceCmd>) -> bool {
+    commands.has_changed().unwrap_or(false) && matches!(*commands.borrow(), SourceCmd::SetTrigger(_))
+}
+
 #[derive(Debug)]
 pub enum TransformCmd {
     Enable,
     Disable,
 }
 
+/// What the transform step does to the measurements, besides running the transforms.
+#[derive(Default)]
+struct TransformStepSettings {
+    /// Attributes attached to all the measurement points.
+    global_attributes: Vec<(Cow<'static, str>, AttributeValue)>,
+    /// Overhead of the transforms, if enabled.
+    overhead: Option<TransformOverhead>,
+    /// Last value of each series, if enabled.
+    last_values: Option<LastValueCache>,
+    /// Whether to number the buffers sent to the outputs.
+    sequence_numbers: bool,
+}
+
 async fn run_transforms(
     mut transforms: Vec<ConfiguredTransform>,
     mut rx: mpsc::Receiver<MeasurementBuffer>,
     tx: broadcast::Sender<OutputMsg>,
     active_flags: Arc<AtomicU64>,
+    settings: TransformStepSettings,
 ) -> anyhow::Result<()> {
+    let TransformStepSettings {
+        global_attributes,
+        overhead,
+        last_values,
+        sequence_numbers,
+    } = settings;
+    let mut next_sequence_number: u64 = 0;
     loop {
         if let Some(mut measurements) = rx.recv().await {
+            let transform_start = overhead.as_ref().map(|_| Instant::now());
+
             // Update the list of active transforms (the PipelineController can update the flags).
             let current_flags = active_flags.load(Ordering::Relaxed);
 
@@ -480,7 +727,11 @@ async fn run_transforms(
             for (i, t) in &mut transforms.iter_mut().enumerate() {
                 let t_flag = 1 << i;
                 if current_flags & t_flag != 0 {
-                    match t.transform.apply(&mut measurements) {
+                    let result = match &t.input_metrics {
+                        Some(metrics) => apply_to_metrics(t.transform.as_mut(), metrics, &mut measurements),
+                        None => t.transform.apply(&mut measurements),
+                    };
+                    match result {
                         Ok(()) => (),
                         Err(TransformError::UnexpectedInput(e)) => {
                             log::error!("Transform function {} received unexpected measurements: {e:#}", t.name);
@@ -496,8 +747,28 @@ async fn run_transforms(
                 }
             }
 
+            // Report the overhead, after the transforms so that they do not modify it.
+            if let (Some(overhead), Some(start)) = (&overhead, transform_start) {
+                overhead.push_measurements(&mut measurements, start.elapsed());
+            }
+
+            // Attach the global attributes, after the transforms so that they cannot remove them.
+            attach_global_attributes(&mut measurements, &global_attributes);
+
+            // Keep the last values for the outputs that use them.
+            if let Some(cache) = &last_values {
+                cache.update(&measurements);
+            }
+
+            // Number the buffer, so that the outputs can detect the buffers they have missed.
+            let sequence_number = sequence_numbers.then(|| {
+                let n = next_sequence_number;
+                next_sequence_number = next_sequence_number.wrapping_add(1);
+                n
+            });
+
             // Send the results to the outputs.
-            tx.send(OutputMsg::WriteMeasurements(measurements))
+            tx.send(OutputMsg::WriteMeasurements(measurements, sequence_number))
                 .context("could not send the measurements from transforms to the outputs")?;
         } else {
             log::debug!("The channel connected to the transform step has been closed, the transforms will stop.");
@@ -507,6 +778,37 @@ async fn run_transforms(
     Ok(())
 }
 
+/// Applies the transform to the measurements of `metrics` only, see [`Transform::input_metrics`].
+fn apply_to_metrics(
+    transform: &mut dyn Transform,
+    metrics: &FxHashSet<RawMetricId>,
+    measurements: &mut MeasurementBuffer,
+) -> Result<(), TransformError> {
+    if !measurements.iter().any(|m| metrics.contains(&m.metric)) {
+        return Ok(());
+    }
+    let (mut selected, mask) = measurements.take_matching(|m| metrics.contains(&m.metric));
+    let result = transform.apply(&mut selected);
+    // put the measurements back, even if the transform has failed, like when it is applied to the whole buffer
+    measurements.put_back(selected, &mask);
+    result
+}
+
+/// Adds the `attributes` to every measurement point,
+/// except to the points that already have an attribute with the same key.
+fn attach_global_attributes(measurements: &mut MeasurementBuffer, attributes: &[(Cow<'static, str>, AttributeValue)]) {
+    if attributes.is_empty() {
+        return;
+    }
+    for m in measurements.iter_mut() {
+        for (key, value) in attributes {
+            if !m.attributes_keys().any(|k| k == key) {
+                m.add_attr(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
 /// A command for an output.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum OutputCmd {
@@ -517,7 +819,8 @@ pub enum OutputCmd {
 
 #[derive(Debug, Clone)]
 pub enum OutputMsg {
-    WriteMeasurements(MeasurementBuffer),
+    /// Measurements to write, with their sequence number if enabled.
+    WriteMeasurements(MeasurementBuffer, Option<u64>),
     RegisterMetrics {
         metrics: Vec<Metric>,
         source_name: String,
@@ -531,6 +834,7 @@ async fn run_output_from_broadcast(
     mut rx: broadcast::Receiver<OutputMsg>,
     mut commands: watch::Receiver<OutputCmd>,
     mut ctx: OutputContext,
+    write_overhead: Option<Arc<AtomicU64>>,
 ) -> anyhow::Result<()> {
     // Two possible designs:
     // A) Use one mpsc channel + one shared variable that contains the current command,
@@ -551,9 +855,19 @@ async fn run_output_from_broadcast(
         output_name: &str,
         output: &mut dyn Output,
         ctx: &mut OutputContext,
+        write_overhead: Option<&AtomicU64>,
+        value_kinds: &mut ValueKindFilter,
     ) -> anyhow::Result<()> {
         match received_msg {
-            OutputMsg::WriteMeasurements(measurements) => {
+            OutputMsg::WriteMeasurements(mut measurements, sequence_number) => {
+                // convert or skip the values that the output cannot write
+                let len_before = measurements.len();
+                value_kinds.apply(&mut measurements, output_name);
+                if measurements.is_empty() && len_before > 0 {
+                    return Ok(());
+                }
+                ctx.sequence_number = sequence_number;
+                let write_start = Instant::now();
                 // output.write() is blocking, do it in a dedicated thread.
 
                 // Output is not Sync, we could move the value to the future and back (idem for ctx),
@@ -562,6 +876,9 @@ async fn run_output_from_broadcast(
                 let res =
                     scoped::spawn_blocking_with_output(output, ctx, move |out, ctx| out.write(&measurements, ctx))
                         .await;
+                if let Some(counter) = write_overhead {
+                    overhead::add_write_duration(counter, write_start.elapsed());
+                }
                 match res {
                     Ok(write_res) => {
                         match write_res {
@@ -601,6 +918,7 @@ async fn run_output_from_broadcast(
         }
     }
 
+    let mut value_kinds = ValueKindFilter::new(output.supported_value_kinds());
     loop {
         tokio::select! {
             received_cmd = commands.changed() => {
@@ -631,7 +949,14 @@ async fn run_output_from_broadcast(
             received_msg = rx.recv() => {
                 match received_msg {
                     Ok(msg) => {
-                        handle_message(msg, &output_name, output.as_mut(), &mut ctx).await?;
+                        let write_overhead = write_overhead.as_deref();
+                        if let Err(e) = handle_message(msg, &output_name, output.as_mut(), &mut ctx, write_overhead, &mut value_kinds).await {
+                            // not a clean end of the stream: stop the output without finalizing it
+                            if let Err(stop_err) = output.stop() {
+                                log::error!("Error while stopping {output_name}: {stop_err:?}");
+                            }
+                            return Err(e);
+                        }
                     },
                     Err(broadcast::error::RecvError::Lagged(n)) => {
                         log::warn!("Output {output_name} is too slow, it lost the oldest {n} messages.");
@@ -644,7 +969,26 @@ async fn run_output_from_broadcast(
             }
         }
     }
-    Ok(())
+    if value_kinds.skipped() > 0 {
+        log::warn!(
+            "Output {output_name} has skipped {} measurements whose values it does not support.",
+            value_kinds.skipped()
+        );
+    }
+    // Clean end of the stream: finalize, then stop, even if finalize fails.
+    // Like write(), finalize() is blocking, do it in a dedicated thread.
+    let finalize = |out: &mut dyn Output, _: &mut OutputContext| out.finalize();
+    let finalized = match scoped::spawn_blocking_with_output(output.as_mut(), &mut ctx, finalize).await {
+        Ok(res) => res,
+        Err(await_err) => Err(anyhow!("the finalization task failed: {await_err}")),
+    };
+    if let Err(e) = &finalized {
+        log::error!("Error while finalizing {output_name}: {e:?}");
+    }
+    if let Err(e) = output.stop() {
+        return Err(e.context(format!("error while stopping {output_name}")));
+    }
+    finalized.with_context(|| format!("error while finalizing {output_name}"))
 }
 
 #[derive(Debug)]
@@ -771,7 +1115,7 @@ async fn pipeline_control_task(
         .source_command_senders_by_plugin
         .values()
         .flatten()
-        .cloned()
+        .map(|(_, sender)| sender.clone())
         .collect();
     let output_command_senders: Vec<watch::Sender<OutputCmd>> = state
         .output_command_senders_by_plugin
@@ -828,6 +1172,7 @@ fn handle_control_message(state: &mut PipelineControllerState, message: ControlM
             plugin_name: plugin,
             source,
             trigger,
+            handle,
         } => {
             log::debug!("Adding new source {requested_name}");
 
@@ -842,25 +1187,79 @@ fn handle_control_message(state: &mut PipelineControllerState, message: ControlM
                 .source_command_senders_by_plugin
                 .entry(plugin)
                 .or_default()
-                .push(command_tx);
+                .push((handle, command_tx));
 
             // submit the task to the tokio Runtime, unless we are shutting down
-            let task = run_source(source_name, source, in_tx, command_rx);
+            let task = run_source(
+                source_name,
+                source,
+                in_tx,
+                command_rx,
+                SourceTaskSettings {
+                    overhead: modif.overhead_metrics,
+                    points_per_poll: modif.points_per_poll,
+                    pipeline_paused: modif.paused.clone(),
+                    clock: modif.clock.clone(),
+                },
+            );
             modif.join_sets.source_set.spawn_on(task, &modif.rt_normal);
         }
 
+        ControlMessage::RemoveSource(handle) => {
+            let removed = state.source_command_senders_by_plugin.values_mut().find_map(|senders| {
+                let i = senders.iter().position(|(h, _)| h == &handle)?;
+                Some(senders.swap_remove(i).1)
+            });
+            match removed {
+                Some(command_tx) => {
+                    log::debug!("Removing source {handle:?}");
+                    // The source flushes its measurements, Source::stop is called, then it is dropped.
+                    command_tx.send_replace(SourceCmd::Stop);
+                    // Keep the sender alive until the source task ends, otherwise the task
+                    // would see a closed channel instead of the Stop command.
+                    state.modifier.rt_normal.spawn(async move { command_tx.closed().await });
+                }
+                None => log::warn!("Cannot remove source {handle:?}: it does not exist or has already been removed."),
+            }
+        }
+
+        ControlMessage::SetSourceTrigger(handle, trigger) => {
+            let sender = state
+                .source_command_senders_by_plugin
+                .values()
+                .flatten()
+                .find_map(|(h, s)| (h == &handle).then_some(s));
+            match sender {
+                Some(command_tx) => {
+                    log::debug!("Changing the trigger of source {handle:?}: {trigger:?}");
+                    command_tx.send_replace(SourceCmd::SetTrigger(Some(trigger)));
+                }
+                None => log::warn!("Cannot change the trigger of source {handle:?}: it does not exist."),
+            }
+        }
+
+        ControlMessage::Pause => {
+            log::info!("Pausing the measurement pipeline.");
+            state.modifier.paused.store(true, Ordering::Relaxed);
+        }
+
+        ControlMessage::Resume => {
+            log::info!("Resuming the measurement pipeline.");
+            state.modifier.paused.store(false, Ordering::Relaxed);
+        }
+
         ControlMessage::ModifySource(ElementCommand {
             destination,
             command: message,
         }) => match destination {
             MessageDestination::Plugin(plugin) => {
-                for s in state.source_command_senders_by_plugin.get(&plugin).unwrap() {
+                for (_, s) in state.source_command_senders_by_plugin.get(&plugin).unwrap() {
                     s.send(message.clone()).unwrap();
                 }
             }
             MessageDestination::All => {
                 for senders in state.source_command_senders_by_plugin.values() {
-                    for s in senders {
+                    for (_, s) in senders {
                         s.send(message.clone()).unwrap();
                     }
                 }
@@ -917,6 +1316,29 @@ impl RunningPipeline {
         }
     }
 
+    /// Lets the pipeline run for `duration`, then shuts it down and blocks the current thread until it stops.
+    ///
+    /// The shutdown is the same as with [`ControlHandle::shutdown`]: the sources flush the measurements
+    /// that they have not sent yet (even if their buffer is not full) and are stopped, then the transforms
+    /// process the last buffers, and the outputs write them before being dropped.
+    /// If the pipeline is shut down by other means before the end of `duration`, this function returns earlier.
+    ///
+    /// This is useful to capture a fixed measurement window, for instance in a benchmark or an automated test.
+    pub fn run_for(mut self, duration: Duration) -> anyhow::Result<()> {
+        self.shutdown_after(duration);
+        self.wait_for_shutdown()
+    }
+
+    /// Requests the pipeline to shut down after `duration`, without blocking, see [`run_for`](Self::run_for).
+    pub fn shutdown_after(&mut self, duration: Duration) {
+        let control_handle = self.control_handle();
+        self._rt_normal.spawn(async move {
+            tokio::time::sleep(duration).await;
+            log::info!("The measurement window of {duration:?} has elapsed, shutting down the pipeline.");
+            control_handle.shutdown();
+        });
+    }
+
     /// Returns a [`ControlHandle`], which allows to change the configuration
     /// of the pipeline while it is running.
     pub fn control_handle(&mut self) -> ControlHandle {
@@ -973,16 +1395,105 @@ impl ControlHandle {
         }
     }
 
+    /// Pauses the measurement pipeline: the managed sources are no longer polled, until [`resume`](Self::resume)
+    /// is called. Unlike [`shutdown`](Self::shutdown), the sources, transforms and outputs are kept with their state.
+    ///
+    /// ## Measurements
+    ///
+    /// At its first trigger after the pause, each source sends the measurements that it has not flushed yet,
+    /// and the buffers that are already in the pipeline are transformed and written as usual.
+    /// After that, no measurement is produced until the pipeline resumes.
+    /// Sources that compute a difference between two polls (such as [`CounterDiff`](crate::plugin::util::CounterDiff))
+    /// keep their state, hence their first measurement after the pause covers the whole paused period.
+    ///
+    /// The pause applies to the sources that are added while the pipeline is paused.
+    /// Autonomous sources are not polled by the pipeline and keep running, but their
+    /// measurements are discarded until the pipeline resumes.
+    pub fn pause(&self) -> Result<(), ControlError> {
+        self.send_message(ControlMessage::Pause, "pause")
+    }
+
+    /// Resumes the measurement pipeline after [`pause`](Self::pause).
+    pub fn resume(&self) -> Result<(), ControlError> {
+        self.send_message(ControlMessage::Resume, "resume")
+    }
+
     /// Adds a new source to the pipeline, without interrupting the elements
     /// (sources, transforms, outputs) that are currently running.
-    pub fn add_source(&self, plugin_name: String, source_name: String, source: Box<dyn Source>, trigger: TriggerSpec) {
+    ///
+    /// The returned handle can be given to [`remove_source`](Self::remove_source) to stop the source.
+    pub fn add_source(
+        &self,
+        plugin_name: String,
+        source_name: String,
+        source: Box<dyn Source>,
+        trigger: TriggerSpec,
+    ) -> SourceHandle {
+        let handle = SourceHandle::new();
         let msg = ControlMessage::AddSource {
             requested_name: source_name,
             plugin_name,
             source,
             trigger,
+            handle: handle.clone(),
         };
-        self.tx.try_send(msg).unwrap()
+        self.tx.try_send(msg).unwrap();
+        handle
+    }
+
+    /// Removes a source from the pipeline, without interrupting the other elements.
+    ///
+    /// The source flushes its last measurements, then [`Source::stop`] is called before the source
+    /// is dropped: any cleanup should be implemented there. Removing a source that has already been
+    /// removed does nothing.
+    ///
+    /// ## Thread safety
+    /// Like the other methods of `ControlHandle`, this can be called from any thread while
+    /// the pipeline is running. The removal is asynchronous: the request is processed by the
+    /// pipeline control task, in the order in which the requests have been sent. Therefore, calling
+    /// `remove_source` right after [`add_source`](Self::add_source) with the same handle is fine.
+    /// The source may still be polled a last time after `remove_source` returns.
+    ///
+    /// If the pipeline is shutting down, all the sources will be stopped anyway and `Ok` is returned.
+    pub fn remove_source(&self, handle: SourceHandle) -> Result<(), ControlError> {
+        self.send_message(ControlMessage::RemoveSource(handle), "remove_source")
+    }
+
+    /// Sends a message to the pipeline control task without blocking.
+    ///
+    /// Returns `Ok` if the pipeline is shutting down, since the message is useless in that case.
+    fn send_message(&self, message: ControlMessage, method: &str) -> Result<(), ControlError> {
+        match self.tx.try_send(message) {
+            Ok(_) => Ok(()),
+            Err(TrySendError::Closed(_)) => {
+                log::debug!("ControlHandle::{method}() has been called but the pipeline is already shutting down.");
+                Ok(())
+            }
+            Err(TrySendError::Full(_)) => Err(ControlError::BufferFull),
+        }
+    }
+
+    /// Replaces the trigger of a managed source, without interrupting the other elements.
+    ///
+    /// This allows to adapt the measurement frequency at runtime, for instance to poll a source
+    /// more often during an anomaly. The new trigger applies on the next tick of the current trigger,
+    /// regardless of its update interval (or immediately if the current trigger is interruptible).
+    /// The polls that have not been flushed yet are kept, and flushed according to the new trigger.
+    pub fn set_source_trigger(&self, handle: &SourceHandle, trigger: TriggerSpec) -> Result<(), ControlError> {
+        let msg = ControlMessage::SetSourceTrigger(handle.clone(), trigger);
+        self.send_message(msg, "set_source_trigger")
+    }
+
+    /// Changes the poll interval of a managed source, see [`set_source_trigger`](Self::set_source_trigger).
+    ///
+    /// The measurements of the source are flushed after each poll. To choose the flush interval, use
+    /// `set_source_trigger` with a trigger built by [`TriggerSpec::builder`].
+    ///
+    /// Fails if the trigger cannot be built, or if the message cannot be sent (see [`ControlError`]).
+    pub fn set_source_interval(&self, handle: &SourceHandle, poll_interval: Duration) -> anyhow::Result<()> {
+        let trigger = TriggerSpec::builder(poll_interval).build()?;
+        self.set_source_trigger(handle, trigger)?;
+        Ok(())
     }
 }
 
@@ -1061,14 +1572,16 @@ impl<'a> BlockingScopedControlHandle<'a> {
 #[cfg(test)]
 mod tests {
     use std::{
+        borrow::Cow,
         sync::{
             atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicU8, Ordering},
-            Arc,
+            Arc, Mutex,
         },
         thread::sleep,
-        time::Duration,
+        time::{Duration, SystemTime},
     };
 
+    use fxhash::FxHashSet;
     use tokio::{
         runtime::Runtime,
         sync::{broadcast, mpsc, watch},
@@ -1076,18 +1589,98 @@ mod tests {
 
     use crate::{
         measurement::{
-            MeasurementAccumulator, MeasurementBuffer, MeasurementPoint, Timestamp, WrappedMeasurementType,
-            WrappedMeasurementValue,
+            AttributeValue, MeasurementAccumulator, MeasurementBuffer, MeasurementPoint, Timestamp,
+            WrappedMeasurementType, WrappedMeasurementValue,
         },
         metrics::{MetricRegistry, RawMetricId},
-        pipeline::{builder::ConfiguredTransform, trigger::TriggerSpec, OutputContext, Transform},
+        pipeline::{builder::ConfiguredTransform, trigger::TriggerSpec, OutputContext, Transform, TransformError},
         resources::{Resource, ResourceConsumer},
+        time::MockClock,
     };
 
     use super::{
-        super::trigger, run_output_from_broadcast, run_source, run_transforms, OutputCmd, OutputMsg, SourceCmd,
+        super::trigger, apply_to_metrics, attach_global_attributes, forward_autonomous_measurements,
+        run_output_from_broadcast, run_source, run_transforms, source_buffer_capacity, ControlError, ControlHandle,
+        OutputCmd, OutputMsg, SourceCmd, SourceHandle, SourceTaskSettings, TransformStepSettings,
+        MAX_PREALLOCATED_POINTS,
     };
 
+    #[test]
+    fn global_attributes() {
+        let point = || {
+            MeasurementPoint::new_untyped(
+                Timestamp::now(),
+                RawMetricId(1),
+                Resource::LocalMachine,
+                ResourceConsumer::LocalMachine,
+                WrappedMeasurementValue::U64(1),
+            )
+        };
+        let mut buf = MeasurementBuffer::new();
+        buf.push(point());
+        buf.push(point().with_attr("node_id", AttributeValue::Str("set-by-source")));
+
+        let attributes = vec![(Cow::Borrowed("node_id"), AttributeValue::Str("node-a"))];
+        attach_global_attributes(&mut buf, &attributes);
+        let values: Vec<String> = buf
+            .iter()
+            .map(|m| {
+                let attrs: Vec<_> = m.attributes().collect();
+                assert_eq!(attrs.len(), 1);
+                attrs[0].1.to_string()
+            })
+            .collect();
+        assert_eq!(values, vec!["node-a", "set-by-source"]);
+    }
+
+    /// Doubles the values of metric 1, and checks that it only receives them.
+    struct DoubleTransform {
+        calls: usize,
+    }
+
+    impl Transform for DoubleTransform {
+        fn apply(&mut self, measurements: &mut MeasurementBuffer) -> Result<(), TransformError> {
+            self.calls += 1;
+            for m in measurements.iter_mut() {
+                assert_eq!(m.metric, RawMetricId(1), "the transform received another metric");
+                m.value = WrappedMeasurementValue::U64(m.value.as_u64().unwrap() * 2);
+            }
+            Ok(())
+        }
+
+        fn input_metrics(&self) -> Option<Vec<RawMetricId>> {
+            Some(vec![RawMetricId(1)])
+        }
+    }
+
+    #[test]
+    fn transform_input_metrics() {
+        let point = |metric: usize, value: u64| {
+            MeasurementPoint::new_untyped(
+                Timestamp::now(),
+                RawMetricId(metric),
+                Resource::LocalMachine,
+                ResourceConsumer::LocalMachine,
+                WrappedMeasurementValue::U64(value),
+            )
+        };
+        let mut transform = DoubleTransform { calls: 0 };
+        let metrics = FxHashSet::from_iter(transform.input_metrics().unwrap());
+
+        // only the measurements of metric 1 are given to the transform, then put back at their place
+        let mut buf = MeasurementBuffer::from(vec![point(1, 10), point(0, 10), point(1, 20), point(2, 10)]);
+        apply_to_metrics(&mut transform, &metrics, &mut buf).unwrap();
+        let values: Vec<(usize, u64)> = buf.iter().map(|m| (m.metric.0, m.value.as_u64().unwrap())).collect();
+        assert_eq!(values, vec![(1, 20), (0, 10), (1, 40), (2, 10)]);
+        assert_eq!(transform.calls, 1);
+
+        // no measurement of metric 1: the transform is not called
+        let mut buf = MeasurementBuffer::from(vec![point(0, 10)]);
+        apply_to_metrics(&mut transform, &metrics, &mut buf).unwrap();
+        assert_eq!(buf.len(), 1);
+        assert_eq!(transform.calls, 1);
+    }
+
     #[test]
     fn source_triggered_by_time_normal() {
         run_source_trigger_test(false);
@@ -1100,6 +1693,7 @@ mod tests {
     fn run_source_trigger_test(with_interruption: bool) {
         let rt = new_rt(2);
         let source = TestSource::new();
+        let source_stop_called = source.stop_called.clone();
 
         let period = Duration::from_millis(10);
         let flush_rounds = 3;
@@ -1148,7 +1742,13 @@ mod tests {
         });
 
         // poll the source for some time
-        rt.spawn(run_source(String::from("test_source"), Box::new(source), tx, cmd_rx));
+        rt.spawn(run_source(
+            String::from("test_source"),
+            Box::new(source),
+            tx,
+            cmd_rx,
+            SourceTaskSettings::default(),
+        ));
         sleep(2 * period);
 
         // pause source
@@ -1196,10 +1796,292 @@ mod tests {
 
         // check that the source is stopped
         sleep(2 * period);
+        assert!(source_stop_called.load(Ordering::Relaxed), "Source::stop should have been called");
 
         // drop the runtime, abort the tasks
     }
 
+    #[test]
+    fn source_trigger_changed_on_next_tick() {
+        let rt = new_rt(2);
+        let period = Duration::from_millis(10);
+        // the commands would only be checked every 1000 polls
+        let tp = trigger::builder::time_interval(period)
+            .update_rounds(1000)
+            .build()
+            .unwrap();
+
+        let (tx, mut rx) = mpsc::channel::<MeasurementBuffer>(64);
+        let (cmd_tx, cmd_rx) = watch::channel(SourceCmd::SetTrigger(Some(tp)));
+        rt.spawn(async move { while rx.recv().await.is_some() {} });
+        let source = TestSource::new();
+        let source_stop_called = source.stop_called.clone();
+        rt.spawn(run_source(
+            String::from("test_source"),
+            Box::new(source),
+            tx,
+            cmd_rx,
+            SourceTaskSettings::default(),
+        ));
+        sleep(3 * period);
+
+        // the new trigger applies on the next tick, and checks the commands on every poll
+        cmd_tx
+            .send(SourceCmd::SetTrigger(Some(new_trigger(false, period, 1))))
+            .unwrap();
+        sleep(3 * period);
+        cmd_tx.send(SourceCmd::Stop).unwrap();
+        sleep(3 * period);
+        assert!(source_stop_called.load(Ordering::Relaxed), "the new trigger should have been applied");
+    }
+
+    #[test]
+    fn source_paused_by_pipeline() {
+        let rt = new_rt(2);
+        let period = Duration::from_millis(10);
+        let tp = new_trigger(false, period, 2);
+
+        let (tx, mut rx) = mpsc::channel::<MeasurementBuffer>(64);
+        let (cmd_tx, cmd_rx) = watch::channel(SourceCmd::SetTrigger(Some(tp)));
+        let paused = Arc::new(AtomicBool::new(false));
+
+        // collect the values produced by the source
+        let values = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let values2 = values.clone();
+        rt.spawn(async move {
+            while let Some(measurements) = rx.recv().await {
+                let mut values = values2.lock().unwrap();
+                values.extend(measurements.iter().map(|m| m.value.as_u64().unwrap()));
+            }
+        });
+        let source = Box::new(TestSource::new());
+        rt.spawn(run_source(
+            String::from("test_source"),
+            source,
+            tx,
+            cmd_rx,
+            SourceTaskSettings {
+                pipeline_paused: paused.clone(),
+                ..Default::default()
+            },
+        ));
+        sleep(5 * period);
+
+        // pause, and wait for the pending measurements to be flushed
+        paused.store(true, Ordering::Relaxed);
+        sleep(2 * period);
+        let n_before_pause = values.lock().unwrap().len();
+        assert!(n_before_pause > 0, "the source should have been polled before the pause");
+
+        // no measurement while paused
+        sleep(5 * period);
+        assert_eq!(values.lock().unwrap().len(), n_before_pause);
+
+        // resume: the source continues from its previous state
+        paused.store(false, Ordering::Relaxed);
+        sleep(5 * period);
+        cmd_tx.send(SourceCmd::Stop).unwrap();
+        sleep(2 * period);
+        let values = values.lock().unwrap();
+        assert!(values.len() > n_before_pause, "the source should have been polled after the pause");
+        let expected: Vec<u64> = (1..=values.len() as u64).collect();
+        assert_eq!(*values, expected, "no poll should be lost or repeated");
+    }
+
+    #[test]
+    fn autonomous_measurements_discarded_while_paused() {
+        let rt = new_rt(1);
+        let point = |value| {
+            MeasurementPoint::new_untyped(
+                Timestamp::now(),
+                RawMetricId(1),
+                Resource::LocalMachine,
+                ResourceConsumer::LocalMachine,
+                WrappedMeasurementValue::U64(value),
+            )
+        };
+        let (src_tx, src_rx) = mpsc::channel::<MeasurementBuffer>(8);
+        let (tx, mut rx) = mpsc::channel::<MeasurementBuffer>(8);
+        let paused = Arc::new(AtomicBool::new(false));
+        let task = rt.spawn(forward_autonomous_measurements(src_rx, tx, paused.clone()));
+
+        rt.block_on(async {
+            // not paused: the measurements are forwarded
+            src_tx.send(MeasurementBuffer::from(vec![point(1)])).await.unwrap();
+            let forwarded = rx.recv().await.expect("the measurements should be forwarded");
+            assert_eq!(forwarded.iter().next().unwrap().value.as_u64(), Some(1));
+
+            // paused: the measurements are discarded
+            paused.store(true, Ordering::Relaxed);
+            src_tx.send(MeasurementBuffer::from(vec![point(2)])).await.unwrap();
+            drop(src_tx);
+            task.await.unwrap().unwrap();
+            let forwarded = rx.recv().await;
+            assert!(forwarded.is_none(), "nothing should be forwarded while paused");
+        });
+    }
+
+    #[test]
+    fn source_timestamps_from_clock() {
+        let rt = new_rt(2);
+        let period = Duration::from_millis(10);
+        let tp = new_trigger(false, period, 1);
+
+        let (tx, mut rx) = mpsc::channel::<MeasurementBuffer>(64);
+        let (cmd_tx, cmd_rx) = watch::channel(SourceCmd::SetTrigger(Some(tp)));
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(100);
+        let clock = MockClock::new(start);
+        rt.spawn(run_source(
+            String::from("test_source"),
+            Box::new(TestSource::new()),
+            tx,
+            cmd_rx,
+            SourceTaskSettings {
+                clock: Arc::new(clock.clone()),
+                ..Default::default()
+            },
+        ));
+        sleep(3 * period);
+        clock.advance(Duration::from_secs(60));
+        sleep(3 * period);
+        cmd_tx.send(SourceCmd::Stop).unwrap();
+
+        // the timestamps come from the mock clock, not from the system clock
+        let timestamps: Vec<SystemTime> = rt.block_on(async {
+            let mut res = Vec::new();
+            while let Some(measurements) = rx.recv().await {
+                res.extend(measurements.iter().map(|m| SystemTime::from(m.timestamp)));
+            }
+            res
+        });
+        assert!(!timestamps.is_empty());
+        assert!(timestamps.contains(&start));
+        assert!(timestamps.contains(&(start + Duration::from_secs(60))));
+        assert!(timestamps.iter().all(|t| *t == start || *t == start + Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn source_timestamps_per_point() {
+        /// Reads two values at different times within one poll.
+        struct TwoReadsSource;
+        impl crate::pipeline::Source for TwoReadsSource {
+            fn poll(
+                &mut self,
+                into: &mut MeasurementAccumulator,
+                timestamp: Timestamp,
+            ) -> Result<(), crate::pipeline::PollError> {
+                let second_read = Timestamp::from(SystemTime::from(timestamp) + Duration::from_millis(5));
+                for (t, metric) in [(timestamp, 1), (second_read, 2)] {
+                    into.push(MeasurementPoint::new_untyped(
+                        t,
+                        RawMetricId(metric),
+                        Resource::LocalMachine,
+                        ResourceConsumer::LocalMachine,
+                        WrappedMeasurementValue::U64(0),
+                    ));
+                }
+                Ok(())
+            }
+        }
+
+        let rt = new_rt(2);
+        let period = Duration::from_millis(10);
+        let tp = new_trigger(false, period, 1);
+
+        let (tx, mut rx) = mpsc::channel::<MeasurementBuffer>(64);
+        let (cmd_tx, cmd_rx) = watch::channel(SourceCmd::SetTrigger(Some(tp)));
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(100);
+        rt.spawn(run_source(
+            String::from("test_source"),
+            Box::new(TwoReadsSource),
+            tx,
+            cmd_rx,
+            SourceTaskSettings {
+                clock: Arc::new(MockClock::new(start)),
+                ..Default::default()
+            },
+        ));
+        sleep(3 * period);
+        cmd_tx.send(SourceCmd::Stop).unwrap();
+
+        // the pipeline keeps the timestamp of each point
+        let points: Vec<(usize, SystemTime)> = rt.block_on(async {
+            let mut res = Vec::new();
+            while let Some(measurements) = rx.recv().await {
+                res.extend(measurements.iter().map(|m| (m.metric.0, SystemTime::from(m.timestamp))));
+            }
+            res
+        });
+        assert!(!points.is_empty());
+        for (metric, t) in points {
+            match metric {
+                1 => assert_eq!(t, start),
+                2 => assert_eq!(t, start + Duration::from_millis(5)),
+                _ => panic!("unexpected metric {metric}"),
+            }
+        }
+    }
+
+    #[test]
+    fn source_buffer_preallocation() {
+        /// Produces 10 points per poll.
+        struct TenPointsSource;
+        impl crate::pipeline::Source for TenPointsSource {
+            fn poll(
+                &mut self,
+                into: &mut MeasurementAccumulator,
+                timestamp: Timestamp,
+            ) -> Result<(), crate::pipeline::PollError> {
+                for _ in 0..10 {
+                    into.push(MeasurementPoint::new_untyped(
+                        timestamp,
+                        RawMetricId(1),
+                        Resource::LocalMachine,
+                        ResourceConsumer::LocalMachine,
+                        WrappedMeasurementValue::U64(1),
+                    ));
+                }
+                Ok(())
+            }
+        }
+
+        // Returns the length and capacity of the first buffer flushed by the source.
+        let first_flush = |points_per_poll: Option<usize>| {
+            let rt = new_rt(2);
+            let period = Duration::from_millis(10);
+            let tp = new_trigger(false, period, 3);
+            let (tx, mut rx) = mpsc::channel::<MeasurementBuffer>(64);
+            let (_cmd_tx, cmd_rx) = watch::channel(SourceCmd::SetTrigger(Some(tp)));
+            let settings = SourceTaskSettings {
+                points_per_poll,
+                ..Default::default()
+            };
+            rt.spawn(run_source(
+                String::from("test_source"),
+                Box::new(TenPointsSource),
+                tx,
+                cmd_rx,
+                settings,
+            ));
+            let buf = rt.block_on(rx.recv()).expect("the source should flush");
+            // drop the runtime first, to abort the source before its channel is closed
+            drop(rt);
+            (buf.len(), buf.capacity())
+        };
+
+        // with the right hint, 3 rounds of 10 points fit in the buffer without growing it
+        assert_eq!(first_flush(Some(10)), (30, 30));
+        // without a hint, the buffer starts with one point per round and grows
+        let (len, capacity) = first_flush(None);
+        assert_eq!(len, 30);
+        assert_ne!(capacity, 30);
+
+        // an unreasonable hint does not allocate a huge buffer
+        let capacity = source_buffer_capacity(usize::MAX, Some(usize::MAX));
+        assert_eq!(capacity, MAX_PREALLOCATED_POINTS);
+        assert_eq!(source_buffer_capacity(4, Some(0)), 4);
+    }
+
     #[test]
     fn transform_task() {
         let rt = new_rt(2);
@@ -1235,6 +2117,7 @@ mod tests {
                 transform: t,
                 name: String::from("test_transform"),
                 plugin_name: String::from(""),
+                input_metrics: None,
             })
             .collect();
 
@@ -1252,7 +2135,7 @@ mod tests {
 
         rt.spawn(async move {
             loop {
-                if let Ok(OutputMsg::WriteMeasurements(measurements)) = out_rx.recv().await {
+                if let Ok(OutputMsg::WriteMeasurements(measurements, _)) = out_rx.recv().await {
                     let current_flags = active_flags2.load(Ordering::Relaxed);
                     let transform1_enabled = current_flags & 1 != 0;
                     let transform2_enabled = current_flags & 2 != 0;
@@ -1283,7 +2166,13 @@ mod tests {
         });
 
         // run the transforms
-        rt.spawn(run_transforms(transforms, src_rx, trans_tx, active_flags3));
+        rt.spawn(run_transforms(
+            transforms,
+            src_rx,
+            trans_tx,
+            active_flags3,
+            TransformStepSettings::default(),
+        ));
 
         // poll the source for some time
         rt.spawn(run_source(
@@ -1291,6 +2180,7 @@ mod tests {
             Box::new(source),
             src_tx,
             src_cmd_rx,
+            SourceTaskSettings::default(),
         ));
         sleep(Duration::from_millis(20));
 
@@ -1317,6 +2207,120 @@ mod tests {
         sleep(Duration::from_millis(20));
     }
 
+    #[test]
+    fn sequence_numbers() {
+        let rt = new_rt(1);
+        let (src_tx, src_rx) = mpsc::channel::<MeasurementBuffer>(8);
+        let (out_tx, mut out_rx) = broadcast::channel::<OutputMsg>(8);
+        for _ in 0..3 {
+            src_tx.try_send(MeasurementBuffer::new()).unwrap();
+        }
+        drop(src_tx);
+        let active_flags = Arc::new(AtomicU64::new(u64::MAX));
+        rt.block_on(run_transforms(
+            Vec::new(),
+            src_rx,
+            out_tx,
+            active_flags,
+            TransformStepSettings {
+                sequence_numbers: true,
+                ..Default::default()
+            },
+        ))
+        .unwrap();
+
+        let mut numbers = Vec::new();
+        while let Ok(OutputMsg::WriteMeasurements(_, n)) = out_rx.try_recv() {
+            numbers.push(n);
+        }
+        assert_eq!(numbers, vec![Some(0), Some(1), Some(2)]);
+    }
+
+    /// Records the calls made by the pipeline, and fails to write if `fail` is true.
+    struct LifecycleOutput {
+        calls: Arc<Mutex<Vec<&'static str>>>,
+        fail: bool,
+    }
+
+    impl crate::pipeline::Output for LifecycleOutput {
+        fn write(&mut self, _: &MeasurementBuffer, _: &OutputContext) -> Result<(), crate::pipeline::WriteError> {
+            self.calls.lock().unwrap().push("write");
+            if self.fail {
+                return Err(crate::pipeline::WriteError::Fatal(anyhow::anyhow!("broken output")));
+            }
+            Ok(())
+        }
+
+        fn finalize(&mut self) -> anyhow::Result<()> {
+            self.calls.lock().unwrap().push("finalize");
+            Ok(())
+        }
+
+        fn stop(&mut self) -> anyhow::Result<()> {
+            self.calls.lock().unwrap().push("stop");
+            Ok(())
+        }
+    }
+
+    impl Drop for LifecycleOutput {
+        fn drop(&mut self) {
+            self.calls.lock().unwrap().push("drop");
+        }
+    }
+
+    #[test]
+    fn control_buffer_full() {
+        let (tx, rx) = mpsc::channel(1);
+        let handle = ControlHandle { tx };
+        handle.remove_source(SourceHandle::new()).unwrap();
+        assert_eq!(handle.remove_source(SourceHandle::new()), Err(ControlError::BufferFull));
+        let interval = Duration::from_secs(1);
+        let trigger = TriggerSpec::at_interval(interval);
+        assert_eq!(
+            handle.set_source_trigger(&SourceHandle::new(), trigger),
+            Err(ControlError::BufferFull)
+        );
+        let err = handle.set_source_interval(&SourceHandle::new(), interval).unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&ControlError::BufferFull));
+
+        // the pipeline is shutting down
+        drop(rx);
+        handle.remove_source(SourceHandle::new()).unwrap();
+    }
+
+    #[test]
+    fn output_finalize() {
+        let rt = new_rt(2);
+        let run_output = |fail: bool| {
+            let calls = Arc::new(Mutex::new(Vec::new()));
+            let output = Box::new(LifecycleOutput {
+                calls: calls.clone(),
+                fail,
+            });
+            let (tx, rx) = broadcast::channel::<OutputMsg>(8);
+            let (_cmd_tx, cmd_rx) = watch::channel(OutputCmd::Run);
+            let msg = OutputMsg::WriteMeasurements(MeasurementBuffer::new(), None);
+            tx.send(msg).unwrap();
+            // end of the stream
+            drop(tx);
+            let ctx = OutputContext::new(MetricRegistry::new());
+            let name = String::from("test_output");
+            let res = rt.block_on(run_output_from_broadcast(name, output, rx, cmd_rx, ctx, None));
+            let calls = calls.lock().unwrap().clone();
+            (res, calls)
+        };
+
+        // clean end: finalize, then stop, then drop
+        let (res, calls) = run_output(false);
+        res.unwrap();
+        assert_eq!(calls, vec!["write", "finalize", "stop", "drop"]);
+
+        // error: the output is stopped but not finalized
+        let (res, calls) = run_output(true);
+        assert!(res.is_err());
+        assert_eq!(calls, vec!["write", "stop", "drop"]);
+    }
+
     #[test]
     fn output_task() {
         let rt = new_rt(3);
@@ -1338,9 +2342,7 @@ mod tests {
             output_count: output_count.clone(),
         });
         let (out_cmd_tx, out_cmd_rx) = watch::channel(OutputCmd::Run);
-        let out_ctx = OutputContext {
-            metrics: MetricRegistry::new(),
-        };
+        let out_ctx = OutputContext::new(MetricRegistry::new());
 
         // start tasks
         rt.spawn(run_output_from_broadcast(
@@ -1349,9 +2351,22 @@ mod tests {
             out_rx,
             out_cmd_rx,
             out_ctx,
+            None,
+        ));
+        rt.spawn(run_transforms(
+            transforms,
+            trans_rx,
+            trans_tx,
+            active_flags,
+            TransformStepSettings::default(),
+        ));
+        rt.spawn(run_source(
+            String::from("test_source"),
+            source,
+            src_tx,
+            src_cmd_rx,
+            SourceTaskSettings::default(),
         ));
-        rt.spawn(run_transforms(transforms, trans_rx, trans_tx, active_flags));
-        rt.spawn(run_source(String::from("test_source"), source, src_tx, src_cmd_rx));
 
         // check the output
         sleep(Duration::from_millis(20));
@@ -1410,10 +2425,14 @@ mod tests {
 
     struct TestSource {
         n_calls: u32,
+        stop_called: Arc<AtomicBool>,
     }
     impl TestSource {
         fn new() -> TestSource {
-            TestSource { n_calls: 0 }
+            TestSource {
+                n_calls: 0,
+                stop_called: Arc::new(AtomicBool::new(false)),
+            }
         }
     }
     impl crate::pipeline::Source for TestSource {
@@ -1433,6 +2452,11 @@ mod tests {
             into.push(point);
             Ok(())
         }
+
+        fn stop(&mut self) -> anyhow::Result<()> {
+            assert!(!self.stop_called.swap(true, Ordering::Relaxed), "stop called twice");
+            Ok(())
+        }
     }
 
     struct TestTransform {
@@ -1,5 +1,11 @@
 //! Utilities for working with OS threads.
 
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::io;
+
+use anyhow::{anyhow, Context};
+
 /// Increases the priority of the current thread.
 pub fn increase_thread_priority() -> std::io::Result<()> {
     #[cfg(target_os = "linux")]
@@ -18,3 +24,149 @@ pub fn increase_thread_priority() -> std::io::Result<()> {
     #[cfg(not(target_os = "linux"))]
     Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "cannot increase the thread priority on this platform"))
 }
+
+/// Restricts the current thread to the given CPUs, identified by their number.
+pub fn set_thread_affinity(cpus: &[usize]) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+        for &cpu in cpus {
+            if cpu >= libc::CPU_SETSIZE as usize {
+                let msg = format!("CPU {cpu} is out of range");
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+            }
+            unsafe { libc::CPU_SET(cpu, &mut set) };
+        }
+        let res = unsafe { libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) };
+        if res < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = cpus;
+        let msg = "cannot set the CPU affinity on this platform";
+        Err(io::Error::new(io::ErrorKind::Unsupported, msg))
+    }
+}
+
+/// Returns the CPUs on which the current thread is allowed to run.
+pub fn thread_affinity() -> io::Result<Vec<usize>> {
+    #[cfg(target_os = "linux")]
+    {
+        let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+        let res = unsafe { libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set) };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let cpus = (0..libc::CPU_SETSIZE as usize).filter(|cpu| unsafe { libc::CPU_ISSET(*cpu, &set) });
+        Ok(cpus.collect())
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let msg = "cannot get the CPU affinity on this platform";
+        Err(io::Error::new(io::ErrorKind::Unsupported, msg))
+    }
+}
+
+/// Pins the current thread to the given CPUs, and logs the affinity that applies.
+///
+/// Failures are logged: the thread keeps running on the CPUs it was allowed to use before.
+pub(crate) fn pin_current_thread(cpus: &[usize]) {
+    let current_thread = std::thread::current();
+    let thread_name = current_thread.name().unwrap_or("<unnamed>");
+    match set_thread_affinity(cpus).and_then(|_| thread_affinity()) {
+        Ok(applied) => log::debug!("Thread {thread_name} pinned to CPUs {}", format_cpu_list(&applied)),
+        Err(e) => log::error!(
+            "Unable to pin thread {thread_name} to CPUs {}: {e}",
+            format_cpu_list(cpus)
+        ),
+    }
+}
+
+/// Parses a list of CPUs in the format of the Linux kernel, such as `0-3,8,10-11`.
+///
+/// The CPUs are returned in ascending order, without duplicates.
+pub fn parse_cpu_list(list: &str) -> anyhow::Result<Vec<usize>> {
+    let mut cpus = BTreeSet::new();
+    for part in list.split(',').map(str::trim) {
+        let parse = |n: &str| parse_cpu(n, list);
+        match part.split_once('-') {
+            Some((first, last)) => {
+                let (first, last) = (parse(first)?, parse(last)?);
+                if first > last {
+                    return Err(anyhow!("invalid CPU range '{part}' in CPU list '{list}'"));
+                }
+                cpus.extend(first..=last);
+            }
+            None => {
+                cpus.insert(parse(part)?);
+            }
+        }
+    }
+    Ok(cpus.into_iter().collect())
+}
+
+fn parse_cpu(n: &str, list: &str) -> anyhow::Result<usize> {
+    n.trim().parse().with_context(|| format!("invalid CPU list '{list}'"))
+}
+
+/// Formats a list of CPUs in the format of the Linux kernel, such as `0-3,8,10-11`.
+///
+/// The CPUs must be sorted in ascending order.
+pub fn format_cpu_list(cpus: &[usize]) -> String {
+    let mut res = String::new();
+    let mut i = 0;
+    while i < cpus.len() {
+        let first = cpus[i];
+        while i + 1 < cpus.len() && cpus[i + 1] == cpus[i] + 1 {
+            i += 1;
+        }
+        if !res.is_empty() {
+            res.push(',');
+        }
+        match cpus[i] {
+            last if last == first => write!(res, "{first}").unwrap(),
+            last => write!(res, "{first}-{last}").unwrap(),
+        }
+        i += 1;
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_cpu_list, parse_cpu_list};
+
+    #[test]
+    fn cpu_list() {
+        assert_eq!(parse_cpu_list("0").unwrap(), vec![0]);
+        assert_eq!(parse_cpu_list("0-3,8,10-11").unwrap(), vec![0, 1, 2, 3, 8, 10, 11]);
+        assert_eq!(parse_cpu_list(" 4, 2-3 ,2").unwrap(), vec![2, 3, 4]);
+        assert!(parse_cpu_list("").is_err());
+        assert!(parse_cpu_list("3-1").is_err());
+        assert!(parse_cpu_list("0,a").is_err());
+        assert!(parse_cpu_list("1-").is_err());
+
+        assert_eq!(format_cpu_list(&[0, 1, 2, 3, 8, 10, 11]), "0-3,8,10-11");
+        assert_eq!(format_cpu_list(&[5]), "5");
+        assert_eq!(format_cpu_list(&[]), "");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn thread_affinity() {
+        // restrict a new thread to one of the CPUs that the test is allowed to use
+        std::thread::spawn(|| {
+            let allowed = super::thread_affinity().unwrap();
+            assert!(!allowed.is_empty());
+            let cpu = *allowed.last().unwrap();
+            super::set_thread_affinity(&[cpu]).unwrap();
+            assert_eq!(super::thread_affinity().unwrap(), vec![cpu]);
+        })
+        .join()
+        .unwrap();
+    }
+}
@@ -0,0 +1,1608 @@
+//! Generic transforms that can be used by any plugin.
+
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, Context};
+
+use crate::config::serde_duration;
+use crate::measurement::{MeasurementBuffer, MeasurementPoint, Timestamp, WrappedMeasurementValue};
+use crate::metrics::{RawMetricId, TypedMetricId};
+use crate::plugin::util::{CounterDiff, CounterDiffUpdate};
+use crate::plugin::AlumetStart;
+use crate::resources::{Resource, ResourceConsumer};
+use crate::time::Clock;
+use crate::units::{PrefixedUnit, Unit, UnitRegistry};
+
+use super::{Transform, TransformError};
+
+/// Converts cumulative counters to the difference between two consecutive values,
+/// for instance to get the energy consumed during each interval from an energy counter.
+///
+/// Only the measurements of the configured counter metrics are modified, the other ones are left untouched.
+/// The transform keeps the previous value of each counter, identified by its metric, resource and consumer.
+/// The first value of each counter is removed from the buffer, since there is no previous value to compare it with.
+///
+/// Wraparounds are handled by [`CounterDiff`], with the maximum value configured for each metric.
+pub struct CounterDiffTransform {
+    /// The counter metrics, with the maximum value of their counter.
+    max_values: HashMap<RawMetricId, u64>,
+    /// State of each counter, by metric.
+    /// There are usually few counters per metric, a linear search is enough.
+    counters: HashMap<RawMetricId, Vec<(Resource, ResourceConsumer, CounterDiff)>>,
+}
+
+impl CounterDiffTransform {
+    /// Creates a transform that applies to no metric, use [`with_counter`](Self::with_counter) to add some.
+    pub fn new() -> Self {
+        Self {
+            max_values: HashMap::new(),
+            counters: HashMap::new(),
+        }
+    }
+
+    /// Applies the transform to the measurements of `metric`, a counter that wraps around at `max_value`.
+    ///
+    /// The values of this metric must be of type `u64`.
+    pub fn with_counter(mut self, metric: RawMetricId, max_value: u64) -> Self {
+        self.max_values.insert(metric, max_value);
+        self
+    }
+}
+
+impl Default for CounterDiffTransform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Transform for CounterDiffTransform {
+    fn apply(&mut self, measurements: &mut MeasurementBuffer) -> Result<(), TransformError> {
+        let mut invalid: Option<RawMetricId> = None;
+        measurements.retain(|m| {
+            let Some(max_value) = self.max_values.get(&m.metric) else {
+                return true; // not a counter, keep it as is
+            };
+            let WrappedMeasurementValue::U64(value) = m.value else {
+                invalid = Some(m.metric);
+                return false;
+            };
+            let counters = self.counters.entry(m.metric).or_default();
+            let i = match counters.iter().position(|(r, c, _)| r == &m.resource && c == &m.consumer) {
+                Some(i) => i,
+                None => {
+                    let counter = CounterDiff::with_max_value(*max_value);
+                    counters.push((m.resource.clone(), m.consumer.clone(), counter));
+                    counters.len() - 1
+                }
+            };
+            match counters[i].2.update(value) {
+                CounterDiffUpdate::FirstTime => false,
+                CounterDiffUpdate::Difference(diff) | CounterDiffUpdate::CorrectedDifference(diff) => {
+                    m.value = WrappedMeasurementValue::U64(diff);
+                    true
+                }
+            }
+        });
+        match invalid {
+            Some(metric) => Err(TransformError::UnexpectedInput(anyhow!(
+                "counter metric {metric:?} should have u64 values, the measurements of type f64 have been removed"
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    fn input_metrics(&self) -> Option<Vec<RawMetricId>> {
+        Some(self.max_values.keys().copied().collect())
+    }
+}
+
+/// Applies several transforms in sequence, so that a plugin can register them as one transform.
+///
+/// If a transform fails, the next ones are not applied, and the error indicates which transform has failed.
+/// The kind of error (fatal or unexpected input) is preserved: the pipeline handles the failure of the chain
+/// like the failure of the transform itself.
+///
+/// ## Example
+/// ```
+/// use alumet::metrics::RawMetricId;
+/// use alumet::pipeline::transforms::{CounterDiffTransform, TransformChain};
+///
+/// # fn example(counter: RawMetricId) {
+/// let chain = TransformChain::new()
+///     .with("counter_diff", Box::new(CounterDiffTransform::new().with_counter(counter, u64::MAX)));
+/// # }
+/// ```
+#[derive(Default)]
+pub struct TransformChain {
+    transforms: Vec<(String, Box<dyn Transform>)>,
+}
+
+impl TransformChain {
+    /// Creates an empty chain, which does nothing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a transform to the chain. The name is used in the error messages.
+    pub fn with(mut self, name: impl Into<String>, transform: Box<dyn Transform>) -> Self {
+        self.transforms.push((name.into(), transform));
+        self
+    }
+
+    /// The number of transforms in the chain.
+    pub fn len(&self) -> usize {
+        self.transforms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transforms.is_empty()
+    }
+}
+
+impl Transform for TransformChain {
+    fn apply(&mut self, measurements: &mut MeasurementBuffer) -> Result<(), TransformError> {
+        for (i, (name, transform)) in self.transforms.iter_mut().enumerate() {
+            let context = || format!("transform {i} of the chain ({name}) failed");
+            transform.apply(measurements).map_err(|e| match e {
+                TransformError::Fatal(e) => TransformError::Fatal(e.context(context())),
+                TransformError::UnexpectedInput(e) => TransformError::UnexpectedInput(e.context(context())),
+            })?;
+        }
+        Ok(())
+    }
+
+    /// The chain applies to the metrics of its transforms, unless one of them needs every measurement.
+    fn input_metrics(&self) -> Option<Vec<RawMetricId>> {
+        let mut metrics = Vec::new();
+        for (_, transform) in &self.transforms {
+            metrics.extend(transform.input_metrics()?);
+        }
+        Some(metrics)
+    }
+}
+
+/// Computes the rate of change per second of some metrics, for instance to get the power (in Watts)
+/// from the energy consumed during each interval (in Joules).
+///
+/// The value of each measurement of a configured metric is divided by the time elapsed since the previous
+/// measurement of the same metric, resource and consumer. The result replaces the measurement, with the output
+/// metric of the rate. The first measurement of each series is removed, since there is no previous measurement.
+///
+/// A series that has not been updated for `expiry`, according to the [`Clock`] of the transform, is forgotten:
+/// its next measurement is treated like the first one, instead of producing an average over the whole gap.
+pub struct RateTransform {
+    clock: Arc<dyn Clock>,
+    expiry: Duration,
+    /// The input metrics, with the metric of their rate.
+    outputs: HashMap<RawMetricId, RawMetricId>,
+    /// State of each series, by metric: timestamp of the previous measurement and time of the last update.
+    /// There are usually few series per metric, a linear search is enough.
+    previous: HashMap<RawMetricId, Vec<(Resource, ResourceConsumer, SystemTime, SystemTime)>>,
+}
+
+impl RateTransform {
+    /// Creates a transform that applies to no metric, use [`with_metric`](Self::with_metric) to add some.
+    ///
+    /// Use [`AlumetStart::clock`](crate::plugin::AlumetStart::clock) to obtain the clock of the pipeline.
+    pub fn new(clock: Arc<dyn Clock>, expiry: Duration) -> Self {
+        Self {
+            clock,
+            expiry,
+            outputs: HashMap::new(),
+            previous: HashMap::new(),
+        }
+    }
+
+    /// Replaces the measurements of `input` by their rate per second, as measurements of `output`.
+    pub fn with_metric(mut self, input: RawMetricId, output: TypedMetricId<f64>) -> Self {
+        self.outputs.insert(input, output.0);
+        self
+    }
+}
+
+impl Transform for RateTransform {
+    fn apply(&mut self, measurements: &mut MeasurementBuffer) -> Result<(), TransformError> {
+        // forget the series that have not been updated for a long time
+        let now = SystemTime::from(self.clock.now());
+        for series in self.previous.values_mut() {
+            series.retain(|(_, _, _, updated)| now.duration_since(*updated).unwrap_or_default() < self.expiry);
+        }
+
+        measurements.retain(|m| {
+            let Some(output) = self.outputs.get(&m.metric) else {
+                return true; // not an input metric, keep it as is
+            };
+            let t = SystemTime::from(m.timestamp);
+            let series = self.previous.entry(m.metric).or_default();
+            let prev_t = match series.iter_mut().find(|(r, c, _, _)| r == &m.resource && c == &m.consumer) {
+                Some((_, _, prev_t, updated)) => {
+                    *updated = now;
+                    std::mem::replace(prev_t, t)
+                }
+                None => {
+                    series.push((m.resource.clone(), m.consumer.clone(), t, now));
+                    return false;
+                }
+            };
+            match t.duration_since(prev_t) {
+                Ok(elapsed) if !elapsed.is_zero() => {
+                    m.metric = *output;
+                    m.value = WrappedMeasurementValue::F64(m.value.as_f64() / elapsed.as_secs_f64());
+                    true
+                }
+                _ => false, // no time elapsed, or the measurements are out of order
+            }
+        });
+        Ok(())
+    }
+
+    fn input_metrics(&self) -> Option<Vec<RawMetricId>> {
+        Some(self.outputs.keys().copied().collect())
+    }
+}
+
+/// Computes the energy efficiency of an application, in joules per operation, from the energy consumed
+/// during each interval (for instance, measured by RAPL) and the throughput of the application, in operations per second.
+///
+/// For each measurement of the energy, the number of operations performed during the interval is estimated
+/// with the latest throughput: `joules_per_op = energy / (throughput * interval)`, where `interval` is the time
+/// elapsed since the previous measurement of the energy. The result is added to the buffer with the output metric,
+/// at the timestamp of the energy measurement. The input measurements are left untouched.
+///
+/// The measurements are matched according to a [`JoinKey`], and the transform keeps the latest energy and throughput
+/// of each key. Since the two metrics are not measured at the same time, nor necessarily at the same rate,
+/// a throughput is only used if its timestamp is within `max_skew` of the energy measurement.
+/// Each energy measurement produces at most one result.
+pub struct EfficiencyTransform {
+    energy: RawMetricId,
+    throughput: RawMetricId,
+    output: RawMetricId,
+    join: JoinKey,
+    max_skew: Duration,
+    on_zero_throughput: ZeroThroughputPolicy,
+    /// Latest inputs of each key. There are usually few keys, a linear search is enough.
+    series: Vec<EfficiencySeries>,
+}
+
+/// How the input measurements are matched by the [`EfficiencyTransform`] and the [`RatioTransform`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JoinKey {
+    /// Match the measurements that have the same resource and the same consumer.
+    #[default]
+    ResourceAndConsumer,
+    /// Match the measurements that have the same resource, whatever their consumer.
+    Resource,
+    /// Match the measurements that have the same consumer, whatever their resource.
+    Consumer,
+    /// Match all the measurements together, for instance the total energy of the machine
+    /// and the throughput of the only application that runs on it.
+    Global,
+}
+
+impl JoinKey {
+    /// Returns the parts of the resource and consumer that identify the key of a measurement.
+    fn select<'a>(
+        &self,
+        resource: &'a Resource,
+        consumer: &'a ResourceConsumer,
+    ) -> (Option<&'a Resource>, Option<&'a ResourceConsumer>) {
+        let resource = matches!(self, JoinKey::ResourceAndConsumer | JoinKey::Resource).then_some(resource);
+        let consumer = matches!(self, JoinKey::ResourceAndConsumer | JoinKey::Consumer).then_some(consumer);
+        (resource, consumer)
+    }
+}
+
+/// What the [`EfficiencyTransform`] does when no operation has been performed during an interval.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ZeroThroughputPolicy {
+    /// Don't produce any measurement for this interval.
+    #[default]
+    Skip,
+    /// Produce an infinite value, which flags the intervals in which energy was consumed for nothing.
+    Infinity,
+}
+
+struct EfficiencySeries {
+    resource: Option<Resource>,
+    consumer: Option<ResourceConsumer>,
+    /// Timestamp of the latest energy measurement.
+    energy_time: Option<SystemTime>,
+    /// Latest energy measurement that can be used: energy (in joules) and the interval that it covers.
+    energy: Option<(f64, Duration)>,
+    /// Latest throughput: timestamp and operations per second.
+    throughput: Option<(SystemTime, f64)>,
+}
+
+impl EfficiencyTransform {
+    /// Creates a transform that divides the measurements of `energy` (in joules) by the operations performed
+    /// during the same interval, computed from the measurements of `throughput` (in operations per second).
+    ///
+    /// By default, the measurements are matched by resource and consumer, the throughput can be one second apart
+    /// from the energy, and no result is produced when the throughput is zero.
+    pub fn new(energy: RawMetricId, throughput: RawMetricId, output: TypedMetricId<f64>) -> Self {
+        Self {
+            energy,
+            throughput,
+            output: output.0,
+            join: JoinKey::default(),
+            max_skew: Duration::from_secs(1),
+            on_zero_throughput: ZeroThroughputPolicy::default(),
+            series: Vec::new(),
+        }
+    }
+
+    /// Creates a transform from the names of the input metrics, which must have been registered
+    /// by the plugins started before the current one, and creates the output metric.
+    pub fn from_names(
+        alumet: &mut AlumetStart,
+        energy_metric: &str,
+        throughput_metric: &str,
+        output_metric: &str,
+    ) -> anyhow::Result<Self> {
+        let find = |name: &str| {
+            alumet.metrics().id_with_name(name).with_context(|| {
+                format!("metric {name} not found: is the plugin that creates it enabled, and declared as a dependency?")
+            })
+        };
+        let energy = find(energy_metric)?;
+        let throughput = find(throughput_metric)?;
+        let unit = custom_unit(alumet, "J/{op}", "J/op")?;
+        let output = alumet.create_metric::<f64>(
+            output_metric,
+            unit,
+            format!("Energy consumed per operation, computed from {energy_metric} and {throughput_metric}."),
+        )?;
+        Ok(Self::new(energy, throughput, output))
+    }
+
+    /// Sets how the energy and throughput measurements are matched.
+    pub fn with_join(mut self, join: JoinKey) -> Self {
+        self.join = join;
+        self
+    }
+
+    /// Sets the maximum time difference between an energy measurement and the throughput used with it.
+    pub fn with_max_skew(mut self, max_skew: Duration) -> Self {
+        self.max_skew = max_skew;
+        self
+    }
+
+    /// Sets what to do when the throughput is zero.
+    pub fn with_zero_throughput(mut self, policy: ZeroThroughputPolicy) -> Self {
+        self.on_zero_throughput = policy;
+        self
+    }
+
+    fn series_mut(&mut self, resource: &Resource, consumer: &ResourceConsumer) -> &mut EfficiencySeries {
+        let (resource, consumer) = self.join.select(resource, consumer);
+        let i = match self
+            .series
+            .iter()
+            .position(|s| s.resource.as_ref() == resource && s.consumer.as_ref() == consumer)
+        {
+            Some(i) => i,
+            None => {
+                self.series.push(EfficiencySeries {
+                    resource: resource.cloned(),
+                    consumer: consumer.cloned(),
+                    energy_time: None,
+                    energy: None,
+                    throughput: None,
+                });
+                self.series.len() - 1
+            }
+        };
+        &mut self.series[i]
+    }
+}
+
+/// Returns the custom unit with the given unique name, and registers it if it does not exist yet.
+///
+/// Several transforms of the same kind can use the same unit, hence it is only created once.
+fn custom_unit(alumet: &mut AlumetStart, unique_name: &str, display_name: &str) -> anyhow::Result<Unit> {
+    match alumet.metrics().units().with_name(unique_name) {
+        Some(unit) => Ok(unit),
+        None => Ok(alumet.create_unit(unique_name, display_name, None)?),
+    }
+}
+
+impl EfficiencySeries {
+    /// Computes the joules per operation if the latest energy and throughput match, and consumes the energy.
+    fn try_compute(&mut self, max_skew: Duration, on_zero: ZeroThroughputPolicy) -> Option<(SystemTime, f64)> {
+        let (t_energy, (joules, interval)) = (self.energy_time?, self.energy?);
+        let (t_throughput, ops_per_sec) = self.throughput?;
+        let skew = t_energy
+            .duration_since(t_throughput)
+            .or_else(|_| t_throughput.duration_since(t_energy))
+            .unwrap_or_default();
+        if skew > max_skew {
+            return None;
+        }
+        self.energy = None; // at most one result per energy measurement
+        let ops = ops_per_sec * interval.as_secs_f64();
+        if ops > 0.0 {
+            Some((t_energy, joules / ops))
+        } else {
+            match on_zero {
+                ZeroThroughputPolicy::Skip => None,
+                ZeroThroughputPolicy::Infinity => Some((t_energy, f64::INFINITY)),
+            }
+        }
+    }
+
+    fn output_point(&self, output: RawMetricId, t: SystemTime, value: f64) -> MeasurementPoint {
+        MeasurementPoint::new_untyped(
+            Timestamp::from(t),
+            output,
+            self.resource.clone().unwrap_or(Resource::LocalMachine),
+            self.consumer.clone().unwrap_or(ResourceConsumer::LocalMachine),
+            WrappedMeasurementValue::F64(value),
+        )
+    }
+}
+
+impl Transform for EfficiencyTransform {
+    fn apply(&mut self, measurements: &mut MeasurementBuffer) -> Result<(), TransformError> {
+        let (max_skew, on_zero, output) = (self.max_skew, self.on_zero_throughput, self.output);
+        let mut results = Vec::new();
+        for m in measurements.iter() {
+            let is_energy = m.metric == self.energy;
+            if !is_energy && m.metric != self.throughput {
+                continue;
+            }
+            let t = SystemTime::from(m.timestamp);
+            let value = m.value.as_f64();
+            let series = self.series_mut(&m.resource, &m.consumer);
+            if is_energy {
+                // the first measurement only gives the start of the next interval
+                let previous = series.energy_time.replace(t);
+                series.energy = previous
+                    .and_then(|prev| t.duration_since(prev).ok())
+                    .filter(|interval| !interval.is_zero())
+                    .map(|interval| (value, interval));
+            } else {
+                series.throughput = Some((t, value));
+            }
+            if let Some((t, value)) = series.try_compute(max_skew, on_zero) {
+                results.push(series.output_point(output, t, value));
+            }
+        }
+        for point in results {
+            measurements.push(point);
+        }
+        Ok(())
+    }
+
+    fn input_metrics(&self) -> Option<Vec<RawMetricId>> {
+        Some(vec![self.energy, self.throughput])
+    }
+}
+
+/// Divides the measurements of a metric by the measurements of another one, for instance
+/// to get the energy per unit of utilization, or the power per degree.
+///
+/// The measurements are matched according to a [`JoinKey`]. Before being matched, their resources can be replaced
+/// with [`with_resource_mapping`](Self::with_resource_mapping), for instance to join the measurements of a GPU
+/// with the measurements of the CPU package it is attached to. The transform keeps the latest numerator and
+/// denominator of each key, and produces a ratio each time one of them is updated, at the timestamp of the update.
+/// The input measurements are left untouched.
+///
+/// An input that is older than `max_staleness`, compared to the update, is not used: no ratio is produced
+/// until it is measured again.
+pub struct RatioTransform {
+    numerator: RawMetricId,
+    denominator: RawMetricId,
+    output: RawMetricId,
+    join: JoinKey,
+    /// Replacements of the resources, applied before the join.
+    resource_map: Vec<(Resource, Resource)>,
+    max_staleness: Duration,
+    on_zero_denominator: ZeroDenominatorPolicy,
+    /// Latest inputs of each key. There are usually few keys, a linear search is enough.
+    series: Vec<RatioSeries>,
+}
+
+/// What the [`RatioTransform`] does when the denominator is zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ZeroDenominatorPolicy {
+    /// Don't produce any measurement.
+    #[default]
+    Skip,
+    /// Produce the result of the floating-point division: an infinite value, or NaN if the numerator is zero too.
+    NonFinite,
+}
+
+/// Configuration of a [`RatioTransform`], to be read from the configuration of a plugin.
+///
+/// ## Example
+/// ```toml
+/// numerator = "rapl_consumed_energy"
+/// denominator = "nvml_gpu_utilization"
+/// output = "energy_per_gpu_utilization"
+/// join = "resource"
+/// max_staleness = "2s"
+///
+/// # the measurements of the GPU are joined with the measurements of the first CPU package
+/// [[resource_mapping]]
+/// from = "gpu:0000:01:00.0"
+/// to = "cpu_package:0"
+/// ```
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct RatioConfig {
+    /// Name of the metric to divide.
+    pub numerator: String,
+    /// Name of the metric to divide by.
+    pub denominator: String,
+    /// Name of the metric to create for the ratio.
+    pub output: String,
+    /// Unit of the ratio, as a standard unit name (for instance "W"), or any other name,
+    /// which is registered as a custom unit. By default, the ratio has no unit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unit: Option<String>,
+    /// How the numerator and denominator are matched.
+    #[serde(default)]
+    pub join: JoinKey,
+    /// Replacements of the resources of the inputs, applied before the join.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub resource_mapping: Vec<ResourceMapping>,
+    /// Maximum age of an input, compared to the other one, for it to be used in a ratio.
+    #[serde(with = "serde_duration", default = "default_max_staleness")]
+    pub max_staleness: Duration,
+    /// What to do when the denominator is zero.
+    #[serde(default)]
+    pub on_zero_denominator: ZeroDenominatorPolicy,
+}
+
+/// Replacement of a resource, written as `kind:id`, such as `cpu_package:0` or `local_machine`.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ResourceMapping {
+    pub from: String,
+    pub to: String,
+}
+
+fn default_max_staleness() -> Duration {
+    Duration::from_secs(1)
+}
+
+struct RatioSeries {
+    resource: Option<Resource>,
+    consumer: Option<ResourceConsumer>,
+    /// Latest numerator: timestamp and value.
+    numerator: Option<(SystemTime, f64)>,
+    /// Latest denominator: timestamp and value.
+    denominator: Option<(SystemTime, f64)>,
+}
+
+impl RatioTransform {
+    /// Creates a transform that divides the measurements of `numerator` by the measurements of `denominator`.
+    ///
+    /// By default, the measurements are matched by resource and consumer, the inputs can be one second apart,
+    /// and no result is produced when the denominator is zero.
+    pub fn new(numerator: RawMetricId, denominator: RawMetricId, output: TypedMetricId<f64>) -> Self {
+        Self {
+            numerator,
+            denominator,
+            output: output.0,
+            join: JoinKey::default(),
+            resource_map: Vec::new(),
+            max_staleness: default_max_staleness(),
+            on_zero_denominator: ZeroDenominatorPolicy::default(),
+            series: Vec::new(),
+        }
+    }
+
+    /// Creates a transform from its configuration. The input metrics must have been registered
+    /// by the plugins started before the current one. The output metric is created.
+    pub fn from_config(alumet: &mut AlumetStart, config: &RatioConfig) -> anyhow::Result<Self> {
+        let find = |name: &str| {
+            alumet.metrics().id_with_name(name).with_context(|| {
+                format!("metric {name} not found: is the plugin that creates it enabled, and declared as a dependency?")
+            })
+        };
+        let numerator = find(&config.numerator)?;
+        let denominator = find(&config.denominator)?;
+        let unit = match &config.unit {
+            None => Unit::Unity,
+            Some(name) => match name.parse() {
+                Ok(unit) => unit,
+                Err(_) => custom_unit(alumet, name, name)?,
+            },
+        };
+        let output = alumet.create_metric::<f64>(
+            &config.output,
+            unit,
+            format!("Ratio of {} to {}.", config.numerator, config.denominator),
+        )?;
+        let mut transform = Self::new(numerator, denominator, output)
+            .with_join(config.join)
+            .with_max_staleness(config.max_staleness)
+            .with_zero_denominator(config.on_zero_denominator);
+        for mapping in &config.resource_mapping {
+            let from = parse_resource(&mapping.from)?;
+            let to = parse_resource(&mapping.to)?;
+            transform = transform.with_resource_mapping(from, to);
+        }
+        Ok(transform)
+    }
+
+    /// Sets how the numerator and denominator are matched.
+    pub fn with_join(mut self, join: JoinKey) -> Self {
+        self.join = join;
+        self
+    }
+
+    /// Replaces the resource `from` by `to` in the inputs, before matching them.
+    pub fn with_resource_mapping(mut self, from: Resource, to: Resource) -> Self {
+        self.resource_map.push((from, to));
+        self
+    }
+
+    /// Sets the maximum time difference between the numerator and the denominator of a ratio.
+    pub fn with_max_staleness(mut self, max_staleness: Duration) -> Self {
+        self.max_staleness = max_staleness;
+        self
+    }
+
+    /// Sets what to do when the denominator is zero.
+    pub fn with_zero_denominator(mut self, policy: ZeroDenominatorPolicy) -> Self {
+        self.on_zero_denominator = policy;
+        self
+    }
+
+    fn series_mut(&mut self, resource: &Resource, consumer: &ResourceConsumer) -> &mut RatioSeries {
+        let resource = match self.resource_map.iter().find(|(from, _)| from == resource) {
+            Some((_, to)) => to,
+            None => resource,
+        };
+        let (resource, consumer) = self.join.select(resource, consumer);
+        let i = match self
+            .series
+            .iter()
+            .position(|s| s.resource.as_ref() == resource && s.consumer.as_ref() == consumer)
+        {
+            Some(i) => i,
+            None => {
+                self.series.push(RatioSeries {
+                    resource: resource.cloned(),
+                    consumer: consumer.cloned(),
+                    numerator: None,
+                    denominator: None,
+                });
+                self.series.len() - 1
+            }
+        };
+        &mut self.series[i]
+    }
+}
+
+/// Parses a resource written as `kind:id`, or `kind` if it has no id.
+fn parse_resource(s: &str) -> anyhow::Result<Resource> {
+    let (kind, id) = s.split_once(':').unwrap_or((s, ""));
+    Resource::parse(kind.to_owned(), id.to_owned()).map_err(|e| anyhow!("invalid resource {s}: {e}"))
+}
+
+impl RatioSeries {
+    /// Computes the ratio of the latest inputs, if they are close enough to `t`, the time of the update.
+    fn try_compute(&self, t: SystemTime, max_staleness: Duration, on_zero: ZeroDenominatorPolicy) -> Option<f64> {
+        let fresh = |input: Option<(SystemTime, f64)>| {
+            let (t_input, value) = input?;
+            let age = t
+                .duration_since(t_input)
+                .or_else(|_| t_input.duration_since(t))
+                .unwrap_or_default();
+            (age <= max_staleness).then_some(value)
+        };
+        let (numerator, denominator) = (fresh(self.numerator)?, fresh(self.denominator)?);
+        if denominator == 0.0 && on_zero == ZeroDenominatorPolicy::Skip {
+            return None;
+        }
+        Some(numerator / denominator)
+    }
+
+    fn output_point(&self, output: RawMetricId, t: SystemTime, value: f64) -> MeasurementPoint {
+        MeasurementPoint::new_untyped(
+            Timestamp::from(t),
+            output,
+            self.resource.clone().unwrap_or(Resource::LocalMachine),
+            self.consumer.clone().unwrap_or(ResourceConsumer::LocalMachine),
+            WrappedMeasurementValue::F64(value),
+        )
+    }
+}
+
+impl Transform for RatioTransform {
+    fn apply(&mut self, measurements: &mut MeasurementBuffer) -> Result<(), TransformError> {
+        let (max_staleness, on_zero, output) = (self.max_staleness, self.on_zero_denominator, self.output);
+        let mut results = Vec::new();
+        for m in measurements.iter() {
+            let is_numerator = m.metric == self.numerator;
+            if !is_numerator && m.metric != self.denominator {
+                continue;
+            }
+            let t = SystemTime::from(m.timestamp);
+            let value = m.value.as_f64();
+            let series = self.series_mut(&m.resource, &m.consumer);
+            if is_numerator {
+                series.numerator = Some((t, value));
+            } else {
+                series.denominator = Some((t, value));
+            }
+            if let Some(ratio) = series.try_compute(t, max_staleness, on_zero) {
+                results.push(series.output_point(output, t, ratio));
+            }
+        }
+        for point in results {
+            measurements.push(point);
+        }
+        Ok(())
+    }
+
+    fn input_metrics(&self) -> Option<Vec<RawMetricId>> {
+        Some(vec![self.numerator, self.denominator])
+    }
+}
+
+/// Fills the gaps in some series of gauges with synthetic points, for the outputs that require regular series.
+///
+/// Each series (metric, resource and consumer) is expected to be measured every `cadence`. When the time elapsed
+/// between two consecutive measurements of a series is long enough to contain some missing samples, the transform
+/// adds one point per missing sample, evenly spaced between the two measurements, with a value computed according
+/// to the [`FillStrategy`]. The gaps that are longer than `max_gap` are not filled: the source has probably stopped.
+///
+/// The synthetic points have the attributes of the measurement before the gap, and the [`FILLED_ATTRIBUTE`] set to
+/// `true`, so that the outputs can tell them apart from the real measurements. They are added to the buffer, after
+/// the other measurements, which are left untouched.
+///
+/// Only configure gauges, such as a power or a temperature: filling a series of deltas, like the energy consumed
+/// since the previous measurement, would count the same energy several times, and interpolating a counter would
+/// fabricate intermediate values that have never existed.
+pub struct GapFillTransform {
+    cadence: Duration,
+    max_gap: Duration,
+    strategy: FillStrategy,
+    /// The gauge metrics.
+    gauges: BTreeSet<RawMetricId>,
+    /// Last measurement of each series, by metric.
+    /// There are usually few series per metric, a linear search is enough.
+    last: HashMap<RawMetricId, Vec<MeasurementPoint>>,
+}
+
+/// How the [`GapFillTransform`] computes the value of a missing sample.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FillStrategy {
+    /// Linear interpolation between the measurements before and after the gap.
+    /// The `u64` values are rounded to the nearest integer.
+    #[default]
+    Linear,
+    /// The value of the measurement before the gap, carried forward.
+    LastKnown,
+}
+
+/// Key of the attribute that flags the synthetic points produced by the [`GapFillTransform`].
+pub const FILLED_ATTRIBUTE: &str = "filled";
+
+/// Configuration of a [`GapFillTransform`], to be read from the configuration of a plugin.
+///
+/// ## Example
+/// ```toml
+/// metrics = ["nvml_instant_power"]
+/// cadence = "1s"
+/// max_gap = "10s"
+/// strategy = "last_known"
+/// ```
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct GapFillConfig {
+    /// Names of the gauge metrics to fill.
+    pub metrics: Vec<String>,
+    /// Expected interval between two measurements of a series.
+    #[serde(with = "serde_duration")]
+    pub cadence: Duration,
+    /// Maximum duration of a gap that is filled.
+    #[serde(with = "serde_duration")]
+    pub max_gap: Duration,
+    /// How the missing values are computed.
+    #[serde(default)]
+    pub strategy: FillStrategy,
+}
+
+impl GapFillTransform {
+    /// Creates a transform that applies to no metric, use [`with_gauge`](Self::with_gauge) to add some.
+    ///
+    /// By default, the missing values are interpolated linearly.
+    ///
+    /// # Panics
+    /// Panics if `cadence` is zero.
+    pub fn new(cadence: Duration, max_gap: Duration) -> Self {
+        assert!(!cadence.is_zero(), "the cadence of the gap filling must not be zero");
+        Self {
+            cadence,
+            max_gap,
+            strategy: FillStrategy::default(),
+            gauges: BTreeSet::new(),
+            last: HashMap::new(),
+        }
+    }
+
+    /// Creates a transform from its configuration. The metrics must have been registered
+    /// by the plugins started before the current one.
+    pub fn from_config(alumet: &mut AlumetStart, config: &GapFillConfig) -> anyhow::Result<Self> {
+        if config.cadence.is_zero() {
+            return Err(anyhow!("the cadence of the gap filling must not be zero"));
+        }
+        let mut transform = Self::new(config.cadence, config.max_gap).with_strategy(config.strategy);
+        for name in &config.metrics {
+            let metric = alumet.metrics().id_with_name(name).with_context(|| {
+                format!("metric {name} not found: is the plugin that creates it enabled, and declared as a dependency?")
+            })?;
+            transform = transform.with_gauge(metric);
+        }
+        Ok(transform)
+    }
+
+    /// Fills the gaps of `metric`, which must be a gauge.
+    pub fn with_gauge(mut self, metric: RawMetricId) -> Self {
+        self.gauges.insert(metric);
+        self
+    }
+
+    /// Sets how the missing values are computed.
+    pub fn with_strategy(mut self, strategy: FillStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Returns the points that fill the gap between `prev` and `next`, if any.
+    fn fill(&self, prev: &MeasurementPoint, next: &MeasurementPoint) -> Vec<MeasurementPoint> {
+        let (t_prev, t_next) = (SystemTime::from(prev.timestamp), SystemTime::from(next.timestamp));
+        let gap = match t_next.duration_since(t_prev) {
+            Ok(gap) if gap <= self.max_gap => gap,
+            _ => return Vec::new(), // too long, or out of order
+        };
+        // the number of samples in the gap, tolerating some jitter
+        let n_missing = (gap.as_secs_f64() / self.cadence.as_secs_f64()).round() as u32;
+        // never more than the samples of the longest gap
+        let max_missing = u32::try_from(self.max_gap.as_nanos() / self.cadence.as_nanos()).unwrap_or(u32::MAX);
+        let n_missing = n_missing.saturating_sub(1).min(max_missing);
+        (1..=n_missing)
+            .map(|k| {
+                let fraction = f64::from(k) / f64::from(n_missing + 1);
+                let value = match (self.strategy, &prev.value, &next.value) {
+                    (FillStrategy::LastKnown, value, _) => value.clone(),
+                    (FillStrategy::Linear, WrappedMeasurementValue::U64(a), WrappedMeasurementValue::U64(b)) => {
+                        let v = *a as f64 + (*b as f64 - *a as f64) * fraction;
+                        WrappedMeasurementValue::U64(v.round() as u64)
+                    }
+                    (FillStrategy::Linear, a, b) => {
+                        let (a, b) = (a.as_f64(), b.as_f64());
+                        WrappedMeasurementValue::F64(a + (b - a) * fraction)
+                    }
+                };
+                let mut point = prev.clone().with_attr(FILLED_ATTRIBUTE, true);
+                point.timestamp = Timestamp::from(t_prev + gap.mul_f64(fraction));
+                point.value = value;
+                point
+            })
+            .collect()
+    }
+}
+
+impl Transform for GapFillTransform {
+    fn apply(&mut self, measurements: &mut MeasurementBuffer) -> Result<(), TransformError> {
+        let mut filled = Vec::new();
+        let mut latest: Option<SystemTime> = None;
+        for m in measurements.iter() {
+            if !self.gauges.contains(&m.metric) {
+                continue;
+            }
+            latest = latest.max(Some(SystemTime::from(m.timestamp)));
+            let series = self.last.entry(m.metric).or_default();
+            let Some(i) = series
+                .iter()
+                .position(|p| p.resource == m.resource && p.consumer == m.consumer)
+            else {
+                series.push(m.clone());
+                continue;
+            };
+            let prev = &self.last[&m.metric][i];
+            if SystemTime::from(m.timestamp) <= SystemTime::from(prev.timestamp) {
+                continue; // out of order, nothing to fill
+            }
+            filled.extend(self.fill(prev, m));
+            self.last.get_mut(&m.metric).unwrap()[i] = m.clone();
+        }
+        for point in filled {
+            measurements.push(point);
+        }
+
+        // forget the series whose gap would be too long to be filled
+        if let Some(cutoff) = latest.and_then(|t| t.checked_sub(self.max_gap)) {
+            for series in self.last.values_mut() {
+                series.retain(|p| SystemTime::from(p.timestamp) >= cutoff);
+            }
+        }
+        Ok(())
+    }
+
+    fn input_metrics(&self) -> Option<Vec<RawMetricId>> {
+        Some(self.gauges.iter().copied().collect())
+    }
+}
+
+/// Leaves the measurements untouched, and optionally logs a summary of what goes through it.
+///
+/// It can be inserted anywhere in the pipeline to check that the measurements flow as expected,
+/// without modifying them. With [`with_summary`](Self::with_summary), the transform counts the buffers, the points
+/// and the metrics it sees, and logs them at most once per `interval`, according to the [`Clock`] of the transform.
+/// The counts of the buffers that are not logged are accumulated in the next summary.
+pub struct IdentityTransform {
+    name: String,
+    summary: Option<SummaryLog>,
+}
+
+/// State of the periodic summary of the [`IdentityTransform`].
+struct SummaryLog {
+    level: log::Level,
+    interval: Duration,
+    clock: Arc<dyn Clock>,
+    /// Time of the last summary, `None` before the first one.
+    last_log: Option<SystemTime>,
+    buffers: usize,
+    points: usize,
+    metrics: BTreeSet<u64>,
+}
+
+impl IdentityTransform {
+    /// Creates a transform that does nothing. The name identifies the transform in the logs.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            summary: None,
+        }
+    }
+
+    /// Logs a summary of the measurements at `level`, at most once per `interval`.
+    ///
+    /// Use [`AlumetStart::clock`](crate::plugin::AlumetStart::clock) to obtain the clock of the pipeline.
+    pub fn with_summary(mut self, level: log::Level, interval: Duration, clock: Arc<dyn Clock>) -> Self {
+        self.summary = Some(SummaryLog {
+            level,
+            interval,
+            clock,
+            last_log: None,
+            buffers: 0,
+            points: 0,
+            metrics: BTreeSet::new(),
+        });
+        self
+    }
+}
+
+impl SummaryLog {
+    fn record(&mut self, measurements: &MeasurementBuffer) {
+        self.buffers += 1;
+        self.points += measurements.len();
+        self.metrics.extend(measurements.iter().map(|m| m.metric.as_u64()));
+    }
+
+    /// Returns the summary of the measurements recorded since the previous one, if it is time to log it.
+    fn take(&mut self, now: SystemTime) -> Option<String> {
+        if let Some(last) = self.last_log {
+            if now.duration_since(last).unwrap_or_default() < self.interval {
+                return None;
+            }
+        }
+        self.last_log = Some(now);
+        let metrics: Vec<String> = self.metrics.iter().map(|id| id.to_string()).collect();
+        let summary = format!(
+            "{} buffer(s), {} point(s), metric ids: [{}]",
+            self.buffers,
+            self.points,
+            metrics.join(", ")
+        );
+        self.buffers = 0;
+        self.points = 0;
+        self.metrics.clear();
+        Some(summary)
+    }
+}
+
+impl Transform for IdentityTransform {
+    fn apply(&mut self, measurements: &mut MeasurementBuffer) -> Result<(), TransformError> {
+        if let Some(summary) = &mut self.summary {
+            summary.record(measurements);
+            // check the level first, to avoid computing the time and the summary for nothing
+            if log::log_enabled!(summary.level) {
+                let now = SystemTime::from(summary.clock.now());
+                if let Some(text) = summary.take(now) {
+                    log::log!(summary.level, "[{}] {text}", self.name);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Rescales the measurements to one canonical unit per dimension, so that the outputs see consistent units,
+/// for instance watts instead of a mix of milliwatts and watts.
+///
+/// The unit of a metric is fixed when the metric is created. Hence, the rescaled measurements are moved
+/// to a new metric, which has the canonical unit, and their values become `f64`. The metrics that are already
+/// in a canonical unit, and the metrics whose unit cannot be converted to any canonical unit, are left untouched.
+pub struct UnitNormalizeTransform {
+    /// Output metric and conversion factor of each input metric.
+    conversions: HashMap<RawMetricId, (RawMetricId, f64)>,
+}
+
+/// Configuration of a [`UnitNormalizeTransform`], to be read from the configuration of a plugin.
+///
+/// ## Example
+/// ```toml
+/// # express the power in kilowatts, the energy in joules and the amounts of data in bytes
+/// units = ["kW", "J", "B"]
+/// suffix = "_normalized"
+/// ```
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct UnitNormalizeConfig {
+    /// Canonical units, at most one per dimension, with an optional prefix (for instance "W" or "kW").
+    #[serde(default = "default_canonical_units")]
+    pub units: Vec<String>,
+    /// Suffix of the name of the normalized metrics, appended to the name of the original metric.
+    #[serde(default = "default_normalized_suffix")]
+    pub suffix: String,
+}
+
+fn default_canonical_units() -> Vec<String> {
+    vec![String::from("W"), String::from("J"), String::from("B")]
+}
+
+fn default_normalized_suffix() -> String {
+    String::from("_normalized")
+}
+
+impl Default for UnitNormalizeConfig {
+    fn default() -> Self {
+        Self {
+            units: default_canonical_units(),
+            suffix: default_normalized_suffix(),
+        }
+    }
+}
+
+impl UnitNormalizeTransform {
+    /// Creates a transform that does not convert any metric. Add conversions with [`with_metric`](Self::with_metric).
+    pub fn new() -> Self {
+        Self {
+            conversions: HashMap::new(),
+        }
+    }
+
+    /// Moves the measurements of `input` to `output`, multiplied by `factor`.
+    pub fn with_metric(mut self, input: RawMetricId, output: TypedMetricId<f64>, factor: f64) -> Self {
+        self.conversions.insert(input, (output.0, factor));
+        self
+    }
+
+    /// Creates a transform from its configuration, for the metrics registered by the plugins started
+    /// before the current one. A normalized metric is created for each metric that needs to be rescaled.
+    pub fn from_config(alumet: &mut AlumetStart, config: &UnitNormalizeConfig) -> anyhow::Result<Self> {
+        let canonical = config
+            .units
+            .iter()
+            .map(|u| u.parse().with_context(|| format!("invalid canonical unit {u}")))
+            .collect::<anyhow::Result<Vec<PrefixedUnit>>>()?;
+        let units = alumet.metrics().units();
+        for (i, a) in canonical.iter().enumerate() {
+            if let Some(b) = canonical[i + 1..]
+                .iter()
+                .find(|b| units.conversion_factor(a, b).is_some())
+            {
+                return Err(anyhow!(
+                    "{a} and {b} measure the same dimension, only one canonical unit per dimension is allowed"
+                ));
+            }
+        }
+
+        let mut planned: Vec<(RawMetricId, String, PrefixedUnit, f64)> = alumet
+            .metrics()
+            .iter()
+            .filter_map(|(id, metric)| {
+                let (unit, factor) = canonical_conversion(&metric.unit, units, &canonical)?;
+                Some((*id, metric.name.clone(), unit.clone(), factor))
+            })
+            .collect();
+        planned.sort_by_key(|(id, ..)| id.as_u64());
+
+        let mut transform = Self::new();
+        for (input, name, unit, factor) in planned {
+            let description = format!("{name}, in {unit}.");
+            let output = alumet.create_metric::<f64>(format!("{name}{}", config.suffix), unit, description)?;
+            transform = transform.with_metric(input, output, factor);
+        }
+        Ok(transform)
+    }
+}
+
+impl Default for UnitNormalizeTransform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns the canonical unit of `unit` and the factor to convert to it,
+/// or `None` if the unit is canonical or cannot be converted to any canonical unit.
+fn canonical_conversion<'a>(
+    unit: &PrefixedUnit,
+    units: &UnitRegistry,
+    canonical: &'a [PrefixedUnit],
+) -> Option<(&'a PrefixedUnit, f64)> {
+    if canonical.contains(unit) {
+        return None;
+    }
+    canonical
+        .iter()
+        .find_map(|c| units.conversion_factor(unit, c).map(|factor| (c, factor)))
+}
+
+impl Transform for UnitNormalizeTransform {
+    fn apply(&mut self, measurements: &mut MeasurementBuffer) -> Result<(), TransformError> {
+        for m in measurements.iter_mut() {
+            if let Some((output, factor)) = self.conversions.get(&m.metric) {
+                m.metric = *output;
+                m.value = WrappedMeasurementValue::F64(m.value.as_f64() * factor);
+            }
+        }
+        Ok(())
+    }
+
+    fn input_metrics(&self) -> Option<Vec<RawMetricId>> {
+        Some(self.conversions.keys().copied().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::marker::PhantomData;
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime};
+
+    use crate::measurement::{MeasurementBuffer, MeasurementPoint, Timestamp, WrappedMeasurementValue};
+    use crate::metrics::{RawMetricId, TypedMetricId};
+    use crate::pipeline::Transform;
+    use crate::resources::{Resource, ResourceConsumer};
+    use crate::time::MockClock;
+
+    use crate::units::{PrefixedUnit, Unit, UnitRegistry};
+
+    use super::{
+        canonical_conversion, CounterDiffTransform, EfficiencyTransform, FillStrategy, GapFillConfig, GapFillTransform,
+        IdentityTransform, JoinKey, RateTransform, RatioConfig, RatioTransform, TransformChain, UnitNormalizeConfig,
+        UnitNormalizeTransform, ZeroDenominatorPolicy, ZeroThroughputPolicy, FILLED_ATTRIBUTE,
+    };
+    use crate::pipeline::TransformError;
+
+    fn point(metric: usize, pkg: u32, value: u64) -> MeasurementPoint {
+        MeasurementPoint::new_untyped(
+            Timestamp::now(),
+            RawMetricId(metric),
+            Resource::CpuPackage { id: pkg },
+            ResourceConsumer::LocalMachine,
+            WrappedMeasurementValue::U64(value),
+        )
+    }
+
+    fn values(buf: &MeasurementBuffer) -> Vec<(usize, u64)> {
+        buf.iter()
+            .map(|m| match m.value {
+                WrappedMeasurementValue::U64(v) => (m.metric.0, v),
+                WrappedMeasurementValue::F64(_) => panic!("unexpected f64 value"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn counter_diff() {
+        let mut t = CounterDiffTransform::new().with_counter(RawMetricId(0), 100);
+
+        // first values: skipped, except for the metric that is not a counter
+        let mut buf = MeasurementBuffer::from(vec![point(0, 0, 10), point(0, 1, 50), point(1, 0, 7)]);
+        t.apply(&mut buf).unwrap();
+        assert_eq!(values(&buf), vec![(1, 7)]);
+
+        // one counter per resource
+        let mut buf = MeasurementBuffer::from(vec![point(0, 0, 15), point(0, 1, 70), point(1, 0, 8)]);
+        t.apply(&mut buf).unwrap();
+        assert_eq!(values(&buf), vec![(0, 5), (0, 20), (1, 8)]);
+
+        // wraparound
+        let mut buf = MeasurementBuffer::from(vec![point(0, 0, 5)]);
+        t.apply(&mut buf).unwrap();
+        assert_eq!(values(&buf), vec![(0, 90)]);
+    }
+
+    #[test]
+    fn rate_with_mock_clock() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        let clock = MockClock::new(start);
+        let output = TypedMetricId(RawMetricId(10), PhantomData);
+        let mut t = RateTransform::new(Arc::new(clock.clone()), Duration::from_secs(60))
+            .with_metric(RawMetricId(0), output);
+        let at = |secs: u64, metric: usize, value: u64| {
+            let mut p = point(metric, 0, value);
+            p.timestamp = Timestamp::from(start + Duration::from_secs(secs));
+            p
+        };
+        let rates = |buf: &MeasurementBuffer| -> Vec<(usize, f64)> {
+            buf.iter().map(|m| (m.metric.0, m.value.as_f64())).collect()
+        };
+
+        // first measurement: no rate yet
+        let mut buf = MeasurementBuffer::from(vec![at(0, 0, 100), at(0, 1, 7)]);
+        t.apply(&mut buf).unwrap();
+        assert_eq!(rates(&buf), vec![(1, 7.0)]);
+
+        clock.advance(Duration::from_secs(2));
+        let mut buf = MeasurementBuffer::from(vec![at(2, 0, 50), at(4, 0, 30)]);
+        t.apply(&mut buf).unwrap();
+        assert_eq!(rates(&buf), vec![(10, 25.0), (10, 15.0)]);
+
+        // the series expires: the next measurement is treated like the first one
+        clock.advance(Duration::from_secs(120));
+        let mut buf = MeasurementBuffer::from(vec![at(122, 0, 1000)]);
+        t.apply(&mut buf).unwrap();
+        assert!(rates(&buf).is_empty());
+        clock.advance(Duration::from_secs(1));
+        let mut buf = MeasurementBuffer::from(vec![at(123, 0, 5)]);
+        t.apply(&mut buf).unwrap();
+        assert_eq!(rates(&buf), vec![(10, 5.0)]);
+    }
+
+    /// Adds a constant to the values, or fails if the buffer contains `fail_on`.
+    struct AddTransform {
+        add: u64,
+        fail_on: Option<u64>,
+    }
+
+    impl Transform for AddTransform {
+        fn apply(&mut self, measurements: &mut MeasurementBuffer) -> Result<(), TransformError> {
+            for m in measurements.iter_mut() {
+                let v = m.value.as_u64().unwrap();
+                if Some(v) == self.fail_on {
+                    return Err(TransformError::UnexpectedInput(anyhow::anyhow!("bad value {v}")));
+                }
+                m.value = WrappedMeasurementValue::U64(v + self.add);
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn transform_chain() {
+        let mut chain = TransformChain::new()
+            .with("add1", Box::new(AddTransform { add: 1, fail_on: None }))
+            .with("add10", Box::new(AddTransform { add: 10, fail_on: Some(2) }))
+            .with("add100", Box::new(AddTransform { add: 100, fail_on: None }));
+        assert_eq!(chain.len(), 3);
+
+        // applied in order
+        let mut buf = MeasurementBuffer::from(vec![point(0, 0, 0)]);
+        chain.apply(&mut buf).unwrap();
+        assert_eq!(values(&buf), vec![(0, 111)]);
+
+        // the second transform fails: the third one is not applied
+        let mut buf = MeasurementBuffer::from(vec![point(0, 0, 1)]);
+        let err = chain.apply(&mut buf).unwrap_err();
+        assert_eq!(values(&buf), vec![(0, 2)]);
+        match err {
+            TransformError::UnexpectedInput(e) => {
+                assert_eq!(format!("{e:#}"), "transform 1 of the chain (add10) failed: bad value 2")
+            }
+            TransformError::Fatal(e) => panic!("unexpected fatal error: {e:?}"),
+        }
+    }
+
+    #[test]
+    fn efficiency() {
+        const ENERGY: usize = 0;
+        const OPS: usize = 1;
+        let output = TypedMetricId(RawMetricId(10), PhantomData);
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        let at = |millis: u64, metric: usize, pkg: u32, value: u64| {
+            let mut p = point(metric, pkg, value);
+            p.timestamp = Timestamp::from(start + Duration::from_millis(millis));
+            p
+        };
+        let results = |buf: &MeasurementBuffer| -> Vec<(u32, f64)> {
+            buf.iter()
+                .filter(|m| m.metric.0 == 10)
+                .map(|m| match m.resource {
+                    Resource::CpuPackage { id } => (id, m.value.as_f64()),
+                    _ => panic!("unexpected resource {:?}", m.resource),
+                })
+                .collect()
+        };
+
+        let mut t = EfficiencyTransform::new(RawMetricId(ENERGY), RawMetricId(OPS), output);
+        // first energy measurement: no interval yet
+        let mut buf = MeasurementBuffer::from(vec![at(0, ENERGY, 0, 10), at(0, OPS, 0, 100)]);
+        t.apply(&mut buf).unwrap();
+        assert!(results(&buf).is_empty());
+        assert_eq!(buf.len(), 2);
+
+        // 20 J in 2 s, at 5 ops/s: 2 J/op (the previous throughput is too old)
+        let mut buf = MeasurementBuffer::from(vec![at(2000, ENERGY, 0, 20), at(2100, OPS, 0, 5)]);
+        t.apply(&mut buf).unwrap();
+        assert_eq!(results(&buf), vec![(0, 2.0)]);
+
+        // the throughput is too far from the energy, then arrives in the next buffer
+        let mut buf = MeasurementBuffer::from(vec![at(3200, ENERGY, 0, 12)]);
+        t.apply(&mut buf).unwrap();
+        assert!(results(&buf).is_empty());
+        let mut buf = MeasurementBuffer::from(vec![at(3500, OPS, 0, 20), at(3500, OPS, 1, 20)]);
+        t.apply(&mut buf).unwrap();
+        // 12 J in 1.2 s, at 20 ops/s
+        assert_eq!(results(&buf), vec![(0, 0.5)]);
+        // the energy has been used, the next throughput does not produce anything
+        let mut buf = MeasurementBuffer::from(vec![at(3600, OPS, 0, 40)]);
+        t.apply(&mut buf).unwrap();
+        assert!(results(&buf).is_empty());
+
+        // zero throughput
+        let mut buf = MeasurementBuffer::from(vec![at(4000, OPS, 0, 0), at(4000, ENERGY, 0, 10)]);
+        t.apply(&mut buf).unwrap();
+        assert!(results(&buf).is_empty());
+        let mut t = t.with_zero_throughput(ZeroThroughputPolicy::Infinity);
+        let mut buf = MeasurementBuffer::from(vec![at(5000, ENERGY, 0, 10)]);
+        t.apply(&mut buf).unwrap();
+        assert_eq!(results(&buf), vec![(0, f64::INFINITY)]);
+
+        // global join: the throughput of the machine applies to the energy of every package
+        let mut t = EfficiencyTransform::new(RawMetricId(ENERGY), RawMetricId(OPS), output)
+            .with_join(JoinKey::Global)
+            .with_max_skew(Duration::from_secs(10));
+        let mut buf = MeasurementBuffer::from(vec![at(0, ENERGY, 0, 1), at(1000, ENERGY, 1, 1), at(1000, OPS, 2, 4)]);
+        t.apply(&mut buf).unwrap();
+        let global: Vec<&MeasurementPoint> = buf.iter().filter(|m| m.metric.0 == 10).collect();
+        assert_eq!(global.len(), 1);
+        assert_eq!(global[0].value.as_f64(), 0.25);
+        assert_eq!(global[0].resource, Resource::LocalMachine);
+    }
+
+    #[test]
+    fn ratio() {
+        const ENERGY: usize = 0;
+        const UTIL: usize = 1;
+        let output = TypedMetricId(RawMetricId(10), PhantomData);
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        let at = |millis: u64, metric: usize, resource: Resource, value: u64| {
+            let mut p = point(metric, 0, value);
+            p.resource = resource;
+            p.timestamp = Timestamp::from(start + Duration::from_millis(millis));
+            p
+        };
+        let results = |buf: &MeasurementBuffer| -> Vec<(Resource, f64)> {
+            buf.iter()
+                .filter(|m| m.metric.0 == 10)
+                .map(|m| (m.resource.clone(), m.value.as_f64()))
+                .collect()
+        };
+        let pkg = |id| Resource::CpuPackage { id };
+        let gpu = || Resource::Gpu {
+            bus_id: "0000:01:00.0".into(),
+        };
+
+        // the GPU is joined with the first package
+        let mut t = RatioTransform::new(RawMetricId(ENERGY), RawMetricId(UTIL), output)
+            .with_join(JoinKey::Resource)
+            .with_resource_mapping(gpu(), pkg(0));
+        let mut buf = MeasurementBuffer::from(vec![at(0, ENERGY, pkg(0), 30), at(0, ENERGY, pkg(1), 50)]);
+        t.apply(&mut buf).unwrap();
+        assert!(results(&buf).is_empty());
+        assert_eq!(buf.len(), 2);
+        let mut buf = MeasurementBuffer::from(vec![at(500, UTIL, gpu(), 60)]);
+        t.apply(&mut buf).unwrap();
+        assert_eq!(results(&buf), vec![(pkg(0), 0.5)]);
+
+        // each update produces a ratio with the latest value of the other input
+        let mut buf = MeasurementBuffer::from(vec![at(1000, ENERGY, pkg(0), 15), at(1200, UTIL, gpu(), 5)]);
+        t.apply(&mut buf).unwrap();
+        assert_eq!(results(&buf), vec![(pkg(0), 0.25), (pkg(0), 3.0)]);
+
+        // the utilization is stale: no ratio
+        let mut buf = MeasurementBuffer::from(vec![at(5000, ENERGY, pkg(0), 10)]);
+        t.apply(&mut buf).unwrap();
+        assert!(results(&buf).is_empty());
+
+        // division by zero
+        let mut buf = MeasurementBuffer::from(vec![at(5100, UTIL, gpu(), 0)]);
+        t.apply(&mut buf).unwrap();
+        assert!(results(&buf).is_empty());
+        let mut t = t.with_zero_denominator(ZeroDenominatorPolicy::NonFinite);
+        let mut buf = MeasurementBuffer::from(vec![at(5200, ENERGY, pkg(0), 10)]);
+        t.apply(&mut buf).unwrap();
+        assert_eq!(results(&buf), vec![(pkg(0), f64::INFINITY)]);
+    }
+
+    #[test]
+    fn gap_fill() {
+        const POWER: usize = 0;
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        let at = |millis: u64, metric: usize, pkg: u32, value: u64| {
+            let mut p = point(metric, pkg, value).with_attr("domain", "package");
+            p.timestamp = Timestamp::from(start + Duration::from_millis(millis));
+            p
+        };
+        let filled = |buf: &MeasurementBuffer| -> Vec<(u64, u32, f64)> {
+            buf.iter()
+                .filter(|m| m.attributes().any(|(k, _)| k == FILLED_ATTRIBUTE))
+                .map(|m| {
+                    let millis = SystemTime::from(m.timestamp).duration_since(start).unwrap().as_millis();
+                    let pkg = match m.resource {
+                        Resource::CpuPackage { id } => id,
+                        _ => panic!("unexpected resource"),
+                    };
+                    (millis as u64, pkg, m.value.as_f64())
+                })
+                .collect()
+        };
+
+        let (cadence, max_gap) = (Duration::from_secs(1), Duration::from_secs(5));
+        let mut t = GapFillTransform::new(cadence, max_gap).with_gauge(RawMetricId(POWER));
+        let mut buf = MeasurementBuffer::from(vec![at(0, POWER, 0, 10), at(0, POWER, 1, 100), at(0, 1, 0, 7)]);
+        t.apply(&mut buf).unwrap();
+        assert!(filled(&buf).is_empty());
+
+        // two samples are missing for package 0, with some jitter, and none for package 1
+        let mut buf = MeasurementBuffer::from(vec![at(3100, POWER, 0, 40), at(1000, POWER, 1, 90), at(3000, 1, 0, 1)]);
+        t.apply(&mut buf).unwrap();
+        assert_eq!(filled(&buf), vec![(1033, 0, 20.0), (2066, 0, 30.0)]);
+        assert_eq!(buf.len(), 5);
+        let synthetic = buf.iter().last().unwrap();
+        assert_eq!(synthetic.attributes().filter(|(k, _)| *k == "domain").count(), 1);
+
+        // the gap is too long
+        let mut buf = MeasurementBuffer::from(vec![at(9200, POWER, 0, 10)]);
+        t.apply(&mut buf).unwrap();
+        assert!(filled(&buf).is_empty());
+
+        // package 1 has been forgotten, because its gap would be too long
+        assert_eq!(t.last[&RawMetricId(POWER)].len(), 1);
+
+        // last known value
+        let mut t = GapFillTransform::new(cadence, max_gap)
+            .with_gauge(RawMetricId(POWER))
+            .with_strategy(FillStrategy::LastKnown);
+        let mut buf = MeasurementBuffer::from(vec![at(0, POWER, 0, 10)]);
+        t.apply(&mut buf).unwrap();
+        let mut buf = MeasurementBuffer::from(vec![at(3000, POWER, 0, 40)]);
+        t.apply(&mut buf).unwrap();
+        assert_eq!(filled(&buf), vec![(1000, 0, 10.0), (2000, 0, 10.0)]);
+    }
+
+    #[test]
+    fn gap_fill_bounds() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        let at = |millis: u64| {
+            let mut p = point(0, 0, 0);
+            p.timestamp = Timestamp::from(start + Duration::from_millis(millis));
+            p
+        };
+        // the longest gap is filled with at most max_gap / cadence points
+        for (cadence, max_gap) in [(400, 1000), (300, 1000), (1000, 1000), (3, 10_000)] {
+            let t = GapFillTransform::new(Duration::from_millis(cadence), Duration::from_millis(max_gap));
+            let filled = t.fill(&at(0), &at(max_gap)).len() as u64;
+            assert!(
+                filled <= max_gap / cadence,
+                "{filled} points with cadence {cadence} and max_gap {max_gap}"
+            );
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "must not be zero")]
+    fn gap_fill_zero_cadence() {
+        GapFillTransform::new(Duration::ZERO, Duration::from_secs(10));
+    }
+
+    #[test]
+    fn gap_fill_config() {
+        let config: GapFillConfig = toml::from_str(
+            r#"
+            metrics = ["power"]
+            cadence = "1s"
+            max_gap = "10s"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.cadence, Duration::from_secs(1));
+        assert_eq!(config.strategy, FillStrategy::Linear);
+    }
+
+    #[test]
+    fn ratio_config() {
+        let config: RatioConfig = toml::from_str(
+            r#"
+            numerator = "energy"
+            denominator = "utilization"
+            output = "energy_per_utilization"
+            join = "resource"
+            max_staleness = "2s"
+
+            [[resource_mapping]]
+            from = "gpu:0000:01:00.0"
+            to = "cpu_package:0"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.join, JoinKey::Resource);
+        assert_eq!(config.max_staleness, Duration::from_secs(2));
+        assert_eq!(config.on_zero_denominator, ZeroDenominatorPolicy::Skip);
+        assert_eq!(config.resource_mapping[0].from, "gpu:0000:01:00.0");
+        assert_eq!(
+            super::parse_resource(&config.resource_mapping[0].from).unwrap(),
+            Resource::Gpu {
+                bus_id: "0000:01:00.0".into()
+            }
+        );
+        assert_eq!(super::parse_resource("local_machine").unwrap(), Resource::LocalMachine);
+        assert!(super::parse_resource("cpu_package:zero").is_err());
+    }
+
+    #[test]
+    fn unit_normalize() {
+        // metric 0 is in mW, metric 1 in W, metric 2 in J and metric 3 in °C
+        let canonical = [PrefixedUnit::from(Unit::Watt), PrefixedUnit::from(Unit::Joule)];
+        let units = UnitRegistry::new();
+        let conversion = |unit: PrefixedUnit| canonical_conversion(&unit, &units, &canonical);
+        assert_eq!(conversion(PrefixedUnit::milli(Unit::Watt)), Some((&canonical[0], 1e-3)));
+        assert_eq!(conversion(PrefixedUnit::kilo(Unit::Joule)), Some((&canonical[1], 1e3)));
+        assert_eq!(conversion(Unit::Watt.into()), None);
+        assert_eq!(conversion(Unit::DegreeCelsius.into()), None);
+
+        let output = TypedMetricId(RawMetricId(10), PhantomData);
+        let mut t = UnitNormalizeTransform::new().with_metric(RawMetricId(0), output, 1e-3);
+        assert_eq!(t.input_metrics(), Some(vec![RawMetricId(0)]));
+        let mut buf = MeasurementBuffer::from(vec![point(0, 0, 2500), point(1, 0, 3), point(0, 1, 500)]);
+        t.apply(&mut buf).unwrap();
+        let res: Vec<(usize, f64)> = buf.iter().map(|m| (m.metric.0, m.value.as_f64())).collect();
+        assert_eq!(res, vec![(10, 2.5), (1, 3.0), (10, 0.5)]);
+        let first = &buf.iter().next().unwrap().value;
+        assert!(matches!(first, WrappedMeasurementValue::F64(_)));
+
+        let config: UnitNormalizeConfig = toml::from_str(r#"units = ["kW", "J"]"#).unwrap();
+        assert_eq!(config.units, vec!["kW", "J"]);
+        assert_eq!(config.suffix, "_normalized");
+    }
+
+    #[test]
+    fn identity_summary() {
+        let clock = Arc::new(MockClock::new(SystemTime::UNIX_EPOCH));
+        let mut t = IdentityTransform::new("debug").with_summary(log::Level::Info, Duration::from_secs(10), clock);
+        let mut buf = MeasurementBuffer::new();
+        buf.push(point(2, 0, 10));
+        buf.push(point(0, 1, 20));
+        t.apply(&mut buf).unwrap();
+        assert_eq!(values(&buf), vec![(2, 10), (0, 20)]);
+
+        // the summaries are throttled, the counts accumulate until the next one
+        let summary = t.summary.as_mut().unwrap();
+        let t0 = SystemTime::UNIX_EPOCH;
+        assert_eq!(summary.take(t0).unwrap(), "1 buffer(s), 2 point(s), metric ids: [0, 2]");
+        summary.record(&buf);
+        buf.push(point(1, 0, 30));
+        summary.record(&buf);
+        assert_eq!(summary.take(t0 + Duration::from_secs(5)), None);
+        assert_eq!(
+            summary.take(t0 + Duration::from_secs(10)).unwrap(),
+            "2 buffer(s), 5 point(s), metric ids: [0, 1, 2]"
+        );
+        assert_eq!(
+            summary.take(t0 + Duration::from_secs(30)).unwrap(),
+            "0 buffer(s), 0 point(s), metric ids: []"
+        );
+    }
+}
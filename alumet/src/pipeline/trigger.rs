@@ -44,7 +44,8 @@ pub(crate) struct TriggerConfig {
     ///
     /// Updating more often increases the overhead of the measurement,
     /// but decreases the time it takes for a [source command](super::runtime::SourceCmd)
-    /// to be applied.
+    /// to be applied. A new trigger ([`SourceCmd::SetTrigger`](super::runtime::SourceCmd::SetTrigger))
+    /// is an exception: it is applied on the next poll.
     pub update_rounds: usize,
 }
 
@@ -0,0 +1,168 @@
+//! Subcommands that plugins contribute to the command-line interface of the application.
+//!
+//! Some plugins can answer useful queries on their own, without starting the measurement pipeline.
+//! For instance, the RAPL plugin can print the power zones of the machine.
+//! To expose such a query, return a [`PluginCommand`] from [`AlumetPlugin::commands`](super::rust::AlumetPlugin::commands).
+//! The application can then dispatch `<app> <plugin> <command> [args...]` with [`run_plugin_command`].
+//!
+//! Plugin commands are optional: an application that does not call [`run_plugin_command`]
+//! simply ignores them.
+
+use std::{fmt, io};
+
+use super::PluginMetadata;
+
+/// A subcommand contributed by a plugin, for instance `zones` in `alumet-agent rapl zones`.
+///
+/// The command runs without initializing the plugin: it must not rely on the plugin configuration.
+pub trait PluginCommand {
+    /// The name of the command. It must be unique among the commands of the plugin.
+    fn name(&self) -> &str;
+
+    /// A short description of the command, displayed in the help.
+    fn about(&self) -> &str;
+
+    /// Runs the command with the arguments that follow its name, and writes the result to `out`.
+    fn run(&self, args: &[String], out: &mut dyn io::Write) -> anyhow::Result<()>;
+}
+
+/// Error returned by [`run_plugin_command`] when the command line does not match any plugin command.
+#[derive(Debug)]
+pub enum CommandError {
+    /// There is no plugin with this name.
+    UnknownPlugin(String),
+    /// The plugin has no command with this name (or no name has been given), here are its commands.
+    UnknownCommand {
+        plugin: String,
+        command: Option<String>,
+        available: Vec<(String, String)>,
+    },
+}
+
+impl std::error::Error for CommandError {}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::UnknownPlugin(plugin) => write!(f, "unknown plugin or command: {plugin}"),
+            CommandError::UnknownCommand {
+                plugin,
+                command,
+                available,
+            } => {
+                match command {
+                    Some(cmd) => write!(f, "plugin {plugin} has no command {cmd}")?,
+                    None => write!(f, "missing command for plugin {plugin}")?,
+                }
+                if available.is_empty() {
+                    write!(f, " (it provides no command)")
+                } else {
+                    write!(f, ", available commands:")?;
+                    for (name, about) in available {
+                        write!(f, "\n  {name}\t{about}")?;
+                    }
+                    Ok(())
+                }
+            }
+        }
+    }
+}
+
+/// Runs the plugin command described by `args`, which is `[plugin, command, command_args...]`.
+pub fn run_plugin_command(plugins: &[PluginMetadata], args: &[String], out: &mut dyn io::Write) -> anyhow::Result<()> {
+    let (plugin_name, args) = args
+        .split_first()
+        .ok_or_else(|| CommandError::UnknownPlugin(String::new()))?;
+    let plugin = plugins
+        .iter()
+        .find(|p| &p.name == plugin_name)
+        .ok_or_else(|| CommandError::UnknownPlugin(plugin_name.clone()))?;
+    let commands = (plugin.commands)();
+    let (command_name, args) = match args.split_first() {
+        Some((name, args)) => (Some(name), args),
+        None => (None, args),
+    };
+    match commands.iter().find(|c| command_name.is_some_and(|n| c.name() == n)) {
+        Some(command) => command.run(args, out),
+        None => Err(CommandError::UnknownCommand {
+            plugin: plugin_name.clone(),
+            command: command_name.cloned(),
+            available: commands
+                .iter()
+                .map(|c| (c.name().to_owned(), c.about().to_owned()))
+                .collect(),
+        }
+        .into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::{run_plugin_command, CommandError, PluginCommand};
+    use crate::plugin::{rust::AlumetPlugin, AlumetStart, ConfigTable, PluginMetadata};
+
+    struct EchoCommand;
+
+    impl PluginCommand for EchoCommand {
+        fn name(&self) -> &str {
+            "echo"
+        }
+
+        fn about(&self) -> &str {
+            "Prints the arguments"
+        }
+
+        fn run(&self, args: &[String], out: &mut dyn io::Write) -> anyhow::Result<()> {
+            writeln!(out, "{}", args.join(" "))?;
+            Ok(())
+        }
+    }
+
+    struct CommandPlugin;
+
+    impl AlumetPlugin for CommandPlugin {
+        fn name() -> &'static str {
+            "cmd"
+        }
+
+        fn version() -> &'static str {
+            "0.0.1"
+        }
+
+        fn init(_config: ConfigTable) -> anyhow::Result<Box<Self>> {
+            Ok(Box::new(CommandPlugin))
+        }
+
+        fn commands() -> Vec<Box<dyn PluginCommand>> {
+            vec![Box::new(EchoCommand)]
+        }
+
+        fn start(&mut self, _alumet: &mut AlumetStart) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        fn stop(&mut self) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| (*a).to_owned()).collect()
+    }
+
+    #[test]
+    fn dispatch_plugin_command() {
+        let plugins = vec![PluginMetadata::from_static::<CommandPlugin>()];
+        let mut out = Vec::new();
+        run_plugin_command(&plugins, &args(&["cmd", "echo", "a", "b"]), &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "a b\n");
+
+        let err = run_plugin_command(&plugins, &args(&["nope", "echo"]), &mut Vec::new()).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(CommandError::UnknownPlugin(p)) if p == "nope"));
+
+        let err = run_plugin_command(&plugins, &args(&["cmd"]), &mut Vec::new()).unwrap_err();
+        assert!(err.to_string().contains("echo\tPrints the arguments"), "{err}");
+    }
+}
@@ -3,7 +3,7 @@
 use std::{
     collections::HashMap,
     ffi::{c_char, CStr},
-    path::Path,
+    path::{Path, PathBuf},
 };
 
 use crate::{
@@ -12,6 +12,7 @@ use crate::{
 };
 use libc::c_void;
 use libloading::{Library, Symbol};
+use serde::Serialize;
 
 use super::{version, AlumetStart, ConfigTable, Plugin};
 use crate::ffi;
@@ -88,8 +89,34 @@ pub enum LoadError {
 }
 
 /// Registry of plugins, to initialize dynamic plugins one by one.
+#[derive(Default)]
 pub struct PluginRegistry {
-    plugins: HashMap<String, Box<dyn Plugin>>,
+    plugins: HashMap<String, RegisteredPlugin>,
+}
+
+struct RegisteredPlugin {
+    plugin: Box<dyn Plugin>,
+    source: PluginSource,
+}
+
+/// Where a plugin comes from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum PluginSource {
+    /// The plugin is compiled into the application.
+    Static,
+    /// The plugin has been loaded from a shared library, with [`load_cdylib`].
+    Dylib { path: PathBuf },
+}
+
+/// Information about a plugin of the [`PluginRegistry`].
+///
+/// It implements [`Serialize`], which allows to list the plugins in a structured format, like TOML or JSON.
+#[derive(Debug, Clone, Serialize)]
+pub struct PluginInfo {
+    pub name: String,
+    pub version: String,
+    pub source: PluginSource,
 }
 
 /// Loads a dynamic plugin from a shared library file, and returns a [`PluginMetadata`] that allows to initialize the plugin.
@@ -104,6 +131,12 @@ pub struct PluginRegistry {
 /// - `plugin_stop: PluginStopFn`: see [`ffi::PluginStopFn`]
 /// - `plugin_drop: DropFn`: see [`ffi::DropFn`]
 ///
+/// The following symbols are optional:
+/// - `PLUGIN_DEPENDENCIES: *const c_char`: the names of the plugins that must be started before this one,
+/// separated by commas, as a null-terminated string (see [`PluginMetadata::dependencies`])
+/// - `PLUGIN_LOG_TARGET: *const c_char`: the target of the log records that the plugin forwards to the logger
+/// of the application, as a null-terminated string (see [`PluginMetadata::log_target`])
+///
 /// ### Declaration in Rust
 /// Declaring such variables and symbols in the Rust language would look like the following:
 /// ```ignore
@@ -113,6 +146,10 @@ pub struct PluginRegistry {
 /// pub static PLUGIN_VERSION: &[u8] = b"0.0.1\0";
 /// #[no_mangle]
 /// pub static ALUMET_VERSION: &[u8] = b"0.1.0\0";
+/// #[no_mangle]
+/// pub static PLUGIN_DEPENDENCIES: &[u8] = b"rapl,csv\0"; // optional
+/// #[no_mangle]
+/// pub static PLUGIN_LOG_TARGET: &[u8] = b"my_plugin\0"; // optional
 ///
 /// #[no_mangle]
 /// pub extern "C" fn plugin_init(config: &ConfigTable) -> *mut MyPluginStruct {}
@@ -130,6 +167,8 @@ pub struct PluginRegistry {
 /// PLUGIN_API const char *PLUGIN_NAME = "my-plugin";
 /// PLUGIN_API const char *PLUGIN_VERSION = "0.0.1";
 /// PLUGIN_API const char *ALUMET_VERSION = "0.1.0";
+/// PLUGIN_API const char *PLUGIN_DEPENDENCIES = "rapl,csv"; // optional
+/// PLUGIN_API const char *PLUGIN_LOG_TARGET = "my_plugin"; // optional
 ///
 /// PLUGIN_API MyPluginStruct *plugin_init(const ConfigTable *config) {}
 /// PLUGIN_API void plugin_start(MyPluginStruct *plugin, AlumetStart *alumet) {}
@@ -178,6 +217,12 @@ pub fn load_cdylib(file: &Path) -> Result<PluginMetadata, LoadError> {
     let sym_default_config: Option<Symbol<ffi::PluginDefaultConfigFn>> =
         unsafe { lib.get(b"plugin_default_config\0") }.ok();
 
+    // if this symbol is none, the plugin has no dependency
+    let sym_dependencies: Option<Symbol<*const *const c_char>> = unsafe { lib.get(b"PLUGIN_DEPENDENCIES\0") }.ok();
+
+    // if this symbol is none, the log records of the plugin cannot be filtered by the application
+    let sym_log_target: Option<Symbol<*const *const c_char>> = unsafe { lib.get(b"PLUGIN_LOG_TARGET\0") }.ok();
+
     log::debug!("symbols loaded");
 
     // convert the C strings to Rust strings, and wraps errors in LoadError::InvalidSymbol
@@ -193,6 +238,11 @@ pub fn load_cdylib(file: &Path) -> Result<PluginMetadata, LoadError> {
     let alumet_version = sym_to_string(&sym_alumet_version, "ALUMET_VERSION")?;
     log::debug!("plugin found: {name} v{version}  (requires ALUMET v{alumet_version})");
 
+    let dependencies = match sym_dependencies {
+        Some(sym) => parse_dependencies(&sym_to_string(&sym, "PLUGIN_DEPENDENCIES")?),
+        None => Vec::new(),
+    };
+
     // get the ALUMET version required by the plugin
     let plugin_alumet_version =
         Version::parse(&alumet_version).map_err(|e| LoadError::InvalidSymbol("ALUMET_VERSION", e.into()))?;
@@ -213,6 +263,13 @@ pub fn load_cdylib(file: &Path) -> Result<PluginMetadata, LoadError> {
     let drop_fn = *sym_drop;
     let default_config_fn = sym_default_config.map(|sym| *sym);
 
+    // A dynamic library has its own copy of the `log` crate, hence its records only go through the logger
+    // of the application if the plugin forwards them, in which case it declares their target.
+    let log_target = match sym_log_target {
+        Some(sym) => Some(sym_to_string(&sym, "PLUGIN_LOG_TARGET")?),
+        None => None,
+    };
+
     // wrap the plugin info in a Rust struct, to allow the plugin to be initialized later
     let initializable_info = PluginMetadata {
         name: name.clone(),
@@ -244,15 +301,29 @@ pub fn load_cdylib(file: &Path) -> Result<PluginMetadata, LoadError> {
                 log::debug!("filling default config");
                 f(&mut config_to_fill);
                 log::debug!("default config filled");
-                Ok(Some(ConfigTable(config_to_fill)))
+                Ok(Some(ConfigTable::from(config_to_fill)))
             }),
             None => Box::new(|| Ok(None)),
         },
+        dependencies,
+        config_required: true,
+        config_schema: Box::new(|| None),
+        commands: Box::new(Vec::new),
+        log_target,
     };
 
     Ok(initializable_info)
 }
 
+/// Parses the value of the `PLUGIN_DEPENDENCIES` symbol: plugin names separated by commas.
+fn parse_dependencies(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(String::from)
+        .collect()
+}
+
 /// Initializes a plugin, using its [`PluginMetadata`] and config table (not the global configuration).
 pub fn initialize(plugin: PluginMetadata, config: ConfigTable) -> anyhow::Result<Box<dyn Plugin>> {
     let plugin_instance = (plugin.init)(config)?;
@@ -260,15 +331,24 @@ pub fn initialize(plugin: PluginMetadata, config: ConfigTable) -> anyhow::Result
 }
 
 /// Extracts the config table of a specific plugin from the global config.
+///
+/// If the table is missing and the plugin does not require a configuration
+/// (see [`PluginMetadata::config_required`]), the default config of the plugin is returned,
+/// or an empty table if the plugin has no default config.
 pub fn plugin_subconfig(plugin: &PluginMetadata, global_config: &mut toml::Table) -> anyhow::Result<ConfigTable> {
     let name = &plugin.name;
     let sub_config = global_config.remove(name);
     match sub_config {
-        Some(toml::Value::Table(t)) => Ok(ConfigTable(t)),
+        Some(toml::Value::Table(t)) => Ok(ConfigTable::from(t)),
         Some(bad_value) => Err(anyhow::anyhow!(
             "invalid plugin configuration for '{name}': the value must be a table, not a {}.",
             bad_value.type_str()
         )),
+        None if !plugin.config_required => {
+            log::debug!("No configuration for plugin '{name}', using its default configuration.");
+            let default = (plugin.default_config)()?;
+            Ok(default.unwrap_or_else(|| ConfigTable::from(toml::Table::new())))
+        }
         None => Err(anyhow::anyhow!("missing plugin configuration for '{name}'")),
     }
 }
@@ -296,15 +376,135 @@ impl From<version::Error> for LoadError {
 }
 
 impl PluginRegistry {
-    /// Adds a plugin to the registry.
+    /// Creates an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a static plugin to the registry.
     pub fn register(&mut self, plugin: Box<dyn Plugin>) {
-        self.plugins.insert(plugin.name().into(), plugin);
+        self.register_with_source(plugin, PluginSource::Static);
+    }
+
+    /// Adds a plugin to the registry, and remembers where it comes from.
+    ///
+    /// For a plugin loaded by [`load_cdylib`], use [`PluginSource::Dylib`] with the path of the library.
+    pub fn register_with_source(&mut self, plugin: Box<dyn Plugin>, source: PluginSource) {
+        self.plugins.insert(plugin.name().into(), RegisteredPlugin { plugin, source });
     }
 
     /// Finds a plugin by its name.
     pub fn get_mut(&mut self, name: &str) -> Option<&mut dyn Plugin> {
-        self.plugins.get_mut(name).map(|b| &mut **b as _)
+        self.plugins.get_mut(name).map(|p| &mut *p.plugin as _)
         // the cast is necessary here to coerce the lifetime
         // `&mut dyn Plugin + 'static` to `&mut dyn Plugin + 'a`
     }
+
+    /// Lists the registered plugins, sorted by name.
+    pub fn list(&self) -> Vec<PluginInfo> {
+        let mut res: Vec<PluginInfo> = self
+            .plugins
+            .values()
+            .map(|p| PluginInfo {
+                name: p.plugin.name().to_owned(),
+                version: p.plugin.version().to_owned(),
+                source: p.source.clone(),
+            })
+            .collect();
+        res.sort_by(|a, b| a.name.cmp(&b.name));
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::plugin::rust::AlumetPlugin;
+    use crate::plugin::{AlumetStart, ConfigTable, PluginMetadata};
+
+    use super::{parse_dependencies, plugin_subconfig, PluginRegistry, PluginSource};
+
+    #[test]
+    fn dependencies_symbol() {
+        assert!(parse_dependencies("").is_empty());
+        assert_eq!(parse_dependencies("rapl"), vec!["rapl"]);
+        assert_eq!(parse_dependencies("rapl, csv,"), vec!["rapl", "csv"]);
+    }
+
+    #[test]
+    fn missing_subconfig() {
+        let mut global: toml::Table = "[other]\nkey = 1".parse().unwrap();
+
+        // required by default
+        let mut plugin = PluginMetadata::from_static::<DummyPlugin>();
+        assert!(plugin.config_required);
+        assert!(plugin_subconfig(&plugin, &mut global).is_err());
+
+        // optional: default config, or empty table
+        plugin.config_required = false;
+        assert!(plugin_subconfig(&plugin, &mut global).unwrap().0.is_empty());
+        plugin.default_config = Box::new(|| Ok(Some(ConfigTable::from("a = true".parse::<toml::Table>().unwrap()))));
+        let config = plugin_subconfig(&plugin, &mut global).unwrap();
+        assert_eq!(config.0.get("a"), Some(&toml::Value::Boolean(true)));
+
+        // invalid table: always an error
+        global.insert(String::from("dummy"), toml::Value::Integer(1));
+        assert!(plugin_subconfig(&plugin, &mut global).is_err());
+    }
+
+    #[test]
+    fn list_plugins() {
+        let mut registry = PluginRegistry::new();
+        registry.register_with_source(
+            Box::new(DummyPlugin),
+            PluginSource::Dylib {
+                path: PathBuf::from("/plugins/libdummy.so"),
+            },
+        );
+        let list = registry.list();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].name, "dummy");
+        assert_eq!(list[0].version, "0.1.0");
+
+        #[derive(serde::Serialize)]
+        struct Output {
+            plugins: Vec<super::PluginInfo>,
+        }
+        let serialized = toml::Table::try_from(Output { plugins: list }).unwrap();
+        let expected = indoc::indoc! {r#"
+            [[plugins]]
+            name = "dummy"
+            version = "0.1.0"
+
+            [plugins.source]
+            kind = "dylib"
+            path = "/plugins/libdummy.so"
+        "#};
+        assert_eq!(serialized, expected.parse::<toml::Table>().unwrap());
+    }
+
+    struct DummyPlugin;
+
+    impl AlumetPlugin for DummyPlugin {
+        fn name() -> &'static str {
+            "dummy"
+        }
+
+        fn version() -> &'static str {
+            "0.1.0"
+        }
+
+        fn init(_config: ConfigTable) -> anyhow::Result<Box<Self>> {
+            Ok(Box::new(DummyPlugin))
+        }
+
+        fn start(&mut self, _alumet: &mut AlumetStart) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        fn stop(&mut self) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
 }
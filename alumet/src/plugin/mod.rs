@@ -85,23 +85,29 @@
 //!
 use std::future::Future;
 use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::Duration;
 
 use tokio_util::sync::CancellationToken;
 
+use crate::config::{parse_duration, ConfigCheck, ConfigSchema, ConfigValueError, UnknownKeysPolicy};
 use crate::measurement::{MeasurementBuffer, MeasurementType, WrappedMeasurementType};
-use crate::metrics::{Metric, MetricCreationError, RawMetricId, TypedMetricId};
+use crate::metrics::{Metric, MetricCreationError, MetricRegistry, RawMetricId, TypedMetricId};
 use crate::pipeline::builder::{AutonomousSourceBuilder, ManagedSourceBuilder, OutputBuilder, TransformBuilder};
-use crate::pipeline::runtime::{IdlePipeline, RunningPipeline};
+use crate::pipeline::runtime::{IdlePipeline, RunningPipeline, SourceHandle};
 use crate::pipeline::trigger::TriggerSpec;
 use crate::pipeline::{builder::PendingPipelineContext, builder::PipelineBuilder};
 use crate::pipeline::{Output, Source, Transform};
-use crate::units::PrefixedUnit;
+use crate::time::Clock;
+use crate::units::{PrefixedUnit, Unit, UnitCreationError, UnitRelation};
 
+use self::command::PluginCommand;
 use self::rust::AlumetPlugin;
 
 #[cfg(feature = "dynamic")]
 pub mod dynload;
 
+pub mod command;
 pub mod event;
 pub mod rust;
 pub mod util;
@@ -122,6 +128,29 @@ pub struct PluginMetadata {
     /// Alumet agent, in case it does not exist. In other cases, the default
     /// config returned by this function is not used, including when
     pub default_config: Box<dyn Fn() -> anyhow::Result<Option<ConfigTable>>>,
+    /// Names of the plugins that must be started before this one.
+    ///
+    /// The agent uses this list to order the plugins: a plugin is always initialized and started
+    /// after all its dependencies. An empty list means that the plugin can start at any time.
+    pub dependencies: Vec<String>,
+    /// Whether the plugin requires a configuration table.
+    ///
+    /// If `false`, the plugin can run without configuration: when its table is missing,
+    /// [`plugin_subconfig`](dynload::plugin_subconfig) returns its default config instead of an error.
+    pub config_required: bool,
+    /// Function that describes the keys of the configuration of the plugin, or returns None
+    /// if they are not described.
+    ///
+    /// The schema is used to add comments to the default configuration file.
+    pub config_schema: Box<dyn Fn() -> Option<ConfigSchema>>,
+    /// Function that returns the subcommands of the plugin (see the [`command`] module).
+    pub commands: Box<dyn Fn() -> Vec<Box<dyn PluginCommand>>>,
+    /// Target of the log records of the plugin, see [`AlumetPlugin::log_target`].
+    ///
+    /// The `log_level` of the plugin's configuration applies to this target and its submodules
+    /// (see the [`logging`](crate::logging) module). If `None`, the records of the plugin cannot be
+    /// filtered by the application, and the `log_level` is ignored.
+    pub log_target: Option<String>,
 }
 
 impl PluginMetadata {
@@ -132,10 +161,21 @@ impl PluginMetadata {
             version: P::version().to_owned(),
             init: Box::new(|conf| P::init(conf).map(|p| p as _)),
             default_config: Box::new(P::default_config),
+            dependencies: P::dependencies().iter().map(|d| (*d).to_owned()).collect(),
+            config_required: P::config_required(),
+            config_schema: Box::new(P::config_schema),
+            commands: Box::new(P::commands),
+            log_target: Some(P::log_target().to_owned()),
         }
     }
 }
 
+/// Returns the name of the crate that defines `T`, which is the root of the log targets of the crate.
+fn crate_name<T>() -> &'static str {
+    let type_name = std::any::type_name::<T>();
+    type_name.split("::").next().unwrap_or(type_name)
+}
+
 /// A configuration table for plugins.
 ///
 /// `ConfigTable` is currently a wrapper around [`toml::Table`].
@@ -163,8 +203,57 @@ impl PluginMetadata {
 /// let my_table: ConfigTable = serialized;
 /// let deserialized: MyConfig = deserialize_config(my_table).expect("deserialization failed");
 /// ```
+///
+/// The second field is set by the agent, to report the keys that the plugin does not use
+/// according to the [`UnknownKeysPolicy`](crate::config::UnknownKeysPolicy) of the agent.
 #[derive(Debug, Clone)]
-pub struct ConfigTable(pub toml::Table);
+pub struct ConfigTable(pub toml::Table, pub(crate) Option<ConfigCheck>);
+
+impl From<toml::Table> for ConfigTable {
+    fn from(table: toml::Table) -> Self {
+        ConfigTable(table, None)
+    }
+}
+
+impl ConfigTable {
+    /// Sets the policy that [`deserialize_config`](rust::deserialize_config) applies to the unknown keys
+    /// of the configuration of `plugin`.
+    pub(crate) fn with_unknown_keys_policy(mut self, plugin: &str, policy: UnknownKeysPolicy) -> Self {
+        self.1 = Some(ConfigCheck {
+            plugin: plugin.to_owned(),
+            policy,
+        });
+        self
+    }
+
+    /// Returns the duration stored at `key`, or `None` if there is no such key.
+    ///
+    /// The value can be a string such as `"500ms"` or `"2s"` (see [`parse_duration`](crate::config::parse_duration)),
+    /// or a bare integer, which is interpreted as a number of **milliseconds**.
+    pub fn get_duration(&self, key: &str) -> Result<Option<Duration>, ConfigValueError> {
+        match self.0.get(key) {
+            None => Ok(None),
+            Some(toml::Value::String(s)) => match parse_duration(s) {
+                Ok(d) => Ok(Some(d)),
+                Err(e) => Err(ConfigValueError::InvalidDuration {
+                    key: key.to_owned(),
+                    source: e,
+                }),
+            },
+            Some(toml::Value::Integer(ms)) => match u64::try_from(*ms) {
+                Ok(ms) => Ok(Some(Duration::from_millis(ms))),
+                Err(_) => Err(ConfigValueError::NegativeDuration {
+                    key: key.to_owned(),
+                    millis: *ms,
+                }),
+            },
+            Some(_) => Err(ConfigValueError::WrongType {
+                key: key.to_owned(),
+                expected: "a duration (string like \"500ms\" or integer number of milliseconds)",
+            }),
+        }
+    }
+}
 
 /// Trait for plugins.
 ///
@@ -234,7 +323,8 @@ impl<'a> AlumetStart<'a> {
     }
 
     /// Creates a new metric with a measurement type `T` (checked at compile time).
-    /// Fails if a metric with the same name already exists.
+    /// If a metric with the same name already exists, its id is returned if it has the same type and unit,
+    /// otherwise this fails (see [`DuplicateMetricPolicy`](crate::metrics::DuplicateMetricPolicy)).
     pub fn create_metric<T: MeasurementType>(
         &mut self,
         name: impl Into<String>,
@@ -247,12 +337,16 @@ impl<'a> AlumetStart<'a> {
             value_type: T::wrapped_type(),
             unit: unit.into(),
         };
-        let untyped_id = self.pipeline_builder.metrics.register(m)?;
+        let untyped_id = self
+            .pipeline_builder
+            .metrics
+            .register_from_plugin(m, &self.current_plugin_name)?;
         Ok(TypedMetricId(untyped_id, PhantomData))
     }
 
     /// Creates a new metric with a measurement type `value_type` (checked at **run time**).
-    /// Fails if a metric with the same name already exists.
+    /// If a metric with the same name already exists, its id is returned if it has the same type and unit,
+    /// otherwise this fails (see [`DuplicateMetricPolicy`](crate::metrics::DuplicateMetricPolicy)).
     ///
     /// Unlike [`TypedMetricId`], an [`RawMetricId`] does not allow to check that the
     /// measured values are of the right type at compile time.
@@ -270,22 +364,63 @@ impl<'a> AlumetStart<'a> {
             value_type,
             unit: unit.into(),
         };
-        self.pipeline_builder.metrics.register(m)
+        self.pipeline_builder
+            .metrics
+            .register_from_plugin(m, &self.current_plugin_name)
+    }
+
+    /// Creates a new custom unit, to measure things that the standard units do not cover.
+    /// Fails if a unit with the same unique name already exists.
+    ///
+    /// The returned unit can be used to create metrics. Outputs display it with `display_name`.
+    /// If `relation` is `None`, the unit cannot be converted to any other unit
+    /// (see [`UnitRegistry::conversion_factor`](crate::units::UnitRegistry::conversion_factor)).
+    pub fn create_unit(
+        &mut self,
+        unique_name: impl Into<String>,
+        display_name: impl Into<String>,
+        relation: Option<UnitRelation>,
+    ) -> Result<Unit, UnitCreationError> {
+        self.pipeline_builder
+            .metrics
+            .units
+            .register(unique_name.into(), display_name.into(), relation)
+    }
+
+    /// Returns the metrics that have been registered so far, by this plugin and by the plugins started before it.
+    ///
+    /// To use the metrics of another plugin, declare it in [`AlumetPlugin::dependencies`].
+    pub fn metrics(&self) -> &MetricRegistry {
+        &self.pipeline_builder.metrics
+    }
+
+    /// Returns the clock of the pipeline, which provides the timestamps of the managed sources.
+    ///
+    /// Transforms that depend on the current time should use it instead of the system clock,
+    /// so that they can be tested with a [`MockClock`](crate::time::MockClock).
+    pub fn clock(&self) -> Arc<dyn Clock> {
+        self.pipeline_builder.clock()
     }
 
     /// Adds a measurement source to the Alumet pipeline.
-    pub fn add_source(&mut self, source: Box<dyn Source>, trigger: TriggerSpec) {
+    ///
+    /// The returned handle allows to remove the source while the pipeline is running,
+    /// with [`ControlHandle::remove_source`](crate::pipeline::runtime::ControlHandle::remove_source).
+    pub fn add_source(&mut self, source: Box<dyn Source>, trigger: TriggerSpec) -> SourceHandle {
         let plugin = self.current_plugin_name().to_owned();
         let name = self
             .pipeline_builder
             .namegen
             .deduplicate(format!("{plugin}/source"), true);
+        let handle = SourceHandle::new();
         self.pipeline_builder.sources.push(ManagedSourceBuilder {
             name,
             plugin,
+            handle: handle.clone(),
             trigger,
             build: Box::new(|_| source),
-        })
+        });
+        handle
     }
 
     /// Adds the builder of a measurement source to the Alumet pipeline.
@@ -300,18 +435,21 @@ impl<'a> AlumetStart<'a> {
         &mut self,
         trigger: TriggerSpec,
         source_builder: F,
-    ) {
+    ) -> SourceHandle {
         let plugin = self.current_plugin_name().to_owned();
         let name = self
             .pipeline_builder
             .namegen
             .deduplicate(format!("{plugin}/source"), true);
+        let handle = SourceHandle::new();
         self.pipeline_builder.sources.push(ManagedSourceBuilder {
             name,
             plugin,
+            handle: handle.clone(),
             trigger,
             build: Box::new(source_builder),
         });
+        handle
     }
 
     /// Adds the builder of an autonomous source to the Alumet pipeline.
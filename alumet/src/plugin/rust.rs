@@ -5,8 +5,9 @@
 use anyhow::{anyhow, Context};
 
 use crate::{
+    config::ConfigSchema,
     pipeline::runtime::{IdlePipeline, RunningPipeline},
-    plugin::{AlumetStart, Plugin},
+    plugin::{command::PluginCommand, AlumetStart, Plugin},
 };
 
 use super::ConfigTable;
@@ -24,6 +25,22 @@ pub trait AlumetPlugin {
     /// The version of the plugin, for instance `"1.2.3"`. It should adhere to semantic versioning.
     fn version() -> &'static str;
 
+    /// The names of the plugins that must be started before this one.
+    ///
+    /// Declare a dependency when your plugin needs something that another plugin registers
+    /// during its start-up phase, for instance a metric. By default, there is no dependency.
+    fn dependencies() -> &'static [&'static str] {
+        &[]
+    }
+
+    /// Whether the plugin requires a configuration table.
+    ///
+    /// Return `false` if the plugin works out of the box with its [default configuration](AlumetPlugin::default_config).
+    /// By default, the configuration is required.
+    fn config_required() -> bool {
+        true
+    }
+
     /// Initializes the plugin.
     ///
     /// Read more about the plugin lifecycle in the [module documentation](super).
@@ -34,6 +51,30 @@ pub trait AlumetPlugin {
         Ok(None)
     }
 
+    /// Describes the keys of the configuration, in order to document the default configuration file.
+    ///
+    /// By default, the configuration is not described.
+    fn config_schema() -> Option<ConfigSchema> {
+        None
+    }
+
+    /// Returns the subcommands that the plugin contributes to the command line of the application.
+    ///
+    /// The commands run without initializing the plugin, see the [`command`](super::command) module.
+    /// By default, the plugin provides no command.
+    fn commands() -> Vec<Box<dyn PluginCommand>> {
+        Vec::new()
+    }
+
+    /// The target of the log records of the plugin, to which the `log_level` of its configuration applies
+    /// (see the [`logging`](crate::logging) module). The level also applies to the submodules of the target.
+    ///
+    /// By default, this is the name of the crate that defines the plugin. Override it if the crate contains
+    /// several plugins, for instance with `module_path!()`, so that each plugin has its own target.
+    fn log_target() -> &'static str {
+        super::crate_name::<Self>()
+    }
+
     /// Starts the plugin, allowing it to register metrics, sources and outputs.
     ///
     /// ## Plugin restart
@@ -94,16 +135,22 @@ impl<P: AlumetPlugin> Plugin for P {
     }
 }
 
+/// Deserializes the configuration of a plugin.
+///
+/// The keys that are not used by `T` are reported, according to the [`UnknownKeysPolicy`](crate::config::UnknownKeysPolicy)
+/// of the agent, which is carried by the `config` given to [`AlumetPlugin::init`].
 pub fn deserialize_config<'de, T: serde::de::Deserialize<'de>>(config: ConfigTable) -> anyhow::Result<T> {
-    toml::Value::Table(config.0)
-        .try_into::<T>()
+    let ConfigTable(table, check) = config;
+    let (res, unused_keys) = crate::config::deserialize_tracked::<T>(table)
         .with_context(|| format!("error when deserializing ConfigTable to {}", std::any::type_name::<T>()))
-        .context(InvalidConfig)
+        .context(InvalidConfig)?;
+    crate::config::report_unknown_keys(&unused_keys, check.as_ref()).context(InvalidConfig)?;
+    Ok(res)
 }
 
 pub fn serialize_config<T: serde::ser::Serialize>(config: T) -> anyhow::Result<ConfigTable> {
     let res = match toml::Value::try_from(config) {
-        Ok(toml::Value::Table(t)) => Ok(ConfigTable(t)),
+        Ok(toml::Value::Table(t)) => Ok(ConfigTable::from(t)),
         Ok(wrong) => Err(anyhow!(
             "{} did not get serialized to a toml Table but to a {}",
             std::any::type_name::<T>(),
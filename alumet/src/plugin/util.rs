@@ -1,10 +1,33 @@
 //! Utilities for implementing plugins.
 
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Context};
+
+use crate::measurement::{
+    AttributeValue, MeasurementBuffer, MeasurementPoint, Timestamp, WrappedMeasurementType, WrappedMeasurementValue,
+};
+use crate::metrics::{RawMetricId, TypedMetricId};
+use crate::pipeline::{Output, OutputContext, WriteError};
+use crate::resources::{Resource, ResourceConsumer};
+use crate::time::Clock;
+
 pub struct CounterDiff {
     pub max_value: u64,
     previous_value: Option<u64>,
 }
 
+/// The state of a [`CounterDiff`], see [`CounterDiff::state`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct CounterDiffState {
+    pub max_value: u64,
+    /// The last value of the counter, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_value: Option<u64>,
+}
+
 pub enum CounterDiffUpdate {
     /// This is the first counter update, its value is not meaningful.
     FirstTime,
@@ -23,12 +46,35 @@ impl CounterDiff {
         }
     }
 
+    /// Returns the state of the counter, which can be saved in order to [restore](Self::from_state) it later,
+    /// for instance when the plugin that uses the counter restarts.
+    pub fn state(&self) -> CounterDiffState {
+        CounterDiffState {
+            max_value: self.max_value,
+            previous_value: self.previous_value,
+        }
+    }
+
+    /// Creates a counter from a saved state.
+    ///
+    /// The next [`update`](Self::update) compares the new value with the saved one, instead of returning
+    /// [`CounterDiffUpdate::FirstTime`]. This is only correct if the state comes from the same counter (the same
+    /// hardware register or file), which has not been reset since the state was saved, and which has wrapped around
+    /// at most once in the meantime: the caller is responsible for checking it.
+    pub fn from_state(state: CounterDiffState) -> CounterDiff {
+        CounterDiff {
+            max_value: state.max_value,
+            previous_value: state.previous_value,
+        }
+    }
+
     pub fn update(&mut self, new_value: u64) -> CounterDiffUpdate {
         debug_assert!(new_value <= self.max_value, "No value can be greater than max_value!");
         let res = match self.previous_value {
             Some(prev) => {
                 if new_value < prev {
-                    let diff = new_value - prev + self.max_value;
+                    // the counter has wrapped around max_value
+                    let diff = self.max_value - prev + new_value;
                     CounterDiffUpdate::CorrectedDifference(diff)
                 } else {
                     let diff = new_value - prev;
@@ -41,3 +87,1027 @@ impl CounterDiff {
         res
     }
 }
+
+/// Rounding of floating-point values, to make their textual representation shorter.
+///
+/// This is meant to be applied by the outputs, when they serialize the measurements:
+/// the measurements themselves are not modified, hence the computations that occur
+/// in the sources and transforms keep the full precision.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rounding {
+    /// Keep this number of digits after the decimal point.
+    DecimalPlaces(u32),
+    /// Keep this number of significant digits (at least 1).
+    SignificantDigits(u32),
+}
+
+impl Rounding {
+    /// Formats `value` according to the rounding settings.
+    ///
+    /// NaN and infinite values are not rounded.
+    pub fn format(&self, value: f64) -> String {
+        if !value.is_finite() {
+            return value.to_string();
+        }
+        match *self {
+            Rounding::DecimalPlaces(n) => format!("{:.*}", n as usize, value),
+            Rounding::SignificantDigits(n) => {
+                if value == 0.0 {
+                    return String::from("0");
+                }
+                let magnitude = value.abs().log10().floor() as i32;
+                let decimals = n.max(1) as i32 - 1 - magnitude;
+                if decimals >= 0 {
+                    format!("{:.*}", decimals as usize, value)
+                } else {
+                    // round the integer part, e.g. 123456 with 3 digits => 123000
+                    let factor = 10_f64.powi(-decimals);
+                    format!("{}", (value / factor).round() * factor)
+                }
+            }
+        }
+    }
+}
+
+/// Selects metrics by name, with glob patterns.
+///
+/// A metric is accepted if its name matches one of the `include` patterns (or if `include` is empty),
+/// and does not match any of the `exclude` patterns. In patterns, `*` matches any sequence of characters,
+/// and `?` matches exactly one character.
+///
+/// This is meant to be read from the configuration of an output, and applied with [`MetricFilter::wrap`].
+///
+/// ## Example
+/// ```toml
+/// [plugins.csv.metric_filter]
+/// include = ["rapl_*", "nvml_*"]
+/// exclude = ["*_total_*"]
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct MetricFilter {
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+impl MetricFilter {
+    /// Returns true if the filter accepts every metric.
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    /// Returns true if the metric with this name passes the filter.
+    pub fn accepts(&self, metric_name: &str) -> bool {
+        let included = self.include.is_empty() || self.include.iter().any(|p| glob_match(p, metric_name));
+        included && !self.exclude.iter().any(|p| glob_match(p, metric_name))
+    }
+
+    /// Applies the filter to the measurements received by `output`.
+    ///
+    /// If the filter is empty, `output` is returned as is.
+    pub fn wrap(self, output: Box<dyn Output>) -> Box<dyn Output> {
+        if self.is_empty() {
+            output
+        } else {
+            Box::new(FilteredOutput {
+                inner: output,
+                filter: self,
+                decisions: HashMap::new(),
+            })
+        }
+    }
+}
+
+/// An output that only receives the measurements of the metrics accepted by a [`MetricFilter`].
+///
+/// The filter is applied in the output stage, therefore each output can have its own filter.
+struct FilteredOutput {
+    inner: Box<dyn Output>,
+    filter: MetricFilter,
+    /// Result of the filter for each metric, to avoid matching the patterns on every measurement.
+    decisions: HashMap<RawMetricId, bool>,
+}
+
+impl Output for FilteredOutput {
+    fn write(&mut self, measurements: &MeasurementBuffer, ctx: &OutputContext) -> Result<(), WriteError> {
+        let mut filtered = MeasurementBuffer::with_capacity(measurements.len());
+        for m in measurements {
+            let keep = *self.decisions.entry(m.metric).or_insert_with(|| {
+                match ctx.metrics.with_id(&m.metric) {
+                    Some(metric) => self.filter.accepts(&metric.name),
+                    None => true, // unknown metric, let the output deal with it
+                }
+            });
+            if keep {
+                filtered.push(m.clone());
+            }
+        }
+        if filtered.is_empty() {
+            return Ok(());
+        }
+        self.inner.write(&filtered, ctx)
+    }
+
+    fn supported_value_kinds(&self) -> &'static [WrappedMeasurementType] {
+        self.inner.supported_value_kinds()
+    }
+
+    fn finalize(&mut self) -> anyhow::Result<()> {
+        self.inner.finalize()
+    }
+
+    fn stop(&mut self) -> anyhow::Result<()> {
+        self.inner.stop()
+    }
+}
+
+/// The attributes that an output receives, to control the cardinality of the series that it creates.
+///
+/// Each entry is the key of an attribute, or a pattern such as `"cgroup_*"` (see [`glob_match`]).
+/// The other attributes are removed from the points before they reach the output. An empty allowlist removes
+/// every attribute. This is meant to be read from the configuration of an output, as an optional list:
+/// without an allowlist, the output receives all the attributes.
+///
+/// ## Example
+/// ```toml
+/// [plugins.influxdb]
+/// attributes = ["domain", "node_id"]
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(transparent)]
+pub struct AttributeAllowlist {
+    pub keys: Vec<String>,
+}
+
+impl AttributeAllowlist {
+    /// Returns true if the attribute with this key passes the allowlist.
+    pub fn accepts(&self, key: &str) -> bool {
+        self.keys.iter().any(|p| glob_match(p, key))
+    }
+
+    /// Applies the allowlist to the measurements received by `output`.
+    pub fn wrap(self, output: Box<dyn Output>) -> Box<dyn Output> {
+        Box::new(AttributeFilteredOutput {
+            inner: output,
+            allowlist: self,
+            decisions: HashMap::new(),
+        })
+    }
+
+    /// Applies the allowlist, if any, to the measurements received by `output`.
+    ///
+    /// Without an allowlist, `output` is returned as is.
+    pub fn wrap_opt(allowlist: Option<Self>, output: Box<dyn Output>) -> Box<dyn Output> {
+        match allowlist {
+            Some(allowlist) => allowlist.wrap(output),
+            None => output,
+        }
+    }
+}
+
+/// An output that only receives the attributes accepted by an [`AttributeAllowlist`].
+struct AttributeFilteredOutput {
+    inner: Box<dyn Output>,
+    allowlist: AttributeAllowlist,
+    /// Result of the allowlist for each attribute key, to avoid matching the patterns on every attribute.
+    decisions: HashMap<String, bool>,
+}
+
+impl Output for AttributeFilteredOutput {
+    fn write(&mut self, measurements: &MeasurementBuffer, ctx: &OutputContext) -> Result<(), WriteError> {
+        let (allowlist, decisions) = (&self.allowlist, &mut self.decisions);
+        let mut filtered = measurements.clone();
+        for m in filtered.iter_mut() {
+            m.retain_attributes(|key, _| match decisions.get(key) {
+                Some(keep) => *keep,
+                None => {
+                    let keep = allowlist.accepts(key);
+                    decisions.insert(key.to_owned(), keep);
+                    keep
+                }
+            });
+        }
+        self.inner.write(&filtered, ctx)
+    }
+
+    fn supported_value_kinds(&self) -> &'static [WrappedMeasurementType] {
+        self.inner.supported_value_kinds()
+    }
+
+    fn finalize(&mut self) -> anyhow::Result<()> {
+        self.inner.finalize()
+    }
+
+    fn stop(&mut self) -> anyhow::Result<()> {
+        self.inner.stop()
+    }
+}
+
+/// Maximum rate at which an output receives measurement points, to protect the system behind it.
+///
+/// The budget is a token bucket: it holds at most `burst` points (by default, one second of points)
+/// and is refilled at `max_points_per_second`. Each point given to the output consumes one token.
+/// When a buffer contains more points than the bucket, the excess is handled according to `overflow`.
+///
+/// This is meant to be read from the configuration of an output, and applied with [`RateLimit::wrap`].
+///
+/// ## Example
+/// ```toml
+/// [plugins.influxdb.rate_limit]
+/// max_points_per_second = 1000
+/// overflow = "mean"
+/// ```
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct RateLimit {
+    pub max_points_per_second: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub burst: Option<u32>,
+    #[serde(default)]
+    pub overflow: OverflowPolicy,
+}
+
+/// What to do with the points that exceed a [`RateLimit`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    /// Drops the excess points, evenly spread over the buffer so that every series keeps some of its points.
+    ///
+    /// The values that reach the output are exact, but some of them are missing.
+    #[default]
+    Drop,
+    /// Merges the points of each series (same metric, resource and consumer) of the buffer into one point,
+    /// whose value is the mean of the values, at the timestamp of the last point.
+    ///
+    /// This suits the metrics that are gauges, like a power or a temperature. If there are still too many
+    /// series, the excess is dropped like with [`Drop`](Self::Drop).
+    Mean,
+    /// Like [`Mean`](Self::Mean), but with the sum of the values.
+    ///
+    /// This suits the metrics that are deltas, like the energy consumed since the previous measurement:
+    /// the total over the buffer is preserved.
+    Sum,
+}
+
+impl RateLimit {
+    /// Applies the rate limit to the measurements received by `output`.
+    ///
+    /// Each time some points are dropped or merged, a measurement of `dropped_metric` is added to the buffer
+    /// given to `output`, with the number of points that did not reach it unchanged. This measurement is not
+    /// counted in the budget.
+    ///
+    /// Use [`AlumetStart::clock`](crate::plugin::AlumetStart::clock) to obtain the clock of the pipeline.
+    pub fn wrap(
+        self,
+        output: Box<dyn Output>,
+        clock: Arc<dyn Clock>,
+        dropped_metric: TypedMetricId<u64>,
+    ) -> anyhow::Result<Box<dyn Output>> {
+        let rate = self.max_points_per_second;
+        if !rate.is_finite() || rate <= 0.0 {
+            return Err(anyhow!("max_points_per_second must be positive, not {rate}"));
+        }
+        let capacity = match self.burst {
+            Some(0) => return Err(anyhow!("the burst of the rate limit must be at least 1")),
+            Some(burst) => burst as f64,
+            None => rate.max(1.0),
+        };
+        let last_refill = SystemTime::from(clock.now());
+        Ok(Box::new(RateLimitedOutput {
+            inner: output,
+            rate,
+            capacity,
+            overflow: self.overflow,
+            clock,
+            dropped_metric,
+            tokens: capacity,
+            last_refill,
+        }))
+    }
+}
+
+/// An output that receives at most a given number of points per second, see [`RateLimit`].
+struct RateLimitedOutput {
+    inner: Box<dyn Output>,
+    rate: f64,
+    capacity: f64,
+    overflow: OverflowPolicy,
+    clock: Arc<dyn Clock>,
+    dropped_metric: TypedMetricId<u64>,
+    /// Number of points that can be written now (the tokens of the bucket).
+    tokens: f64,
+    last_refill: SystemTime,
+}
+
+impl Output for RateLimitedOutput {
+    fn write(&mut self, measurements: &MeasurementBuffer, ctx: &OutputContext) -> Result<(), WriteError> {
+        let now = SystemTime::from(self.clock.now());
+        let elapsed = now.duration_since(self.last_refill).unwrap_or_default();
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.rate).min(self.capacity);
+        self.last_refill = now;
+
+        let allowed = self.tokens.floor() as usize;
+        if measurements.len() <= allowed {
+            self.tokens -= measurements.len() as f64;
+            return self.inner.write(measurements, ctx);
+        }
+
+        let points: Vec<MeasurementPoint> = match self.overflow {
+            OverflowPolicy::Drop => measurements.iter().cloned().collect(),
+            OverflowPolicy::Mean | OverflowPolicy::Sum => aggregate_series(measurements, self.overflow),
+        };
+        let kept = keep_evenly(points, allowed);
+        self.tokens -= kept.len() as f64;
+        let dropped = (measurements.len() - kept.len()) as u64;
+
+        let mut limited = MeasurementBuffer::with_capacity(kept.len() + 1);
+        limited.extend_from_points(kept);
+        limited.push(MeasurementPoint::new(
+            self.clock.now(),
+            self.dropped_metric,
+            Resource::LocalMachine,
+            ResourceConsumer::LocalMachine,
+            dropped,
+        ));
+        self.inner.write(&limited, ctx)
+    }
+
+    fn supported_value_kinds(&self) -> &'static [WrappedMeasurementType] {
+        self.inner.supported_value_kinds()
+    }
+
+    fn finalize(&mut self) -> anyhow::Result<()> {
+        self.inner.finalize()
+    }
+
+    fn stop(&mut self) -> anyhow::Result<()> {
+        self.inner.stop()
+    }
+}
+
+/// Merges the points of each series into one, in the order of their first appearance.
+fn aggregate_series(measurements: &MeasurementBuffer, overflow: OverflowPolicy) -> Vec<MeasurementPoint> {
+    // for each series: the merged point, the sum of the values and the number of points
+    let mut merged: Vec<(MeasurementPoint, WrappedMeasurementValue, u64)> = Vec::new();
+    // index of the series of each metric, there are usually few series per metric
+    let mut by_metric: HashMap<RawMetricId, Vec<usize>> = HashMap::new();
+    for m in measurements {
+        let series = by_metric.entry(m.metric).or_default();
+        let existing = series
+            .iter()
+            .copied()
+            .find(|&i| merged[i].0.resource == m.resource && merged[i].0.consumer == m.consumer);
+        match existing {
+            Some(i) => {
+                let (point, sum, count) = &mut merged[i];
+                *sum = match (&*sum, &m.value) {
+                    (WrappedMeasurementValue::U64(a), WrappedMeasurementValue::U64(b)) => {
+                        WrappedMeasurementValue::U64(a.saturating_add(*b))
+                    }
+                    (a, b) => WrappedMeasurementValue::F64(a.as_f64() + b.as_f64()),
+                };
+                *count += 1;
+                // keep the timestamp and attributes of the last point
+                *point = m.clone();
+            }
+            None => {
+                series.push(merged.len());
+                merged.push((m.clone(), m.value.clone(), 1));
+            }
+        }
+    }
+    merged
+        .into_iter()
+        .map(|(mut point, sum, count)| {
+            point.value = match (overflow, sum) {
+                (OverflowPolicy::Mean, WrappedMeasurementValue::U64(sum)) => {
+                    WrappedMeasurementValue::U64(sum.saturating_add(count / 2) / count)
+                }
+                (OverflowPolicy::Mean, WrappedMeasurementValue::F64(sum)) => {
+                    WrappedMeasurementValue::F64(sum / count as f64)
+                }
+                (_, sum) => sum,
+            };
+            point
+        })
+        .collect()
+}
+
+/// Keeps `n` points, evenly spread over `points`.
+fn keep_evenly(points: Vec<MeasurementPoint>, n: usize) -> Vec<MeasurementPoint> {
+    let len = points.len();
+    if n >= len {
+        return points;
+    }
+    // point i is kept if the number of kept points, scaled to the buffer, increases at i
+    points
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| (i + 1) * n / len > i * n / len)
+        .map(|(_, m)| m)
+        .collect()
+}
+
+/// Maximum number of distinct series that an output receives, to protect the time series database behind it
+/// from a cardinality explosion, for instance when there is one series per process.
+///
+/// A series is identified by its metric, resource and consumer. When a point belongs to a new series and the limit
+/// is reached, the point is handled according to `eviction`.
+///
+/// This is meant to be read from the configuration of an output, and applied with [`SeriesLimit::wrap`].
+///
+/// ## Example
+/// ```toml
+/// [plugins.influxdb.series_limit]
+/// max_series = 10000
+/// eviction = "least_recently_updated"
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SeriesLimit {
+    pub max_series: usize,
+    #[serde(default)]
+    pub eviction: EvictionPolicy,
+}
+
+/// What to do with the new series that exceed a [`SeriesLimit`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EvictionPolicy {
+    /// Forgets the series that has not been updated for the longest time, to make room for the new one.
+    ///
+    /// The output keeps receiving the series that are alive, such as the current processes, but the forgotten
+    /// series still exist in the database: the limit applies to the series that are updated together, not to
+    /// the total number of series stored. A series that is forgotten and comes back is counted as a new series.
+    #[default]
+    LeastRecentlyUpdated,
+    /// Drops the points of the new series, and keeps the series that have been seen first.
+    ///
+    /// The output never receives more than `max_series` series in total, but the series that appear
+    /// after the limit is reached, such as new processes, are never sent.
+    RejectNew,
+}
+
+impl SeriesLimit {
+    /// Applies the series limit to the measurements received by `output`.
+    ///
+    /// Each time some series are evicted or rejected, a measurement of `dropped_metric` is added to the buffer
+    /// given to `output`, with the number of series that were dropped. This measurement is not counted in the limit.
+    pub fn wrap(self, output: Box<dyn Output>, dropped_metric: TypedMetricId<u64>) -> anyhow::Result<Box<dyn Output>> {
+        if self.max_series == 0 {
+            return Err(anyhow!("max_series must be at least 1"));
+        }
+        Ok(Box::new(SeriesLimitedOutput {
+            inner: output,
+            limit: self,
+            dropped_metric,
+            series: HashMap::new(),
+            by_age: BTreeMap::new(),
+            next_update: 0,
+        }))
+    }
+}
+
+type SeriesKey = (RawMetricId, Resource, ResourceConsumer);
+
+/// An output that receives at most a given number of series, see [`SeriesLimit`].
+struct SeriesLimitedOutput {
+    inner: Box<dyn Output>,
+    limit: SeriesLimit,
+    dropped_metric: TypedMetricId<u64>,
+    /// The series that the output receives, with the number of their last update.
+    series: HashMap<SeriesKey, u64>,
+    /// The series ordered by last update, to find the least recently updated one.
+    by_age: BTreeMap<u64, SeriesKey>,
+    /// Number of the next update of a series.
+    next_update: u64,
+}
+
+impl SeriesLimitedOutput {
+    /// Returns true if the point of series `key` can be given to the output, in the write whose first update
+    /// number is `write_start`.
+    ///
+    /// The series that are evicted or rejected are inserted into `dropped`.
+    fn admit(&mut self, key: SeriesKey, write_start: u64, dropped: &mut HashSet<SeriesKey>) -> bool {
+        if let Some(last) = self.series.get_mut(&key) {
+            if *last < write_start {
+                let key = self.by_age.remove(&*last).unwrap();
+                *last = self.next_update;
+                self.by_age.insert(self.next_update, key);
+                self.next_update += 1;
+            }
+            return true;
+        }
+        if self.series.len() >= self.limit.max_series {
+            // The series updated by this write are never evicted: the output must not receive
+            // more than max_series series at once.
+            let oldest = self.by_age.first_key_value().map(|(last, _)| *last);
+            let evictable = oldest.filter(|last| *last < write_start);
+            match (self.limit.eviction, evictable) {
+                (EvictionPolicy::LeastRecentlyUpdated, Some(last)) => {
+                    let evicted = self.by_age.remove(&last).unwrap();
+                    self.series.remove(&evicted);
+                    dropped.insert(evicted);
+                }
+                _ => {
+                    dropped.insert(key);
+                    return false;
+                }
+            }
+        }
+        self.series.insert(key.clone(), self.next_update);
+        self.by_age.insert(self.next_update, key);
+        self.next_update += 1;
+        true
+    }
+}
+
+impl Output for SeriesLimitedOutput {
+    fn write(&mut self, measurements: &MeasurementBuffer, ctx: &OutputContext) -> Result<(), WriteError> {
+        let write_start = self.next_update;
+        let mut dropped = HashSet::new();
+        let mut limited = MeasurementBuffer::with_capacity(measurements.len() + 1);
+        for m in measurements {
+            let key = (m.metric, m.resource.clone(), m.consumer.clone());
+            if self.admit(key, write_start, &mut dropped) {
+                limited.push(m.clone());
+            }
+        }
+        if dropped.is_empty() {
+            return self.inner.write(measurements, ctx);
+        }
+        limited.push(MeasurementPoint::new(
+            Timestamp::now(),
+            self.dropped_metric,
+            Resource::LocalMachine,
+            ResourceConsumer::LocalMachine,
+            dropped.len() as u64,
+        ));
+        self.inner.write(&limited, ctx)
+    }
+
+    fn supported_value_kinds(&self) -> &'static [WrappedMeasurementType] {
+        self.inner.supported_value_kinds()
+    }
+
+    fn finalize(&mut self) -> anyhow::Result<()> {
+        self.inner.finalize()
+    }
+
+    fn stop(&mut self) -> anyhow::Result<()> {
+        self.inner.stop()
+    }
+}
+
+/// JSON representation of a measurement point, for the outputs that send JSON documents.
+#[derive(serde::Serialize, Debug, PartialEq)]
+pub struct JsonPoint<'a> {
+    pub metric: &'a str,
+    /// Unique name of the unit (UCUM), which can be parsed back. Only serialized if set with [`Self::with_unit`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unit: Option<String>,
+    /// Plugin that created the metric.
+    pub plugin: Option<&'a str>,
+    /// Nanoseconds since the UNIX epoch.
+    pub timestamp: u64,
+    pub value: serde_json::Value,
+    pub resource_kind: &'a str,
+    pub resource_id: Option<String>,
+    pub consumer_kind: &'a str,
+    pub consumer_id: Option<String>,
+    pub attributes: BTreeMap<&'a str, serde_json::Value>,
+}
+
+impl<'a> JsonPoint<'a> {
+    /// Converts a measurement point to JSON. Fails if its timestamp is before the UNIX epoch.
+    pub fn new(m: &'a MeasurementPoint, metric_name: &'a str, plugin: Option<&'a str>) -> anyhow::Result<Self> {
+        let timestamp = SystemTime::from(m.timestamp)
+            .duration_since(UNIX_EPOCH)
+            .with_context(|| format!("the timestamp of {metric_name} is before the UNIX epoch"))?
+            .as_nanos() as u64;
+        let value = match m.value {
+            WrappedMeasurementValue::F64(v) => serde_json::Value::from(v),
+            WrappedMeasurementValue::U64(v) => serde_json::Value::from(v),
+        };
+        let attributes = m
+            .attributes()
+            .map(|(key, value)| {
+                let value = match value {
+                    AttributeValue::F64(v) => serde_json::Value::from(*v),
+                    AttributeValue::U64(v) => serde_json::Value::from(*v),
+                    AttributeValue::Bool(v) => serde_json::Value::from(*v),
+                    AttributeValue::Str(v) => serde_json::Value::from(*v),
+                    AttributeValue::String(v) => serde_json::Value::from(v.as_str()),
+                };
+                (key, value)
+            })
+            .collect();
+        Ok(JsonPoint {
+            metric: metric_name,
+            unit: None,
+            plugin,
+            timestamp,
+            value,
+            resource_kind: m.resource.kind(),
+            resource_id: m.resource.id_string(),
+            consumer_kind: m.consumer.kind(),
+            consumer_id: m.consumer.id_string(),
+            attributes,
+        })
+    }
+
+    /// Adds the unit of the metric, for the outputs that need it to replay the measurements.
+    pub fn with_unit(mut self, unit: String) -> Self {
+        self.unit = Some(unit);
+        self
+    }
+}
+
+/// Returns the hostname of the machine.
+///
+/// The characters that are not valid UTF-8 are replaced by `U+FFFD REPLACEMENT CHARACTER`.
+pub fn system_hostname() -> std::io::Result<String> {
+    ::hostname::get().map(|name| name.to_string_lossy().into_owned())
+}
+
+/// Returns true if `name` matches the glob `pattern`, where `*` matches any sequence
+/// of characters (including an empty one) and `?` matches exactly one character.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // position of the last `*` in the pattern, and the position in the name when it was encountered
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = backtrack {
+            // let the last `*` match one more character
+            p = star_p + 1;
+            n = star_n + 1;
+            backtrack = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use std::marker::PhantomData;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use crate::measurement::{AttributeValue, MeasurementBuffer, MeasurementPoint, Timestamp, WrappedMeasurementValue};
+    use crate::metrics::{Metric, MetricRegistry, RawMetricId, TypedMetricId};
+    use crate::pipeline::{Output, OutputContext, WriteError};
+    use crate::resources::{Resource, ResourceConsumer};
+    use crate::time::MockClock;
+    use crate::units::Unit;
+
+    use super::{
+        glob_match, AttributeAllowlist, CounterDiff, CounterDiffUpdate, EvictionPolicy, JsonPoint, MetricFilter,
+        OverflowPolicy, RateLimit, Rounding, SeriesLimit,
+    };
+
+    #[test]
+    fn counter_diff_state() {
+        let mut counter = CounterDiff::with_max_value(100);
+        assert!(matches!(counter.update(90), CounterDiffUpdate::FirstTime));
+        let state = counter.state();
+        assert_eq!(state.previous_value, Some(90));
+
+        // the restored counter continues where the previous one stopped, even across a wraparound
+        let mut restored = CounterDiff::from_state(state);
+        assert!(matches!(restored.update(5), CounterDiffUpdate::CorrectedDifference(15)));
+        assert_eq!(restored.max_value, 100);
+        let unused = CounterDiff::with_max_value(100).state();
+        assert_eq!(CounterDiff::from_state(unused).previous_value, None);
+    }
+
+    #[test]
+    fn rounding() {
+        let decimals = Rounding::DecimalPlaces(2);
+        assert_eq!(decimals.format(3.14159), "3.14");
+        assert_eq!(decimals.format(-0.005001), "-0.01");
+        assert_eq!(decimals.format(12.0), "12.00");
+
+        let digits = Rounding::SignificantDigits(3);
+        assert_eq!(digits.format(3.14159), "3.14");
+        assert_eq!(digits.format(0.00123456), "0.00123");
+        assert_eq!(digits.format(123456.7), "123000");
+        assert_eq!(digits.format(0.0), "0");
+        assert_eq!(digits.format(f64::NAN), "NaN");
+    }
+
+    #[test]
+    fn glob() {
+        assert!(glob_match("rapl_*", "rapl_consumed_energy"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*energy", "rapl_consumed_energy"));
+        assert!(glob_match("*_consumed_*", "rapl_consumed_energy"));
+        assert!(glob_match("nvml_?pu", "nvml_gpu"));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+        assert!(glob_match("exact", "exact"));
+        assert!(!glob_match("exact", "exactly"));
+        assert!(!glob_match("rapl_*", "nvml_power"));
+        assert!(!glob_match("nvml_?pu", "nvml_pu"));
+        assert!(!glob_match("a*b", "aXbY"));
+    }
+
+    #[test]
+    fn metric_filter() {
+        let all = MetricFilter::default();
+        assert!(all.is_empty());
+        assert!(all.accepts("anything"));
+
+        let filter = MetricFilter {
+            include: vec![String::from("rapl_*"), String::from("nvml_*")],
+            exclude: vec![String::from("*_total_*")],
+        };
+        assert!(filter.accepts("rapl_consumed_energy"));
+        assert!(filter.accepts("nvml_power"));
+        assert!(!filter.accepts("rapl_total_consumed_energy"));
+        assert!(!filter.accepts("cpu_utilization"));
+
+        let exclude_only = MetricFilter {
+            include: Vec::new(),
+            exclude: vec![String::from("alumet_*")],
+        };
+        assert!(exclude_only.accepts("rapl_consumed_energy"));
+        assert!(!exclude_only.accepts("alumet_poll_duration"));
+    }
+
+    struct CollectingOutput(Arc<Mutex<Vec<RawMetricId>>>);
+
+    impl Output for CollectingOutput {
+        fn write(&mut self, measurements: &MeasurementBuffer, _ctx: &OutputContext) -> Result<(), WriteError> {
+            self.0.lock().unwrap().extend(measurements.iter().map(|m| m.metric));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn filtered_output() {
+        let mut metrics = MetricRegistry::new();
+        let mut register = |name: &str| {
+            metrics
+                .register(Metric {
+                    name: name.to_owned(),
+                    description: String::new(),
+                    value_type: crate::measurement::WrappedMeasurementType::U64,
+                    unit: Unit::Unity.into(),
+                })
+                .unwrap()
+        };
+        let kept = register("rapl_consumed_energy");
+        let removed = register("cpu_utilization");
+        let ctx = OutputContext::new(metrics);
+
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let filter = MetricFilter {
+            include: vec![String::from("rapl_*")],
+            exclude: Vec::new(),
+        };
+        let mut output = filter.wrap(Box::new(CollectingOutput(received.clone())));
+
+        let point = |metric| {
+            MeasurementPoint::new_untyped(
+                Timestamp::now(),
+                metric,
+                Resource::LocalMachine,
+                ResourceConsumer::LocalMachine,
+                WrappedMeasurementValue::U64(1),
+            )
+        };
+        let buf = MeasurementBuffer::from(vec![point(kept), point(removed), point(kept)]);
+        output.write(&buf, &ctx).unwrap();
+        assert_eq!(*received.lock().unwrap(), vec![kept, kept]);
+
+        // nothing to write: the inner output is not called
+        let buf = MeasurementBuffer::from(vec![point(removed)]);
+        output.write(&buf, &ctx).unwrap();
+        assert_eq!(received.lock().unwrap().len(), 2);
+    }
+
+    struct AttributesOutput(Arc<Mutex<Vec<Vec<String>>>>);
+
+    impl Output for AttributesOutput {
+        fn write(&mut self, measurements: &MeasurementBuffer, _ctx: &OutputContext) -> Result<(), WriteError> {
+            let mut received = self.0.lock().unwrap();
+            for m in measurements {
+                received.push(m.attributes_keys().map(String::from).collect());
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn attribute_allowlist() {
+        let ctx = OutputContext::new(MetricRegistry::new());
+        let point = MeasurementPoint::new_untyped(
+            Timestamp::now(),
+            RawMetricId(1),
+            Resource::LocalMachine,
+            ResourceConsumer::LocalMachine,
+            WrappedMeasurementValue::U64(1),
+        )
+        .with_attr("domain", "package")
+        .with_attr("cgroup_path", "/a")
+        .with_attr("cgroup_id", 12_u64)
+        .with_attr("pid", 42_u64);
+        let buf = MeasurementBuffer::from(vec![point.clone(), point]);
+
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let allowlist = AttributeAllowlist {
+            keys: vec![String::from("domain"), String::from("cgroup_*")],
+        };
+        assert!(allowlist.accepts("cgroup_path"));
+        assert!(!allowlist.accepts("pid"));
+        let mut output = allowlist.wrap(Box::new(AttributesOutput(received.clone())));
+        output.write(&buf, &ctx).unwrap();
+        let expected = vec!["domain", "cgroup_path", "cgroup_id"];
+        let res = std::mem::take(&mut *received.lock().unwrap());
+        assert_eq!(res, vec![expected.clone(), expected]);
+
+        // empty allowlist: no attributes
+        let mut output = AttributeAllowlist::default().wrap(Box::new(AttributesOutput(received.clone())));
+        output.write(&buf, &ctx).unwrap();
+        assert_eq!(*received.lock().unwrap(), vec![Vec::<String>::new(), Vec::new()]);
+    }
+
+    struct ValuesOutput(Arc<Mutex<Vec<(RawMetricId, f64)>>>);
+
+    impl Output for ValuesOutput {
+        fn write(&mut self, measurements: &MeasurementBuffer, _ctx: &OutputContext) -> Result<(), WriteError> {
+            let mut values = self.0.lock().unwrap();
+            values.extend(measurements.iter().map(|m| (m.metric, m.value.as_f64())));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn rate_limited_output() {
+        let ctx = OutputContext::new(MetricRegistry::new());
+        let (a, b) = (RawMetricId(1), RawMetricId(2));
+        let dropped = TypedMetricId(RawMetricId(10), PhantomData);
+        let point = |metric, value| {
+            MeasurementPoint::new_untyped(
+                Timestamp::now(),
+                metric,
+                Resource::LocalMachine,
+                ResourceConsumer::LocalMachine,
+                value,
+            )
+        };
+        let f = |metric, v: f64| point(metric, WrappedMeasurementValue::F64(v));
+        let u = |metric, v: u64| point(metric, WrappedMeasurementValue::U64(v));
+
+        let clock = MockClock::new(UNIX_EPOCH + Duration::from_secs(100));
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let limit = RateLimit {
+            max_points_per_second: 10.0,
+            burst: Some(4),
+            overflow: OverflowPolicy::Drop,
+        };
+        let inner = Box::new(ValuesOutput(received.clone()));
+        let mut output = limit.wrap(inner, Arc::new(clock.clone()), dropped).unwrap();
+
+        // within the budget
+        let buf = MeasurementBuffer::from(vec![f(a, 1.0), f(a, 2.0), f(b, 3.0)]);
+        output.write(&buf, &ctx).unwrap();
+        assert_eq!(
+            std::mem::take(&mut *received.lock().unwrap()),
+            vec![(a, 1.0), (a, 2.0), (b, 3.0)]
+        );
+
+        // one token left: one point is kept, two are dropped
+        output.write(&buf, &ctx).unwrap();
+        assert_eq!(
+            std::mem::take(&mut *received.lock().unwrap()),
+            vec![(b, 3.0), (dropped.0, 2.0)]
+        );
+
+        // the bucket is refilled, up to the burst
+        clock.advance(Duration::from_secs(1));
+        let limit = RateLimit {
+            max_points_per_second: 10.0,
+            burst: Some(4),
+            overflow: OverflowPolicy::Mean,
+        };
+        let inner = Box::new(ValuesOutput(received.clone()));
+        let mut output = limit.wrap(inner, Arc::new(clock.clone()), dropped).unwrap();
+        let buf = MeasurementBuffer::from(vec![f(a, 1.0), u(b, 1), f(a, 2.0), f(a, 3.0), u(b, 2), f(a, 4.0)]);
+        output.write(&buf, &ctx).unwrap();
+        assert_eq!(
+            std::mem::take(&mut *received.lock().unwrap()),
+            vec![(a, 2.5), (b, 2.0), (dropped.0, 4.0)]
+        );
+
+        let limit = RateLimit {
+            max_points_per_second: 1.0,
+            burst: Some(1),
+            overflow: OverflowPolicy::Sum,
+        };
+        let inner = Box::new(ValuesOutput(received.clone()));
+        let mut output = limit.wrap(inner, Arc::new(clock.clone()), dropped).unwrap();
+        output.write(&buf, &ctx).unwrap();
+        // two series for one token: only the last one is kept
+        assert_eq!(
+            std::mem::take(&mut *received.lock().unwrap()),
+            vec![(b, 3.0), (dropped.0, 5.0)]
+        );
+
+        let invalid = RateLimit {
+            max_points_per_second: 0.0,
+            burst: None,
+            overflow: OverflowPolicy::Drop,
+        };
+        let inner = Box::new(ValuesOutput(received.clone()));
+        assert!(invalid.wrap(inner, Arc::new(clock), dropped).is_err());
+    }
+
+    #[test]
+    fn series_limited_output() {
+        let ctx = OutputContext::new(MetricRegistry::new());
+        let metric = RawMetricId(1);
+        let dropped = TypedMetricId(RawMetricId(10), PhantomData);
+        let point = |pid: u32| {
+            MeasurementPoint::new_untyped(
+                Timestamp::now(),
+                metric,
+                Resource::LocalMachine,
+                ResourceConsumer::Process { pid },
+                WrappedMeasurementValue::F64(pid as f64),
+            )
+        };
+        let write = |output: &mut Box<dyn Output>, pids: &[u32], received: &Arc<Mutex<Vec<(RawMetricId, f64)>>>| {
+            let buf = MeasurementBuffer::from(pids.iter().map(|pid| point(*pid)).collect::<Vec<_>>());
+            output.write(&buf, &ctx).unwrap();
+            std::mem::take(&mut *received.lock().unwrap())
+        };
+
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let limit = SeriesLimit {
+            max_series: 2,
+            eviction: EvictionPolicy::LeastRecentlyUpdated,
+        };
+        let mut output = limit.wrap(Box::new(ValuesOutput(received.clone())), dropped).unwrap();
+        let res = write(&mut output, &[1, 2, 1], &received);
+        assert_eq!(res, vec![(metric, 1.0), (metric, 2.0), (metric, 1.0)]);
+        // pid 2 is the least recently updated series: it is evicted for pid 3
+        assert_eq!(write(&mut output, &[1], &received), vec![(metric, 1.0)]);
+        let res = write(&mut output, &[3], &received);
+        assert_eq!(res, vec![(metric, 3.0), (dropped.0, 1.0)]);
+        // the series of the same write are not evicted: there are never more than 2 series at once
+        let res = write(&mut output, &[4, 5, 6], &received);
+        assert_eq!(res, vec![(metric, 4.0), (metric, 5.0), (dropped.0, 3.0)]);
+
+        let limit = SeriesLimit {
+            max_series: 2,
+            eviction: EvictionPolicy::RejectNew,
+        };
+        let mut output = limit.wrap(Box::new(ValuesOutput(received.clone())), dropped).unwrap();
+        let res = write(&mut output, &[1, 2], &received);
+        assert_eq!(res, vec![(metric, 1.0), (metric, 2.0)]);
+        let res = write(&mut output, &[3, 2, 3, 4], &received);
+        assert_eq!(res, vec![(metric, 2.0), (dropped.0, 2.0)]);
+
+        let invalid = SeriesLimit {
+            max_series: 0,
+            eviction: EvictionPolicy::default(),
+        };
+        assert!(invalid.wrap(Box::new(ValuesOutput(received)), dropped).is_err());
+    }
+
+    #[test]
+    fn json_point() {
+        let m = MeasurementPoint::new_untyped(
+            Timestamp::from(UNIX_EPOCH + Duration::from_millis(1500)),
+            RawMetricId::from_u64(0),
+            Resource::CpuPackage { id: 1 },
+            ResourceConsumer::LocalMachine,
+            WrappedMeasurementValue::F64(12.5),
+        )
+        .with_attr("domain", AttributeValue::Str("package"));
+        let point = JsonPoint::new(&m, "rapl_consumed_energy", Some("rapl")).unwrap();
+        assert_eq!(
+            serde_json::to_string(&point).unwrap(),
+            r#"{"metric":"rapl_consumed_energy","plugin":"rapl","timestamp":1500000000,"value":12.5,"resource_kind":"cpu_package","resource_id":"1","consumer_kind":"local_machine","consumer_id":null,"attributes":{"domain":"package"}}"#
+        );
+
+        let before_epoch = MeasurementPoint::new_untyped(
+            Timestamp::from(UNIX_EPOCH - Duration::from_secs(1)),
+            RawMetricId::from_u64(0),
+            Resource::LocalMachine,
+            ResourceConsumer::LocalMachine,
+            WrappedMeasurementValue::U64(1),
+        );
+        assert!(JsonPoint::new(&before_epoch, "m", None).is_err());
+    }
+}
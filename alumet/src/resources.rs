@@ -33,6 +33,21 @@
 //!
 //! Unlike metrics and units, resources are not registered in a global registry,
 //! but created each time they are needed.
+//!
+//! ## Equality, hashing and ordering
+//!
+//! [`Resource`] and [`ResourceConsumer`] implement `Eq`, `Hash` and `Ord`, so that they can be used as keys
+//! of a `HashMap` or a `BTreeMap`, for instance to keep some state per resource in a transform.
+//! Cloning them is cheap: their strings are usually `&'static str` (see [`StrCow`]).
+//!
+//! Two resources are equal if they have the same variant and the same fields. Beware: a [`Resource::Custom`]
+//! whose kind is a known kind, like `Resource::custom("cpu_package", "0")`, is not equal to the corresponding variant,
+//! here `Resource::CpuPackage { id: 0 }`. Use [`Resource::normalize`] (or [`Resource::parse`]) to obtain the
+//! canonical form of a resource that has been read from a string.
+//!
+//! The canonical ordering sorts by variant first, in the order of their declaration, then by fields.
+//! The numeric ids are compared as numbers, hence `CpuPackage { id: 2 }` comes before `CpuPackage { id: 10 }`,
+//! unlike their string representations.
 
 use std::{borrow::Cow, fmt};
 
@@ -41,7 +56,7 @@ pub type StrCow = Cow<'static, str>;
 
 /// Hardware or software entity for which metrics can be gathered.
 #[non_exhaustive]
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 #[repr(C)]
 pub enum Resource {
     /// The whole local machine, for instance the whole physical server.
@@ -60,7 +75,7 @@ pub enum Resource {
 
 /// Consumer of a [`resource`](Resource).
 #[non_exhaustive]
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 #[repr(C)]
 pub enum ResourceConsumer {
     /// The whole local machine.
@@ -241,3 +256,89 @@ impl<'a> fmt::Display for LazyDisplayable<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::hash_map::DefaultHasher;
+    use std::collections::{BTreeSet, HashMap};
+    use std::hash::{Hash, Hasher};
+
+    use super::{Resource, ResourceConsumer};
+
+    fn hash(value: &impl Hash) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn equal_resources_hash_equally() {
+        let a = Resource::parse("gpu", String::from("0000:01:00.0")).unwrap();
+        let b = Resource::Gpu {
+            bus_id: "0000:01:00.0".into(),
+        };
+        assert_eq!(a, b);
+        assert_eq!(hash(&a), hash(&b));
+        assert_eq!(hash(&a.clone()), hash(&a));
+
+        let c = ResourceConsumer::parse("process", "42").unwrap();
+        assert_eq!(c, ResourceConsumer::Process { pid: 42 });
+        assert_eq!(hash(&c), hash(&ResourceConsumer::Process { pid: 42 }));
+    }
+
+    #[test]
+    fn distinct_resources_are_distinct_keys() {
+        // same id but different kind, or same kind but different id
+        let resources = vec![
+            Resource::LocalMachine,
+            Resource::CpuPackage { id: 0 },
+            Resource::CpuPackage { id: 1 },
+            Resource::CpuCore { id: 0 },
+            Resource::Dram { pkg_id: 0 },
+            Resource::Dram { pkg_id: 1 },
+            Resource::custom("cpu_package", "0"),
+            Resource::custom("psys", "0"),
+        ];
+        let mut map = HashMap::new();
+        for (i, r) in resources.iter().enumerate() {
+            assert_eq!(map.insert(r.clone(), i), None, "{r:?} collides with another resource");
+        }
+        for (i, r) in resources.iter().enumerate() {
+            assert_eq!(map[r], i);
+        }
+        let hashes: BTreeSet<u64> = resources.iter().map(hash).collect();
+        assert_eq!(hashes.len(), resources.len());
+
+        let consumers = [
+            ResourceConsumer::LocalMachine,
+            ResourceConsumer::Process { pid: 1 },
+            ResourceConsumer::Process { pid: 2 },
+            ResourceConsumer::ControlGroup { path: "/a".into() },
+            ResourceConsumer::custom("cgroup", "/a"),
+        ];
+        let consumers: BTreeSet<_> = consumers.into_iter().collect();
+        assert_eq!(consumers.len(), 5);
+    }
+
+    #[test]
+    fn canonical_ordering() {
+        let mut resources = vec![
+            Resource::Dram { pkg_id: 0 },
+            Resource::CpuPackage { id: 10 },
+            Resource::custom("psys", "0"),
+            Resource::CpuPackage { id: 2 },
+            Resource::LocalMachine,
+        ];
+        resources.sort();
+        assert_eq!(
+            resources,
+            vec![
+                Resource::LocalMachine,
+                Resource::CpuPackage { id: 2 },
+                Resource::CpuPackage { id: 10 },
+                Resource::Dram { pkg_id: 0 },
+                Resource::custom("psys", "0"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,78 @@
+//! Source of time for the measurement pipeline.
+//!
+//! The pipeline does not read the system clock directly: it uses the [`Clock`] of the
+//! [`PipelineBuilder`](crate::pipeline::builder::PipelineBuilder), which is the [`SystemClock`] by default.
+//! The timestamps of the managed sources come from this clock, and the time-dependent transforms
+//! can obtain it with [`AlumetStart::clock`](crate::plugin::AlumetStart::clock).
+//!
+//! In tests, use a [`MockClock`] to control the time deterministically.
+//!
+//! ## Example
+//! ```
+//! use std::time::{Duration, SystemTime};
+//! use alumet::time::{Clock, MockClock};
+//!
+//! let clock = MockClock::new(SystemTime::UNIX_EPOCH);
+//! clock.advance(Duration::from_secs(2));
+//! let now = SystemTime::from(clock.now());
+//! assert_eq!(now, SystemTime::UNIX_EPOCH + Duration::from_secs(2));
+//! ```
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use crate::measurement::Timestamp;
+
+/// A source of timestamps.
+pub trait Clock: Send + Sync {
+    /// Returns the current time.
+    fn now(&self) -> Timestamp;
+}
+
+/// The real clock, which returns the current system time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Timestamp {
+        Timestamp::now()
+    }
+}
+
+/// A clock that only changes when it is told to, for testing purposes.
+///
+/// The clones of a `MockClock` share the same time.
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    time: Arc<Mutex<SystemTime>>,
+}
+
+impl MockClock {
+    /// Creates a clock that is stopped at `start`.
+    pub fn new(start: SystemTime) -> Self {
+        Self {
+            time: Arc::new(Mutex::new(start)),
+        }
+    }
+
+    /// Moves the clock forward.
+    pub fn advance(&self, duration: Duration) {
+        *self.time.lock().unwrap() += duration;
+    }
+
+    /// Sets the current time of the clock.
+    pub fn set(&self, time: SystemTime) {
+        *self.time.lock().unwrap() = time;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Timestamp {
+        Timestamp::from(*self.time.lock().unwrap())
+    }
+}
+
+/// Returns the default clock of the pipeline.
+pub(crate) fn system_clock() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
+}
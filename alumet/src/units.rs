@@ -1,6 +1,8 @@
 //! Definition of measurement units.
 
 use std::{
+    collections::HashMap,
+    error::Error,
     fmt::{self, Debug, Display},
     str::FromStr,
 };
@@ -71,7 +73,7 @@ pub enum Unit {
 /// let milliA = PrefixedUnit::milli(Unit::Ampere);
 /// let nanoSec = PrefixedUnit::nano(Unit::Second);
 /// ```
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct PrefixedUnit {
     pub base_unit: Unit,
     pub prefix: UnitPrefix,
@@ -232,6 +234,38 @@ impl Display for PrefixedUnit {
     }
 }
 
+impl FromStr for PrefixedUnit {
+    type Err = anyhow::Error;
+
+    /// Parses a standard unit with an optional prefix, such as `"W"` or `"mW"`.
+    ///
+    /// The [`unique_name`](PrefixedUnit::unique_name) of a unit is accepted too, for instance `"milliW"`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(unit) = Unit::from_str(s) {
+            return Ok(unit.into());
+        }
+        let long_prefixes = [
+            UnitPrefix::Nano,
+            UnitPrefix::Micro,
+            UnitPrefix::Milli,
+            UnitPrefix::Kilo,
+            UnitPrefix::Mega,
+            UnitPrefix::Giga,
+        ];
+        for prefix in long_prefixes {
+            let base_unit = s.strip_prefix(prefix.unique_name()).map(Unit::from_str);
+            if let Some(Ok(base_unit)) = base_unit {
+                return Ok(PrefixedUnit { base_unit, prefix });
+            }
+        }
+        let mut chars = s.chars();
+        let prefix = chars.next().ok_or_else(|| anyhow!("Empty unit"))?;
+        let prefix = UnitPrefix::from_str(&prefix.to_string()).map_err(|_| anyhow!("Unknown unit {s}"))?;
+        let base_unit = Unit::from_str(chars.as_str()).map_err(|_| anyhow!("Unknown unit {s}"))?;
+        Ok(PrefixedUnit { base_unit, prefix })
+    }
+}
+
 impl UnitPrefix {
     /// Returns the unique name of the unit, as specified by the Unified Code for Units of Measure (UCUM).
     ///
@@ -288,3 +322,344 @@ impl FromStr for UnitPrefix {
         Ok(res)
     }
 }
+
+impl UnitPrefix {
+    /// Returns the multiplier that corresponds to this prefix, for instance `1e3` for `Kilo`.
+    pub fn scale(&self) -> f64 {
+        match self {
+            UnitPrefix::Nano => 1e-9,
+            UnitPrefix::Micro => 1e-6,
+            UnitPrefix::Milli => 1e-3,
+            UnitPrefix::Plain => 1.0,
+            UnitPrefix::Kilo => 1e3,
+            UnitPrefix::Mega => 1e6,
+            UnitPrefix::Giga => 1e9,
+        }
+    }
+}
+
+/// Relation between a custom unit and a standard unit: one custom unit is equal to `factor` times `unit`.
+///
+/// For instance, a unit "kilocalorie" can be defined as `4184` times [`Unit::Joule`].
+#[derive(Debug, Clone)]
+pub struct UnitRelation {
+    pub factor: f64,
+    pub unit: PrefixedUnit,
+}
+
+/// A registry of the custom units defined by the plugins.
+///
+/// Custom units are registered during the plugin startup phase, with
+/// [`AlumetStart::create_unit`](crate::plugin::AlumetStart::create_unit).
+/// A custom unit without a [`UnitRelation`] has its own dimension: it can only be converted
+/// to a multiple of itself, for instance `ktransaction` to `transaction`.
+#[derive(Debug, Clone, Default)]
+pub struct UnitRegistry {
+    /// Custom units by unique name: display name and relation to a standard unit.
+    units: HashMap<String, (String, Option<UnitRelation>)>,
+}
+
+impl UnitRegistry {
+    /// Creates a new, empty registry.
+    pub(crate) fn new() -> UnitRegistry {
+        UnitRegistry::default()
+    }
+
+    /// Registers a new custom unit and returns it.
+    ///
+    /// Fails if the unique name is already used by a standard unit or by another custom unit,
+    /// or if the relation does not refer to a standard unit.
+    pub(crate) fn register(
+        &mut self,
+        unique_name: String,
+        display_name: String,
+        relation: Option<UnitRelation>,
+    ) -> Result<Unit, UnitCreationError> {
+        if Unit::from_str(&unique_name).is_ok() || self.units.contains_key(&unique_name) {
+            return Err(UnitCreationError(format!(
+                "A unit with this name already exists: {unique_name}"
+            )));
+        }
+        if let Some(rel) = &relation {
+            if let Unit::Custom { unique_name: other, .. } = &rel.unit.base_unit {
+                return Err(UnitCreationError(format!(
+                    "The unit {unique_name} must be defined in terms of a standard unit, not {other}"
+                )));
+            }
+            if !rel.factor.is_finite() || rel.factor == 0.0 {
+                return Err(UnitCreationError(format!(
+                    "Invalid factor for the unit {unique_name}: {}",
+                    rel.factor
+                )));
+            }
+        }
+        let unit = Unit::Custom {
+            unique_name: unique_name.clone(),
+            display_name: display_name.clone(),
+        };
+        self.units.insert(unique_name, (display_name, relation));
+        Ok(unit)
+    }
+
+    /// Finds the custom unit that has the given unique name.
+    pub fn with_name(&self, unique_name: &str) -> Option<Unit> {
+        self.units.get(unique_name).map(|(display_name, _)| Unit::Custom {
+            unique_name: unique_name.to_owned(),
+            display_name: display_name.clone(),
+        })
+    }
+
+    /// Returns the relation of a custom unit to a standard unit, if it has been declared.
+    pub fn relation(&self, unit: &Unit) -> Option<&UnitRelation> {
+        match unit {
+            Unit::Custom { unique_name, .. } => self.units.get(unique_name).and_then(|(_, rel)| rel.as_ref()),
+            _ => None,
+        }
+    }
+
+    /// Returns the factor to apply to a value in `from` to express it in `to`,
+    /// or `None` if the two units have different dimensions.
+    ///
+    /// Standard units are only converted to the multiples of themselves (for instance `mJ` to `J`).
+    /// Custom units are converted through their [`UnitRelation`], if any.
+    pub fn conversion_factor(&self, from: &PrefixedUnit, to: &PrefixedUnit) -> Option<f64> {
+        let (from_scale, from_base) = self.resolve(from);
+        let (to_scale, to_base) = self.resolve(to);
+        (from_base == to_base).then(|| from_scale / to_scale)
+    }
+
+    /// Expresses a unit as a scale and a base unit.
+    fn resolve<'a>(&'a self, unit: &'a PrefixedUnit) -> (f64, &'a Unit) {
+        let scale = unit.prefix.scale();
+        match self.relation(&unit.base_unit) {
+            Some(rel) => (scale * rel.factor * rel.unit.prefix.scale(), &rel.unit.base_unit),
+            None => (scale, &unit.base_unit),
+        }
+    }
+}
+
+/// Error which can occur when creating a new custom unit.
+#[derive(Debug)]
+pub struct UnitCreationError(String);
+
+impl Error for UnitCreationError {}
+
+impl Display for UnitCreationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// The prefixes to use when formatting a value with [`format_with_prefix`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PrefixSystem {
+    /// Decimal prefixes of the SI (powers of 1000): `kW`, `MJ`, `GB`, ...
+    #[default]
+    Si,
+    /// Binary prefixes (powers of 1024) for amounts of information: `KiB`, `MiB`, ...
+    ///
+    /// Binary prefixes only make sense for data, the other units are formatted with SI prefixes.
+    Binary,
+}
+
+/// SI prefixes used for formatting, from the smallest to the largest.
+const SI_PREFIXES: [(&str, f64); 9] = [
+    ("n", 1e-9),
+    ("μ", 1e-6),
+    ("m", 1e-3),
+    ("", 1.0),
+    ("k", 1e3),
+    ("M", 1e6),
+    ("G", 1e9),
+    ("T", 1e12),
+    ("P", 1e15),
+];
+
+/// Binary prefixes used for formatting, from the smallest to the largest.
+const BINARY_PREFIXES: [(&str, f64); 6] = [
+    ("", 1.0),
+    ("Ki", 1024.0),
+    ("Mi", 1048576.0),
+    ("Gi", 1073741824.0),
+    ("Ti", 1099511627776.0),
+    ("Pi", 1125899906842624.0),
+];
+
+/// Number of decimal places kept by [`format_with_prefix`].
+const FORMAT_DECIMALS: i32 = 2;
+
+/// Formats a value in a human-readable way, by choosing the most appropriate prefix for its unit.
+///
+/// The prefix is chosen such that the displayed number is at least 1 and less than the base of the system
+/// (1000 or 1024), and the number is rounded to two decimal places. For instance, `1500 W` becomes `1.5 kW`,
+/// and `1536 B` becomes `1.5 KiB` with [`PrefixSystem::Binary`].
+///
+/// The prefix of `unit` is taken into account: `1500 mJ` becomes `1.5 J`.
+/// Units that do not accept prefixes (temperatures, dimensionless values and custom units) are never prefixed.
+///
+/// ## Example
+/// ```
+/// use alumet::units::{format_with_prefix, PrefixSystem, PrefixedUnit, Unit};
+///
+/// assert_eq!(format_with_prefix(1500.0, &Unit::Watt.into(), PrefixSystem::Si), "1.5 kW");
+/// assert_eq!(format_with_prefix(2048.0, &PrefixedUnit::kilo(Unit::Byte), PrefixSystem::Binary), "1.95 MiB");
+/// ```
+pub fn format_with_prefix(value: f64, unit: &PrefixedUnit, system: PrefixSystem) -> String {
+    let base = &unit.base_unit;
+    let prefixable = matches!(
+        base,
+        Unit::Second | Unit::Watt | Unit::Joule | Unit::Volt | Unit::Ampere | Unit::Hertz | Unit::WattHour | Unit::Byte
+    );
+    if !prefixable || !value.is_finite() {
+        return format!("{} {unit}", round_for_display(value)).trim_end().to_owned();
+    }
+
+    let value = value * unit.prefix.scale();
+    let prefixes: &[(&str, f64)] = match (system, base) {
+        (PrefixSystem::Binary, Unit::Byte) => &BINARY_PREFIXES,
+        (_, Unit::Byte) => &SI_PREFIXES[3..], // no fraction of a byte
+        _ => &SI_PREFIXES,
+    };
+
+    // Find the largest prefix that keeps the number above 1, then check that rounding
+    // does not push the number to the base of the system (e.g. 999.999 W must be 1 kW, not 1000 W).
+    let abs = value.abs();
+    let mut i = if abs == 0.0 {
+        prefixes.iter().position(|(_, scale)| *scale == 1.0).unwrap()
+    } else {
+        prefixes.iter().rposition(|(_, scale)| abs >= *scale).unwrap_or(0)
+    };
+    let mut scaled = round_for_display(value / prefixes[i].1);
+    if i + 1 < prefixes.len() && scaled.abs() >= prefixes[i + 1].1 / prefixes[i].1 {
+        i += 1;
+        scaled = round_for_display(value / prefixes[i].1);
+    }
+    format!("{scaled} {}{base}", prefixes[i].0)
+}
+
+fn round_for_display(value: f64) -> f64 {
+    let factor = 10f64.powi(FORMAT_DECIMALS);
+    let rounded = (value * factor).round() / factor;
+    if rounded == 0.0 {
+        0.0 // avoid "-0"
+    } else {
+        rounded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_with_prefix, PrefixSystem, PrefixedUnit, Unit, UnitRegistry, UnitRelation};
+
+    fn si(value: f64, unit: impl Into<PrefixedUnit>) -> String {
+        format_with_prefix(value, &unit.into(), PrefixSystem::Si)
+    }
+
+    fn bin(value: f64, unit: impl Into<PrefixedUnit>) -> String {
+        format_with_prefix(value, &unit.into(), PrefixSystem::Binary)
+    }
+
+    #[test]
+    fn power_and_energy() {
+        assert_eq!(si(0.0, Unit::Watt), "0 W");
+        assert_eq!(si(1.0, Unit::Watt), "1 W");
+        assert_eq!(si(999.0, Unit::Watt), "999 W");
+        assert_eq!(si(1000.0, Unit::Watt), "1 kW");
+        assert_eq!(si(1500.0, Unit::Watt), "1.5 kW");
+        assert_eq!(si(2_500_000.0, Unit::Watt), "2.5 MW");
+        assert_eq!(si(-1500.0, Unit::Watt), "-1.5 kW");
+        assert_eq!(si(0.5, Unit::Joule), "500 mJ");
+        assert_eq!(si(0.000_012, Unit::Joule), "12 μJ");
+        assert_eq!(si(1500.0, PrefixedUnit::milli(Unit::Joule)), "1.5 J");
+        assert_eq!(si(3.2, PrefixedUnit::kilo(Unit::WattHour)), "3.2 kWh");
+        // binary prefixes do not apply to energy
+        assert_eq!(bin(2048.0, Unit::Joule), "2.05 kJ");
+    }
+
+    #[test]
+    fn rounding_boundaries() {
+        // rounding must not produce "1000 W"
+        assert_eq!(si(999.999, Unit::Watt), "1 kW");
+        assert_eq!(si(999.994, Unit::Watt), "999.99 W");
+        assert_eq!(si(0.999_999, Unit::Watt), "1 W");
+        assert_eq!(bin(1023.999, Unit::Byte), "1 KiB");
+        assert_eq!(si(1e30, Unit::Watt), "1000000000000000 PW");
+        assert_eq!(si(1e-12, Unit::Watt), "0 nW");
+    }
+
+    #[test]
+    fn data() {
+        assert_eq!(si(0.5, Unit::Byte), "0.5 B");
+        assert_eq!(si(1000.0, Unit::Byte), "1 kB");
+        assert_eq!(bin(1000.0, Unit::Byte), "1000 B");
+        assert_eq!(bin(1023.0, Unit::Byte), "1023 B");
+        assert_eq!(bin(1024.0, Unit::Byte), "1 KiB");
+        assert_eq!(bin(1536.0, Unit::Byte), "1.5 KiB");
+        assert_eq!(bin(1_048_576.0, Unit::Byte), "1 MiB");
+        assert_eq!(bin(1.0, PrefixedUnit::giga(Unit::Byte)), "953.67 MiB");
+    }
+
+    #[test]
+    fn not_prefixable() {
+        assert_eq!(si(1500.0, Unit::DegreeCelsius), "1500 °C");
+        assert_eq!(si(1500.0, Unit::Unity), "1500");
+        assert_eq!(si(f64::NAN, Unit::Watt), "NaN W");
+    }
+
+    #[test]
+    fn custom_units() {
+        let mut units = UnitRegistry::new();
+        let tx = units
+            .register("{transaction}".to_owned(), "tx".to_owned(), None)
+            .unwrap();
+        let kcal = units
+            .register(
+                "kcal".to_owned(),
+                "kcal".to_owned(),
+                Some(UnitRelation {
+                    factor: 4.184,
+                    unit: PrefixedUnit::kilo(Unit::Joule),
+                }),
+            )
+            .unwrap();
+        assert_eq!(tx.to_string(), "tx");
+        assert_eq!(si(1500.0, tx.clone()), "1500 tx");
+        assert_eq!(units.with_name("{transaction}"), Some(tx.clone()));
+
+        // name conflicts
+        units.register("{transaction}".to_owned(), "t".to_owned(), None).unwrap_err();
+        units.register("J".to_owned(), "J".to_owned(), None).unwrap_err();
+        let relation = Some(UnitRelation {
+            factor: 2.0,
+            unit: tx.clone().into(),
+        });
+        units.register("2tx".to_owned(), "2tx".to_owned(), relation).unwrap_err();
+
+        // conversions
+        let factor = |from: PrefixedUnit, to: PrefixedUnit| units.conversion_factor(&from, &to);
+        assert_eq!(factor(kcal.clone().into(), Unit::Joule.into()), Some(4184.0));
+        assert_eq!(factor(PrefixedUnit::kilo(tx.clone()), tx.clone().into()), Some(1000.0));
+        assert_eq!(factor(PrefixedUnit::milli(Unit::Joule), Unit::Joule.into()), Some(1e-3));
+        assert_eq!(factor(tx.clone().into(), Unit::Unity.into()), None);
+        assert_eq!(factor(kcal.into(), tx.into()), None);
+        assert_eq!(factor(Unit::Watt.into(), Unit::Joule.into()), None);
+    }
+
+    #[test]
+    fn parse_prefixed_unit() {
+        let parse = |s: &str| s.parse::<PrefixedUnit>();
+        assert_eq!(parse("W").unwrap(), PrefixedUnit::from(Unit::Watt));
+        assert_eq!(parse("mW").unwrap(), PrefixedUnit::milli(Unit::Watt));
+        assert_eq!(parse("μJ").unwrap(), PrefixedUnit::micro(Unit::Joule));
+        assert_eq!(parse("kW.h").unwrap(), PrefixedUnit::kilo(Unit::WattHour));
+        assert_eq!(parse("GB").unwrap(), PrefixedUnit::giga(Unit::Byte));
+        assert_eq!(parse("milliW").unwrap(), PrefixedUnit::milli(Unit::Watt));
+        assert_eq!(parse("kiloW.h").unwrap(), PrefixedUnit::kilo(Unit::WattHour));
+        let unit = PrefixedUnit::micro(Unit::Joule);
+        assert_eq!(parse(&unit.unique_name()).unwrap(), unit);
+        assert!(parse("xW").is_err());
+        assert!(parse("m").is_err());
+        assert!(parse("").is_err());
+    }
+}
@@ -1,10 +1,20 @@
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
 use alumet::{
     agent::AgentBuilder,
+    measurement::{MeasurementAccumulator, MeasurementBuffer, MeasurementPoint, Timestamp},
+    metrics::TypedMetricId,
+    pipeline::{trigger, Output, OutputContext, PollError, Source, WriteError},
     plugin::{
         rust::{serialize_config, AlumetPlugin},
         AlumetStart, ConfigTable,
     },
+    resources::{Resource, ResourceConsumer},
     static_plugins,
+    units::Unit,
 };
 use serde::Serialize;
 
@@ -118,3 +128,79 @@ impl Default for MyPluginConfig {
         }
     }
 }
+
+#[test]
+fn run_for_fixed_duration() {
+    let plugins = static_plugins![WindowPlugin];
+    let mut config = toml::Table::new();
+    config.insert(String::from("plugins"), toml::Value::Table(toml::Table::new()));
+    let mut agent = AgentBuilder::new(plugins).config_value(config).build();
+    let config = agent.load_config().unwrap();
+    let running = agent.start(config).unwrap();
+    running.run_for(Duration::from_millis(200)).unwrap();
+
+    // The source flushes its buffer every 1000 polls, hence every point has been written by the final flush.
+    let polls = POLLS.load(Ordering::SeqCst);
+    assert!(polls > 0);
+    assert_eq!(WRITTEN.load(Ordering::SeqCst), polls);
+    assert_eq!(STOPPED.load(Ordering::SeqCst), 1);
+}
+
+static POLLS: AtomicUsize = AtomicUsize::new(0);
+static WRITTEN: AtomicUsize = AtomicUsize::new(0);
+static STOPPED: AtomicUsize = AtomicUsize::new(0);
+
+struct WindowPlugin;
+impl AlumetPlugin for WindowPlugin {
+    fn name() -> &'static str {
+        "window"
+    }
+
+    fn version() -> &'static str {
+        "0.0.1"
+    }
+
+    fn init(_config: ConfigTable) -> anyhow::Result<Box<Self>> {
+        Ok(Box::new(WindowPlugin))
+    }
+
+    fn start(&mut self, alumet: &mut AlumetStart) -> anyhow::Result<()> {
+        let metric = alumet.create_metric::<u64>("window_polls", Unit::Unity, "number of polls")?;
+        let trigger = trigger::builder::time_interval(Duration::from_millis(10))
+            .flush_rounds(1000)
+            .build()?;
+        alumet.add_source(Box::new(CountingSource(metric)), trigger);
+        alumet.add_output(Box::new(CountingOutput));
+        Ok(())
+    }
+
+    fn stop(&mut self) -> anyhow::Result<()> {
+        STOPPED.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+struct CountingSource(TypedMetricId<u64>);
+
+impl Source for CountingSource {
+    fn poll(&mut self, acc: &mut MeasurementAccumulator, timestamp: Timestamp) -> Result<(), PollError> {
+        let n = POLLS.fetch_add(1, Ordering::SeqCst) as u64;
+        acc.push(MeasurementPoint::new(
+            timestamp,
+            self.0,
+            Resource::LocalMachine,
+            ResourceConsumer::LocalMachine,
+            n,
+        ));
+        Ok(())
+    }
+}
+
+struct CountingOutput;
+
+impl Output for CountingOutput {
+    fn write(&mut self, measurements: &MeasurementBuffer, _ctx: &OutputContext) -> Result<(), WriteError> {
+        WRITTEN.fetch_add(measurements.len(), Ordering::SeqCst);
+        Ok(())
+    }
+}
@@ -1,16 +1,20 @@
-use std::{process, time::Duration};
+use std::{collections::BTreeMap, path::PathBuf, process, time::Duration};
 
 use alumet::{
-    agent::{static_plugins, Agent, AgentBuilder, AgentConfig},
+    agent::{static_plugins, Agent, AgentBuilder, AgentConfig, NODE_ID_ATTRIBUTE},
+    config::UnknownKeysPolicy,
+    measurement::AttributeValue,
+    pipeline::{branches::BranchConfig, probe::StartupProbe, threading},
     plugin::{
+        command::run_plugin_command,
         event::{self, StartConsumerMeasurement},
         rust::InvalidConfig,
+        util::system_hostname,
     },
     resources::ResourceConsumer,
 };
 
 use clap::{Args, Parser, Subcommand};
-use env_logger::Env;
 
 use plugin_csv::CsvPlugin;
 use plugin_perf::PerfPlugin;
@@ -21,7 +25,8 @@ use serde::{Deserialize, Serialize};
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 fn main() {
-    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+    // The plugins can override the global log level with the `log_level` key of their config.
+    alumet::logging::init_env_logger().expect("failed to set the logger");
     log::info!("Starting ALUMET agent v{VERSION}");
 
     // Parse command-line arguments.
@@ -30,6 +35,15 @@ fn main() {
     // Specifies the plugins that we want to load.
     let plugins = static_plugins![RaplPlugin, CsvPlugin, SocketControlPlugin, PerfPlugin];
 
+    if let Some(Commands::Plugin(plugin_args)) = &args.command {
+        // Run a command provided by a plugin, e.g. `rapl zones`, and stop.
+        if let Err(err) = run_plugin_command(&plugins, plugin_args, &mut std::io::stdout()) {
+            log::error!("{err:#}");
+            process::exit(1);
+        }
+        return;
+    }
+
     // Build the measurement agent.
     let mut agent = AgentBuilder::new(plugins)
         .config_path("alumet-config.toml")
@@ -50,6 +64,7 @@ fn main() {
 
     // Load the config.
     let mut agent_config = agent.load_config().unwrap();
+    let duration = args.duration;
     apply_config(&mut agent, &mut agent_config, args);
 
     // Start the measurement.
@@ -65,14 +80,20 @@ fn main() {
     // Keep the pipeline running until...
     match cmd {
         Commands::Run => {
-            // ...the program stops (on SIGTERM or on a "stop" command).
-            running_agent.wait_for_shutdown().unwrap();
+            // ...the program stops (on SIGTERM or on a "stop" command), or the duration elapses.
+            match duration {
+                Some(duration) => running_agent.run_for(duration).unwrap(),
+                None => running_agent.wait_for_shutdown().unwrap(),
+            }
         }
         Commands::Exec(ExecArgs {
             program: external_command,
             args,
         }) => {
             // ...another process, that we'll launch now, exits.
+            if let Some(duration) = duration {
+                running_agent.pipeline.shutdown_after(duration);
+            }
 
             // Spawn the process.
             let mut p = process::Command::new(external_command.clone())
@@ -95,7 +116,7 @@ fn main() {
             control_handle.shutdown();
             running_agent.wait_for_shutdown().unwrap();
         }
-        Commands::RegenConfig => unreachable!(),
+        Commands::RegenConfig | Commands::Plugin(_) => unreachable!(),
     }
     log::info!("ALUMET agent has stopped.")
 }
@@ -105,11 +126,35 @@ fn apply_config(agent: &mut Agent, global_config: &mut AgentConfig, cli_args: Cl
     // Apply the config file
     let app_config: AppConfig = global_config.take_app_config().try_into().unwrap();
     agent.sources_max_update_interval(app_config.max_update_interval);
+    agent.measure_pipeline_overhead(app_config.measure_pipeline_overhead);
+    agent.points_per_poll(app_config.points_per_poll);
+    agent.emit_agent_info(app_config.emit_agent_info);
+    agent.unknown_config_keys(app_config.unknown_config_keys);
+    agent.branches(app_config.pipelines);
+    agent.startup_probe(app_config.startup_probe);
+    if let Some(cpu_list) = app_config.cpu_affinity {
+        match threading::parse_cpu_list(&cpu_list) {
+            Ok(cpus) => agent.cpu_affinity(Some(cpus)),
+            Err(e) => {
+                log::error!("Invalid cpu_affinity in the configuration: {e:#}");
+                process::exit(1);
+            }
+        }
+    }
 
     // Apply the CLI args (they override the file)
     if let Some(max_update_interval) = cli_args.max_update_interval {
         agent.sources_max_update_interval(max_update_interval);
     }
+
+    agent.effective_config_path(cli_args.effective_config.or(app_config.effective_config_path));
+
+    // Identify the node in every measurement point, defaulting to the hostname.
+    let node_id = cli_args.node_id.or(app_config.node_id);
+    match node_id.or_else(|| system_hostname().ok()) {
+        Some(node_id) => agent.add_global_attribute(NODE_ID_ATTRIBUTE, AttributeValue::String(node_id)),
+        None => log::warn!("Could not determine the hostname, please set the node_id in the configuration."),
+    }
 }
 
 /// Structure of the config file, excluding plugin configs.
@@ -117,12 +162,67 @@ fn apply_config(agent: &mut Agent, global_config: &mut AgentConfig, cli_args: Cl
 struct AppConfig {
     #[serde(with = "humantime_serde")]
     max_update_interval: Duration,
+
+    /// Identifier of the node, attached to every measurement point.
+    /// Defaults to the hostname.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    node_id: Option<String>,
+
+    /// Measures the time spent by Alumet in its sources, transforms and outputs,
+    /// and reports it with the metrics `alumet_poll_duration`, `alumet_transform_duration`
+    /// and `alumet_write_duration` (in nanoseconds).
+    #[serde(default)]
+    measure_pipeline_overhead: bool,
+
+    /// Expected number of measurement points produced by each poll of a source, for instance
+    /// the number of RAPL domains times the number of CPU sockets.
+    /// It is used to pre-allocate the buffers of the sources, a larger value reduces the reallocations.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    points_per_poll: Option<usize>,
+
+    /// If true, Alumet produces measurements that describe its version and its plugins,
+    /// with the metrics `alumet_agent_info` and `alumet_plugin_info`, when it starts.
+    #[serde(default)]
+    emit_agent_info: bool,
+
+    /// What to do when the configuration of a plugin contains unknown keys: "warn" or "error".
+    #[serde(default)]
+    unknown_config_keys: UnknownKeysPolicy,
+
+    /// Named processing branches, each one applies some transforms to the measurements of one output only.
+    /// For instance, `[pipelines.smoothed]` with `output = "prometheus"` and `transforms = ["smoothing"]`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pipelines: BTreeMap<String, BranchConfig>,
+
+    /// Restricts the threads of the measurement pipeline to these CPUs, for instance `"0-3,8"` (Linux only).
+    /// By default, they can run on any CPU.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cpu_affinity: Option<String>,
+
+    /// Polls each source once when the agent starts, to detect the misconfigured sources early:
+    /// "disabled", "warn" (log the failures and continue) or "error" (fail to start).
+    #[serde(default)]
+    startup_probe: StartupProbe,
+
+    /// If set, the effective configuration (with the default values, and without the references to
+    /// environment variables) is written to this file when the agent starts. The secrets are redacted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    effective_config_path: Option<PathBuf>,
 }
 
 impl Default for AppConfig {
     fn default() -> Self {
         Self {
             max_update_interval: Duration::from_millis(500),
+            node_id: None,
+            measure_pipeline_overhead: false,
+            points_per_poll: None,
+            emit_agent_info: false,
+            unknown_config_keys: UnknownKeysPolicy::Warn,
+            pipelines: BTreeMap::new(),
+            cpu_affinity: None,
+            startup_probe: StartupProbe::Disabled,
+            effective_config_path: None,
         }
     }
 }
@@ -139,6 +239,24 @@ struct Cli {
     /// i.e. commands will be applied faster, at the cost of a higher overhead.
     #[arg(long, value_parser = humantime_serde::re::humantime::parse_duration)]
     max_update_interval: Option<Duration>,
+
+    /// Identifier of the node, attached to every measurement point.
+    ///
+    /// Overrides the `node_id` of the config file. Defaults to the hostname.
+    #[arg(long)]
+    node_id: Option<String>,
+
+    /// Stops the measurement after this duration, for instance `30s`.
+    ///
+    /// The last measurements are written to the outputs before the agent exits.
+    #[arg(long, value_parser = humantime_serde::re::humantime::parse_duration)]
+    duration: Option<Duration>,
+
+    /// Writes the effective configuration to this file when the agent starts.
+    ///
+    /// Overrides the `effective_config_path` of the config file.
+    #[arg(long)]
+    effective_config: Option<PathBuf>,
 }
 
 #[derive(Subcommand, Clone)]
@@ -155,6 +273,10 @@ enum Commands {
     ///
     /// If the file exists, it will be overwritten.
     RegenConfig,
+
+    /// Run a command provided by a plugin, for instance `rapl zones`.
+    #[command(external_subcommand)]
+    Plugin(Vec<String>),
 }
 
 #[derive(Args, Clone)]
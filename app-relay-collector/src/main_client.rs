@@ -4,13 +4,13 @@ use alumet::agent::{static_plugins, Agent, AgentBuilder, AgentConfig};
 use alumet::plugin::rust::InvalidConfig;
 
 use clap::Parser;
-use env_logger::Env;
 use serde::{Deserialize, Serialize};
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 fn main() {
-    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+    // The plugins can override the global log level with the `log_level` key of their config.
+    alumet::logging::init_env_logger().expect("failed to set the logger");
     log::info!("Starting ALUMET relay agent v{VERSION}");
 
     // Parse command-line arguments.
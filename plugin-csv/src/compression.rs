@@ -0,0 +1,175 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+
+/// Compression of the output file.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    /// Plain text.
+    #[default]
+    None,
+    /// Gzip, the extension `.gz` is added to the file name if needed.
+    Gzip,
+}
+
+impl Compression {
+    /// Returns the path of the file to write, with the extension of the compression format.
+    pub fn file_path(&self, path: &Path) -> PathBuf {
+        match self {
+            Compression::Gzip if path.extension().map_or(true, |ext| ext != "gz") => {
+                let mut name = path.as_os_str().to_owned();
+                name.push(".gz");
+                PathBuf::from(name)
+            }
+            _ => path.to_owned(),
+        }
+    }
+}
+
+/// Writer of the output file, which compresses the data if needed.
+///
+/// With gzip, each flush ends the current gzip member and the next write starts a new one.
+/// A file made of several members is a valid gzip file: everything that has been flushed
+/// can be decompressed, even if Alumet stops abruptly.
+pub enum FileWriter {
+    Plain(BufWriter<File>),
+    Gzip {
+        /// The encoder of the current member, `None` if nothing has been written since the last flush.
+        encoder: Option<GzEncoder<BufWriter<File>>>,
+        /// The file, between two members.
+        file: Option<BufWriter<File>>,
+    },
+}
+
+impl FileWriter {
+    pub fn create(path: &Path, compression: Compression) -> io::Result<Self> {
+        let file = BufWriter::new(File::create(path)?);
+        Ok(match compression {
+            Compression::None => FileWriter::Plain(file),
+            Compression::Gzip => FileWriter::Gzip {
+                encoder: None,
+                file: Some(file),
+            },
+        })
+    }
+
+    /// Writes the end of the data and flushes the file.
+    ///
+    /// With gzip, it ends the current member. Calling `finish` more than once is harmless.
+    pub fn finish(&mut self) -> io::Result<()> {
+        match self {
+            FileWriter::Plain(w) => w.flush(),
+            FileWriter::Gzip { encoder, file } => {
+                // Unlike `finish`, `try_finish` keeps the encoder, and therefore the file, if it fails.
+                if let Some(current) = encoder.as_mut() {
+                    current.try_finish()?;
+                }
+                if let Some(current) = encoder.take() {
+                    *file = Some(current.finish()?);
+                }
+                file.as_mut().ok_or_else(file_lost)?.flush()
+            }
+        }
+    }
+}
+
+impl Write for FileWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            FileWriter::Plain(w) => w.write(buf),
+            FileWriter::Gzip { encoder, file } => {
+                let current = match encoder.take() {
+                    Some(current) => current,
+                    None => {
+                        let file = file.take().ok_or_else(file_lost)?;
+                        GzEncoder::new(file, flate2::Compression::default())
+                    }
+                };
+                encoder.insert(current).write(buf)
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.finish()
+    }
+}
+
+/// Error returned when the file has been lost because a gzip member could not be ended.
+fn file_lost() -> io::Error {
+    io::Error::new(io::ErrorKind::Other, "the file has been lost after a compression error")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        io::{Read, Write},
+        path::Path,
+    };
+
+    use flate2::read::MultiGzDecoder;
+
+    use super::{Compression, FileWriter};
+
+    fn decompress(path: &Path) -> String {
+        let mut res = String::new();
+        MultiGzDecoder::new(fs::File::open(path).unwrap())
+            .read_to_string(&mut res)
+            .unwrap();
+        res
+    }
+
+    #[test]
+    fn gzip_members() {
+        let dir = std::env::temp_dir().join("alumet-test-csv-gzip");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = Compression::Gzip.file_path(&dir.join("out.csv"));
+        assert_eq!(path, dir.join("out.csv.gz"));
+        assert_eq!(Compression::Gzip.file_path(&path), path);
+        assert_eq!(Compression::None.file_path(&dir.join("out.csv")), dir.join("out.csv"));
+
+        let mut writer = FileWriter::create(&path, Compression::Gzip).unwrap();
+        writer.write_all(b"a;b\n").unwrap();
+        writer.flush().unwrap();
+        // the flushed data is readable before the end
+        assert_eq!(decompress(&path), "a;b\n");
+
+        // flushing without writing does not add an empty member
+        let len = fs::metadata(&path).unwrap().len();
+        writer.flush().unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), len);
+
+        writer.write_all(b"1;2\n").unwrap();
+        writer.finish().unwrap();
+        writer.finish().unwrap();
+        drop(writer);
+        assert_eq!(decompress(&path), "a;b\n1;2\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn gzip_error() {
+        // Every write to /dev/full fails with "no space left on device".
+        let mut writer = match FileWriter::create(Path::new("/dev/full"), Compression::Gzip) {
+            Ok(writer) => writer,
+            Err(_) => return, // not available on this system
+        };
+        // incompressible data, larger than the buffers
+        let data: Vec<u8> = (0..1_000_000u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 24) as u8)
+            .collect();
+        assert!(writer.write_all(&data).and_then(|_| writer.finish()).is_err());
+
+        // the writer keeps returning errors, instead of panicking
+        assert!(writer.write_all(&data).is_err());
+        assert!(writer.finish().is_err());
+    }
+}
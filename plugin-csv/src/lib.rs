@@ -1,14 +1,17 @@
+mod compression;
 mod csv;
 mod output;
 // TODO mod input
 
-use std::path::PathBuf;
+use std::{collections::HashMap, path::PathBuf};
 
 use alumet::plugin::{
     rust::{deserialize_config, serialize_config, AlumetPlugin},
+    util::{AttributeAllowlist, MetricFilter, Rounding},
     ConfigTable,
 };
-use output::CsvOutput;
+use compression::Compression;
+use output::{CsvOutput, CsvOutputSettings, ValueRounding};
 use serde::{Deserialize, Serialize};
 
 pub struct CsvPlugin {
@@ -34,15 +37,21 @@ impl AlumetPlugin for CsvPlugin {
     }
 
     fn start(&mut self, alumet: &mut alumet::plugin::AlumetStart) -> anyhow::Result<()> {
-        let output = Box::new(CsvOutput::new(
-            &self.config.output_path,
-            self.config.force_flush,
-            self.config.append_unit_to_metric_name,
-            self.config.use_unit_display_name,
-            self.config.csv_delimiter,
-            self.config.csv_escaped_quote.take().unwrap_or(String::from("\"\"")),
-        )?);
-        alumet.add_output(output);
+        let settings = CsvOutputSettings {
+            force_flush: self.config.force_flush,
+            append_unit_to_metric_name: self.config.append_unit_to_metric_name,
+            use_unit_display_name: self.config.use_unit_display_name,
+            delimiter: self.config.csv_delimiter,
+            escaped_quote: self.config.csv_escaped_quote.take().unwrap_or(String::from("\"\"")),
+            rounding: ValueRounding {
+                default: self.config.round_values,
+                per_metric: std::mem::take(&mut self.config.round_values_per_metric),
+            },
+            compression: self.config.compression,
+        };
+        let output = Box::new(CsvOutput::new(&self.config.output_path, settings)?);
+        let output = AttributeAllowlist::wrap_opt(self.config.attributes.take(), output);
+        alumet.add_output(std::mem::take(&mut self.config.metric_filter).wrap(output));
         Ok(())
     }
 
@@ -59,6 +68,31 @@ struct Config {
     use_unit_display_name: bool,
     csv_delimiter: char,
     csv_escaped_quote: Option<String>,
+
+    /// Rounds the floating-point values before writing them, for instance `{ significant_digits = 6 }`
+    /// or `{ decimal_places = 3 }`. Only the text written to the file is rounded.
+    /// Disabled by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    round_values: Option<Rounding>,
+
+    /// Rounding of the values of specific metrics, by metric name. Overrides `round_values`.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    round_values_per_metric: HashMap<String, Rounding>,
+
+    /// Only writes the metrics whose name matches these patterns, for instance `{ include = ["rapl_*"] }`.
+    /// By default, all the metrics are written.
+    #[serde(default, skip_serializing_if = "MetricFilter::is_empty")]
+    metric_filter: MetricFilter,
+
+    /// Only writes the attributes whose key matches these patterns, an empty list removes all the attributes.
+    /// By default, all the attributes are written.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    attributes: Option<AttributeAllowlist>,
+
+    /// Compresses the file: "none" (default) or "gzip". With "gzip", `.gz` is appended to `output_path`
+    /// if needed, and each flush (see `force_flush`) ends a gzip member, so that the file stays readable.
+    #[serde(default)]
+    compression: Compression,
 }
 
 impl Default for Config {
@@ -70,6 +104,11 @@ impl Default for Config {
             append_unit_to_metric_name: true,
             csv_delimiter: ';',
             csv_escaped_quote: None,
+            round_values: None,
+            round_values_per_metric: HashMap::new(),
+            metric_filter: MetricFilter::default(),
+            attributes: None,
+            compression: Compression::default(),
         }
     }
 }
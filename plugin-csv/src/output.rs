@@ -1,17 +1,17 @@
 use std::{
-    collections::HashSet,
-    fs::File,
-    io::{self, BufWriter, Write},
+    collections::{HashMap, HashSet},
+    io::{self, Write},
     path::Path,
     time::SystemTime,
 };
 
 use alumet::measurement::MeasurementBuffer;
-use alumet::{measurement::WrappedMeasurementValue, pipeline::OutputContext};
+use alumet::{measurement::WrappedMeasurementValue, pipeline::OutputContext, plugin::util::Rounding};
 use anyhow::Context;
 use time::format_description::well_known::Rfc3339;
 use time::OffsetDateTime;
 
+use crate::compression::{Compression, FileWriter};
 use crate::csv::CsvHelper;
 
 pub struct CsvOutput {
@@ -26,31 +26,65 @@ pub struct CsvOutput {
     append_unit_to_metric_name: bool,
     use_unit_display_name: bool,
 
-    /// File writer
-    writer: BufWriter<File>,
+    /// File writer, which compresses the data if configured so
+    writer: FileWriter,
 
     /// CSV utility
     csv_helper: CsvHelper,
+
+    /// parameter: how to round the floating-point values
+    rounding: ValueRounding,
+}
+
+/// Rounding of the floating-point values, applied when writing them to the file.
+#[derive(Default)]
+pub struct ValueRounding {
+    /// Rounding of all the metrics that are not in `per_metric`, if any.
+    pub default: Option<Rounding>,
+    /// Rounding by metric name.
+    pub per_metric: HashMap<String, Rounding>,
+}
+
+impl ValueRounding {
+    fn format(&self, metric_name: &str, value: f64) -> String {
+        match self.per_metric.get(metric_name).or(self.default.as_ref()) {
+            Some(rounding) => rounding.format(value),
+            None => value.to_string(),
+        }
+    }
+}
+
+/// Settings of the [`CsvOutput`].
+pub struct CsvOutputSettings {
+    /// Flush after each write.
+    pub force_flush: bool,
+    /// Append the unit to the metric name.
+    pub append_unit_to_metric_name: bool,
+    /// Use the display name of the unit instead of its unique name.
+    pub use_unit_display_name: bool,
+    /// Separator of the CSV fields.
+    pub delimiter: char,
+    /// Replacement of the quotes in the CSV fields.
+    pub escaped_quote: String,
+    /// Rounding of the floating-point values.
+    pub rounding: ValueRounding,
+    /// Compression of the file.
+    pub compression: Compression,
 }
 
 impl CsvOutput {
-    pub fn new(
-        output_file: impl AsRef<Path>,
-        force_flush: bool,
-        append_unit_to_metric_name: bool,
-        use_unit_display_name: bool,
-        delimiter: char,
-        escaped_quote: String,
-    ) -> io::Result<Self> {
-        let writer = BufWriter::new(File::create(output_file)?);
-        let helper = CsvHelper::new(delimiter, escaped_quote);
+    pub fn new(output_file: impl AsRef<Path>, settings: CsvOutputSettings) -> io::Result<Self> {
+        let path = settings.compression.file_path(output_file.as_ref());
+        let writer = FileWriter::create(&path, settings.compression)?;
+        let helper = CsvHelper::new(settings.delimiter, settings.escaped_quote);
         Ok(Self {
             attributes_in_header: None,
-            force_flush,
-            append_unit_to_metric_name,
-            use_unit_display_name,
+            force_flush: settings.force_flush,
+            append_unit_to_metric_name: settings.append_unit_to_metric_name,
+            use_unit_display_name: settings.use_unit_display_name,
             writer,
             csv_helper: helper,
+            rounding: settings.rounding,
         })
     }
 }
@@ -122,7 +156,7 @@ impl alumet::pipeline::Output for CsvOutput {
             let datetime: OffsetDateTime = SystemTime::from(m.timestamp).into();
             let datetime: String = datetime.format(&Rfc3339)?;
             let value = match m.value {
-                WrappedMeasurementValue::F64(x) => x.to_string(),
+                WrappedMeasurementValue::F64(x) => self.rounding.format(&full_metric.name, x),
                 WrappedMeasurementValue::U64(x) => x.to_string(),
             };
             let resource_kind = m.resource.kind().to_owned();
@@ -184,6 +218,21 @@ impl alumet::pipeline::Output for CsvOutput {
         }
         Ok(())
     }
+
+    fn finalize(&mut self) -> anyhow::Result<()> {
+        // Writes the end of the compressed stream, if any, and the data that has not been flushed yet.
+        self.writer.finish().context("failed to finalize the CSV file")
+    }
+}
+
+impl Drop for CsvOutput {
+    fn drop(&mut self) {
+        // The output has not been finalized if it has stopped because of an error:
+        // finish the file anyway. Finishing a file that has already been finalized does nothing.
+        if let Err(e) = self.writer.finish() {
+            log::error!("Failed to finalize the CSV file: {e}");
+        }
+    }
 }
 
 fn escape_late_attribute(s: &str) -> String {
@@ -1,9 +1,14 @@
 use std::collections::HashSet;
 
 use alumet::{
+    agent::NODE_ID_ATTRIBUTE,
     measurement::{AttributeValue, WrappedMeasurementValue},
     pipeline::Output,
-    plugin::rust::{deserialize_config, serialize_config, AlumetPlugin},
+    plugin::{
+        rust::{deserialize_config, serialize_config, AlumetPlugin},
+        util::{AttributeAllowlist, MetricFilter, RateLimit, SeriesLimit},
+    },
+    units::Unit,
 };
 use anyhow::Context;
 use serde::{Deserialize, Serialize};
@@ -51,14 +56,40 @@ impl AlumetPlugin for InfluxDbPlugin {
         log::info!("Test successfull.");
 
         // Create the output.
-        alumet.add_output(Box::new(InfluxDbOutput {
+        let output = Box::new(InfluxDbOutput {
             client: influx_client,
             org: config.org,
             bucket: config.bucket,
             attributes_as: config.attributes_as,
             attributes_as_tags: config.attributes_as_tags.unwrap_or_default(),
             attributes_as_fields: config.attributes_as_fields.unwrap_or_default(),
-        }));
+        });
+        let output = AttributeAllowlist::wrap_opt(config.attributes, output);
+        let output: Box<dyn Output> = match config.rate_limit {
+            Some(limit) => {
+                let dropped = alumet.create_metric::<u64>(
+                    "output_dropped_points",
+                    Unit::Unity,
+                    "Number of points that exceeded the rate limit of the output, and were dropped or merged.",
+                )?;
+                limit
+                    .wrap(output, alumet.clock(), dropped)
+                    .context("invalid rate_limit")?
+            }
+            None => output,
+        };
+        let output: Box<dyn Output> = match config.series_limit {
+            Some(limit) => {
+                let dropped = alumet.create_metric::<u64>(
+                    "output_dropped_series",
+                    Unit::Unity,
+                    "Number of series that exceeded the series limit of the output, and were evicted or rejected.",
+                )?;
+                limit.wrap(output, dropped).context("invalid series_limit")?
+            }
+            None => output,
+        };
+        alumet.add_output(config.metric_filter.wrap(output));
         Ok(())
     }
 
@@ -106,7 +137,11 @@ impl Output for InfluxDbOutput {
 
             // Returns true if the attribute with this key should be serialized as an InfluxDB tag,
             // false if it should become a field.
+            // The node identifier is always a tag, unless configured otherwise, because it identifies the series.
             let partition_tag = |key: &str| -> bool {
+                if key == NODE_ID_ATTRIBUTE {
+                    return !self.attributes_as_fields.contains(key);
+                }
                 match &self.attributes_as {
                     AttributeAs::Tag => {
                         // default is tag => tag unless if in set
@@ -175,6 +210,20 @@ struct Config {
     attributes_as: AttributeAs,
     attributes_as_tags: Option<HashSet<String>>,
     attributes_as_fields: Option<HashSet<String>>,
+    /// Only sends the attributes whose key matches these patterns, as tags or fields. An empty list sends no
+    /// attribute, and the series are only identified by their metric, resource and consumer.
+    /// By default, all the attributes are sent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    attributes: Option<AttributeAllowlist>,
+    /// Only sends the metrics whose name matches these patterns. By default, all the metrics are sent.
+    #[serde(default, skip_serializing_if = "MetricFilter::is_empty")]
+    metric_filter: MetricFilter,
+    /// Maximum number of points sent per second. By default, there is no limit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rate_limit: Option<RateLimit>,
+    /// Maximum number of distinct series sent. By default, there is no limit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    series_limit: Option<SeriesLimit>,
 }
 
 /// How to serialize Alumet attributes by default?
@@ -199,6 +248,10 @@ impl Default for Config {
             attributes_as: AttributeAs::Field,
             attributes_as_tags: None,
             attributes_as_fields: None,
+            attributes: None,
+            metric_filter: MetricFilter::default(),
+            rate_limit: None,
+            series_limit: None,
         }
     }
 }
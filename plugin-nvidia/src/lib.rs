@@ -1,6 +1,7 @@
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 
 use alumet::{
+    config::{ConfigSchema, ConfigValueType},
     pipeline::trigger::TriggerSpec,
     plugin::{
         rust::{deserialize_config, serialize_config, AlumetPlugin},
@@ -33,6 +34,64 @@ impl AlumetPlugin for NvidiaPlugin {
         Ok(Some(config))
     }
 
+    fn config_schema() -> Option<ConfigSchema> {
+        let schema = ConfigSchema::new()
+            .entry(
+                "poll_interval",
+                ConfigValueType::Duration,
+                "Initial interval between two Nvidia measurements.",
+            )
+            .entry(
+                "flush_interval",
+                ConfigValueType::Duration,
+                "Initial interval between two flushing of Nvidia measurements.",
+            )
+            .entry(
+                "max_poll_backoff",
+                ConfigValueType::Duration,
+                "Maximum time without polling a device that keeps failing.",
+            )
+            .optional_entry(
+                "processes_poll_interval",
+                ConfigValueType::Duration,
+                "5s",
+                "If set, the number of processes running on each GPU is polled at this interval.\nBy default, all the measurements are polled every poll_interval.",
+            )
+            .optional_entry(
+                "ecc_poll_interval",
+                ConfigValueType::Duration,
+                "1m",
+                "If set, the number of ECC memory errors of each GPU that has ECC enabled is polled at this interval.\nDisabled by default.",
+            )
+            .optional_entry(
+                "devices",
+                ConfigValueType::Array,
+                vec![0],
+                "The NVML devices to monitor, by index or by UUID, for instance [0, \"GPU-a1b2c3d4-...\"].\nIf not set, all the devices are monitored.",
+            )
+            .entry(
+                "max_devices",
+                ConfigValueType::Integer,
+                "Maximum number of NVML devices to detect. Additional devices are ignored.",
+            )
+            .entry(
+                "power_average_window",
+                ConfigValueType::Duration,
+                "Window over which the power of the GPUs is averaged, for instance 1s, when the driver supports it.\nThe driver averages the power over 1s, a longer window averages the last values of the driver.\nDisabled (0s) by default: the instantaneous power is measured.",
+            )
+            .entry(
+                "power_samples",
+                ConfigValueType::Boolean,
+                "Set to true to retrieve the power samples buffered by the driver on each poll, when the GPU supports it.\nThis gives several power measurements per poll_interval.",
+            )
+            .entry(
+                "mig",
+                ConfigValueType::Boolean,
+                "Monitor each MIG instance of the GPUs that have MIG enabled, in addition to the GPUs themselves.\nThe power and energy are only measured for the whole GPU, they are not split between its MIG instances.",
+            );
+        Some(schema)
+    }
+
     fn init(config: ConfigTable) -> anyhow::Result<Box<Self>> {
         let config = deserialize_config(config)?;
         Ok(Box::new(NvidiaPlugin { config }))
@@ -63,7 +122,11 @@ impl NvidiaPlugin {
     /// For Jetson edge devices, use [`start_jetson`] instead.
     #[cfg(feature = "nvml")]
     fn start_nvml(&self, alumet: &mut alumet::plugin::AlumetStart) -> anyhow::Result<()> {
-        let nvml = nvml::NvmlDevices::detect(true)?;
+        let nvml = nvml::NvmlDevices::detect(true, self.config.max_devices, self.config.mig)?;
+        if !nvml.library_loaded {
+            // Not an error (detect has logged it): the plugin can be enabled on machines without NVIDIA GPU.
+            return Ok(());
+        }
         let stats = nvml.detection_stats();
         if stats.found_devices == 0 {
             return Err(anyhow!("No NVML-compatible GPU found. If your device is a Jetson edge device, please disable the `nvml` feature of the plugin."));
@@ -89,13 +152,109 @@ impl NvidiaPlugin {
                 );
             }
         }
+        for (_, device) in &nvml.mig_devices {
+            log::info!("Found MIG instance {} with features: {}", device.id(), device.features);
+        }
+        if !nvml.mig_devices.is_empty() {
+            log::info!(
+                "The power and energy of the GPUs are not measured per MIG instance, but only for the whole GPU."
+            );
+        }
+
+        // Only monitor the selected devices, if any.
+        let selected: Vec<usize> = match &self.config.devices {
+            Some(selectors) => {
+                let uuids: Vec<Option<String>> = nvml
+                    .devices
+                    .iter()
+                    .map(|d| d.as_ref().and_then(|d| d.as_wrapper().uuid().ok()))
+                    .collect();
+                nvml::select_devices(selectors, &uuids)
+            }
+            None => (0..nvml.devices.len()).collect(),
+        };
+        if selected.is_empty() {
+            return Err(anyhow!("None of the configured NVML devices has been found (see previous warnings)."));
+        }
 
         let metrics = nvml::Metrics::new(alumet)?;
+        let max_skipped_polls =
+            (self.config.max_poll_backoff.as_secs_f64() / self.config.poll_interval.as_secs_f64()) as u32;
 
-        for maybe_device in nvml.devices {
+        for (i, maybe_device) in nvml.devices.into_iter().enumerate() {
+            if !selected.contains(&i) {
+                log::debug!("Skipping NVML device {i}, which is not selected in the configuration.");
+                continue;
+            }
             if let Some(device) = maybe_device {
-                let source = nvml::NvmlSource::new(device, metrics.clone())?;
-                let trigger = TriggerSpec::builder(self.config.poll_interval)
+                self.add_nvml_sources(alumet, device, &metrics, max_skipped_polls)?;
+            }
+        }
+        // The MIG instances of the selected GPUs are monitored as well.
+        for (parent, device) in nvml.mig_devices {
+            if selected.contains(&parent) {
+                self.add_nvml_sources(alumet, device, &metrics, max_skipped_polls)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds the source(s) that measure an NVML device.
+    #[cfg(feature = "nvml")]
+    fn add_nvml_sources(
+        &self,
+        alumet: &mut alumet::plugin::AlumetStart,
+        device: nvml::ManagedDevice,
+        metrics: &nvml::Metrics,
+        max_skipped_polls: u32,
+    ) -> anyhow::Result<()> {
+        let device = Arc::new(device);
+        if let Some(ecc_interval) = self.config.ecc_poll_interval {
+            if device.features.has_ecc() {
+                let max_skipped_polls =
+                    (self.config.max_poll_backoff.as_secs_f64() / ecc_interval.as_secs_f64()) as u32;
+                let backoff = nvml::PollBackoff::new(max_skipped_polls);
+                let groups = nvml::MeasurementGroups::ECC;
+                let source = nvml::NvmlSource::new(device.clone(), groups, metrics.clone(), backoff)?;
+                let trigger = TriggerSpec::builder(ecc_interval)
+                    .flush_interval(self.config.flush_interval)
+                    .build()?;
+                alumet.add_source(Box::new(source), trigger);
+            } else {
+                log::info!(
+                    "ECC is disabled on NVML device {}, its ECC errors will not be measured.",
+                    device.id()
+                );
+            }
+        }
+
+        let trigger = TriggerSpec::builder(self.config.poll_interval)
+            .flush_interval(self.config.flush_interval)
+            .build()?;
+        match self.config.processes_poll_interval {
+            None => {
+                let backoff = nvml::PollBackoff::new(max_skipped_polls);
+                let groups = nvml::MeasurementGroups::ALL;
+                let source = nvml::NvmlSource::new(device, groups, metrics.clone(), backoff)?
+                    .with_power_average_window(self.config.power_average_window)
+                    .with_power_samples(self.config.power_samples);
+                alumet.add_source(Box::new(source), trigger);
+            }
+            Some(processes_interval) => {
+                // Two sources share the same device, with different intervals.
+                let backoff = nvml::PollBackoff::new(max_skipped_polls);
+                let groups = nvml::MeasurementGroups::POWER;
+                let source = nvml::NvmlSource::new(device.clone(), groups, metrics.clone(), backoff)?
+                    .with_power_average_window(self.config.power_average_window)
+                    .with_power_samples(self.config.power_samples);
+                alumet.add_source(Box::new(source), trigger);
+
+                let max_skipped_polls =
+                    (self.config.max_poll_backoff.as_secs_f64() / processes_interval.as_secs_f64()) as u32;
+                let backoff = nvml::PollBackoff::new(max_skipped_polls);
+                let groups = nvml::MeasurementGroups::PROCESSES;
+                let source = nvml::NvmlSource::new(device, groups, metrics.clone(), backoff)?;
+                let trigger = TriggerSpec::builder(processes_interval)
                     .flush_interval(self.config.flush_interval)
                     .build()?;
                 alumet.add_source(Box::new(source), trigger);
@@ -139,6 +298,73 @@ struct Config {
     /// Initial interval between two flushing of Nvidia measurements.
     #[serde(with = "humantime_serde")]
     flush_interval: Duration,
+
+    /// Maximum time without polling a device that keeps failing.
+    ///
+    /// When a device fails repeatedly, the plugin skips an exponentially growing number of polls,
+    /// up to this duration, and resumes normal polling as soon as the device works again.
+    #[serde(with = "humantime_serde", default = "default_max_poll_backoff")]
+    max_poll_backoff: Duration,
+
+    /// If set, the number of processes running on each GPU is polled at this interval,
+    /// by a separate source that shares the device with the source of the other measurements.
+    /// By default, all the measurements are polled every `poll_interval`.
+    #[serde(with = "humantime_serde", default, skip_serializing_if = "Option::is_none")]
+    processes_poll_interval: Option<Duration>,
+
+    /// If set, the number of corrected and uncorrected ECC errors of the device memory is polled at this interval,
+    /// by a separate source, in the metrics `nvml_ecc_errors_volatile` (since the driver was loaded) and
+    /// `nvml_ecc_errors_aggregate` (during the lifetime of the device). The GPUs with ECC disabled are skipped.
+    /// Disabled by default.
+    #[serde(with = "humantime_serde", default, skip_serializing_if = "Option::is_none")]
+    ecc_poll_interval: Option<Duration>,
+
+    /// The NVML devices to monitor, by index or by UUID, for instance `[0, "GPU-a1b2c3d4-..."]`.
+    ///
+    /// UUIDs are stable across reboots, unlike indices. If not set, all the devices are monitored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    devices: Option<Vec<DeviceSelector>>,
+
+    /// Maximum number of NVML devices to detect. Additional devices are ignored.
+    #[serde(default = "default_max_devices")]
+    max_devices: u32,
+
+    /// If true, each MIG (Multi-Instance GPU) instance of the GPUs that have MIG enabled is monitored
+    /// as a separate `gpu_mig` resource, whose id is `<parent bus id>/mig<instance index>`.
+    ///
+    /// NVML only measures the power and energy of the physical GPU: they are reported for the GPU,
+    /// and are neither estimated nor split between its MIG instances.
+    /// GPUs with MIG disabled are monitored as usual.
+    #[serde(default)]
+    mig: bool,
+
+    /// Window over which the power of the GPUs is averaged. Disabled by default (`"0s"`).
+    ///
+    /// When set, and when the driver supports it, the power is averaged by the driver over one second, which is less
+    /// noisy than the instantaneous power, and gives a better estimate of the energy. A longer window averages the last
+    /// values given by the driver. The devices that do not support the average power fall back to the instantaneous
+    /// power. The `power_method` attribute of the measurements of `nvml_instant_power` tells which method was used:
+    /// `"average"` or `"instant"`.
+    #[serde(with = "humantime_serde", default)]
+    power_average_window: Duration,
+
+    /// If true, the power samples that the driver has buffered since the previous poll are retrieved on each poll
+    /// (with `nvmlDeviceGetSamples`), and each sample is pushed to `nvml_instant_power` at the time it was measured,
+    /// with `power_method = "sampled"`. The driver samples the power more often than the usual poll interval,
+    /// hence this gives a higher resolution. The devices that do not buffer samples measure the power once per poll.
+    /// Disabled by default.
+    #[serde(default)]
+    power_samples: bool,
+}
+
+/// Identifies a GPU in the configuration.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+enum DeviceSelector {
+    /// Index of the device, as numbered by NVML.
+    Index(usize),
+    /// UUID of the device.
+    Uuid(String),
 }
 
 impl Default for Config {
@@ -146,6 +372,22 @@ impl Default for Config {
         Self {
             poll_interval: Duration::from_secs(1), // 1Hz
             flush_interval: Duration::from_secs(5),
+            max_poll_backoff: default_max_poll_backoff(),
+            processes_poll_interval: None,
+            ecc_poll_interval: None,
+            max_devices: default_max_devices(),
+            devices: None,
+            mig: false,
+            power_average_window: Duration::ZERO,
+            power_samples: false,
         }
     }
 }
+
+fn default_max_poll_backoff() -> Duration {
+    Duration::from_secs(60)
+}
+
+fn default_max_devices() -> u32 {
+    64
+}
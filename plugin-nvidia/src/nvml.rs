@@ -1,5 +1,7 @@
+use std::collections::VecDeque;
 use std::fmt::Display;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use alumet::measurement::Timestamp;
 use alumet::metrics::MetricCreationError;
@@ -15,12 +17,32 @@ use alumet::{
     units::Unit,
 };
 use anyhow::Context;
-use nvml_wrapper::{error::NvmlError, Device, Nvml};
-use nvml_wrapper_sys::bindings::nvmlDevice_t;
+use nvml_wrapper::{
+    enum_wrappers::device::{EccCounter, MemoryError, Sampling},
+    enums::device::SampleValue,
+    error::NvmlError,
+    structs::device::FieldId,
+    Device, Nvml,
+};
+use nvml_wrapper_sys::bindings::{nvmlDevice_t, NVML_DEVICE_MIG_ENABLE};
+
+use crate::DeviceSelector;
+
+/// Field of NVML that contains the power of the device averaged by the driver over about one second, in milliwatts.
+///
+/// It is only available in recent versions of NVML (CUDA 12 and later), hence it is defined here.
+const NVML_FI_DEV_POWER_AVERAGE: u32 = 185;
+
+/// Window over which the driver averages [`NVML_FI_DEV_POWER_AVERAGE`].
+pub const NVML_POWER_AVERAGE_WINDOW: Duration = Duration::from_secs(1);
 
 /// Detected NVML devices.
 pub struct NvmlDevices {
     pub devices: Vec<Option<ManagedDevice>>,
+    /// MIG instances of the devices, with the index of their parent device in `devices`.
+    pub mig_devices: Vec<(usize, ManagedDevice)>,
+    /// Whether the NVML library has been loaded. If `false`, NVML is not installed and there is no device.
+    pub library_loaded: bool,
 }
 
 /// An NVML device that has been probed for available features.
@@ -32,8 +54,10 @@ pub struct ManagedDevice {
     pub handle: nvmlDevice_t,
     /// Status of the optional features: which feature is available on this device?
     pub features: OptionalFeatures,
-    /// PCI bus ID of the device.
+    /// PCI bus ID of the device. For a MIG instance, this is the bus ID of the parent GPU.
     pub bus_id: String,
+    /// Index of the MIG instance in its parent GPU, or `None` if this is a physical GPU.
+    pub mig_index: Option<u32>,
 }
 
 /// Statistics about the device detection.
@@ -44,15 +68,101 @@ pub struct DetectionStats {
 }
 
 /// Measurement source that queries NVML devices.
+///
+/// The device can be shared by several sources that are polled at different intervals,
+/// each source querying different [`MeasurementGroups`].
 pub struct NvmlSource {
     /// Internal state to compute the difference between two increments of the counter.
     energy_counter: CounterDiff,
     /// Handle to the GPU, with features information.
-    device: ManagedDevice,
+    device: Arc<ManagedDevice>,
+    /// The measurements that this source queries.
+    groups: MeasurementGroups,
     /// Alumet metrics IDs.
     metrics: Metrics,
     /// Alumet resource ID.
     resource: Resource,
+    /// Skips some polls when the device fails repeatedly.
+    backoff: PollBackoff,
+    /// Averages the power over the configured window, or `None` to measure the instantaneous power.
+    power_window: Option<PowerWindow>,
+    /// Retrieves the power samples buffered by the driver, if enabled and supported.
+    power_samples: Option<PowerSamples>,
+}
+
+/// Exponential backoff applied when polling a device fails several times in a row.
+///
+/// After `n` consecutive failures, the next `min(2^(n-1), max_skipped_polls)` polls are skipped.
+pub struct PollBackoff {
+    consecutive_failures: u32,
+    remaining_skips: u32,
+    max_skipped_polls: u32,
+}
+
+impl PollBackoff {
+    pub fn new(max_skipped_polls: u32) -> Self {
+        Self {
+            consecutive_failures: 0,
+            remaining_skips: 0,
+            max_skipped_polls,
+        }
+    }
+
+    /// Returns true if the current poll should be skipped.
+    fn should_skip(&mut self) -> bool {
+        if self.remaining_skips > 0 {
+            self.remaining_skips -= 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn on_failure(&mut self) {
+        self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+        let exp = (self.consecutive_failures - 1).min(31);
+        self.remaining_skips = (1u32 << exp).min(self.max_skipped_polls);
+    }
+
+    fn on_success(&mut self) {
+        self.consecutive_failures = 0;
+        self.remaining_skips = 0;
+    }
+}
+
+/// Groups of measurements, which can be polled at different intervals.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MeasurementGroups {
+    /// Energy, power and utilization of the device.
+    pub power: bool,
+    /// Number of processes running on the device, which usually changes less frequently.
+    pub processes: bool,
+    /// Number of ECC errors of the device memory, which changes very rarely.
+    pub ecc: bool,
+}
+
+impl MeasurementGroups {
+    /// All the groups except [`ecc`](Self::ecc), which is only polled when enabled in the configuration.
+    pub const ALL: MeasurementGroups = MeasurementGroups {
+        power: true,
+        processes: true,
+        ecc: false,
+    };
+    pub const POWER: MeasurementGroups = MeasurementGroups {
+        power: true,
+        processes: false,
+        ecc: false,
+    };
+    pub const PROCESSES: MeasurementGroups = MeasurementGroups {
+        power: false,
+        processes: true,
+        ecc: false,
+    };
+    pub const ECC: MeasurementGroups = MeasurementGroups {
+        power: false,
+        processes: false,
+        ecc: true,
+    };
 }
 
 // The pointer `nvmlDevice_t` returned by NVML can be sent between threads.
@@ -60,26 +170,255 @@ pub struct NvmlSource {
 unsafe impl Send for NvmlSource {}
 
 impl NvmlSource {
-    pub fn new(device: ManagedDevice, metrics: Metrics) -> Result<NvmlSource, NvmlError> {
-        let bus_id = std::borrow::Cow::Owned(device.bus_id.clone());
+    pub fn new(
+        device: Arc<ManagedDevice>,
+        groups: MeasurementGroups,
+        metrics: Metrics,
+        backoff: PollBackoff,
+    ) -> Result<NvmlSource, NvmlError> {
+        let resource = device.resource();
         Ok(NvmlSource {
             energy_counter: CounterDiff::with_max_value(u64::MAX),
             device,
+            groups,
             metrics,
-            resource: Resource::Gpu { bus_id },
+            resource,
+            backoff,
+            power_window: None,
+            power_samples: None,
         })
     }
+
+    /// Measures the power averaged over `window` when the device supports it, instead of the instantaneous power.
+    ///
+    /// The driver averages the power over [`NVML_POWER_AVERAGE_WINDOW`]. A longer window averages the last values
+    /// given by the driver. A zero window measures the instantaneous power.
+    pub fn with_power_average_window(mut self, window: Duration) -> Self {
+        self.power_window = (!window.is_zero()).then(|| PowerWindow::new(window));
+        self
+    }
+
+    /// Retrieves, on each poll, the power samples that the driver has buffered since the previous poll,
+    /// instead of measuring the power once per poll (see [`PowerSamples`]).
+    ///
+    /// The devices that do not support it measure the power once per poll, as if it was disabled.
+    pub fn with_power_samples(mut self, enabled: bool) -> Self {
+        let supported = self.device.features.power_samples;
+        if enabled && !supported {
+            log::info!(
+                "NVML device {} does not provide power samples, its power will be measured once per poll.",
+                self.device.id()
+            );
+        }
+        self.power_samples = (enabled && supported).then(|| PowerSamples::new(SystemTime::now()));
+        self
+    }
+
+    /// Returns the method used to measure the power of the device, once per poll.
+    fn power_method(&self) -> Option<PowerMethod> {
+        let features = &self.device.features;
+        if features.average_power && self.power_window.is_some() {
+            Some(PowerMethod::Average)
+        } else if features.instant_power {
+            Some(PowerMethod::Instant)
+        } else {
+            None
+        }
+    }
+}
+
+/// How the power of a device is measured, given in the `power_method` attribute of the measurements.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerMethod {
+    /// Power averaged by the driver, see [`NVML_FI_DEV_POWER_AVERAGE`].
+    Average,
+    /// Instantaneous power, as given by `nvmlDeviceGetPowerUsage`.
+    Instant,
+    /// Power samples buffered by the driver, see [`PowerSamples`].
+    Sampled,
+}
+
+impl PowerMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PowerMethod::Average => "average",
+            PowerMethod::Instant => "instant",
+            PowerMethod::Sampled => "sampled",
+        }
+    }
+}
+
+/// Averages the power over a sliding window.
+///
+/// Each value is already an average computed by the driver: the window keeps the values measured
+/// less than `window` ago, and returns their mean.
+pub struct PowerWindow {
+    window: Duration,
+    values: VecDeque<(SystemTime, u64)>,
+}
+
+impl PowerWindow {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            values: VecDeque::new(),
+        }
+    }
+
+    /// Adds a value, measured at time `t`, and returns the average of the window.
+    fn push(&mut self, t: SystemTime, milli_watts: u64) -> u64 {
+        if self.values.back().is_some_and(|(last, _)| *last > t) {
+            // the clock went backward, start again from this value
+            self.values.clear();
+        }
+        self.values.push_back((t, milli_watts));
+        while let Some((oldest, _)) = self.values.front() {
+            let age = t.duration_since(*oldest).unwrap_or_default();
+            if age < self.window {
+                break;
+            }
+            self.values.pop_front();
+        }
+        let sum: u64 = self.values.iter().map(|(_, v)| v).sum();
+        sum / self.values.len() as u64
+    }
+}
+
+/// Power samples buffered by the driver, retrieved with `nvmlDeviceGetSamples`.
+///
+/// The driver measures the power more often than the source is polled, and keeps the last samples in a buffer.
+/// Each poll retrieves the samples that are newer than the last one seen, and pushes one measurement per sample,
+/// at the time of the sample: the resolution is higher than the poll interval, and no sample is pushed twice.
+pub struct PowerSamples {
+    /// CPU timestamp of the last sample seen, in microseconds since the Unix epoch.
+    last_seen_us: u64,
+}
+
+impl PowerSamples {
+    /// Only the samples measured after `start` will be retrieved.
+    pub fn new(start: SystemTime) -> Self {
+        let since_epoch = start.duration_since(UNIX_EPOCH).unwrap_or_default();
+        Self {
+            last_seen_us: since_epoch.as_micros() as u64,
+        }
+    }
+
+    /// Keeps the samples `(timestamp_us, milli_watts)` that are newer than the last one seen,
+    /// in chronological order, and remembers the newest one.
+    fn keep_new(&mut self, mut samples: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+        samples.retain(|(t, _)| *t > self.last_seen_us);
+        samples.sort_unstable_by_key(|(t, _)| *t);
+        if let Some((newest, _)) = samples.last() {
+            self.last_seen_us = *newest;
+        }
+        samples
+    }
 }
 
 impl alumet::pipeline::Source for NvmlSource {
     fn poll(&mut self, measurements: &mut MeasurementAccumulator, timestamp: Timestamp) -> Result<(), PollError> {
-        let features = &self.device.features;
-        let device = self.device.as_wrapper();
+        if !self.backoff.should_skip() {
+            match self.poll_device(measurements, timestamp) {
+                Ok(()) => {
+                    if self.backoff.consecutive_failures > 0 {
+                        log::info!(
+                            "NVML device {} is working again after {} failed polls.",
+                            self.device.id(),
+                            self.backoff.consecutive_failures
+                        );
+                    }
+                    self.backoff.on_success();
+                }
+                Err(e) => {
+                    if self.backoff.consecutive_failures == 0 {
+                        log::warn!(
+                            "Failed to poll NVML device {}, some polls will be skipped until it works again: {e}",
+                            self.device.id()
+                        );
+                    } else {
+                        log::debug!("Failed to poll NVML device {} again: {e}", self.device.id());
+                    }
+                    self.backoff.on_failure();
+                }
+            }
+        }
+        // When the device is shared by several sources, only one of them reports the failures.
+        if self.groups.power {
+            measurements.push(MeasurementPoint::new(
+                timestamp,
+                self.metrics.consecutive_poll_failures,
+                self.resource.clone(),
+                ResourceConsumer::LocalMachine,
+                self.backoff.consecutive_failures as u64,
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl NvmlSource {
+    fn poll_device(&mut self, measurements: &mut MeasurementAccumulator, timestamp: Timestamp) -> Result<(), PollError> {
+        // clone the Arc to be able to borrow self mutably in poll_power
+        let managed = self.device.clone();
+        let features = &managed.features;
+        let device = managed.as_wrapper();
 
         // no consumer, we just monitor the device here
         let consumer = ResourceConsumer::LocalMachine;
 
-        if features.total_energy_consumption {
+        if self.groups.power {
+            self.poll_power(&device, measurements, timestamp)?;
+        }
+        if self.groups.ecc && features.ecc {
+            self.poll_ecc(&device, measurements, timestamp)?;
+        }
+        if !self.groups.processes {
+            return Ok(());
+        }
+
+        let n_compute_processes = match features.running_compute_processes {
+            AvailableVersion::Latest => Some(device.running_compute_processes_count()?),
+            AvailableVersion::V2 => Some(device.running_compute_processes_count_v2()?),
+            AvailableVersion::None => None,
+        };
+        if let Some(n) = n_compute_processes {
+            measurements.push(MeasurementPoint::new(
+                timestamp,
+                self.metrics.running_compute_processes,
+                self.resource.clone(),
+                consumer.clone(),
+                n as u64,
+            ));
+        }
+
+        let n_graphic_processes = match features.running_graphics_processes {
+            AvailableVersion::Latest => Some(device.running_graphics_processes_count()?),
+            AvailableVersion::V2 => Some(device.running_graphics_processes_count_v2()?),
+            AvailableVersion::None => None,
+        };
+        if let Some(n) = n_graphic_processes {
+            measurements.push(MeasurementPoint::new(
+                timestamp,
+                self.metrics.running_graphics_processes,
+                self.resource.clone(),
+                consumer.clone(),
+                n as u64,
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Polls the energy, power and utilization of the device.
+    fn poll_power(
+        &mut self,
+        device: &Device,
+        measurements: &mut MeasurementAccumulator,
+        timestamp: Timestamp,
+    ) -> Result<(), PollError> {
+        let consumer = ResourceConsumer::LocalMachine;
+
+        if self.device.features.total_energy_consumption {
             // the difference in milliJoules
             let diff = match self.energy_counter.update(device.total_energy_consumption()?) {
                 CounterDiffUpdate::FirstTime => None,
@@ -99,18 +438,30 @@ impl alumet::pipeline::Source for NvmlSource {
             }
         }
 
-        if features.instant_power {
+        let sampled = self.power_samples.is_some() && self.poll_power_samples(device, measurements)?;
+        if let Some(method) = self.power_method().filter(|_| !sampled) {
             // the power in milliWatts
-            let milli_watts = device.power_usage()?;
-            measurements.push(MeasurementPoint::new(
-                timestamp,
-                self.metrics.instant_power,
-                self.resource.clone(),
-                consumer.clone(),
-                milli_watts as u64,
-            ))
+            let milli_watts = match method {
+                PowerMethod::Average => {
+                    let average = read_average_power(device)?;
+                    let window = self.power_window.as_mut().unwrap();
+                    window.push(SystemTime::from(timestamp), average)
+                }
+                PowerMethod::Instant => device.power_usage()? as u64,
+            };
+            measurements.push(
+                MeasurementPoint::new(
+                    timestamp,
+                    self.metrics.instant_power,
+                    self.resource.clone(),
+                    consumer.clone(),
+                    milli_watts,
+                )
+                .with_attr("power_method", method.as_str()),
+            )
         }
 
+        let features = &self.device.features;
         if features.major_utilization {
             let u = device.utilization_rates()?;
             measurements.push(MeasurementPoint::new(
@@ -164,38 +515,92 @@ impl alumet::pipeline::Source for NvmlSource {
                 u.sampling_period as u64,
             ));
         }
+        Ok(())
+    }
 
-        let n_compute_processes = match features.running_compute_processes {
-            AvailableVersion::Latest => Some(device.running_compute_processes_count()?),
-            AvailableVersion::V2 => Some(device.running_compute_processes_count_v2()?),
-            AvailableVersion::None => None,
+    /// Pushes the power samples that the driver has buffered since the previous poll.
+    ///
+    /// Returns `false` if the device does not provide the samples anymore: they are disabled, and the power
+    /// must be measured once per poll instead.
+    fn poll_power_samples(
+        &mut self,
+        device: &Device,
+        measurements: &mut MeasurementAccumulator,
+    ) -> Result<bool, PollError> {
+        let samples = self.power_samples.as_mut().unwrap();
+        let buffered = match device.samples(Sampling::Power, samples.last_seen_us) {
+            Ok(buffered) => buffered,
+            // no new sample since the previous poll
+            Err(NvmlError::NotFound) => return Ok(true),
+            Err(NvmlError::NotSupported) => {
+                log::warn!(
+                    "NVML device {} no longer provides power samples, its power will be measured once per poll.",
+                    self.device.id()
+                );
+                self.power_samples = None;
+                return Ok(false);
+            }
+            Err(e) => return Err(e.into()),
         };
-        if let Some(n) = n_compute_processes {
-            measurements.push(MeasurementPoint::new(
-                timestamp,
-                self.metrics.running_compute_processes,
-                self.resource.clone(),
-                consumer.clone(),
-                n as u64,
-            ));
+        let buffered = buffered
+            .into_iter()
+            .map(|s| (s.timestamp, sample_to_milli_watts(s.value)))
+            .collect();
+        for (timestamp_us, milli_watts) in samples.keep_new(buffered) {
+            let t = UNIX_EPOCH + Duration::from_micros(timestamp_us);
+            measurements.push(
+                MeasurementPoint::new(
+                    Timestamp::from(t),
+                    self.metrics.instant_power,
+                    self.resource.clone(),
+                    ResourceConsumer::LocalMachine,
+                    milli_watts,
+                )
+                .with_attr("power_method", PowerMethod::Sampled.as_str()),
+            );
         }
+        Ok(true)
+    }
 
-        let n_graphic_processes = match features.running_graphics_processes {
-            AvailableVersion::Latest => Some(device.running_graphics_processes_count()?),
-            AvailableVersion::V2 => Some(device.running_graphics_processes_count_v2()?),
-            AvailableVersion::None => None,
-        };
-        if let Some(n) = n_graphic_processes {
-            measurements.push(MeasurementPoint::new(
-                timestamp,
-                self.metrics.running_graphics_processes,
-                self.resource.clone(),
-                consumer.clone(),
-                n as u64,
-            ));
+    /// Polls the number of corrected and uncorrected ECC errors of the device.
+    ///
+    /// The volatile counts are reset when the driver reloads, the aggregate counts persist across reboots:
+    /// they are reported in two different metrics, with the kind of error in the `error_type` attribute.
+    fn poll_ecc(
+        &self,
+        device: &Device,
+        measurements: &mut MeasurementAccumulator,
+        timestamp: Timestamp,
+    ) -> Result<(), PollError> {
+        let metrics = [
+            (self.metrics.ecc_errors_volatile, true),
+            (self.metrics.ecc_errors_aggregate, false),
+        ];
+        for (metric, volatile) in metrics {
+            for corrected in [true, false] {
+                let counter = if volatile {
+                    EccCounter::Volatile
+                } else {
+                    EccCounter::Aggregate
+                };
+                let (error_type, error_type_name) = if corrected {
+                    (MemoryError::Corrected, "corrected")
+                } else {
+                    (MemoryError::Uncorrected, "uncorrected")
+                };
+                let count = device.total_ecc_errors(error_type, counter)?;
+                measurements.push(
+                    MeasurementPoint::new(
+                        timestamp,
+                        metric,
+                        self.resource.clone(),
+                        ResourceConsumer::LocalMachine,
+                        count,
+                    )
+                    .with_attr("error_type", error_type_name),
+                );
+            }
         }
-
-        // TODO explore device.samples() to gather multiple metrics at once
         Ok(())
     }
 }
@@ -213,6 +618,9 @@ pub struct Metrics {
     encoder_sampling_period_us: TypedMetricId<u64>,
     running_compute_processes: TypedMetricId<u64>,
     running_graphics_processes: TypedMetricId<u64>,
+    consecutive_poll_failures: TypedMetricId<u64>,
+    ecc_errors_volatile: TypedMetricId<u64>,
+    ecc_errors_aggregate: TypedMetricId<u64>,
 }
 
 impl Metrics {
@@ -252,6 +660,21 @@ impl Metrics {
                 Unit::Unity,
                 "number of graphic processes running on the device",
             )?,
+            consecutive_poll_failures: alumet.create_metric(
+                "nvml_consecutive_poll_failures",
+                Unit::Unity,
+                "number of consecutive failures to poll the device, 0 when the device works properly",
+            )?,
+            ecc_errors_volatile: alumet.create_metric(
+                "nvml_ecc_errors_volatile",
+                Unit::Unity,
+                "number of ECC errors of the device memory since the driver was loaded",
+            )?,
+            ecc_errors_aggregate: alumet.create_metric(
+                "nvml_ecc_errors_aggregate",
+                Unit::Unity,
+                "number of ECC errors of the device memory during its lifetime",
+            )?,
         })
     }
 }
@@ -261,11 +684,17 @@ impl Metrics {
 pub struct OptionalFeatures {
     total_energy_consumption: bool,
     instant_power: bool,
+    /// The power averaged by the driver is available, see [`NVML_FI_DEV_POWER_AVERAGE`].
+    average_power: bool,
+    /// The driver buffers power samples, see [`PowerSamples`].
+    power_samples: bool,
     major_utilization: bool,
     decoder_utilization: bool,
     encoder_utilization: bool,
     running_compute_processes: AvailableVersion,
     running_graphics_processes: AvailableVersion,
+    /// ECC is enabled, hence the ECC error counters are meaningful.
+    ecc: bool,
 }
 
 /// Indicates which version of a NVML function is available on a given device.
@@ -282,11 +711,14 @@ impl OptionalFeatures {
         Ok(Self {
             total_energy_consumption: is_supported(device.total_energy_consumption())?,
             instant_power: is_supported(device.power_usage())?,
+            average_power: check_average_power(device),
+            power_samples: check_power_samples(device),
             major_utilization: is_supported(device.utilization_rates())?,
             decoder_utilization: is_supported(device.decoder_utilization())?,
             encoder_utilization: is_supported(device.encoder_utilization())?,
             running_compute_processes: check_running_compute_processes(device)?,
             running_graphics_processes: check_running_graphics_processes(device)?,
+            ecc: check_ecc(device),
         })
     }
 
@@ -297,12 +729,19 @@ impl OptionalFeatures {
     pub fn has_any(&self) -> bool {
         self.total_energy_consumption
             || self.instant_power
+            || self.average_power
+            || self.power_samples
             || self.major_utilization
             || self.decoder_utilization
             || self.encoder_utilization
             || self.running_compute_processes != AvailableVersion::None
             || self.running_graphics_processes != AvailableVersion::None
     }
+
+    /// Returns true if the ECC error counters of the device can be measured.
+    pub fn has_ecc(&self) -> bool {
+        self.ecc
+    }
 }
 
 impl Display for OptionalFeatures {
@@ -314,6 +753,12 @@ impl Display for OptionalFeatures {
         if self.instant_power {
             available.push("instant_power");
         }
+        if self.average_power {
+            available.push("average_power");
+        }
+        if self.power_samples {
+            available.push("power_samples");
+        }
         if self.major_utilization {
             available.push("major_utilization");
         }
@@ -333,6 +778,9 @@ impl Display for OptionalFeatures {
             AvailableVersion::V2 => available.push("running_graphics_processes(v2)"),
             AvailableVersion::None => (),
         };
+        if self.ecc {
+            available.push("ecc");
+        }
         write!(f, "{}", available.join(", "))
     }
 }
@@ -359,6 +807,66 @@ fn check_running_graphics_processes(device: &Device) -> Result<AvailableVersion,
     }
 }
 
+/// Returns true if ECC is currently enabled on the device.
+///
+/// ECC is optional: a device on which it cannot be checked is monitored as usual, without the ECC counters.
+fn check_ecc(device: &Device) -> bool {
+    match device.is_ecc_enabled() {
+        Ok(state) => state.currently_enabled,
+        Err(NvmlError::NotSupported) => false,
+        Err(e) => {
+            log::debug!("Failed to check whether ECC is enabled: {e}");
+            false
+        }
+    }
+}
+
+/// Returns true if the device gives the power averaged by the driver.
+///
+/// Like ECC, the average power is optional: older drivers do not know the field, and the instantaneous power
+/// is measured instead.
+fn check_average_power(device: &Device) -> bool {
+    match read_average_power(device) {
+        Ok(_) => true,
+        Err(NvmlError::NotSupported | NvmlError::InvalidArg | NvmlError::FailedToLoadSymbol(_)) => false,
+        Err(e) => {
+            log::debug!("Failed to check whether the average power is available: {e}");
+            false
+        }
+    }
+}
+
+/// Returns true if the driver buffers power samples, which can be retrieved with `nvmlDeviceGetSamples`.
+///
+/// Like the average power, the samples are optional.
+fn check_power_samples(device: &Device) -> bool {
+    match device.samples(Sampling::Power, 0) {
+        // an empty buffer is reported as NotFound
+        Ok(_) | Err(NvmlError::NotFound) => true,
+        Err(NvmlError::NotSupported | NvmlError::InvalidArg | NvmlError::FailedToLoadSymbol(_)) => false,
+        Err(e) => {
+            log::debug!("Failed to check whether the power samples are available: {e}");
+            false
+        }
+    }
+}
+
+/// Reads the power averaged by the driver, in milliwatts.
+fn read_average_power(device: &Device) -> Result<u64, NvmlError> {
+    let mut samples = device.field_values_for(&[FieldId(NVML_FI_DEV_POWER_AVERAGE)])?;
+    let sample = samples.pop().ok_or(NvmlError::NotSupported)??;
+    Ok(sample_to_milli_watts(sample.value?))
+}
+
+fn sample_to_milli_watts(value: SampleValue) -> u64 {
+    match value {
+        SampleValue::F64(v) => v.max(0.0).round() as u64,
+        SampleValue::U32(v) => v as u64,
+        SampleValue::U64(v) => v,
+        SampleValue::I64(v) => v.max(0) as u64,
+    }
+}
+
 fn is_supported<T>(res: Result<T, NvmlError>) -> Result<bool, NvmlError> {
     match res {
         Ok(_) => Ok(true),
@@ -372,13 +880,35 @@ impl NvmlDevices {
     ///
     /// If `skip_failed_devices` is true, inaccessible GPUs will be ignored.
     /// If `ski_failed_devices` is false, the function will return an error at the first inaccessible GPU.
-    pub fn detect(skip_failed_devices: bool) -> anyhow::Result<NvmlDevices> {
-        let nvml = Arc::new(Nvml::init().context(
-            "NVML initialization failed, please check your driver (do you have a dekstop/server NVidia GPU?",
-        )?);
+    ///
+    /// At most `max_devices` devices are detected, the others are ignored with a warning.
+    ///
+    /// If `enumerate_mig` is true, the MIG instances of the GPUs that have MIG enabled are detected
+    /// as well, and stored in `mig_devices`. GPUs with MIG disabled or unsupported have no instance.
+    ///
+    /// If the NVML library is not installed, no device is detected and `library_loaded` is false.
+    /// If the library is installed but fails to initialize, an error is returned.
+    pub fn detect(skip_failed_devices: bool, max_devices: u32, enumerate_mig: bool) -> anyhow::Result<NvmlDevices> {
+        let nvml = match Nvml::init() {
+            Ok(nvml) => Arc::new(nvml),
+            Err(e) if is_library_missing(&e) => {
+                log::info!("NVML is not installed (libnvidia-ml.so could not be loaded), no GPU will be detected: {e}");
+                return Ok(NvmlDevices {
+                    devices: Vec::new(),
+                    mig_devices: Vec::new(),
+                    library_loaded: false,
+                });
+            }
+            Err(e) => {
+                return Err(e).context(
+                    "NVML initialization failed, please check your driver (do you have a dekstop/server NVidia GPU?",
+                )
+            }
+        };
 
-        let count = nvml.device_count()?;
+        let count = cap_device_count(nvml.device_count()?, max_devices);
         let mut devices = Vec::with_capacity(count as usize);
+        let mut mig_devices = Vec::new();
         for i in 0..count {
             let device = match nvml
                 .device_by_index(i)
@@ -391,11 +921,18 @@ impl NvmlDevices {
                         let handle = unsafe { gpu.handle() };
                         let lib = nvml.clone();
                         let bus_id = pci_info?.bus_id;
+                        if enumerate_mig {
+                            match detect_mig_instances(&nvml, &gpu, &bus_id) {
+                                Ok(instances) => mig_devices.extend(instances.into_iter().map(|d| (i as usize, d))),
+                                Err(e) => log::warn!("Failed to enumerate the MIG instances of GPU {bus_id}: {e}"),
+                            }
+                        }
                         let d = ManagedDevice {
                             lib,
                             handle,
                             features,
                             bus_id,
+                            mig_index: None,
                         };
                         Some(d)
                     } else {
@@ -429,7 +966,11 @@ impl NvmlDevices {
             };
             devices.push(device);
         }
-        Ok(NvmlDevices { devices })
+        Ok(NvmlDevices {
+            devices,
+            mig_devices,
+            library_loaded: true,
+        })
     }
 
     pub fn detection_stats(&self) -> DetectionStats {
@@ -452,8 +993,239 @@ impl NvmlDevices {
     }
 }
 
+/// Returns true if the error means that the NVML library is not installed,
+/// as opposed to an installed library that does not work.
+fn is_library_missing(e: &NvmlError) -> bool {
+    matches!(e, NvmlError::LibloadingError(_) | NvmlError::LibraryNotFound)
+}
+
+/// Returns the indices of the devices that match the `selectors`, in ascending order.
+///
+/// `uuids` contains the UUID of each detected device, or `None` if the device is not working
+/// or if its UUID is unknown. Selectors that match no device are ignored, with a warning.
+pub fn select_devices(selectors: &[DeviceSelector], uuids: &[Option<String>]) -> Vec<usize> {
+    let mut selected = Vec::with_capacity(selectors.len());
+    for selector in selectors {
+        let index = match selector {
+            DeviceSelector::Index(i) if *i < uuids.len() => Some(*i),
+            DeviceSelector::Index(_) => None,
+            DeviceSelector::Uuid(uuid) => uuids.iter().position(|u| u.as_ref() == Some(uuid)),
+        };
+        match index {
+            Some(i) => selected.push(i),
+            None => log::warn!(
+                "Ignoring NVML device {selector:?} from the configuration: no such device ({} devices found).",
+                uuids.len()
+            ),
+        }
+    }
+    selected.sort_unstable();
+    selected.dedup();
+    selected
+}
+
+/// Detects the MIG instances of a GPU.
+///
+/// Returns an empty list if MIG is disabled or not supported by the GPU.
+fn detect_mig_instances(nvml: &Arc<Nvml>, gpu: &Device, bus_id: &str) -> Result<Vec<ManagedDevice>, NvmlError> {
+    match gpu.mig_mode() {
+        Ok(mode) if mode.current == NVML_DEVICE_MIG_ENABLE => (),
+        Ok(_) => {
+            log::debug!("MIG is disabled on GPU {bus_id}.");
+            return Ok(Vec::new());
+        }
+        Err(NvmlError::NotSupported) => {
+            log::debug!("GPU {bus_id} does not support MIG.");
+            return Ok(Vec::new());
+        }
+        Err(e) => return Err(e),
+    }
+
+    let max_count = gpu.max_mig_device_count()?;
+    let mut instances = Vec::new();
+    for i in 0..max_count {
+        match gpu
+            .mig_device_by_index(i)
+            .and_then(OptionalFeatures::with_detected_features)
+        {
+            Ok((mig, features)) => {
+                if features.has_any() {
+                    instances.push(ManagedDevice {
+                        lib: nvml.clone(),
+                        handle: unsafe { mig.handle() },
+                        features,
+                        bus_id: bus_id.to_owned(),
+                        mig_index: Some(i),
+                    });
+                } else {
+                    log::warn!("Skipping MIG instance {i} of GPU {bus_id} because it supports no useful feature.");
+                }
+            }
+            // not all the slots are used by an instance
+            Err(NvmlError::NotFound) => (),
+            Err(e) => log::warn!("Skipping MIG instance {i} of GPU {bus_id} because of error: {e}"),
+        }
+    }
+    Ok(instances)
+}
+
+/// Returns the resource id of a MIG instance, which contains the bus id of its parent GPU.
+fn mig_resource_id(bus_id: &str, mig_index: u32) -> String {
+    format!("{bus_id}/mig{mig_index}")
+}
+
+/// Limits the number of devices to detect, to avoid allocating a huge amount of memory
+/// if NVML returns an unexpected number of devices.
+fn cap_device_count(count: u32, max_devices: u32) -> u32 {
+    if count > max_devices {
+        log::warn!(
+            "NVML reports {count} devices, which is more than the maximum of {max_devices}: only the first {max_devices} devices will be used. If this is expected, increase `max_devices` in the configuration."
+        );
+        max_devices
+    } else {
+        count
+    }
+}
+
 impl ManagedDevice {
     pub fn as_wrapper<'a>(&'a self) -> Device<'a> {
         unsafe { Device::new(self.handle, &self.lib) }
     }
+
+    /// Returns a human-readable identifier of the device, for the logs.
+    pub fn id(&self) -> String {
+        match self.mig_index {
+            Some(i) => mig_resource_id(&self.bus_id, i),
+            None => self.bus_id.clone(),
+        }
+    }
+
+    /// Returns the resource that corresponds to the device.
+    ///
+    /// A MIG instance is a `gpu_mig` resource whose id is `<parent bus id>/mig<instance index>`.
+    pub fn resource(&self) -> Resource {
+        match self.mig_index {
+            Some(i) => Resource::custom("gpu_mig", mig_resource_id(&self.bus_id, i)),
+            None => Resource::Gpu {
+                bus_id: std::borrow::Cow::Owned(self.bus_id.clone()),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::DeviceSelector;
+
+    use std::time::{Duration, SystemTime};
+
+    use nvml_wrapper::{enums::device::SampleValue, error::NvmlError};
+
+    use super::{
+        cap_device_count, is_library_missing, mig_resource_id, sample_to_milli_watts, select_devices, PollBackoff,
+        PowerSamples, PowerWindow,
+    };
+
+    #[test]
+    fn device_selection() {
+        let uuids = vec![Some(String::from("GPU-0")), None, Some(String::from("GPU-2"))];
+        let selectors = vec![
+            DeviceSelector::Uuid(String::from("GPU-2")),
+            DeviceSelector::Index(0),
+            DeviceSelector::Index(2), // duplicate
+            DeviceSelector::Index(3), // invalid
+            DeviceSelector::Uuid(String::from("GPU-unknown")),
+        ];
+        assert_eq!(select_devices(&selectors, &uuids), vec![0, 2]);
+        assert!(select_devices(&[], &uuids).is_empty());
+    }
+
+    #[test]
+    fn backoff() {
+        let mut backoff = PollBackoff::new(4);
+        assert!(!backoff.should_skip());
+
+        // skip 1, then 2, then 4, then 4 (capped)
+        for expected_skips in [1, 2, 4, 4] {
+            backoff.on_failure();
+            for _ in 0..expected_skips {
+                assert!(backoff.should_skip());
+            }
+            assert!(!backoff.should_skip());
+        }
+        assert_eq!(backoff.consecutive_failures, 4);
+
+        backoff.on_success();
+        assert_eq!(backoff.consecutive_failures, 0);
+        assert!(!backoff.should_skip());
+    }
+
+    #[test]
+    fn mig_id() {
+        assert_eq!(mig_resource_id("00000000:01:00.0", 0), "00000000:01:00.0/mig0");
+        assert_eq!(mig_resource_id("00000000:01:00.0", 6), "00000000:01:00.0/mig6");
+    }
+
+    #[test]
+    fn missing_library() {
+        assert!(is_library_missing(&NvmlError::LibraryNotFound));
+        // the library is there, but the driver is not working
+        assert!(!is_library_missing(&NvmlError::DriverNotLoaded));
+        assert!(!is_library_missing(&NvmlError::Uninitialized));
+    }
+
+    #[test]
+    fn device_count_cap() {
+        assert_eq!(cap_device_count(0, 64), 0);
+        assert_eq!(cap_device_count(8, 64), 8);
+        assert_eq!(cap_device_count(64, 64), 64);
+        assert_eq!(cap_device_count(u32::MAX, 64), 64);
+    }
+
+    #[test]
+    fn power_window() {
+        let t0 = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        let at = |secs: u64| t0 + Duration::from_secs(secs);
+
+        // a window of the size of the polling interval keeps the last value
+        let mut window = PowerWindow::new(Duration::from_secs(1));
+        assert_eq!(window.push(at(0), 100), 100);
+        assert_eq!(window.push(at(1), 300), 300);
+
+        // a longer window averages the last values
+        let mut window = PowerWindow::new(Duration::from_secs(3));
+        assert_eq!(window.push(at(0), 100), 100);
+        assert_eq!(window.push(at(1), 200), 150);
+        assert_eq!(window.push(at(2), 300), 200);
+        assert_eq!(window.push(at(3), 700), 400);
+
+        // the clock going backward does not break the window
+        assert_eq!(window.push(at(1), 500), 500);
+    }
+
+    #[test]
+    fn power_samples() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_micros(1_000);
+        let mut samples = PowerSamples::new(start);
+
+        // the samples measured before the start are ignored, the others are sorted
+        let new = samples.keep_new(vec![(1_200, 30), (900, 10), (1_100, 20)]);
+        assert_eq!(new, vec![(1_100, 20), (1_200, 30)]);
+        assert_eq!(samples.last_seen_us, 1_200);
+
+        // the buffer still contains the samples of the previous poll: no duplicate
+        let new = samples.keep_new(vec![(1_100, 20), (1_200, 30), (1_300, 40)]);
+        assert_eq!(new, vec![(1_300, 40)]);
+        assert!(samples.keep_new(vec![(1_300, 40)]).is_empty());
+        assert!(samples.keep_new(Vec::new()).is_empty());
+        assert_eq!(samples.last_seen_us, 1_300);
+    }
+
+    #[test]
+    fn average_power_sample() {
+        assert_eq!(sample_to_milli_watts(SampleValue::U32(250_000)), 250_000);
+        assert_eq!(sample_to_milli_watts(SampleValue::U64(1)), 1);
+        assert_eq!(sample_to_milli_watts(SampleValue::F64(12.6)), 13);
+        assert_eq!(sample_to_milli_watts(SampleValue::I64(-1)), 0);
+    }
 }
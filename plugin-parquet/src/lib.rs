@@ -0,0 +1,96 @@
+mod output;
+pub mod schema;
+
+use std::{path::PathBuf, time::Duration};
+
+use alumet::plugin::{
+    rust::{deserialize_config, serialize_config, AlumetPlugin},
+    util::{AttributeAllowlist, MetricFilter},
+    ConfigTable,
+};
+use output::{ParquetOutput, Rotation};
+use serde::{Deserialize, Serialize};
+
+pub struct ParquetPlugin {
+    config: Config,
+}
+
+impl AlumetPlugin for ParquetPlugin {
+    fn name() -> &'static str {
+        "parquet"
+    }
+
+    fn version() -> &'static str {
+        env!("CARGO_PKG_VERSION")
+    }
+
+    fn default_config() -> anyhow::Result<Option<ConfigTable>> {
+        Ok(Some(serialize_config(Config::default())?))
+    }
+
+    fn init(config: ConfigTable) -> anyhow::Result<Box<Self>> {
+        let config: Config = deserialize_config(config)?;
+        Ok(Box::new(ParquetPlugin { config }))
+    }
+
+    fn start(&mut self, alumet: &mut alumet::plugin::AlumetStart) -> anyhow::Result<()> {
+        let rotation = Rotation {
+            max_file_size: self.config.max_file_size,
+            max_file_duration: self.config.max_file_duration,
+        };
+        let output = ParquetOutput::new(self.config.output_dir.clone(), rotation, self.config.max_row_group_size)?
+            .with_metric_schema(self.config.metric_schema);
+        let output = AttributeAllowlist::wrap_opt(self.config.attributes.clone(), Box::new(output));
+        alumet.add_output(self.config.metric_filter.clone().wrap(output));
+        Ok(())
+    }
+
+    fn stop(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct Config {
+    /// Directory in which the Parquet files are created.
+    output_dir: PathBuf,
+
+    /// Once a file reaches this size (in bytes), it is closed and a new file is created.
+    max_file_size: u64,
+
+    /// Once a file has been open for this duration, it is closed and a new file is created.
+    #[serde(with = "humantime_serde")]
+    max_file_duration: Duration,
+
+    /// Maximum number of rows in a Parquet row group.
+    /// The rows are buffered in memory until a group is complete.
+    max_row_group_size: usize,
+
+    /// Writes, next to each data file, a Parquet file without rows whose schema describes the metrics:
+    /// one field per metric, with its type, and its unit and description in the field metadata.
+    #[serde(default)]
+    metric_schema: bool,
+
+    /// Only writes the metrics whose name matches these patterns. By default, all the metrics are written.
+    #[serde(default, skip_serializing_if = "MetricFilter::is_empty")]
+    metric_filter: MetricFilter,
+
+    /// Only writes the attributes whose key matches these patterns, an empty list removes all the attributes.
+    /// By default, all the attributes are written.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    attributes: Option<AttributeAllowlist>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            output_dir: PathBuf::from("alumet-parquet"),
+            max_file_size: 64 * 1024 * 1024, // 64 MiB
+            max_file_duration: Duration::from_secs(3600),
+            max_row_group_size: 8192,
+            metric_schema: false,
+            metric_filter: MetricFilter::default(),
+            attributes: None,
+        }
+    }
+}
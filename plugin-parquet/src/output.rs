@@ -0,0 +1,328 @@
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use alumet::{
+    measurement::{MeasurementBuffer, Timestamp, WrappedMeasurementValue},
+    metrics::{MetricRegistry, RawMetricId},
+    pipeline::{OutputContext, WriteError},
+    resources::{Resource, ResourceConsumer},
+};
+use anyhow::Context;
+use arrow::{
+    array::{ArrayRef, Float64Array, StringArray, TimestampNanosecondArray, UInt64Array},
+    datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit},
+    record_batch::RecordBatch,
+};
+use parquet::{arrow::ArrowWriter, file::properties::WriterProperties};
+
+use crate::schema::metrics_schema;
+
+/// When to close the current file and start a new one.
+pub struct Rotation {
+    pub max_file_size: u64,
+    pub max_file_duration: Duration,
+}
+
+pub struct ParquetOutput {
+    output_dir: PathBuf,
+    rotation: Rotation,
+    schema: SchemaRef,
+    properties: WriterProperties,
+    /// Whether to describe the metrics in a schema file next to each data file.
+    metric_schema: bool,
+    /// The file that is being written, if any.
+    current: Option<CurrentFile>,
+    /// Number of files created so far, used to name the files.
+    ///
+    /// Two rotations can happen in the same millisecond, hence the timestamp is not enough.
+    file_count: u64,
+}
+
+struct CurrentFile {
+    writer: ArrowWriter<File>,
+    path: PathBuf,
+    opened_at: Instant,
+}
+
+impl ParquetOutput {
+    pub fn new(output_dir: PathBuf, rotation: Rotation, max_row_group_size: usize) -> anyhow::Result<Self> {
+        fs::create_dir_all(&output_dir)
+            .with_context(|| format!("could not create output directory {}", output_dir.display()))?;
+        let properties = WriterProperties::builder()
+            .set_max_row_group_size(max_row_group_size)
+            .build();
+        Ok(Self {
+            output_dir,
+            rotation,
+            schema: Arc::new(measurement_schema()),
+            properties,
+            metric_schema: false,
+            current: None,
+            file_count: 0,
+        })
+    }
+
+    /// Writes, next to each data file, a Parquet file without rows whose schema describes the metrics
+    /// that are registered when the data file is created, see [`metrics_schema`].
+    ///
+    /// The schema file of `alumet-<time>-<n>.parquet` is `alumet-<time>-<n>.metrics.parquet`.
+    pub fn with_metric_schema(mut self, enabled: bool) -> Self {
+        self.metric_schema = enabled;
+        self
+    }
+
+    /// Writes a batch of rows to the current file, after creating a new one if necessary.
+    ///
+    /// Returns the path of the new file, if one has been created.
+    fn write_batch(&mut self, batch: &RecordBatch) -> anyhow::Result<Option<PathBuf>> {
+        let new_file = self.rotate()?;
+        let current = self.current.as_mut().unwrap();
+        current
+            .writer
+            .write(batch)
+            .with_context(|| format!("failed to write to {}", current.path.display()))?;
+        Ok(new_file)
+    }
+
+    /// Creates a new file if there is no current file, or if the current one is too big or too old.
+    ///
+    /// Returns the path of the new file, if one has been created.
+    fn rotate(&mut self) -> anyhow::Result<Option<PathBuf>> {
+        if let Some(current) = &self.current {
+            let size = current.writer.bytes_written() + current.writer.in_progress_size();
+            if size as u64 >= self.rotation.max_file_size
+                || current.opened_at.elapsed() >= self.rotation.max_file_duration
+            {
+                self.close_current()?;
+            }
+        }
+        if self.current.is_some() {
+            return Ok(None);
+        }
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let path = self
+            .output_dir
+            .join(format!("alumet-{}-{}.parquet", now.as_millis(), self.file_count));
+        self.file_count += 1;
+        let file = File::create(&path).with_context(|| format!("could not create {}", path.display()))?;
+        let writer = ArrowWriter::try_new(file, self.schema.clone(), Some(self.properties.clone()))?;
+        log::debug!("Writing measurements to {}", path.display());
+        self.current = Some(CurrentFile {
+            writer,
+            path: path.clone(),
+            opened_at: Instant::now(),
+        });
+        Ok(Some(path))
+    }
+
+    /// Flushes and closes the current file, if any.
+    fn close_current(&mut self) -> anyhow::Result<()> {
+        if let Some(current) = self.current.take() {
+            current
+                .writer
+                .close()
+                .with_context(|| format!("failed to close {}", current.path.display()))?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes a Parquet file without rows, whose schema describes the metrics.
+fn write_metric_schema(path: &Path, metrics: &MetricRegistry) -> anyhow::Result<()> {
+    let file = File::create(path).with_context(|| format!("could not create {}", path.display()))?;
+    let schema = Arc::new(metrics_schema(metrics.iter()));
+    ArrowWriter::try_new(file, schema, None)?
+        .close()
+        .with_context(|| format!("failed to close {}", path.display()))?;
+    Ok(())
+}
+
+/// Returns the schema of the Parquet files.
+///
+/// Measurement values are stored as `f64`, hence integers above 2^53 lose some precision.
+fn measurement_schema() -> Schema {
+    Schema::new(vec![
+        Field::new(
+            "timestamp",
+            DataType::Timestamp(TimeUnit::Nanosecond, Some("UTC".into())),
+            false,
+        ),
+        Field::new("metric_id", DataType::UInt64, false),
+        Field::new("metric_name", DataType::Utf8, false),
+        Field::new("resource_kind", DataType::Utf8, false),
+        Field::new("resource_id", DataType::Utf8, true),
+        Field::new("consumer_kind", DataType::Utf8, false),
+        Field::new("consumer_id", DataType::Utf8, true),
+        Field::new("value", DataType::Float64, false),
+        Field::new("unit", DataType::Utf8, false),
+    ])
+}
+
+/// Converts the measurements to an Arrow batch that follows [`measurement_schema`].
+/// The fields of a measurement point that are written to the Parquet files.
+type PointFields<'a> = (
+    &'a Timestamp,
+    &'a RawMetricId,
+    &'a Resource,
+    &'a ResourceConsumer,
+    &'a WrappedMeasurementValue,
+);
+
+fn to_record_batch(
+    schema: SchemaRef,
+    measurements: &MeasurementBuffer,
+    ctx: &OutputContext,
+) -> anyhow::Result<RecordBatch> {
+    let n = measurements.len();
+    let mut timestamps = Vec::with_capacity(n);
+    let mut metric_ids = Vec::with_capacity(n);
+    let mut metric_names = Vec::with_capacity(n);
+    let mut resource_kinds = Vec::with_capacity(n);
+    let mut resource_ids = Vec::with_capacity(n);
+    let mut consumer_kinds = Vec::with_capacity(n);
+    let mut consumer_ids = Vec::with_capacity(n);
+    let mut values = Vec::with_capacity(n);
+    let mut units = Vec::with_capacity(n);
+    // A buffer with the columnar layout is read column by column, without building its points.
+    let points: Box<dyn Iterator<Item = PointFields>> = match measurements.as_columns() {
+        Some(columns) => Box::new(
+            columns
+                .timestamps()
+                .iter()
+                .zip(columns.metrics())
+                .zip(columns.resources())
+                .zip(columns.consumers())
+                .zip(columns.values())
+                .map(|((((t, m), r), c), v)| (t, m, r, c, v)),
+        ),
+        None => Box::new(
+            measurements
+                .iter()
+                .map(|m| (&m.timestamp, &m.metric, &m.resource, &m.consumer, &m.value)),
+        ),
+    };
+    for (timestamp, metric_id, resource, consumer, value) in points {
+        let t = SystemTime::from(*timestamp).duration_since(UNIX_EPOCH)?;
+        let metric = ctx
+            .metrics
+            .with_id(metric_id)
+            .with_context(|| format!("unknown metric {metric_id:?}"))?;
+        timestamps.push(t.as_nanos() as i64);
+        metric_ids.push(metric_id.as_u64());
+        metric_names.push(metric.name.clone());
+        resource_kinds.push(resource.kind());
+        resource_ids.push(resource.id_string());
+        consumer_kinds.push(consumer.kind());
+        consumer_ids.push(consumer.id_string());
+        values.push(value.as_f64());
+        units.push(metric.unit.to_string());
+    }
+
+    let arrays: Vec<ArrayRef> = vec![
+        Arc::new(TimestampNanosecondArray::from(timestamps).with_timezone("UTC")),
+        Arc::new(UInt64Array::from(metric_ids)),
+        Arc::new(StringArray::from(metric_names)),
+        Arc::new(StringArray::from(resource_kinds)),
+        Arc::new(StringArray::from_iter(resource_ids)),
+        Arc::new(StringArray::from(consumer_kinds)),
+        Arc::new(StringArray::from_iter(consumer_ids)),
+        Arc::new(Float64Array::from(values)),
+        Arc::new(StringArray::from(units)),
+    ];
+    Ok(RecordBatch::try_new(schema, arrays)?)
+}
+
+impl alumet::pipeline::Output for ParquetOutput {
+    fn write(&mut self, measurements: &MeasurementBuffer, ctx: &OutputContext) -> Result<(), WriteError> {
+        if measurements.is_empty() {
+            return Ok(());
+        }
+        let batch = to_record_batch(self.schema.clone(), measurements, ctx)?;
+        let new_file = self.write_batch(&batch)?;
+        if let Some(data_file) = new_file.filter(|_| self.metric_schema) {
+            // alumet-<time>-<n>.parquet -> alumet-<time>-<n>.metrics.parquet
+            write_metric_schema(&data_file.with_extension("metrics.parquet"), &ctx.metrics)?;
+        }
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> anyhow::Result<()> {
+        // write the footer of the last file, otherwise it would not be readable
+        self.close_current()
+    }
+}
+
+impl Drop for ParquetOutput {
+    fn drop(&mut self) {
+        // The output has not been finalized if it has stopped because of an error:
+        // write the footer of the last file anyway.
+        if let Err(e) = self.close_current() {
+            log::error!("Error while closing the Parquet output: {e:?}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, sync::Arc, time::Duration};
+
+    use alumet::pipeline::Output;
+    use arrow::{
+        array::{ArrayRef, Float64Array, StringArray, TimestampNanosecondArray, UInt64Array},
+        record_batch::RecordBatch,
+    };
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    use super::{ParquetOutput, Rotation};
+
+    /// Builds a batch of `n` rows that follows the schema of the output.
+    fn batch(output: &ParquetOutput, n: usize) -> RecordBatch {
+        let strings = |s: &str| Arc::new(StringArray::from(vec![s; n])) as ArrayRef;
+        let arrays: Vec<ArrayRef> = vec![
+            Arc::new(TimestampNanosecondArray::from(vec![0; n]).with_timezone("UTC")),
+            Arc::new(UInt64Array::from(vec![0; n])),
+            strings("rapl_consumed_energy"),
+            strings("local_machine"),
+            Arc::new(StringArray::from(vec![None::<&str>; n])),
+            strings("local_machine"),
+            Arc::new(StringArray::from(vec![None::<&str>; n])),
+            Arc::new(Float64Array::from(vec![1.5; n])),
+            strings("J"),
+        ];
+        RecordBatch::try_new(output.schema.clone(), arrays).unwrap()
+    }
+
+    #[test]
+    fn write_rotate_read() {
+        let dir = std::env::temp_dir().join(format!("alumet-test-parquet-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        // rotate after every batch
+        let rotation = Rotation {
+            max_file_size: 1,
+            max_file_duration: Duration::from_secs(3600),
+        };
+        let mut output = ParquetOutput::new(dir.clone(), rotation, 1024).unwrap();
+        for n in 1..=3 {
+            let new_file = output.write_batch(&batch(&output, n)).unwrap();
+            assert!(new_file.is_some(), "batch {n} should have been written to a new file");
+        }
+        output.finalize().unwrap();
+
+        // the rotations may happen in the same millisecond, but no file is overwritten
+        let mut rows: Vec<usize> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| {
+                let file = File::open(entry.unwrap().path()).unwrap();
+                let reader = ParquetRecordBatchReaderBuilder::try_new(file).unwrap().build().unwrap();
+                reader.map(|batch| batch.unwrap().num_rows()).sum()
+            })
+            .collect();
+        rows.sort();
+        assert_eq!(rows, vec![1, 2, 3]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+
+use alumet::{
+    measurement::WrappedMeasurementType,
+    metrics::{Metric, RawMetricId},
+};
+use arrow::datatypes::{DataType, Field, Schema};
+
+/// Key of the field metadata that contains the unique name of the unit (UCUM), which can be parsed back.
+pub const UNIT_KEY: &str = "unit";
+/// Key of the field metadata that contains the unit, as displayed to humans.
+pub const UNIT_DISPLAY_KEY: &str = "unit_display";
+/// Key of the field metadata that contains the description of the metric.
+pub const DESCRIPTION_KEY: &str = "description";
+/// Key of the field metadata that contains the id of the metric, as in the `metric_id` column.
+pub const METRIC_ID_KEY: &str = "metric_id";
+
+/// Returns an Arrow schema that describes the metrics: one nullable field per metric, named after the metric,
+/// with the type of its values and its unit in the field metadata.
+///
+/// The fields are sorted by metric name, so that the same metrics always give the same schema.
+pub fn metrics_schema<'a>(metrics: impl IntoIterator<Item = (&'a RawMetricId, &'a Metric)>) -> Schema {
+    let mut metrics: Vec<_> = metrics.into_iter().collect();
+    metrics.sort_by(|(_, a), (_, b)| a.name.cmp(&b.name));
+    let fields: Vec<Field> = metrics
+        .into_iter()
+        .map(|(id, metric)| {
+            let data_type = match metric.value_type {
+                WrappedMeasurementType::F64 => DataType::Float64,
+                WrappedMeasurementType::U64 => DataType::UInt64,
+            };
+            let metadata = HashMap::from([
+                (UNIT_KEY.to_owned(), metric.unit.unique_name()),
+                (UNIT_DISPLAY_KEY.to_owned(), metric.unit.display_name()),
+                (DESCRIPTION_KEY.to_owned(), metric.description.clone()),
+                (METRIC_ID_KEY.to_owned(), id.as_u64().to_string()),
+            ]);
+            Field::new(&metric.name, data_type, true).with_metadata(metadata)
+        })
+        .collect();
+    Schema::new(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use alumet::{
+        measurement::WrappedMeasurementType,
+        metrics::{Metric, RawMetricId},
+        units::{PrefixedUnit, Unit},
+    };
+    use arrow::datatypes::DataType;
+
+    use super::{metrics_schema, DESCRIPTION_KEY, METRIC_ID_KEY, UNIT_DISPLAY_KEY, UNIT_KEY};
+
+    #[test]
+    fn schema_matches_metrics() {
+        let metric = |name: &str, value_type, unit| Metric {
+            name: name.to_owned(),
+            description: format!("description of {name}"),
+            value_type,
+            unit,
+        };
+        let energy = metric("rapl_consumed_energy", WrappedMeasurementType::F64, Unit::Joule.into());
+        let milliwatt = PrefixedUnit::milli(Unit::Watt);
+        let power = metric("nvml_power", WrappedMeasurementType::U64, milliwatt);
+        let ids = [RawMetricId::from_u64(0), RawMetricId::from_u64(1)];
+        let schema = metrics_schema([(&ids[0], &energy), (&ids[1], &power)]);
+
+        // sorted by name
+        let names: Vec<&str> = schema.fields().iter().map(|f| f.name().as_str()).collect();
+        assert_eq!(names, vec!["nvml_power", "rapl_consumed_energy"]);
+
+        let power = schema.field_with_name("nvml_power").unwrap();
+        assert_eq!(power.data_type(), &DataType::UInt64);
+        assert!(power.is_nullable());
+        assert_eq!(power.metadata()[UNIT_KEY], "milliW");
+        assert_eq!(power.metadata()[UNIT_DISPLAY_KEY], "mW");
+        assert_eq!(power.metadata()[DESCRIPTION_KEY], "description of nvml_power");
+        assert_eq!(power.metadata()[METRIC_ID_KEY], "1");
+
+        let energy = schema.field_with_name("rapl_consumed_energy").unwrap();
+        assert_eq!(energy.data_type(), &DataType::Float64);
+        assert_eq!(energy.metadata()[UNIT_KEY], "J");
+        assert_eq!(energy.metadata()[METRIC_ID_KEY], "0");
+
+        // the unit can be parsed back
+        let unit: PrefixedUnit = power.metadata()[UNIT_KEY].parse().unwrap();
+        assert_eq!(unit, PrefixedUnit::milli(Unit::Watt));
+    }
+}
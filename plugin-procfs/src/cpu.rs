@@ -0,0 +1,177 @@
+//! CPU utilization, computed from `/proc/stat`.
+//!
+//! See https://www.kernel.org/doc/html/latest/filesystems/proc.html#miscellaneous-kernel-statistics-in-proc-stat
+
+use std::{collections::HashMap, fs};
+
+use alumet::{
+    measurement::{MeasurementAccumulator, MeasurementPoint, Timestamp},
+    metrics::TypedMetricId,
+    pipeline::PollError,
+    resources::{Resource, ResourceConsumer},
+};
+use anyhow::{anyhow, Context};
+
+const PROC_STAT_PATH: &str = "/proc/stat";
+
+/// Identifies a line of `/proc/stat`: `None` for the aggregate line `cpu`, `Some(n)` for `cpuN`.
+type CpuId = Option<u32>;
+
+/// Cumulative CPU times, in jiffies (USER_HZ).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CpuTimes {
+    /// Time spent doing nothing (idle + iowait).
+    pub idle: u64,
+    /// Total time, including idle.
+    pub total: u64,
+}
+
+/// Measures the CPU utilization of each core, and of the whole machine.
+pub struct CpuUtilizationSource {
+    metric: TypedMetricId<f64>,
+    tracker: UtilizationTracker,
+}
+
+/// Computes the utilization between two readings of `/proc/stat`.
+#[derive(Default)]
+struct UtilizationTracker {
+    previous: HashMap<CpuId, CpuTimes>,
+}
+
+impl CpuUtilizationSource {
+    pub fn new(metric: TypedMetricId<f64>) -> Self {
+        Self {
+            metric,
+            tracker: UtilizationTracker::default(),
+        }
+    }
+}
+
+impl alumet::pipeline::Source for CpuUtilizationSource {
+    fn poll(&mut self, measurements: &mut MeasurementAccumulator, timestamp: Timestamp) -> Result<(), PollError> {
+        let content = fs::read_to_string(PROC_STAT_PATH).with_context(|| format!("failed to read {PROC_STAT_PATH}"))?;
+        let times = parse_proc_stat(&content)?;
+        let points = self.tracker.update(times).into_iter().map(|(cpu, utilization)| {
+            let resource = match cpu {
+                None => Resource::LocalMachine,
+                Some(id) => Resource::CpuCore { id },
+            };
+            MeasurementPoint::new(timestamp, self.metric, resource, ResourceConsumer::LocalMachine, utilization)
+        });
+        measurements.push_many(points);
+        Ok(())
+    }
+}
+
+impl UtilizationTracker {
+    /// Updates the state with the new CPU times, and returns the utilization of each CPU since the previous update.
+    ///
+    /// CPUs that are new, or that were offline during the previous update, have no utilization yet.
+    /// CPUs that went offline are forgotten.
+    fn update(&mut self, times: Vec<(CpuId, CpuTimes)>) -> Vec<(CpuId, f64)> {
+        let mut res = Vec::with_capacity(times.len());
+        let mut current = HashMap::with_capacity(times.len());
+        for (cpu, t) in times {
+            if let Some(utilization) = self.previous.get(&cpu).and_then(|prev| utilization(prev, &t)) {
+                res.push((cpu, utilization));
+            }
+            current.insert(cpu, t);
+        }
+        self.previous = current;
+        res
+    }
+}
+
+/// Computes the utilization ratio between two readings.
+///
+/// Returns `None` if no time has elapsed, or if the counters went backwards
+/// (which can happen when a CPU is unplugged and plugged again).
+fn utilization(prev: &CpuTimes, current: &CpuTimes) -> Option<f64> {
+    let d_total = current.total.checked_sub(prev.total)?;
+    let d_idle = current.idle.checked_sub(prev.idle)?;
+    if d_total == 0 || d_idle > d_total {
+        return None;
+    }
+    Some(1.0 - (d_idle as f64 / d_total as f64))
+}
+
+/// Parses the `cpu` lines of `/proc/stat`.
+///
+/// Offline CPUs have no line in `/proc/stat`, thus they are not included in the result.
+fn parse_proc_stat(content: &str) -> anyhow::Result<Vec<(CpuId, CpuTimes)>> {
+    let mut res = Vec::new();
+    for line in content.lines().filter(|l| l.starts_with("cpu")) {
+        let mut fields = line.split_ascii_whitespace();
+        let name = fields.next().unwrap();
+        let cpu: CpuId = match &name[3..] {
+            "" => None,
+            n => Some(n.parse().with_context(|| format!("invalid cpu line in /proc/stat: {line}"))?),
+        };
+        // user nice system idle iowait irq softirq steal guest guest_nice
+        // guest and guest_nice are already included in user and nice, don't count them twice.
+        let values = fields
+            .take(8)
+            .map(|v| v.parse::<u64>())
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("invalid value in /proc/stat: {line}"))?;
+        if values.len() < 5 {
+            return Err(anyhow!("not enough values in /proc/stat: {line}"));
+        }
+        let times = CpuTimes {
+            idle: values[3] + values[4],
+            total: values.iter().sum(),
+        };
+        res.push((cpu, times));
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_proc_stat, CpuTimes, UtilizationTracker};
+
+    const STAT_1: &str = "\
+cpu  100 0 100 700 100 0 0 0 0 0
+cpu0 50 0 50 350 50 0 0 0 0 0
+cpu1 50 0 50 350 50 0 0 0 0 0
+intr 1234 0 0
+ctxt 5678
+";
+
+    #[test]
+    fn parse() {
+        let times = parse_proc_stat(STAT_1).unwrap();
+        assert_eq!(
+            times,
+            vec![
+                (None, CpuTimes { idle: 800, total: 1000 }),
+                (Some(0), CpuTimes { idle: 400, total: 500 }),
+                (Some(1), CpuTimes { idle: 400, total: 500 }),
+            ]
+        );
+        assert!(parse_proc_stat("cpuX 1 2 3 4 5").is_err());
+    }
+
+    #[test]
+    fn utilization_with_offline_cpu() {
+        let mut tracker = UtilizationTracker::default();
+        assert!(tracker.update(parse_proc_stat(STAT_1).unwrap()).is_empty());
+
+        // cpu1 goes offline, cpu0 is fully busy
+        let stat_2 = "\
+cpu  200 0 100 700 100 0 0 0 0 0
+cpu0 150 0 50 350 50 0 0 0 0 0
+";
+        let res = tracker.update(parse_proc_stat(stat_2).unwrap());
+        assert_eq!(res, vec![(None, 1.0), (Some(0), 1.0)]);
+
+        // cpu1 is back: no utilization for this round, cpu0 is half busy
+        let stat_3 = "\
+cpu  300 0 100 800 100 0 0 0 0 0
+cpu0 200 0 50 400 50 0 0 0 0 0
+cpu1 50 0 50 400 50 0 0 0 0 0
+";
+        let res = tracker.update(parse_proc_stat(stat_3).unwrap());
+        assert_eq!(res, vec![(None, 0.5), (Some(0), 0.5)]);
+    }
+}
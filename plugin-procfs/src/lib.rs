@@ -0,0 +1,118 @@
+mod cpu;
+mod self_usage;
+mod sysfs;
+
+use std::{str::FromStr, time::Duration};
+
+use alumet::{
+    pipeline::trigger::TriggerSpec,
+    plugin::{
+        rust::{deserialize_config, serialize_config, AlumetPlugin},
+        ConfigTable,
+    },
+    units::Unit,
+};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use cpu::CpuUtilizationSource;
+use self_usage::ProcessUsageSource;
+use sysfs::{SysfsEntry, SysfsFile, SysfsSource};
+
+/// Collects system-wide measurements from the `/proc` filesystem, and from sysfs files.
+pub struct ProcfsPlugin {
+    config: Config,
+}
+
+impl AlumetPlugin for ProcfsPlugin {
+    fn name() -> &'static str {
+        "procfs"
+    }
+
+    fn version() -> &'static str {
+        env!("CARGO_PKG_VERSION")
+    }
+
+    fn default_config() -> anyhow::Result<Option<ConfigTable>> {
+        Ok(Some(serialize_config(Config::default())?))
+    }
+
+    fn init(config: ConfigTable) -> anyhow::Result<Box<Self>> {
+        let config: Config = deserialize_config(config)?;
+        Ok(Box::new(ProcfsPlugin { config }))
+    }
+
+    fn start(&mut self, alumet: &mut alumet::plugin::AlumetStart) -> anyhow::Result<()> {
+        let metric = alumet.create_metric::<f64>(
+            "cpu_utilization",
+            Unit::Unity,
+            "Ratio of time spent by the CPU in non-idle states, between 0 and 1",
+        )?;
+        let trigger = TriggerSpec::builder(self.config.poll_interval)
+            .flush_interval(self.config.flush_interval)
+            .build()?;
+        alumet.add_source(Box::new(CpuUtilizationSource::new(metric)), trigger.clone());
+
+        if !self.config.sysfs_files.is_empty() {
+            let mut files = Vec::with_capacity(self.config.sysfs_files.len());
+            for entry in &self.config.sysfs_files {
+                let unit = Unit::from_str(&entry.unit)
+                    .with_context(|| format!("invalid unit for sysfs file {}", entry.path.display()))?;
+                let description = format!("Value of {}", entry.path.display());
+                let metric = alumet.create_metric::<u64>(&entry.metric, unit, description)?;
+                files.push((metric, SysfsFile::new(entry.path.clone(), entry.counter)));
+            }
+            alumet.add_source(Box::new(SysfsSource::new(files)), trigger.clone());
+        }
+
+        if self.config.self_monitoring {
+            let memory_metric = alumet.create_metric::<u64>(
+                "alumet_memory_rss",
+                Unit::Byte,
+                "Resident set size of the ALUMET agent",
+            )?;
+            let cpu_metric = alumet.create_metric::<f64>(
+                "alumet_cpu_time",
+                Unit::Second,
+                "CPU time (user + system) used by the ALUMET agent since the previous measurement",
+            )?;
+            let source = ProcessUsageSource::for_self(memory_metric, cpu_metric)?;
+            alumet.add_source(Box::new(source), trigger);
+        }
+        Ok(())
+    }
+
+    fn stop(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct Config {
+    /// Initial interval between two measurements.
+    #[serde(with = "humantime_serde")]
+    poll_interval: Duration,
+
+    /// Initial interval between two flushing of measurements.
+    #[serde(with = "humantime_serde")]
+    flush_interval: Duration,
+
+    /// Sysfs files that contain an integer value, to measure in addition to the CPU utilization.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    sysfs_files: Vec<SysfsEntry>,
+
+    /// If true, measures the memory and CPU usage of the ALUMET agent itself.
+    #[serde(default)]
+    self_monitoring: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            poll_interval: Duration::from_secs(1),
+            flush_interval: Duration::from_secs(5),
+            sysfs_files: Vec::new(),
+            self_monitoring: false,
+        }
+    }
+}
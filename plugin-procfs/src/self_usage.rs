@@ -0,0 +1,178 @@
+//! Resource usage of a process, in particular of the ALUMET agent itself,
+//! computed from `/proc/<pid>/statm` and `/proc/<pid>/stat`.
+//!
+//! See https://www.kernel.org/doc/html/latest/filesystems/proc.html#process-specific-subdirectories
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use alumet::{
+    measurement::{MeasurementAccumulator, MeasurementPoint, Timestamp},
+    metrics::TypedMetricId,
+    pipeline::PollError,
+    plugin::util::{CounterDiff, CounterDiffUpdate},
+    resources::{Resource, ResourceConsumer},
+};
+use anyhow::{anyhow, Context};
+
+/// Measures the memory (resident set size) and the CPU time of a process.
+///
+/// Monitoring the agent with this source helps to detect leaks in long-running deployments.
+pub struct ProcessUsageSource {
+    /// Directory of the process in procfs, for instance `/proc/self`.
+    proc_dir: PathBuf,
+    /// Pid of the process, to report the measurements for the right consumer.
+    pid: u32,
+    memory_metric: TypedMetricId<u64>,
+    cpu_metric: TypedMetricId<f64>,
+    /// CPU time (user + system), in clock ticks.
+    cpu_ticks: CounterDiff,
+    /// Size of a memory page, in bytes.
+    page_size: u64,
+    /// Number of clock ticks per second (USER_HZ).
+    ticks_per_second: u64,
+}
+
+impl ProcessUsageSource {
+    /// Creates a source that measures the current process, that is, the ALUMET agent.
+    pub fn for_self(memory_metric: TypedMetricId<u64>, cpu_metric: TypedMetricId<f64>) -> anyhow::Result<Self> {
+        let pid = std::process::id();
+        Self::new(PathBuf::from("/proc/self"), pid, memory_metric, cpu_metric)
+    }
+
+    /// Creates a source that measures the process `pid`, whose procfs directory is `proc_dir`.
+    pub fn new(
+        proc_dir: PathBuf,
+        pid: u32,
+        memory_metric: TypedMetricId<u64>,
+        cpu_metric: TypedMetricId<f64>,
+    ) -> anyhow::Result<Self> {
+        let page_size = sysconf(libc::_SC_PAGESIZE).context("failed to get the size of a memory page")?;
+        let ticks_per_second = sysconf(libc::_SC_CLK_TCK).context("failed to get the number of clock ticks")?;
+        Ok(Self {
+            proc_dir,
+            pid,
+            memory_metric,
+            cpu_metric,
+            cpu_ticks: CounterDiff::with_max_value(u64::MAX),
+            page_size,
+            ticks_per_second,
+        })
+    }
+}
+
+impl alumet::pipeline::Source for ProcessUsageSource {
+    fn poll(&mut self, measurements: &mut MeasurementAccumulator, timestamp: Timestamp) -> Result<(), PollError> {
+        let statm = read_proc_file(&self.proc_dir.join("statm"))?;
+        let stat = read_proc_file(&self.proc_dir.join("stat"))?;
+        let resident_pages = parse_statm_resident(&statm)?;
+        let cpu_ticks = parse_stat_cpu_ticks(&stat)?;
+
+        let consumer = ResourceConsumer::Process { pid: self.pid };
+        measurements.push(MeasurementPoint::new(
+            timestamp,
+            self.memory_metric,
+            Resource::LocalMachine,
+            consumer.clone(),
+            resident_pages * self.page_size,
+        ));
+
+        let cpu_delta = match self.cpu_ticks.update(cpu_ticks) {
+            CounterDiffUpdate::FirstTime => None,
+            CounterDiffUpdate::Difference(diff) => Some(diff),
+            CounterDiffUpdate::CorrectedDifference(diff) => Some(diff),
+        };
+        if let Some(ticks) = cpu_delta {
+            let seconds = ticks as f64 / self.ticks_per_second as f64;
+            measurements.push(MeasurementPoint::new(
+                timestamp,
+                self.cpu_metric,
+                Resource::LocalMachine,
+                consumer,
+                seconds,
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn read_proc_file(path: &Path) -> anyhow::Result<String> {
+    fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))
+}
+
+/// Returns the value of a system configuration variable.
+fn sysconf(name: libc::c_int) -> anyhow::Result<u64> {
+    let value = unsafe { libc::sysconf(name) };
+    if value <= 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(value as u64)
+}
+
+/// Parses `/proc/<pid>/statm` and returns the resident set size, in pages.
+fn parse_statm_resident(content: &str) -> anyhow::Result<u64> {
+    // size resident shared text lib data dt
+    let resident = content
+        .split_ascii_whitespace()
+        .nth(1)
+        .ok_or_else(|| anyhow!("not enough values in statm: {content:?}"))?;
+    resident
+        .parse()
+        .with_context(|| format!("invalid resident size in statm: {content:?}"))
+}
+
+/// Parses `/proc/<pid>/stat` and returns the CPU time (user + system) of the process, in clock ticks.
+fn parse_stat_cpu_ticks(content: &str) -> anyhow::Result<u64> {
+    // The second field is the name of the executable, in parentheses.
+    // It can contain spaces and parentheses: the other fields start after the last ')'.
+    let end_of_comm = content
+        .rfind(')')
+        .ok_or_else(|| anyhow!("invalid stat, missing ')': {content:?}"))?;
+    // state ppid pgrp session tty_nr tpgid flags minflt cminflt majflt cmajflt utime stime ...
+    let values = content[end_of_comm + 1..]
+        .split_ascii_whitespace()
+        .skip(11)
+        .take(2)
+        .map(|v| v.parse::<u64>())
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("invalid cpu time in stat: {content:?}"))?;
+    if values.len() < 2 {
+        return Err(anyhow!("not enough values in stat: {content:?}"));
+    }
+    Ok(values[0] + values[1])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_stat_cpu_ticks, parse_statm_resident};
+
+    #[test]
+    fn parse_statm() {
+        assert_eq!(parse_statm_resident("5437 1207 923 237 0 549 0\n").unwrap(), 1207);
+        assert!(parse_statm_resident("5437").is_err());
+        assert!(parse_statm_resident("5437 x").is_err());
+    }
+
+    #[test]
+    fn parse_stat() {
+        let stat = "1234 (alumet-agent) S 1 1234 1234 0 -1 4194560 1583 0 0 0 42 8 0 0 20 0 4 0 123 0 0\n";
+        assert_eq!(parse_stat_cpu_ticks(stat).unwrap(), 50);
+
+        // the name of the executable can contain spaces and parentheses
+        let stat = "1234 (a) b (c) S 1 1234 1234 0 -1 4194560 1583 0 0 0 7 3 0 0 20 0 4 0 123 0 0\n";
+        assert_eq!(parse_stat_cpu_ticks(stat).unwrap(), 10);
+
+        assert!(parse_stat_cpu_ticks("1234 (a) S 1 1234").is_err());
+        assert!(parse_stat_cpu_ticks("1234 a S").is_err());
+    }
+
+    #[test]
+    fn parse_self() {
+        let statm = std::fs::read_to_string("/proc/self/statm").unwrap();
+        let stat = std::fs::read_to_string("/proc/self/stat").unwrap();
+        assert!(parse_statm_resident(&statm).unwrap() > 0);
+        parse_stat_cpu_ticks(&stat).unwrap();
+    }
+}
@@ -0,0 +1,176 @@
+//! Generic source for integer values exposed by sysfs files.
+//!
+//! Many counters of the kernel are available as a single integer in a sysfs file,
+//! for instance `/sys/class/net/eth0/statistics/rx_bytes`. This source reads a list
+//! of such files, and reports either their raw value (gauge) or the difference
+//! between two polls (counter).
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use alumet::{
+    measurement::{MeasurementAccumulator, MeasurementPoint, Timestamp},
+    metrics::TypedMetricId,
+    pipeline::PollError,
+    plugin::util::{CounterDiff, CounterDiffUpdate},
+    resources::{Resource, ResourceConsumer},
+};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+/// A sysfs file to read, as specified in the configuration.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SysfsEntry {
+    /// Path of the file, which must contain a single unsigned integer.
+    pub path: PathBuf,
+    /// Name of the metric to create.
+    pub metric: String,
+    /// Unit of the value, for instance `B` or `J` (see [`alumet::units::Unit`]).
+    #[serde(default = "default_unit")]
+    pub unit: String,
+    /// If true, the file contains a monotonic counter and the source reports the difference
+    /// between two polls. Otherwise, the value is reported as is.
+    #[serde(default)]
+    pub counter: bool,
+}
+
+fn default_unit() -> String {
+    String::from("1")
+}
+
+/// Reads integer values from sysfs files.
+///
+/// Each file is read independently: a file that is missing or that cannot be parsed
+/// is skipped, without preventing the other files from being measured.
+pub struct SysfsSource {
+    files: Vec<(TypedMetricId<u64>, SysfsFile)>,
+}
+
+/// The state of one sysfs file.
+pub struct SysfsFile {
+    path: PathBuf,
+    /// Overflow-correcting counter, for the files that contain a counter.
+    counter: Option<CounterDiff>,
+    /// Whether the previous read has failed, to avoid logging the same error at each poll.
+    failing: bool,
+}
+
+impl SysfsSource {
+    pub fn new(files: Vec<(TypedMetricId<u64>, SysfsFile)>) -> Self {
+        Self { files }
+    }
+}
+
+impl SysfsFile {
+    pub fn new(path: PathBuf, is_counter: bool) -> Self {
+        Self {
+            path,
+            counter: is_counter.then(|| CounterDiff::with_max_value(u64::MAX)),
+            failing: false,
+        }
+    }
+
+    /// Reads the file and returns the value to report, if any.
+    ///
+    /// Counters have no value on the first successful read.
+    fn read(&mut self) -> anyhow::Result<Option<u64>> {
+        let value = read_value(&self.path)?;
+        let res = match &mut self.counter {
+            None => Some(value),
+            Some(counter) => match counter.update(value) {
+                CounterDiffUpdate::FirstTime => None,
+                CounterDiffUpdate::Difference(diff) => Some(diff),
+                CounterDiffUpdate::CorrectedDifference(diff) => Some(diff),
+            },
+        };
+        Ok(res)
+    }
+
+    /// Like [`read`](Self::read), but logs the errors instead of returning them.
+    fn read_or_log(&mut self) -> Option<u64> {
+        match self.read() {
+            Ok(value) => {
+                if self.failing {
+                    log::info!("{} can be read again.", self.path.display());
+                    self.failing = false;
+                }
+                value
+            }
+            Err(e) => {
+                if !self.failing {
+                    log::warn!("Skipping {} until it can be read: {e:#}", self.path.display());
+                    self.failing = true;
+                }
+                None
+            }
+        }
+    }
+}
+
+impl alumet::pipeline::Source for SysfsSource {
+    fn poll(&mut self, measurements: &mut MeasurementAccumulator, timestamp: Timestamp) -> Result<(), PollError> {
+        let points = self.files.iter_mut().filter_map(|(metric, file)| {
+            let value = file.read_or_log()?;
+            Some(MeasurementPoint::new(
+                timestamp,
+                *metric,
+                Resource::LocalMachine,
+                ResourceConsumer::LocalMachine,
+                value,
+            ))
+        });
+        measurements.push_many(points);
+        Ok(())
+    }
+}
+
+/// Reads a file that contains a single unsigned integer.
+fn read_value(path: &Path) -> anyhow::Result<u64> {
+    let content = fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    content
+        .trim()
+        .parse()
+        .with_context(|| format!("invalid integer in {}: {content:?}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::SysfsFile;
+
+    #[test]
+    fn gauges_and_counters() {
+        let dir = std::env::temp_dir().join(format!("alumet-sysfs-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let gauge_path = dir.join("gauge");
+        let counter_path = dir.join("counter");
+        fs::write(&gauge_path, "12\n").unwrap();
+        fs::write(&counter_path, "100\n").unwrap();
+
+        let mut gauge = SysfsFile::new(gauge_path.clone(), false);
+        let mut counter = SysfsFile::new(counter_path.clone(), true);
+        let mut missing = SysfsFile::new(dir.join("missing"), false);
+        assert_eq!(gauge.read_or_log(), Some(12));
+        assert_eq!(counter.read_or_log(), None);
+        assert_eq!(missing.read_or_log(), None);
+        assert!(missing.failing);
+
+        fs::write(&gauge_path, "7\n").unwrap();
+        fs::write(&counter_path, "150\n").unwrap();
+        assert_eq!(gauge.read_or_log(), Some(7));
+        assert_eq!(counter.read_or_log(), Some(50));
+
+        // invalid content: the file is skipped, and the counter keeps its previous value
+        fs::write(&counter_path, "oops\n").unwrap();
+        assert_eq!(counter.read_or_log(), None);
+        assert!(counter.failing);
+        fs::write(&counter_path, "160\n").unwrap();
+        assert_eq!(counter.read_or_log(), Some(10));
+        assert!(!counter.failing);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,298 @@
+//! High-frequency measurement of one RAPL zone, to profile short bursts of activity.
+//!
+//! The usual RAPL source is polled by the pipeline, typically every second. The busy reader instead reads
+//! the counter of one powercap zone in a dedicated thread, as often as `min_interval` allows, independently
+//! of the scheduler of the pipeline, and sends the measurements to the pipeline in batches.
+//!
+//! This is costly: below [`SPIN_THRESHOLD`], the reader spins between two reads, which keeps one CPU core busy.
+//! Hence, it must be enabled explicitly, and it stops after `max_duration`.
+//!
+//! RAPL updates its counters about every millisecond. The reads that see the same value as the previous one
+//! do not produce any measurement: each measurement is the energy consumed since the previous change of the counter,
+//! timestamped with the time of the read that saw the change.
+
+use std::{
+    fs::{self, File},
+    os::unix::fs::FileExt,
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant},
+};
+
+use alumet::{
+    measurement::{MeasurementBuffer, MeasurementPoint, Timestamp},
+    metrics::TypedMetricId,
+    plugin::util::{CounterDiff, CounterDiffUpdate},
+    resources::{Resource, ResourceConsumer},
+};
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
+
+use crate::{domains::RaplDomainType, powercap::PowerZone};
+
+/// Shortest allowed interval between two reads.
+pub const MIN_INTERVAL: Duration = Duration::from_micros(10);
+
+/// Longest allowed reading session.
+pub const MAX_DURATION: Duration = Duration::from_secs(3600);
+
+/// When the next read is further away than this, the reader sleeps instead of spinning.
+pub const SPIN_THRESHOLD: Duration = Duration::from_micros(200);
+
+/// Configuration of the busy reader.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BusyReadConfig {
+    /// Zone to read: the name of its directory, such as "intel-rapl:0", or its name, such as "package-0".
+    pub zone: String,
+
+    /// Minimum interval between two reads of the counter.
+    #[serde(with = "humantime_serde", default = "default_min_interval")]
+    pub min_interval: Duration,
+
+    /// The reader stops after this duration.
+    #[serde(with = "humantime_serde", default = "default_max_duration")]
+    pub max_duration: Duration,
+
+    /// Interval between two batches of measurements sent to the pipeline.
+    #[serde(with = "humantime_serde", default = "default_flush_interval")]
+    pub flush_interval: Duration,
+}
+
+fn default_min_interval() -> Duration {
+    Duration::from_micros(100)
+}
+
+fn default_max_duration() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_flush_interval() -> Duration {
+    Duration::from_millis(100)
+}
+
+impl BusyReadConfig {
+    /// Checks that the reader is bounded.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.min_interval < MIN_INTERVAL {
+            return Err(anyhow!(
+                "busy_read.min_interval must be at least {MIN_INTERVAL:?}, got {:?}",
+                self.min_interval
+            ));
+        }
+        if self.max_duration.is_zero() || self.max_duration > MAX_DURATION {
+            return Err(anyhow!(
+                "busy_read.max_duration must be between 0 and {MAX_DURATION:?}, got {:?}",
+                self.max_duration
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Returns the zone whose directory or name is `zone`.
+pub fn find_zone<'a>(zones: &'a [PowerZone], zone: &str) -> anyhow::Result<&'a PowerZone> {
+    let found: Vec<&PowerZone> = zones
+        .iter()
+        .filter(|z| z.name == zone || z.path.file_name().is_some_and(|dir| dir == zone))
+        .collect();
+    match found[..] {
+        [z] => Ok(z),
+        [] => Err(anyhow!("RAPL power zone {zone} not found")),
+        _ => Err(anyhow!(
+            "several RAPL power zones are named {zone}, use the name of a directory instead, for instance {:?}",
+            found[0].path.file_name().unwrap_or_default()
+        )),
+    }
+}
+
+/// Reads the counter of one powercap zone in a tight loop.
+pub struct BusyReader {
+    config: BusyReadConfig,
+    path: PathBuf,
+    file: File,
+    counter: CounterDiff,
+    metric: TypedMetricId<u64>,
+    resource: Resource,
+    domain: RaplDomainType,
+}
+
+impl BusyReader {
+    pub fn open(zone: &PowerZone, metric: TypedMetricId<u64>, config: BusyReadConfig) -> anyhow::Result<Self> {
+        let path = zone.energy_path();
+        let file = File::open(&path).with_context(|| format!("Could not open {}", path.display()))?;
+        let max_path = zone.max_energy_path();
+        let max_value = fs::read_to_string(&max_path)
+            .with_context(|| format!("Could not read {}", max_path.display()))?
+            .trim_end()
+            .parse()
+            .with_context(|| format!("Could not parse {}", max_path.display()))?;
+        Ok(Self {
+            config,
+            path,
+            file,
+            counter: CounterDiff::with_max_value(max_value),
+            metric,
+            resource: zone.domain.to_resource(zone.socket_id.unwrap_or(0)),
+            domain: zone.domain,
+        })
+    }
+
+    /// Reads the counter until `max_duration` has elapsed or `is_cancelled` returns `true`,
+    /// and gives the measurements to `send`, which returns `false` if the pipeline is closed.
+    pub fn run(
+        mut self,
+        is_cancelled: impl Fn() -> bool,
+        mut send: impl FnMut(MeasurementBuffer) -> bool,
+    ) -> anyhow::Result<()> {
+        let (metric, resource, domain) = (self.metric, self.resource.clone(), self.domain);
+        let mut buf = [0u8; 32];
+        let (file, path) = (&self.file, &self.path);
+        let read = || -> anyhow::Result<u64> {
+            let n = file.read_at(&mut buf, 0)?;
+            let content = std::str::from_utf8(&buf[..n])?;
+            content
+                .trim_end()
+                .parse()
+                .with_context(|| format!("Could not parse {}: '{content}'", path.display()))
+        };
+        let emit = |deltas: Vec<(Timestamp, u64)>| {
+            let points = deltas.into_iter().map(|(t, uj)| {
+                MeasurementPoint::new(t, metric, resource.clone(), ResourceConsumer::LocalMachine, uj)
+                    .with_attr("domain", domain.as_str())
+            });
+            send(points.collect::<Vec<_>>().into())
+        };
+        let start = Instant::now();
+        let reads = busy_read(&self.config, &mut self.counter, read, emit, is_cancelled)?;
+        log::info!(
+            "Busy reading of {} done: {reads} reads in {:?}",
+            self.path.display(),
+            start.elapsed()
+        );
+        Ok(())
+    }
+}
+
+/// The loop of the busy reader, which returns the number of reads.
+///
+/// `emit` receives batches of energy differences, at most once per `flush_interval`, and returns `false` to stop.
+fn busy_read(
+    config: &BusyReadConfig,
+    counter: &mut CounterDiff,
+    mut read: impl FnMut() -> anyhow::Result<u64>,
+    mut emit: impl FnMut(Vec<(Timestamp, u64)>) -> bool,
+    is_cancelled: impl Fn() -> bool,
+) -> anyhow::Result<u64> {
+    let start = Instant::now();
+    let mut next_read = start;
+    let mut last_flush = start;
+    let mut batch = Vec::new();
+    let mut reads = 0;
+    loop {
+        let now = Instant::now();
+        if now - start >= config.max_duration || is_cancelled() {
+            break;
+        }
+        if next_read > now {
+            let wait = next_read - now;
+            if wait > SPIN_THRESHOLD {
+                thread::sleep(wait);
+            } else {
+                std::hint::spin_loop();
+            }
+            continue;
+        }
+        let value = read()?;
+        let timestamp = Timestamp::now();
+        reads += 1;
+        next_read = now + config.min_interval;
+        match counter.update(value) {
+            CounterDiffUpdate::FirstTime | CounterDiffUpdate::Difference(0) => (),
+            CounterDiffUpdate::Difference(delta) | CounterDiffUpdate::CorrectedDifference(delta) => {
+                batch.push((timestamp, delta))
+            }
+        }
+        if now - last_flush >= config.flush_interval && !batch.is_empty() {
+            last_flush = now;
+            if !emit(std::mem::take(&mut batch)) {
+                return Ok(reads);
+            }
+        }
+    }
+    if !batch.is_empty() {
+        emit(batch);
+    }
+    Ok(reads)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, time::Duration};
+
+    use alumet::plugin::util::CounterDiff;
+
+    use super::{busy_read, BusyReadConfig};
+
+    fn config(min_interval: Duration, max_duration: Duration) -> BusyReadConfig {
+        BusyReadConfig {
+            zone: String::from("package-0"),
+            min_interval,
+            max_duration,
+            flush_interval: Duration::from_millis(10),
+        }
+    }
+
+    #[test]
+    fn bounded_loop() {
+        // the counter increases by 5 every 4 reads, and wraps around at 20
+        let value = Cell::new(0u64);
+        let calls = Cell::new(0u64);
+        let read = || -> anyhow::Result<u64> {
+            calls.set(calls.get() + 1);
+            if calls.get() % 4 == 0 {
+                value.set((value.get() + 5) % 20);
+            }
+            Ok(value.get())
+        };
+        let mut deltas = Vec::new();
+        let emit = |batch: Vec<_>| {
+            deltas.extend(batch.into_iter().map(|(_, d)| d));
+            true
+        };
+        let config = config(Duration::from_millis(1), Duration::from_millis(50));
+        let mut counter = CounterDiff::with_max_value(20);
+        let reads = busy_read(&config, &mut counter, read, emit, || false).unwrap();
+
+        // the reads are spaced by min_interval, and stop after max_duration
+        assert!(reads > 0 && reads <= 51, "{reads} reads");
+        // one measurement per change of the counter, including after the wraparound
+        assert_eq!(deltas.len() as u64, reads / 4);
+        assert!(deltas.iter().all(|d| *d == 5), "{deltas:?}");
+
+        // cancellation stops the loop, and a closed pipeline too
+        let config = BusyReadConfig {
+            max_duration: Duration::from_secs(60),
+            ..config
+        };
+        let mut counter = CounterDiff::with_max_value(20);
+        let reads = busy_read(&config, &mut counter, || Ok(0), |_| true, || true).unwrap();
+        assert_eq!(reads, 0);
+        let mut n = 0;
+        let read = || -> anyhow::Result<u64> {
+            n += 1;
+            Ok(n)
+        };
+        let mut counter = CounterDiff::with_max_value(u64::MAX);
+        let reads = busy_read(&config, &mut counter, read, |_| false, || false).unwrap();
+        assert!(reads < 1000, "{reads} reads");
+    }
+
+    #[test]
+    fn validate() {
+        let valid = |min_interval, max_duration| config(min_interval, max_duration).validate().is_ok();
+        assert!(valid(Duration::from_micros(100), Duration::from_secs(10)));
+        assert!(!valid(Duration::from_micros(1), Duration::from_secs(10)));
+        assert!(!valid(Duration::from_micros(100), Duration::ZERO));
+        assert!(!valid(Duration::from_micros(100), Duration::from_secs(7200)));
+    }
+}
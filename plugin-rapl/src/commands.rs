@@ -0,0 +1,60 @@
+//! Standalone queries, available on the command line of the application.
+
+use std::{io, path::Path};
+
+use alumet::plugin::command::PluginCommand;
+use anyhow::anyhow;
+
+use crate::powercap;
+
+/// Prints the tree of the RAPL power zones, as discovered in the powercap sysfs.
+///
+/// Usage: `zones [--path <dir>]`, where `dir` is the directory of the powercap control type,
+/// for instance `intel-rapl`. By default, it is chosen from the vendor of the CPU.
+pub struct ZonesCommand;
+
+impl PluginCommand for ZonesCommand {
+    fn name(&self) -> &str {
+        "zones"
+    }
+
+    fn about(&self) -> &str {
+        "Prints the RAPL power zones found in the powercap sysfs"
+    }
+
+    fn run(&self, args: &[String], out: &mut dyn io::Write) -> anyhow::Result<()> {
+        let zones = match args {
+            [] => powercap::all_power_zones()?,
+            [flag, path] if flag == "--path" => powercap::all_power_zones_at(Path::new(path))?,
+            _ => return Err(anyhow!("invalid arguments {args:?}, usage: zones [--path <dir>]")),
+        };
+        if zones.top.is_empty() {
+            writeln!(out, "No RAPL power zone found.")?;
+        }
+        for zone in &zones.top {
+            writeln!(out, "{zone}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alumet::plugin::command::PluginCommand;
+
+    use super::ZonesCommand;
+
+    #[test]
+    fn print_zones() {
+        let fixture = concat!(env!("CARGO_MANIFEST_DIR"), "/test-data/powercap-2sockets/intel-rapl");
+        let mut out = Vec::new();
+        let args = [String::from("--path"), String::from(fixture)];
+        ZonesCommand.run(&args, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("- package-0 (Package)"), "{out}");
+        assert_eq!(out.lines().filter(|l| l.contains("- core (PP0)")).count(), 2, "{out}");
+        assert!(out.contains("- psys (Platform)"), "{out}");
+
+        assert!(ZonesCommand.run(&[String::from("--nope")], &mut Vec::new()).is_err());
+    }
+}
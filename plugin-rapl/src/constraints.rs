@@ -0,0 +1,119 @@
+//! Power limits of the RAPL zones, read from the `constraint_N_*` files of powercap,
+//! and whether the zones are enabled, read from their `enabled` file.
+
+use alumet::{
+    measurement::{MeasurementAccumulator, MeasurementPoint, Timestamp},
+    metrics::TypedMetricId,
+    pipeline::{PollError, Source},
+    resources::ResourceConsumer,
+};
+
+use crate::{domains::RaplDomainType, powercap::PowerZone};
+
+const POWER_LIMIT_UNIT: f64 = 0.000_001; // 1 microWatt
+
+/// Measures the power limits (constraints) of the powercap zones.
+///
+/// Each constraint of each zone produces one measurement, annotated with the domain,
+/// the number of the constraint, its name and its time window (when available).
+/// The zones whose constraints cannot be read are skipped.
+pub struct PowerLimitProbe {
+    metric: TypedMetricId<f64>,
+    zones: Vec<PowerZone>,
+}
+
+impl PowerLimitProbe {
+    pub fn new(metric: TypedMetricId<f64>, zones: Vec<PowerZone>) -> Self {
+        // A sub-zone without a socket cannot be attributed to the right resource (see OpenedZone::open).
+        let zones = zones
+            .into_iter()
+            .filter(|z| z.socket_id.is_some() || z.domain == RaplDomainType::Platform)
+            .collect();
+        Self { metric, zones }
+    }
+}
+
+impl Source for PowerLimitProbe {
+    fn poll(&mut self, measurements: &mut MeasurementAccumulator, timestamp: Timestamp) -> Result<(), PollError> {
+        for zone in &self.zones {
+            let constraints = match zone.constraints() {
+                Ok(constraints) => constraints,
+                Err(e) => {
+                    log::warn!("Skipping the power limits of {}: {e:#}", zone.path.display());
+                    continue;
+                }
+            };
+            let resource = zone.domain.to_resource(zone.socket_id.unwrap_or(0));
+            for constraint in constraints {
+                let mut point = MeasurementPoint::new(
+                    timestamp,
+                    self.metric,
+                    resource.clone(),
+                    ResourceConsumer::LocalMachine,
+                    constraint.power_limit_uw as f64 * POWER_LIMIT_UNIT,
+                )
+                .with_attr("domain", zone.domain.as_str())
+                .with_attr("constraint", constraint.index as u64);
+                if let Some(control_type) = &zone.control_type {
+                    point = point.with_attr("control_type", control_type.clone());
+                }
+                if let Some(name) = constraint.name {
+                    point = point.with_attr("constraint_name", name);
+                }
+                if let Some(time_window) = constraint.time_window_us {
+                    point = point.with_attr("time_window_us", time_window);
+                }
+                measurements.push(point);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Measures whether the powercap zones are enabled: 1 if enabled, 0 if disabled.
+///
+/// The energy counter of a disabled zone may be stale. The zones whose state cannot be read are skipped.
+pub struct ZoneEnabledProbe {
+    metric: TypedMetricId<u64>,
+    zones: Vec<PowerZone>,
+}
+
+impl ZoneEnabledProbe {
+    pub fn new(metric: TypedMetricId<u64>, zones: Vec<PowerZone>) -> Self {
+        // same as PowerLimitProbe
+        let zones = zones
+            .into_iter()
+            .filter(|z| z.socket_id.is_some() || z.domain == RaplDomainType::Platform)
+            .collect();
+        Self { metric, zones }
+    }
+}
+
+impl Source for ZoneEnabledProbe {
+    fn poll(&mut self, measurements: &mut MeasurementAccumulator, timestamp: Timestamp) -> Result<(), PollError> {
+        for zone in &self.zones {
+            let enabled = match zone.is_enabled() {
+                Ok(enabled) => enabled,
+                Err(e) => {
+                    log::debug!("Skipping the state of {}: {e:#}", zone.path.display());
+                    continue;
+                }
+            };
+            let resource = zone.domain.to_resource(zone.socket_id.unwrap_or(0));
+            let mut point = MeasurementPoint::new(
+                timestamp,
+                self.metric,
+                resource,
+                ResourceConsumer::LocalMachine,
+                enabled as u64,
+            )
+            .with_attr("domain", zone.domain.as_str())
+            .with_attr("zone", zone.name.clone());
+            if let Some(control_type) = &zone.control_type {
+                point = point.with_attr("control_type", control_type.clone());
+            }
+            measurements.push(point);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,181 @@
+//! Persistence of the state of the energy counters across restarts of the plugin.
+//!
+//! When the plugin stops, the last value of each RAPL counter is saved to a file. When it starts again,
+//! the counters are restored, so that the first poll measures the energy consumed since the last poll
+//! of the previous run, instead of being skipped.
+//!
+//! Restoring a counter is only safe if the saved value comes from the same counter, and if the counter has not
+//! been reset and has wrapped around at most once since the state was saved. Hence, the saved state is ignored if:
+//! - the machine has rebooted (the boot id of the kernel has changed), because the counters restart from zero,
+//! - the state is older than the maximum age, because the counter may have wrapped around several times,
+//! - the identity of the counter (path of the powercap zone) differs,
+//! - the maximum value of the counter differs.
+//!
+//! Reloading the RAPL kernel module resets the powercap counters without changing their path: the state
+//! must not be restored in that case.
+//!
+//! Only the powercap counters are saved: a perf_event counter starts from zero when it is opened,
+//! hence the state of the previous run never applies to it.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+use alumet::plugin::util::CounterDiffState;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+const BOOT_ID_PATH: &str = "/proc/sys/kernel/random/boot_id";
+
+/// State of the energy counters of a probe, as saved in the file.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct CounterSnapshot {
+    /// Boot id of the kernel when the state was saved.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    boot_id: Option<String>,
+    /// When the state was saved.
+    saved_at: SystemTime,
+    #[serde(default)]
+    counters: Vec<SavedCounter>,
+}
+
+/// The state of one energy counter.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct SavedCounter {
+    /// Identity of the counter: the path of the powercap zone.
+    pub id: String,
+    pub state: CounterDiffState,
+}
+
+/// File that stores the state of the energy counters between two runs of the plugin.
+#[derive(Debug, Clone)]
+pub(crate) struct CounterStore {
+    path: PathBuf,
+    /// Maximum age of a state that can be restored.
+    max_age: Duration,
+}
+
+impl CounterStore {
+    pub fn new(path: PathBuf, max_age: Duration) -> Self {
+        Self { path, max_age }
+    }
+
+    /// Loads the saved counters that can be restored, by identity.
+    ///
+    /// Returns an empty map if there is no saved state, or if it is not safe to restore it.
+    pub fn load(&self) -> HashMap<String, CounterDiffState> {
+        if !self.path.exists() {
+            return HashMap::new();
+        }
+        let snapshot = fs::read_to_string(&self.path)
+            .context("read error")
+            .and_then(|content| toml::from_str::<CounterSnapshot>(&content).context("invalid content"));
+        match snapshot {
+            Ok(snapshot) => self.restorable(snapshot, boot_id().as_deref(), SystemTime::now()),
+            Err(e) => {
+                log::warn!(
+                    "Could not load the state of the RAPL counters from {}: {e:#}",
+                    self.path.display()
+                );
+                HashMap::new()
+            }
+        }
+    }
+
+    /// Saves the state of the counters, replacing the previous state.
+    pub fn save(&self, counters: Vec<SavedCounter>) -> anyhow::Result<()> {
+        let snapshot = CounterSnapshot {
+            boot_id: boot_id(),
+            saved_at: SystemTime::now(),
+            counters,
+        };
+        let content = toml::to_string(&snapshot)?;
+        // write the whole file at once, so that an interrupted save does not leave a truncated state
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, content).with_context(|| format!("failed to write {}", tmp_path.display()))?;
+        fs::rename(&tmp_path, &self.path).with_context(|| format!("failed to replace {}", self.path.display()))?;
+        Ok(())
+    }
+
+    /// Returns the counters of the snapshot, if it is safe to restore them.
+    fn restorable(
+        &self,
+        snapshot: CounterSnapshot,
+        boot_id: Option<&str>,
+        now: SystemTime,
+    ) -> HashMap<String, CounterDiffState> {
+        if snapshot.boot_id.is_none() || snapshot.boot_id.as_deref() != boot_id {
+            log::info!("The state of the RAPL counters has been saved before a reboot, it will not be restored.");
+            return HashMap::new();
+        }
+        let age = now.duration_since(snapshot.saved_at).unwrap_or_default();
+        if age > self.max_age {
+            log::info!(
+                "The state of the RAPL counters has been saved {}s ago, which is more than the maximum of {}s: it will not be restored.",
+                age.as_secs(),
+                self.max_age.as_secs()
+            );
+            return HashMap::new();
+        }
+        snapshot.counters.into_iter().map(|c| (c.id, c.state)).collect()
+    }
+}
+
+/// Returns the boot id of the kernel, which changes at each boot.
+fn boot_id() -> Option<String> {
+    fs::read_to_string(BOOT_ID_PATH).ok().map(|id| id.trim().to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        time::{Duration, SystemTime},
+    };
+
+    use alumet::plugin::util::CounterDiffState;
+
+    use super::{CounterSnapshot, CounterStore, SavedCounter};
+
+    #[test]
+    fn save_and_restore() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_path_buf();
+        let store = CounterStore::new(dir.join("counters.toml"), Duration::from_secs(60));
+        assert!(store.load().is_empty());
+
+        let state = CounterDiffState {
+            max_value: 262143328850,
+            previous_value: Some(123456),
+        };
+        let counter = SavedCounter {
+            id: String::from("/sys/devices/virtual/powercap/intel-rapl/intel-rapl:0"),
+            state,
+        };
+        store.save(vec![counter.clone()]).unwrap();
+        let content = fs::read_to_string(dir.join("counters.toml")).unwrap();
+        let snapshot: CounterSnapshot = toml::from_str(&content).unwrap();
+        assert_eq!(snapshot.counters, vec![counter.clone()]);
+
+        // the state is restored if it has been saved during the same boot, recently enough
+        let snapshot = |boot_id: &str| CounterSnapshot {
+            boot_id: Some(boot_id.to_owned()),
+            saved_at: SystemTime::UNIX_EPOCH,
+            counters: vec![counter.clone()],
+        };
+        let t = SystemTime::UNIX_EPOCH + Duration::from_secs(5);
+        let restored = store.restorable(snapshot("a"), Some("a"), t);
+        assert_eq!(restored.get(&counter.id), Some(&state));
+        assert!(store.restorable(snapshot("a"), Some("b"), t).is_empty());
+        assert!(store.restorable(snapshot("a"), None, t).is_empty());
+        let later = SystemTime::UNIX_EPOCH + Duration::from_secs(61);
+        assert!(store.restorable(snapshot("a"), Some("a"), later).is_empty());
+
+        // invalid content
+        fs::write(dir.join("counters.toml"), "not a state").unwrap();
+        assert!(store.load().is_empty());
+    }
+}
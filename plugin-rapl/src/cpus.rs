@@ -13,7 +13,7 @@ pub struct CpuId {
 }
 
 /// Cpu vendor that supports RAPL energy counters.
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CpuVendor {
     Intel,
     Amd,
@@ -75,7 +75,39 @@ pub fn online_cpus() -> anyhow::Result<Vec<u32>> {
     parse_cpu_list(&list)
 }
 
+/// Returns the vendor of the CPU, read in `/proc/cpuinfo`, or in the output of `lscpu`
+/// if `/proc/cpuinfo` cannot be read.
 pub fn cpu_vendor() -> anyhow::Result<CpuVendor> {
+    let path = "/proc/cpuinfo";
+    let vendor = match fs::read_to_string(path) {
+        Ok(cpuinfo) => parse_cpuinfo_vendor(&cpuinfo)
+            .with_context(|| format!("vendor_id not found in {path}"))?
+            .to_owned(),
+        Err(e) => {
+            log::debug!("failed to read {path}, using lscpu instead: {e}");
+            lscpu_vendor()?
+        }
+    };
+    parse_vendor(&vendor)
+}
+
+/// Extracts the vendor id, for instance `GenuineIntel`, from the content of `/proc/cpuinfo`.
+fn parse_cpuinfo_vendor(cpuinfo: &str) -> Option<&str> {
+    cpuinfo.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        (key.trim() == "vendor_id").then(|| value.trim())
+    })
+}
+
+fn parse_vendor(vendor: &str) -> anyhow::Result<CpuVendor> {
+    match vendor {
+        "AuthenticAMD" | "HygonGenuine" => Ok(CpuVendor::Amd),
+        "GenuineIntel" => Ok(CpuVendor::Intel),
+        _ => Err(anyhow!("Unsupported CPU vendor {vendor}")),
+    }
+}
+
+fn lscpu_vendor() -> anyhow::Result<String> {
     // run: LC_ALL=C lscpu
     let child = Command::new("lscpu")
         .env("LC_ALL", "C")
@@ -92,19 +124,12 @@ pub fn cpu_vendor() -> anyhow::Result<CpuVendor> {
         .context("vendor id not found in lscpu output")?
         .get(1)
         .unwrap();
-    let vendor = group.as_str().trim();
-
-    // turn it into the right enum variant
-    match vendor {
-        "AuthenticAMD" => Ok(CpuVendor::Amd),
-        "GenuineIntel" => Ok(CpuVendor::Intel),
-        _ => Err(anyhow!("Unsupported CPU vendor {vendor}")),
-    }
+    Ok(group.as_str().trim().to_owned())
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{parse_cpu_and_socket_list, CpuId};
+    use super::{parse_cpu_and_socket_list, parse_cpuinfo_vendor, parse_vendor, CpuId, CpuVendor};
 
     #[test]
     fn test_parse_cpumask() -> anyhow::Result<()> {
@@ -137,4 +162,15 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_parse_cpuinfo_vendor() {
+        let cpuinfo = "processor\t: 0\nvendor_id\t: AuthenticAMD\ncpu family\t: 23\n\nprocessor\t: 1\nvendor_id\t: AuthenticAMD\n";
+        let vendor = parse_cpuinfo_vendor(cpuinfo);
+        assert_eq!(vendor, Some("AuthenticAMD"));
+        assert_eq!(parse_vendor(vendor.unwrap()).unwrap(), CpuVendor::Amd);
+        assert_eq!(parse_vendor("GenuineIntel").unwrap(), CpuVendor::Intel);
+        assert!(parse_vendor("ARM").is_err());
+        assert_eq!(parse_cpuinfo_vendor("processor\t: 0\nCPU implementer\t: 0x41\n"), None);
+    }
 }
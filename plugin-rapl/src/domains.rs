@@ -3,7 +3,7 @@ use std::{fmt, str::FromStr};
 use alumet::resources::Resource;
 
 /// A known RAPL domain.
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum RaplDomainType {
     /// entire socket
     Package,
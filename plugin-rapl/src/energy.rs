@@ -0,0 +1,586 @@
+//! Energy accounting shared by the RAPL probes (perf_events and powercap).
+
+use std::{collections::BTreeMap, str::FromStr, time::SystemTime};
+
+use alumet::{
+    measurement::{MeasurementAccumulator, MeasurementPoint, Timestamp, WrappedMeasurementValue},
+    plugin::util::{CounterDiff, CounterDiffState, CounterDiffUpdate},
+    resources::{Resource, ResourceConsumer},
+};
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+
+use crate::{domains::RaplDomainType, Metrics};
+
+/// Number of polls used by [`PsysOverlapCheck`], not counting the polls where psys or the packages are not measured.
+const PSYS_CHECK_SAMPLES: usize = 10;
+
+/// Minimum correlation between the energy of psys and of the packages for psys to be considered as
+/// including the packages.
+const PSYS_CORRELATION_THRESHOLD: f64 = 0.9;
+
+/// One microjoule, in joules.
+const MICRO_JOULE: f64 = 1e-6;
+
+/// The quantity measured by the RAPL probes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum EmittedQuantity {
+    /// Energy consumed since the previous measurement, in joules.
+    #[default]
+    Energy,
+    /// Average power since the previous measurement, in watts.
+    Power,
+    /// Energy consumed since the previous measurement, as an integer number of microjoules.
+    ///
+    /// Unlike `Energy`, the values are exact integers: summing them over a long run does not accumulate
+    /// floating-point rounding errors, and the sum never differs from the energy counted by RAPL by more
+    /// than one microjoule. The conversion to other units is left to the outputs.
+    #[serde(rename = "energy_uj")]
+    MicroJoules,
+}
+
+/// What to do when a RAPL counter slightly decreases between two polls.
+///
+/// This is not an overflow: it happens when the counter is read while the hardware updates it.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum NegativeDeltaPolicy {
+    /// Report a consumption of zero joules.
+    #[default]
+    Clamp,
+    /// Don't report any measurement for this poll.
+    Drop,
+    /// Report the negative energy, so that the sum of the measurements remains exact.
+    PassThrough,
+}
+
+/// Multiplicative factors applied to the energy of some RAPL domains, to correct their measurements,
+/// for instance after a comparison with a wall meter.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Calibration {
+    factors: Vec<(RaplDomainType, f64)>,
+}
+
+impl Calibration {
+    /// Parses the factors of the configuration, indexed by the name of the domain.
+    ///
+    /// Returns an error if a domain is unknown, or if a factor is not a positive number.
+    pub fn parse(factors: &BTreeMap<String, f64>) -> anyhow::Result<Self> {
+        let factors = factors
+            .iter()
+            .map(|(domain, factor)| {
+                let domain = RaplDomainType::from_str(domain).map_err(|d| anyhow!("unknown RAPL domain '{d}'"))?;
+                if !(factor.is_finite() && *factor > 0.0) {
+                    return Err(anyhow!("the factor of {domain} must be positive, not {factor}"));
+                }
+                Ok((domain, *factor))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Self { factors })
+    }
+
+    /// Returns the factor of the domain, 1.0 if it is not calibrated.
+    pub fn factor(&self, domain: RaplDomainType) -> f64 {
+        self.factors
+            .iter()
+            .find(|(d, _)| *d == domain)
+            .map_or(1.0, |(_, factor)| *factor)
+    }
+}
+
+/// The energy counter of a RAPL domain.
+pub(crate) struct EnergyCounter {
+    pub domain: RaplDomainType,
+    /// The corresponding ResourceId
+    pub resource: Resource,
+    /// Overflow-correcting counter, to compute the energy consumption difference.
+    counter: CounterDiff,
+    /// The scale to apply to the counter values in order to get joules.
+    scale: f64,
+    /// Whether this domain is counted in the total energy.
+    in_total: bool,
+    /// How to report the small decreases of the counter.
+    pub negative_delta: NegativeDeltaPolicy,
+    /// Calibration factor applied to the energy, 1.0 by default (see [`Calibration`]).
+    pub calibration: f64,
+    /// Powercap control type of the zone, reported in the `control_type` attribute, if recorded. `None` with perf_events.
+    pub control_type: Option<String>,
+    /// Time of the previous update, to compute the power.
+    previous_time: Option<SystemTime>,
+    /// Number of times the counter has been corrected by [`CounterDiff`] since the zone was opened:
+    /// overflows, and small decreases of the counter (see [`NegativeDeltaPolicy`]).
+    pub overflow_corrections: u64,
+    /// Energy that has been measured but not reported yet, in microjoules, because only integers are reported
+    /// in [`EmittedQuantity::MicroJoules`] mode. It is negative if more energy has been reported than measured.
+    /// Only used by the counters that are not in microjoules, or that are calibrated.
+    uj_carry: f64,
+    /// Negative energy that has not been deducted from the reported microjoules yet, for the counters in microjoules.
+    uj_debt: i128,
+    /// Energy measured by the last update, in joules, if any.
+    last_joules: Option<f64>,
+}
+
+impl EnergyCounter {
+    pub fn new(
+        domain: RaplDomainType,
+        socket: u32,
+        max_value: u64,
+        scale: f64,
+        total_excluded_domains: &[RaplDomainType],
+    ) -> Self {
+        Self {
+            domain,
+            resource: domain.to_resource(socket),
+            counter: CounterDiff::with_max_value(max_value),
+            scale,
+            in_total: !total_excluded_domains.contains(&domain),
+            negative_delta: NegativeDeltaPolicy::default(),
+            calibration: 1.0,
+            control_type: None,
+            previous_time: None,
+            overflow_corrections: 0,
+            uj_carry: 0.0,
+            uj_debt: 0,
+            last_joules: None,
+        }
+    }
+
+    /// Returns the state of the counter, to restore it later with [`Self::restore`].
+    pub fn counter_state(&self) -> CounterDiffState {
+        self.counter.state()
+    }
+
+    /// Restores the state of the counter, saved by a previous run.
+    ///
+    /// The state is ignored if its maximum value differs from the one of this counter, which means that it
+    /// does not come from the same counter. Returns `true` if the state has been restored.
+    ///
+    /// The power cannot be computed by the first update after a restore, because the time of the previous
+    /// update is not saved: only the energy is reported.
+    pub fn restore(&mut self, state: CounterDiffState) -> bool {
+        if state.max_value != self.counter.max_value {
+            return false;
+        }
+        self.counter = CounterDiff::from_state(state);
+        true
+    }
+
+    /// Updates the counter with its new value, and returns the quantity to report, if any:
+    /// the energy in joules, or the average power in watts since the previous update.
+    fn measure(&mut self, counter_value: u64, timestamp: Timestamp, quantity: EmittedQuantity) -> Option<f64> {
+        let time = SystemTime::from(timestamp);
+        let previous_time = self.previous_time.replace(time);
+        let joules = self.delta(counter_value)? * self.joules_per_unit();
+        match quantity {
+            EmittedQuantity::Energy => Some(joules),
+            EmittedQuantity::Power => {
+                let elapsed = time.duration_since(previous_time?).ok().filter(|d| !d.is_zero())?;
+                Some(joules / elapsed.as_secs_f64())
+            }
+        }
+    }
+
+    /// Updates the counter with its new value, and returns the energy in microjoules since the previous update,
+    /// or `None` if there is nothing to report.
+    ///
+    /// If the counter is in microjoules and not calibrated (powercap), its difference is reported as is,
+    /// without any floating-point operation. Otherwise, the fractional part of the energy is carried over to
+    /// the next update, so that the sum of the returned values never drifts from the energy counted by RAPL.
+    fn measure_uj(&mut self, counter_value: u64) -> Option<u64> {
+        if self.joules_per_unit() == MICRO_JOULE {
+            let micro_joules = self.exact_delta(counter_value)? + self.uj_debt;
+            self.uj_debt = micro_joules.min(0);
+            // the delta of a u64 counter fits in a u64
+            return Some(micro_joules.max(0) as u64);
+        }
+        let micro_joules = self.delta(counter_value)? * (self.joules_per_unit() * 1e6) + self.uj_carry;
+        let reported = micro_joules.max(0.0).floor();
+        self.uj_carry = micro_joules - reported;
+        Some(reported as u64)
+    }
+
+    /// Updates the counter with its new value, and returns the difference with the previous value,
+    /// in counter units, or `None` if there is nothing to report.
+    fn delta(&mut self, counter_value: u64) -> Option<f64> {
+        self.exact_delta(counter_value).map(|d| d as f64)
+    }
+
+    /// Like [`Self::delta`], without converting the difference to `f64`.
+    /// It is negative if the counter has decreased (see [`NegativeDeltaPolicy::PassThrough`]).
+    fn exact_delta(&mut self, counter_value: u64) -> Option<i128> {
+        let delta = self.delta_with_policy(counter_value);
+        self.last_joules = delta.map(|d| d as f64 * self.joules_per_unit());
+        delta
+    }
+
+    /// The energy of one unit of the counter, in joules, with the calibration factor.
+    fn joules_per_unit(&self) -> f64 {
+        self.scale * self.calibration
+    }
+
+    /// Like [`Self::exact_delta`], without remembering the energy.
+    fn delta_with_policy(&mut self, counter_value: u64) -> Option<i128> {
+        let max_value = self.counter.max_value;
+        match self.counter.update(counter_value) {
+            CounterDiffUpdate::FirstTime => None,
+            CounterDiffUpdate::Difference(diff) => Some(diff.into()),
+            CounterDiffUpdate::CorrectedDifference(diff) if diff > max_value / 2 => {
+                self.overflow_corrections += 1;
+                // An overflow cannot produce such a large difference between two polls:
+                // the counter has decreased a little, which is not an overflow.
+                let decrease = max_value - diff;
+                log::debug!(
+                    "The RAPL counter of domain {} has decreased by {decrease}, applying policy {:?}",
+                    self.domain,
+                    self.negative_delta
+                );
+                match self.negative_delta {
+                    NegativeDeltaPolicy::Clamp => Some(0),
+                    NegativeDeltaPolicy::Drop => None,
+                    NegativeDeltaPolicy::PassThrough => Some(-i128::from(decrease)),
+                }
+            }
+            CounterDiffUpdate::CorrectedDifference(diff) => {
+                self.overflow_corrections += 1;
+                log::debug!("Overflow on the RAPL counter of domain {}", self.domain);
+                Some(diff.into())
+            }
+        }
+    }
+}
+
+/// Builds the measurements of one poll of a RAPL probe.
+pub(crate) struct EnergyMeasurements<'m, 'a, 'b> {
+    metrics: &'m Metrics,
+    timestamp: Timestamp,
+    measurements: &'a mut MeasurementAccumulator<'b>,
+    /// Sum of the energy (or power) of the domains that are in the total, if any.
+    total: Option<f64>,
+    /// Sum of the energy of the domains that are in the total, in [`EmittedQuantity::MicroJoules`] mode.
+    total_uj: Option<u64>,
+    /// Energy of psys and of the packages, for the [`PsysOverlapCheck`].
+    psys_sample: PsysSample,
+}
+
+impl<'m, 'a, 'b> EnergyMeasurements<'m, 'a, 'b> {
+    pub fn new(metrics: &'m Metrics, timestamp: Timestamp, measurements: &'a mut MeasurementAccumulator<'b>) -> Self {
+        Self {
+            metrics,
+            timestamp,
+            measurements,
+            total: None,
+            total_uj: None,
+            psys_sample: PsysSample::default(),
+        }
+    }
+
+    /// Updates the counter with its new value, and pushes the energy consumed (or the power) since the previous update.
+    pub fn update(&mut self, counter: &mut EnergyCounter, counter_value: u64) {
+        // correct any overflows, convert to joules, microjoules or watts and push
+        let value = match self.metrics.quantity {
+            EmittedQuantity::MicroJoules => counter.measure_uj(counter_value).map(|uj| {
+                if counter.in_total {
+                    *self.total_uj.get_or_insert(0) += uj;
+                }
+                WrappedMeasurementValue::U64(uj)
+            }),
+            quantity => counter.measure(counter_value, self.timestamp, quantity).map(|value| {
+                if counter.in_total {
+                    *self.total.get_or_insert(0.0) += value;
+                }
+                WrappedMeasurementValue::F64(value)
+            }),
+        };
+        if let Some(joules) = counter.last_joules {
+            self.psys_sample.add(counter, joules);
+        }
+        if let Some(value) = value {
+            let mut point = MeasurementPoint::new_untyped(
+                self.timestamp,
+                self.metrics.consumed_energy,
+                counter.resource.clone(),
+                ResourceConsumer::LocalMachine,
+                value,
+            )
+            .with_attr("domain", counter.domain.as_str());
+            if counter.calibration != 1.0 {
+                // for transparency, the applied factor is part of the measurement
+                point = point.with_attr("calibration", counter.calibration);
+            }
+            if let Some(control_type) = &counter.control_type {
+                point = point.with_attr("control_type", control_type.clone());
+            }
+            self.measurements.push(point);
+        }
+    }
+
+    /// Pushes the total energy (or power), if any domain has been included in it.
+    ///
+    /// Returns the energy of psys and of the packages, to feed the [`PsysOverlapCheck`].
+    pub fn finish(self) -> PsysSample {
+        let total = match (self.total, self.total_uj) {
+            (Some(value), _) => Some(WrappedMeasurementValue::F64(value)),
+            (None, Some(uj)) => Some(WrappedMeasurementValue::U64(uj)),
+            (None, None) => None,
+        };
+        if let Some(total) = total {
+            self.measurements.push(MeasurementPoint::new_untyped(
+                self.timestamp,
+                self.metrics.total_consumed_energy,
+                Resource::LocalMachine,
+                ResourceConsumer::LocalMachine,
+                total,
+            ));
+        }
+        self.psys_sample
+    }
+}
+
+/// Energy of the psys and package domains during one poll, in joules.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct PsysSample {
+    psys: Option<f64>,
+    packages: Option<f64>,
+    /// Whether psys and at least one package are counted in the total.
+    psys_in_total: bool,
+    packages_in_total: bool,
+}
+
+impl PsysSample {
+    fn add(&mut self, counter: &EnergyCounter, joules: f64) {
+        match counter.domain {
+            RaplDomainType::Platform => {
+                *self.psys.get_or_insert(0.0) += joules;
+                self.psys_in_total |= counter.in_total;
+            }
+            RaplDomainType::Package => {
+                *self.packages.get_or_insert(0.0) += joules;
+                self.packages_in_total |= counter.in_total;
+            }
+            _ => (),
+        }
+    }
+}
+
+/// One-time check of the overlap between psys and the packages.
+///
+/// On most machines, the psys domain measures the whole platform, including the packages:
+/// adding both to the total counts the energy of the packages twice. This check compares the energy
+/// of psys and of the packages over the first polls, and logs its conclusion once.
+pub(crate) struct PsysOverlapCheck {
+    /// Energy of psys and of the packages, in joules, at each poll.
+    samples: Vec<(f64, f64)>,
+}
+
+impl PsysOverlapCheck {
+    pub fn new() -> Self {
+        Self {
+            samples: Vec::with_capacity(PSYS_CHECK_SAMPLES),
+        }
+    }
+
+    /// Records the energy of one poll. Returns `true` when the check is over (and its result has been logged).
+    pub fn record(&mut self, sample: PsysSample) -> bool {
+        if let (Some(psys), Some(packages)) = (sample.psys, sample.packages) {
+            self.samples.push((psys, packages));
+        }
+        if self.samples.len() < PSYS_CHECK_SAMPLES {
+            return false;
+        }
+        match correlation(&self.samples) {
+            Some(r) if r >= PSYS_CORRELATION_THRESHOLD => {
+                if sample.psys_in_total && sample.packages_in_total {
+                    log::warn!("The energy of psys tracks the energy of the packages (correlation: {r:.2}): psys probably includes the packages, which are counted twice in the total. Consider adding \"platform\" to total_excluded_domains.");
+                } else {
+                    log::info!("The energy of psys tracks the energy of the packages (correlation: {r:.2}), psys probably includes the packages.");
+                }
+            }
+            Some(r) => {
+                log::info!("The energy of psys does not track the energy of the packages (correlation: {r:.2}).")
+            }
+            None => log::info!("Could not check whether psys overlaps with the packages: their energy has not varied."),
+        }
+        true
+    }
+}
+
+/// Pearson correlation coefficient of the pairs, or `None` if one of the series is constant.
+fn correlation(samples: &[(f64, f64)]) -> Option<f64> {
+    let n = samples.len() as f64;
+    let (mean_x, mean_y) = samples
+        .iter()
+        .fold((0.0, 0.0), |(sx, sy), (x, y)| (sx + x / n, sy + y / n));
+    let (mut cov, mut var_x, mut var_y) = (0.0, 0.0, 0.0);
+    for (x, y) in samples {
+        cov += (x - mean_x) * (y - mean_y);
+        var_x += (x - mean_x).powi(2);
+        var_y += (y - mean_y).powi(2);
+    }
+    if var_x <= f64::EPSILON || var_y <= f64::EPSILON {
+        return None;
+    }
+    Some(cov / (var_x * var_y).sqrt())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::time::{Duration, SystemTime};
+
+    use alumet::measurement::Timestamp;
+
+    use crate::domains::RaplDomainType;
+
+    use super::{correlation, Calibration, EmittedQuantity, EnergyCounter, NegativeDeltaPolicy};
+
+    #[test]
+    fn negative_delta_policies() {
+        let max_value = 1000;
+        let deltas = |policy| {
+            let mut counter = EnergyCounter::new(RaplDomainType::Package, 0, max_value, 1.0, &[]);
+            counter.negative_delta = policy;
+            // 990 -> 10 is an overflow, 10 -> 8 is a tiny decrease
+            [990, 995, 10, 8, 20].map(|v| counter.delta(v))
+        };
+        assert_eq!(
+            deltas(NegativeDeltaPolicy::Clamp),
+            [None, Some(5.0), Some(15.0), Some(0.0), Some(12.0)]
+        );
+        assert_eq!(
+            deltas(NegativeDeltaPolicy::Drop),
+            [None, Some(5.0), Some(15.0), None, Some(12.0)]
+        );
+        assert_eq!(
+            deltas(NegativeDeltaPolicy::PassThrough),
+            [None, Some(5.0), Some(15.0), Some(-2.0), Some(12.0)]
+        );
+    }
+
+    #[test]
+    fn psys_package_correlation() {
+        // psys = packages + a roughly constant consumption of the rest of the platform
+        let tracking: Vec<(f64, f64)> = [10.0, 25.0, 12.0, 40.0, 18.0]
+            .iter()
+            .enumerate()
+            .map(|(i, pkg)| (pkg + 5.0 + (i % 2) as f64 * 0.5, *pkg))
+            .collect();
+        assert!(correlation(&tracking).unwrap() > 0.99);
+
+        let independent = vec![(10.0, 20.0), (30.0, 20.5), (10.0, 21.0), (30.0, 20.0), (20.0, 40.0)];
+        assert!(correlation(&independent).unwrap().abs() < 0.5);
+
+        let constant = vec![(10.0, 20.0), (10.0, 25.0), (10.0, 30.0)];
+        assert_eq!(correlation(&constant), None);
+    }
+
+    #[test]
+    fn count_overflow_corrections() {
+        let mut counter = EnergyCounter::new(RaplDomainType::Package, 0, 1000, 1.0, &[]);
+        // 990 -> 10 is an overflow, 10 -> 8 is a tiny decrease, both are corrected
+        for v in [990, 995, 10, 20, 8, 30] {
+            counter.delta(v);
+        }
+        assert_eq!(counter.overflow_corrections, 2);
+    }
+
+    #[test]
+    fn restore_counter_state() {
+        let t0 = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        let mut counter = EnergyCounter::new(RaplDomainType::Package, 0, 1000, 1.0, &[]);
+        counter.delta(900);
+        let state = counter.counter_state();
+
+        // the restored counter measures the energy since the saved value, even after an overflow
+        let mut restored = EnergyCounter::new(RaplDomainType::Package, 0, 1000, 1.0, &[]);
+        assert!(restored.restore(state));
+        let energy = restored.measure(50, Timestamp::from(t0), EmittedQuantity::Energy);
+        assert_eq!(energy, Some(150.0));
+        let mut restored = EnergyCounter::new(RaplDomainType::Package, 0, 1000, 1.0, &[]);
+        assert!(restored.restore(state));
+        assert_eq!(restored.measure(950, Timestamp::from(t0), EmittedQuantity::Power), None);
+
+        // not the same counter
+        let mut other = EnergyCounter::new(RaplDomainType::Package, 0, 2000, 1.0, &[]);
+        assert!(!other.restore(state));
+        assert_eq!(other.delta(950), None);
+    }
+
+    #[test]
+    fn energy_and_power() {
+        let t0 = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        let at = |millis: u64| Timestamp::from(t0 + Duration::from_millis(millis));
+        let measures = |quantity| {
+            // counter in microjoules
+            let mut counter = EnergyCounter::new(RaplDomainType::Package, 0, u64::MAX, 1e-6, &[]);
+            [
+                counter.measure(1_000_000, at(0), quantity),
+                counter.measure(3_000_000, at(2000), quantity),
+                counter.measure(4_500_000, at(2500), quantity),
+                counter.measure(5_000_000, at(2500), quantity),
+            ]
+        };
+        assert_eq!(measures(EmittedQuantity::Energy), [None, Some(2.0), Some(1.5), Some(0.5)]);
+        // no power if no time has elapsed
+        assert_eq!(measures(EmittedQuantity::Power), [None, Some(1.0), Some(3.0), None]);
+    }
+
+    #[test]
+    fn calibration() {
+        let t0 = Timestamp::from(SystemTime::UNIX_EPOCH + Duration::from_secs(1000));
+        let factors = BTreeMap::from([(String::from("package"), 0.95), (String::from("dram"), 1.5)]);
+        let calibration = Calibration::parse(&factors).unwrap();
+        assert_eq!(calibration.factor(RaplDomainType::Package), 0.95);
+        assert_eq!(calibration.factor(RaplDomainType::Dram), 1.5);
+        assert_eq!(calibration.factor(RaplDomainType::PP0), 1.0);
+
+        let mut counter = EnergyCounter::new(RaplDomainType::Package, 0, u64::MAX, 1e-6, &[]);
+        counter.calibration = calibration.factor(RaplDomainType::Package);
+        counter.measure(0, t0, EmittedQuantity::Energy);
+        assert_eq!(counter.measure(2_000_000, t0, EmittedQuantity::Energy), Some(1.9));
+        assert_eq!(counter.measure_uj(3_000_000), Some(950_000));
+
+        // invalid factors
+        let invalid = |domain: &str, factor: f64| Calibration::parse(&BTreeMap::from([(domain.to_owned(), factor)]));
+        assert!(invalid("package", 0.0).is_err());
+        assert!(invalid("package", -1.0).is_err());
+        assert!(invalid("package", f64::NAN).is_err());
+        assert!(invalid("nope", 1.0).is_err());
+    }
+
+    #[test]
+    fn integer_micro_joules() {
+        // powercap: the counter is already in microjoules
+        let mut counter = EnergyCounter::new(RaplDomainType::Package, 0, u64::MAX, 1e-6, &[]);
+        let values = [1_000_000, 3_000_003, 3_000_004].map(|v| counter.measure_uj(v));
+        assert_eq!(values, [None, Some(2_000_003), Some(1)]);
+
+        // perf_events: one unit is 2^-14 J = 61.03515625 µJ, the fractions are carried over
+        let mut counter = EnergyCounter::new(RaplDomainType::Package, 0, u64::MAX, 2f64.powi(-14), &[]);
+        assert_eq!(counter.measure_uj(0), None);
+        let total: u64 = (1..=1000).map(|v| counter.measure_uj(v).unwrap()).sum();
+        assert_eq!(total, 61_035); // 1000 units = 61035.15625 µJ
+        assert_eq!(counter.measure_uj(1002), Some(122)); // 0.15625 + 122.0703125
+
+        // a negative delta is deducted from the next measurements
+        let mut counter = EnergyCounter::new(RaplDomainType::Package, 0, 1_000_000, 1e-6, &[]);
+        counter.negative_delta = NegativeDeltaPolicy::PassThrough;
+        let values = [100, 90, 95, 120].map(|v| counter.measure_uj(v));
+        assert_eq!(values, [None, Some(0), Some(0), Some(20)]);
+    }
+
+    #[test]
+    fn exact_micro_joules_sum() {
+        // 2^53 + 1 cannot be represented by a f64: the deltas must not go through floating-point numbers
+        let delta: u64 = (1 << 53) + 1;
+        let mut counter = EnergyCounter::new(RaplDomainType::Package, 0, u64::MAX, 1e-6, &[]);
+        assert_eq!(counter.measure_uj(0), None);
+        let mut total: u64 = 0;
+        for i in 1..=1000 {
+            let uj = counter.measure_uj(i * delta).unwrap();
+            assert_eq!(uj, delta);
+            total += uj;
+        }
+        assert_eq!(total, 1000 * delta);
+    }
+}
@@ -1,31 +1,78 @@
-use std::{path::PathBuf, time::Duration};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
 
 use alumet::{
+    config::{ConfigSchema, ConfigValueType},
+    metrics::{MetricId, RawMetricId, TypedMetricId},
     pipeline::{trigger, Source},
     plugin::{
-        rust::{deserialize_config, serialize_config, AlumetPlugin},
+        command::PluginCommand,
+        rust::{deserialize_config, serialize_config, AlumetPlugin, InvalidConfig},
         ConfigTable,
     },
-    units::Unit,
+    units::{PrefixedUnit, Unit},
 };
 use anyhow::{anyhow, Context};
-use indoc::indoc;
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    busy_read::{BusyReadConfig, BusyReader},
     consistency::{check_domains_consistency, SafeSubset},
+    constraints::{PowerLimitProbe, ZoneEnabledProbe},
+    counter_state::CounterStore,
+    domains::RaplDomainType,
+    energy::{Calibration, EmittedQuantity, NegativeDeltaPolicy},
     perf_event::PerfEventProbe,
-    powercap::PowercapProbe,
+    power_supply::{PowerSupplyMetrics, PowerSupplyProbe},
+    powercap::{ControlTypes, PowercapProbe},
+    thermal::ThermalProbe,
 };
 
+mod busy_read;
+mod commands;
 mod consistency;
+mod constraints;
+mod counter_state;
 mod cpus;
 mod domains;
+mod energy;
 mod perf_event;
+mod power_supply;
 mod powercap;
+mod thermal;
 
 pub struct RaplPlugin {
     config: Config,
+    /// Parsed version of `config.total_excluded_domains`.
+    total_excluded_domains: Vec<RaplDomainType>,
+    /// Parsed version of `config.calibration`.
+    calibration: Calibration,
+}
+
+/// Metrics pushed by the RAPL probes.
+///
+/// The type of the measured values depends on `quantity`: `u64` for [`EmittedQuantity::MicroJoules`], `f64` otherwise.
+#[derive(Clone, Copy)]
+pub(crate) struct Metrics {
+    /// Energy consumed by each domain since the previous measurement (or average power, see `quantity`).
+    consumed_energy: RawMetricId,
+    /// Energy consumed by all the domains that are not excluded from the total (or their power).
+    total_consumed_energy: RawMetricId,
+    /// Whether the metrics are energies or powers.
+    quantity: EmittedQuantity,
+    /// Number of overflow corrections of the counter of each zone.
+    overflow_corrections: TypedMetricId<u64>,
+}
+
+impl Metrics {
+    /// The metrics of the energy (or power) of the domains.
+    fn energy_metrics(&self) -> Vec<RawMetricId> {
+        vec![self.consumed_energy, self.total_consumed_energy]
+    }
 }
 
 impl AlumetPlugin for RaplPlugin {
@@ -42,14 +89,180 @@ impl AlumetPlugin for RaplPlugin {
         Ok(Some(config))
     }
 
+    fn config_required() -> bool {
+        false // RAPL works out of the box
+    }
+
+    fn commands() -> Vec<Box<dyn PluginCommand>> {
+        vec![Box::new(commands::ZonesCommand)]
+    }
+
+    fn config_schema() -> Option<ConfigSchema> {
+        let schema = ConfigSchema::new()
+            .entry(
+                "poll_interval",
+                ConfigValueType::Duration,
+                "Initial interval between two RAPL measurements.",
+            )
+            .entry(
+                "flush_interval",
+                ConfigValueType::Duration,
+                "Initial interval between two flushing of RAPL measurements.",
+            )
+            .entry(
+                "backend",
+                ConfigValueType::String,
+                "Interface to use to read the RAPL counters: \"auto\", \"perf_events\" or \"powercap\".",
+            )
+            .entry(
+                "no_perf_events",
+                ConfigValueType::Boolean,
+                "Set to true to disable perf_events and always use the powercap sysfs (only with the auto backend).",
+            )
+            .entry(
+                "total_excluded_domains",
+                ConfigValueType::Array,
+                "RAPL domains that are not added to the total, for instance [\"platform\", \"pp0\", \"pp1\"].",
+            )
+            .optional_entry(
+                "powercap_path",
+                ConfigValueType::String,
+                "/sys/devices/virtual/powercap/intel-rapl",
+                "Directory of the RAPL powercap control type.\nDetected from the CPU vendor by default.",
+            )
+            .optional_entry(
+                "powercap_control_types",
+                ConfigValueType::Array,
+                "[\"intel-rapl\", \"intel-rapl-mmio\"]",
+                "Powercap control types to scan, by order of preference. If set, powercap_path is ignored.\nThe measurements record their control type in the control_type attribute.",
+            )
+            .entry(
+                "deduplicate_zones",
+                ConfigValueType::Boolean,
+                "Set to false to measure the zones that overlap with a zone of a previous control type (counted twice in the total).",
+            )
+            .optional_entry(
+                "zone_rescan_interval",
+                ConfigValueType::Duration,
+                "1m",
+                "If set, the powercap power zones are discovered again at this interval.\nDisabled by default.",
+            )
+            .optional_entry(
+                "counter_state_file",
+                ConfigValueType::String,
+                "/var/lib/alumet/rapl-counters.toml",
+                "If set, the state of the powercap counters is saved to this file when the plugin stops, and restored when it starts.\nDisabled by default.",
+            )
+            .entry(
+                "counter_state_max_age",
+                ConfigValueType::Duration,
+                "Maximum age of a saved counter state that can be restored.",
+            )
+            .entry(
+                "skip_disabled_zones",
+                ConfigValueType::Boolean,
+                "Set to true to ignore the powercap zones that are disabled. By default, they are measured, with a warning.",
+            )
+            .optional_entry(
+                "zone_enabled_interval",
+                ConfigValueType::Duration,
+                "1m",
+                "If set, whether each powercap zone is enabled is measured at this interval, in the metric rapl_zone_enabled.",
+            )
+            .entry(
+                "negative_delta",
+                ConfigValueType::String,
+                "What to do when a counter slightly decreases: \"clamp\", \"drop\" or \"pass_through\".",
+            )
+            .optional_entry(
+                "calibration",
+                ConfigValueType::Table,
+                "{ package = 0.95 }",
+                "Multiplicative factors applied to the energy of the domains, to correct their measurements.\nThe factors must be positive, 1.0 by default.",
+            )
+            .entry(
+                "check_psys_overlap",
+                ConfigValueType::Boolean,
+                "Set to false to disable the startup check of the overlap between psys and the packages.",
+            )
+            .entry(
+                "emit",
+                ConfigValueType::String,
+                "Quantity to measure: \"energy\" (in joules), \"energy_uj\" (in integer microjoules) or \"power\" (in watts).",
+            )
+            .optional_entry(
+                "power_limits_interval",
+                ConfigValueType::Duration,
+                "10s",
+                "If set, the power limits of the powercap zones are measured at this interval.\nDisabled by default.",
+            )
+            .optional_entry(
+                "thermal_zones_interval",
+                ConfigValueType::Duration,
+                "5s",
+                "If set, the temperature of the thermal zones is measured at this interval.\nDisabled by default.",
+            )
+            .entry(
+                "thermal_zone_types",
+                ConfigValueType::Array,
+                "Types of the thermal zones to measure, for instance [\"x86_pkg_temp\"]. If empty, all the zones are measured.",
+            )
+            .entry(
+                "thermal_path",
+                ConfigValueType::String,
+                "Directory that contains the thermal zones.",
+            )
+            .optional_entry(
+                "power_supply_interval",
+                ConfigValueType::Duration,
+                "5s",
+                "If set, the power of the batteries and the state of the AC adapters are measured at this interval.\nDisabled by default.",
+            )
+            .entry(
+                "power_supply_path",
+                ConfigValueType::String,
+                "Directory that contains the power supplies (batteries and AC adapters).",
+            )
+            .optional_entry(
+                "busy_read",
+                ConfigValueType::Table,
+                "{ zone = \"package-0\", min_interval = \"100us\", max_duration = \"10s\" }",
+                "If set, one powercap zone is read in a tight loop by a dedicated thread, for at most max_duration.\nCostly, disabled by default.",
+            );
+        Some(schema)
+    }
+
     fn init(config: ConfigTable) -> anyhow::Result<Box<Self>> {
-        let config = deserialize_config(config)?;
-        Ok(Box::new(RaplPlugin { config }))
+        let config: Config = deserialize_config(config)?;
+        if config.no_perf_events && config.backend == Backend::PerfEvents {
+            return Err(anyhow!("no_perf_events cannot be used with backend = \"perf_events\"")).context(InvalidConfig);
+        }
+        let total_excluded_domains = config
+            .total_excluded_domains
+            .iter()
+            .map(|d| RaplDomainType::from_str(d).map_err(|d| anyhow!("unknown RAPL domain '{d}'")))
+            .collect::<anyhow::Result<Vec<_>>>()
+            .context("invalid total_excluded_domains")
+            .context(InvalidConfig)?;
+        let calibration = Calibration::parse(&config.calibration)
+            .context("invalid calibration")
+            .context(InvalidConfig)?;
+        if let Some(busy) = &config.busy_read {
+            busy.validate().context(InvalidConfig)?;
+        }
+        Ok(Box::new(RaplPlugin {
+            config,
+            total_excluded_domains,
+            calibration,
+        }))
     }
 
     fn start(&mut self, alumet: &mut alumet::plugin::AlumetStart) -> anyhow::Result<()> {
-        let mut use_perf = !self.config.no_perf_events;
-        let mut use_powercap = true;
+        let (mut use_perf, mut use_powercap) = match self.config.backend {
+            Backend::Auto => (!self.config.no_perf_events, true),
+            Backend::PerfEvents => (true, false),
+            Backend::Powercap => (false, true),
+        };
         let mut check_consistency = true;
 
         if let Ok(false) = std::path::Path::new(perf_event::PERF_SYSFS_DIR).try_exists() {
@@ -71,9 +284,14 @@ impl AlumetPlugin for RaplPlugin {
             }
         }
 
+        let control_types = self.control_types();
+
         // Discover RAPL domains available in perf_events and powercap. Beware, this can fail!
         let try_perf_events = perf_event::all_power_events();
-        let try_power_zones = powercap::all_power_zones();
+        let skip_disabled = self.config.skip_disabled_zones;
+        let try_power_zones = control_types
+            .power_zones()
+            .map(|zones| powercap::check_enabled_zones(zones, skip_disabled));
 
         let (available_domains, subset_indicator) = match (try_perf_events, try_power_zones) {
             (Ok(perf_events), Ok(power_zones)) => {
@@ -130,27 +348,106 @@ impl AlumetPlugin for RaplPlugin {
             consistency::mkstring(&available_domains.domains, ", ")
         );
 
-        // Create the metric.
-        let metric = alumet.create_metric::<f64>(
-            "rapl_consumed_energy",
-            Unit::Joule,
-            "Energy consumed since the previous measurement, as reported by RAPL.",
+        // Create the metrics.
+        let overflow_corrections = alumet.create_metric::<u64>(
+            "rapl_overflow_corrections",
+            Unit::Unity,
+            "Number of times the RAPL counter of the zone has been corrected because it wrapped around or decreased (powercap only).",
         )?;
+        let metrics = match self.config.emit {
+            EmittedQuantity::Energy => Metrics {
+                consumed_energy: alumet
+                    .create_metric::<f64>(
+                        "rapl_consumed_energy",
+                        Unit::Joule,
+                        "Energy consumed since the previous measurement, as reported by RAPL.",
+                    )?
+                    .untyped_id(),
+                total_consumed_energy: alumet
+                    .create_metric::<f64>(
+                        "rapl_total_consumed_energy",
+                        Unit::Joule,
+                        "Sum of the energy consumed by the non-overlapping RAPL domains since the previous measurement.",
+                    )?
+                    .untyped_id(),
+                quantity: EmittedQuantity::Energy,
+                overflow_corrections,
+            },
+            EmittedQuantity::MicroJoules => Metrics {
+                consumed_energy: alumet
+                    .create_metric::<u64>(
+                        "rapl_consumed_energy",
+                        PrefixedUnit::micro(Unit::Joule),
+                        "Energy consumed since the previous measurement, as reported by RAPL.",
+                    )?
+                    .untyped_id(),
+                total_consumed_energy: alumet
+                    .create_metric::<u64>(
+                        "rapl_total_consumed_energy",
+                        PrefixedUnit::micro(Unit::Joule),
+                        "Sum of the energy consumed by the non-overlapping RAPL domains since the previous measurement.",
+                    )?
+                    .untyped_id(),
+                quantity: EmittedQuantity::MicroJoules,
+                overflow_corrections,
+            },
+            EmittedQuantity::Power => Metrics {
+                consumed_energy: alumet
+                    .create_metric::<f64>(
+                        "rapl_consumed_power",
+                        Unit::Watt,
+                        "Average power since the previous measurement, computed from the energy reported by RAPL.",
+                    )?
+                    .untyped_id(),
+                total_consumed_energy: alumet
+                    .create_metric::<f64>(
+                        "rapl_total_consumed_power",
+                        Unit::Watt,
+                        "Sum of the power of the non-overlapping RAPL domains since the previous measurement.",
+                    )?
+                    .untyped_id(),
+                quantity: EmittedQuantity::Power,
+                overflow_corrections,
+            },
+        };
+        let excluded = &self.total_excluded_domains;
+        let rescan = self.config.zone_rescan_interval;
+        if rescan.is_some() && use_perf {
+            log::info!("zone_rescan_interval only applies to powercap, it will be used if perf_events fails.");
+        }
+        let counter_store = self
+            .config
+            .counter_state_file
+            .clone()
+            .map(|path| CounterStore::new(path, self.config.counter_state_max_age));
+        if counter_store.is_some() && use_perf {
+            log::info!("counter_state_file only applies to powercap, it will be used if perf_events fails.");
+        }
+        let settings = ProbeSettings {
+            calibration: self.calibration.clone(),
+            control_types: control_types.clone(),
+            negative_delta: self.config.negative_delta,
+            check_psys_overlap: self.config.check_psys_overlap,
+            zone_rescan_interval: rescan,
+            skip_disabled_zones: skip_disabled,
+            counter_store,
+        };
 
         // Create the measurement source.
         let source = match (use_perf, use_powercap) {
             (true, true) => {
                 // prefer perf_events, fallback to powercap if it fails
-                setup_perf_events_probe_or_fallback(metric, &available_domains)?
+                setup_perf_events_probe_or_fallback(metrics, &available_domains, excluded, settings)?
             }
             (true, false) => {
                 // only use perf
-                setup_perf_events_probe(metric, &available_domains)
+                let domains = &available_domains;
+                setup_perf_events_probe(metrics, domains, excluded, &settings)
                     .context("Failed to create RAPL probe based on perf_events")?
             }
             (false, true) => {
                 // only use powercap
-                setup_powercap_probe(metric, &available_domains)
+                setup_powercap_probe(metrics, &available_domains, excluded, settings)
                     .context("Failed to create RAPL probe based on powercap")?
             }
             (false, false) => {
@@ -168,6 +465,96 @@ impl AlumetPlugin for RaplPlugin {
             .build()
             .unwrap();
         alumet.add_source(source, trigger);
+
+        // Measure the power limits, if enabled.
+        if let Some(interval) = self.config.power_limits_interval {
+            match control_types.power_zones() {
+                Ok(zones) => {
+                    let metric = alumet.create_metric::<f64>(
+                        "rapl_power_limit",
+                        Unit::Watt,
+                        "Power limit (constraint) of a RAPL domain, as reported by powercap.",
+                    )?;
+                    let trigger = trigger::builder::time_interval(interval).build().unwrap();
+                    alumet.add_source(Box::new(PowerLimitProbe::new(metric, zones.flat)), trigger);
+                }
+                Err(e) => log::warn!("The power limits cannot be measured without powercap: {e:#}"),
+            }
+        }
+
+        // Measure whether the zones are enabled, if enabled.
+        if let Some(interval) = self.config.zone_enabled_interval {
+            match control_types.power_zones() {
+                Ok(zones) => {
+                    let metric = alumet.create_metric::<u64>(
+                        "rapl_zone_enabled",
+                        Unit::Unity,
+                        "1 if the powercap zone is enabled, 0 if it is disabled (its energy counter may be stale).",
+                    )?;
+                    let trigger = trigger::builder::time_interval(interval).build().unwrap();
+                    alumet.add_source(Box::new(ZoneEnabledProbe::new(metric, zones.flat)), trigger);
+                }
+                Err(e) => log::warn!("The state of the zones cannot be measured without powercap: {e:#}"),
+            }
+        }
+
+        // Measure the temperature of the thermal zones, if enabled.
+        if let Some(interval) = self.config.thermal_zones_interval {
+            let metric = alumet.create_metric::<f64>(
+                "thermal_zone_temperature",
+                Unit::DegreeCelsius,
+                "Temperature of a thermal zone, as reported by the Linux kernel.",
+            )?;
+            let probe = ThermalProbe::new(
+                metric,
+                self.config.thermal_path.clone(),
+                self.config.thermal_zone_types.clone(),
+            );
+            let trigger = trigger::builder::time_interval(interval).build().unwrap();
+            alumet.add_source(Box::new(probe), trigger);
+        }
+
+        // Measure the batteries and AC adapters, if enabled.
+        if let Some(interval) = self.config.power_supply_interval {
+            let metrics = PowerSupplyMetrics {
+                discharge_power: alumet.create_metric::<f64>(
+                    "battery_discharge_power",
+                    Unit::Watt,
+                    "Power drawn from a battery, zero while it is charging.",
+                )?,
+                ac_online: alumet.create_metric::<u64>(
+                    "ac_online",
+                    Unit::Unity,
+                    "1 if the AC adapter is plugged, 0 otherwise.",
+                )?,
+            };
+            let probe = PowerSupplyProbe::new(metrics, self.config.power_supply_path.clone());
+            let trigger = trigger::builder::time_interval(interval).build().unwrap();
+            alumet.add_source(Box::new(probe), trigger);
+        }
+
+        // Read one zone at high frequency, if enabled.
+        if let Some(config) = &self.config.busy_read {
+            let zones = control_types.power_zones().context("busy_read requires powercap")?;
+            let zone = busy_read::find_zone(&zones.flat, &config.zone)?;
+            let metric = alumet.create_metric::<u64>(
+                "rapl_busy_read_energy",
+                PrefixedUnit::micro(Unit::Joule),
+                "Energy consumed by a RAPL zone since the previous change of its counter, read at high frequency.",
+            )?;
+            let reader = BusyReader::open(zone, metric, config.clone())?;
+            log::warn!(
+                "Busy reading of the RAPL zone {} every {:?} for {:?}: this can keep one CPU core busy.",
+                zone.path.display(),
+                config.min_interval,
+                config.max_duration
+            );
+            alumet.add_autonomous_source(move |_, cancel_token, tx| async move {
+                // the loop blocks, it must not run on the threads of the async runtime
+                let send = move |buffer| tx.blocking_send(buffer).is_ok();
+                tokio::task::spawn_blocking(move || reader.run(|| cancel_token.is_cancelled(), send)).await?
+            });
+        }
         Ok(())
     }
 
@@ -176,19 +563,92 @@ impl AlumetPlugin for RaplPlugin {
     }
 }
 
+impl RaplPlugin {
+    /// Returns the powercap control types to use: the ones of `powercap_control_types`, if any,
+    /// or the single control type of [`Self::powercap_path`].
+    fn control_types(&self) -> ControlTypes {
+        let names = &self.config.powercap_control_types;
+        if names.is_empty() {
+            return ControlTypes::single(self.powercap_path());
+        }
+        if self.config.powercap_path.is_some() {
+            log::warn!("powercap_path is ignored, because powercap_control_types is set.");
+        }
+        // an absolute path replaces the powercap directory
+        let powercap_dir = Path::new(powercap::POWERCAP_PATH);
+        let paths = names.iter().map(|name| powercap_dir.join(name)).collect();
+        let control_types = ControlTypes {
+            paths,
+            deduplicate: self.config.deduplicate_zones,
+            record_control_type: true,
+        };
+        log::info!("Using the powercap control types {control_types} (from the config).");
+        control_types
+    }
+
+    /// Returns the directory of the powercap control type to use: the one of the config, if any,
+    /// or the one that matches the CPU vendor.
+    fn powercap_path(&self) -> PathBuf {
+        if let Some(path) = &self.config.powercap_path {
+            log::info!("Using the powercap control type {} (from the config).", path.display());
+            return path.clone();
+        }
+        let vendor = match cpus::cpu_vendor() {
+            Ok(vendor) => Some(vendor),
+            Err(e) => {
+                log::warn!("Could not detect the CPU vendor, all the powercap control types will be scanned: {e:#}");
+                None
+            }
+        };
+        match powercap::find_control_type(Path::new(powercap::POWERCAP_PATH), vendor) {
+            Ok(path) => {
+                log::info!(
+                    "Using the powercap control type {} (detected, CPU vendor: {vendor:?}).",
+                    path.display()
+                );
+                path
+            }
+            Err(e) => {
+                // keep the usual path, the errors will mention it
+                log::warn!("Could not find the RAPL powercap control type: {e:#}");
+                PathBuf::from(powercap::POWERCAP_RAPL_PATH)
+            }
+        }
+    }
+}
+
+/// Settings of the RAPL probes. Some of them only apply to powercap.
+struct ProbeSettings {
+    calibration: Calibration,
+    /// Control types in which the power zones are discovered again (powercap only).
+    control_types: ControlTypes,
+    negative_delta: NegativeDeltaPolicy,
+    check_psys_overlap: bool,
+    /// Interval of the discovery of the power zones (powercap only).
+    zone_rescan_interval: Option<Duration>,
+    /// Whether the rescan ignores the disabled zones (powercap only).
+    skip_disabled_zones: bool,
+    /// Where to save the state of the counters (powercap only).
+    counter_store: Option<CounterStore>,
+}
+
 fn setup_perf_events_probe_or_fallback(
-    metric: alumet::metrics::TypedMetricId<f64>,
+    metrics: Metrics,
     available_domains: &SafeSubset,
+    total_excluded_domains: &[RaplDomainType],
+    settings: ProbeSettings,
 ) -> anyhow::Result<Box<dyn Source>> {
-    setup_perf_events_probe(metric, available_domains).or_else(|_| {
+    setup_perf_events_probe(metrics, available_domains, total_excluded_domains, &settings).or_else(|_| {
         log::warn!("I will fallback to the powercap sysfs, but perf_events is more efficient (see https://hal.science/hal-04420527).");
-        setup_powercap_probe(metric, available_domains)
+        setup_powercap_probe(metrics, available_domains, total_excluded_domains, settings)
     })
 }
 
 fn setup_perf_events_probe(
-    metric: alumet::metrics::TypedMetricId<f64>,
+    metrics: Metrics,
     available_domains: &SafeSubset,
+    total_excluded_domains: &[RaplDomainType],
+    settings: &ProbeSettings,
 ) -> Result<Box<dyn Source>, anyhow::Error> {
     fn resolve_application_path() -> std::io::Result<PathBuf> {
         std::env::current_exe()?.canonicalize()
@@ -213,8 +673,16 @@ fn setup_perf_events_probe(
     log::debug!("Events to read: {events_on_cpus:?}");
 
     // Try to create the source
-    match PerfEventProbe::new(metric, &events_on_cpus) {
-        Ok(perf_event_probe) => Ok(Box::new(perf_event_probe)),
+    match PerfEventProbe::new(metrics, &events_on_cpus, total_excluded_domains) {
+        Ok(perf_event_probe) => {
+            let mut probe = perf_event_probe
+                .with_negative_delta(settings.negative_delta)
+                .with_calibration(&settings.calibration);
+            if settings.check_psys_overlap {
+                probe = probe.with_psys_overlap_check();
+            }
+            Ok(Box::new(probe))
+        }
         Err(e) => {
             // perf_events failed, log an error and try powercap instead
             log::warn!("I could not use perf_events to read RAPL energy counters: {e}");
@@ -245,19 +713,39 @@ fn setup_perf_events_probe(
 }
 
 fn setup_powercap_probe(
-    metric: alumet::metrics::TypedMetricId<f64>,
+    metrics: Metrics,
     available_domains: &SafeSubset,
+    total_excluded_domains: &[RaplDomainType],
+    settings: ProbeSettings,
 ) -> anyhow::Result<Box<dyn Source>> {
-    match PowercapProbe::new(metric, &available_domains.power_zones) {
-        Ok(powercap_probe) => Ok(Box::new(powercap_probe)),
+    match PowercapProbe::new(metrics, &available_domains.power_zones, total_excluded_domains) {
+        Ok(powercap_probe) => {
+            let mut probe = powercap_probe
+                .with_negative_delta(settings.negative_delta)
+                .with_calibration(&settings.calibration);
+            if settings.check_psys_overlap {
+                probe = probe.with_psys_overlap_check();
+            }
+            if let Some(store) = settings.counter_store {
+                probe = probe.with_counter_store(store);
+            }
+            match settings.zone_rescan_interval {
+                Some(interval) => {
+                    let probe = probe.with_rescan(settings.control_types, interval, settings.skip_disabled_zones);
+                    Ok(Box::new(probe))
+                }
+                None => Ok(Box::new(probe)),
+            }
+        }
         Err(e) => {
-            let msg = indoc! {"
+            let control_types = &settings.control_types;
+            let msg = indoc::formatdoc! {"
                 I could not use the powercap sysfs to read RAPL energy counters.
                 This is probably caused by insufficient privileges.
-                Please check that you have read access to everything in '/sys/devices/virtual/powercap/intel-rapl'.
+                Please check that you have read access to everything in {control_types}.
                     
                 A solution could be:
-                    sudo chmod a+r -R /sys/devices/virtual/powercap/intel-rapl
+                    sudo chmod a+r -R {control_types}
             "};
             log::error!("{msg}");
             Err(e)
@@ -275,8 +763,137 @@ struct Config {
     #[serde(with = "humantime_serde")]
     flush_interval: Duration,
 
+    /// Interface to use to read the RAPL counters.
+    #[serde(default)]
+    backend: Backend,
+
     /// Set to true to disable perf_events and always use the powercap sysfs.
+    ///
+    /// Only applies to the `auto` backend: it is equivalent to `backend = "powercap"`.
+    #[serde(default)]
     no_perf_events: bool,
+
+    /// RAPL domains that are not added to `rapl_total_consumed_energy`.
+    ///
+    /// Excluded domains are still measured and reported in `rapl_consumed_energy`.
+    /// By default, `platform` (psys) is excluded because it overlaps with the package domain
+    /// on most machines, and `pp0` and `pp1` are excluded because they are parts of the package.
+    #[serde(default = "default_total_excluded_domains")]
+    total_excluded_domains: Vec<String>,
+
+    /// Directory of the RAPL powercap control type.
+    ///
+    /// By default, it is chosen from the vendor of the CPU (read in `/proc/cpuinfo`), or by scanning all the
+    /// powercap control types. Set it if sysfs is mounted at a non-standard location, for instance in a container.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    powercap_path: Option<PathBuf>,
+
+    /// Powercap control types in which the power zones are discovered, by order of preference, for instance
+    /// `["intel-rapl", "intel-rapl-mmio"]`. Each one is a directory of `/sys/devices/virtual/powercap`, or an
+    /// absolute path. The control types that do not exist are skipped. If set, `powercap_path` is ignored.
+    /// Every measurement of powercap has the control type of its zone in the `control_type` attribute.
+    /// Without this option, the measurements have no `control_type` attribute.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    powercap_control_types: Vec<String>,
+
+    /// If true, a zone that has the same domain and socket as a zone of a previous control type is not measured,
+    /// nor its sub-zones, because both zones measure the same energy, which would be counted twice in the total.
+    /// Only applies with several `powercap_control_types`.
+    #[serde(default = "default_true")]
+    deduplicate_zones: bool,
+
+    /// If set, the powercap power zones are discovered again at this interval, in order to
+    /// handle the zones that appear or disappear, for instance when the kernel module is reloaded.
+    /// Disabled by default.
+    #[serde(with = "humantime_serde", default, skip_serializing_if = "Option::is_none")]
+    zone_rescan_interval: Option<Duration>,
+
+    /// If set, the state of the powercap counters is saved to this file when the plugin stops, and restored when
+    /// it starts again, so that the energy consumed between the two runs is measured by the first poll.
+    /// The state is not restored after a reboot, or if it is older than `counter_state_max_age`.
+    /// Disabled by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    counter_state_file: Option<PathBuf>,
+
+    /// Maximum age of a saved counter state that can be restored. It must be shorter than the time it takes
+    /// for a counter to wrap around, otherwise an overflow could be missed.
+    #[serde(with = "humantime_serde", default = "default_counter_state_max_age")]
+    counter_state_max_age: Duration,
+
+    /// If true, the powercap zones that are disabled (their `enabled` file contains 0) are not measured,
+    /// nor their sub-zones. By default, they are measured with a warning, because their energy counter may be stale.
+    #[serde(default)]
+    skip_disabled_zones: bool,
+
+    /// If set, whether each powercap zone is enabled is measured at this interval, in the metric
+    /// `rapl_zone_enabled` (1 if enabled, 0 if disabled), with the domain and the name of the zone in the attributes.
+    /// Disabled by default.
+    #[serde(with = "humantime_serde", default, skip_serializing_if = "Option::is_none")]
+    zone_enabled_interval: Option<Duration>,
+
+    /// What to do when a counter slightly decreases between two polls, which can happen when it is read
+    /// during its update: `clamp` reports zero joules, `drop` reports nothing, `pass_through` reports
+    /// the negative energy.
+    #[serde(default)]
+    negative_delta: NegativeDeltaPolicy,
+
+    /// Multiplicative factors applied to the energy of some domains, to correct their measurements, for instance
+    /// `{ package = 0.95 }` after a comparison with a wall meter. The factors must be positive, 1.0 by default.
+    /// The calibrated measurements have the applied factor in their `calibration` attribute.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    calibration: BTreeMap<String, f64>,
+
+    /// If true, the energy of psys and of the packages are compared during the first polls, and a warning is
+    /// logged if psys seems to include the packages while both are counted in the total. The check is done once.
+    #[serde(default = "default_true")]
+    check_psys_overlap: bool,
+
+    /// Quantity to measure: `energy` (in joules, metrics `rapl_consumed_energy` and `rapl_total_consumed_energy`)
+    /// or `power` (in watts, metrics `rapl_consumed_power` and `rapl_total_consumed_power`), which is the energy
+    /// divided by the time elapsed between two polls.
+    ///
+    /// `energy_uj` measures the energy as integer microjoules (`u64` values) instead of floating-point joules:
+    /// the sum of the measurements over a long run is exact, because no rounding error accumulates.
+    #[serde(default)]
+    emit: EmittedQuantity,
+
+    /// If set, the power limits of the powercap zones (`constraint_N_power_limit_uw`) are measured at this interval,
+    /// in the metric `rapl_power_limit`, with the name and time window of each constraint in the attributes.
+    /// Disabled by default.
+    #[serde(with = "humantime_serde", default, skip_serializing_if = "Option::is_none")]
+    power_limits_interval: Option<Duration>,
+
+    /// If set, the temperature of the thermal zones (`thermal_zone*/temp`) is measured at this interval,
+    /// in the metric `thermal_zone_temperature`, with the type of each zone in the `zone_type` attribute.
+    /// Disabled by default.
+    #[serde(with = "humantime_serde", default, skip_serializing_if = "Option::is_none")]
+    thermal_zones_interval: Option<Duration>,
+
+    /// Types of the thermal zones to measure, as found in their `type` file, for instance `["x86_pkg_temp"]`.
+    /// If empty, all the zones are measured.
+    #[serde(default)]
+    thermal_zone_types: Vec<String>,
+
+    /// Directory that contains the thermal zones.
+    #[serde(default = "default_thermal_path")]
+    thermal_path: PathBuf,
+
+    /// If set, the batteries and AC adapters of the machine are measured at this interval: the power drawn
+    /// from each battery in the metric `battery_discharge_power`, with its `status` in the attributes, and
+    /// whether each AC adapter is plugged in the metric `ac_online`. Nothing is measured if there is no battery.
+    /// Disabled by default.
+    #[serde(with = "humantime_serde", default, skip_serializing_if = "Option::is_none")]
+    power_supply_interval: Option<Duration>,
+
+    /// Directory that contains the power supplies.
+    #[serde(default = "default_power_supply_path")]
+    power_supply_path: PathBuf,
+
+    /// If set, one powercap zone is read in a tight loop by a dedicated thread, for a limited time,
+    /// in the metric `rapl_busy_read_energy`. This is meant to profile short bursts of activity, and keeps
+    /// one CPU core busy when the interval is short. Disabled by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    busy_read: Option<BusyReadConfig>,
 }
 
 impl Default for Config {
@@ -284,7 +901,65 @@ impl Default for Config {
         Self {
             poll_interval: Duration::from_secs(1), // 1Hz
             flush_interval: Duration::from_secs(5),
+            backend: Backend::Auto,
             no_perf_events: false, // prefer perf_events
+            total_excluded_domains: default_total_excluded_domains(),
+            powercap_path: None,
+            powercap_control_types: Vec::new(),
+            deduplicate_zones: true,
+            zone_rescan_interval: None,
+            counter_state_file: None,
+            counter_state_max_age: default_counter_state_max_age(),
+            skip_disabled_zones: false,
+            zone_enabled_interval: None,
+            negative_delta: NegativeDeltaPolicy::default(),
+            calibration: BTreeMap::new(),
+            check_psys_overlap: true,
+            emit: EmittedQuantity::default(),
+            power_limits_interval: None,
+            thermal_zones_interval: None,
+            thermal_zone_types: Vec::new(),
+            thermal_path: default_thermal_path(),
+            power_supply_interval: None,
+            power_supply_path: default_power_supply_path(),
+            busy_read: None,
         }
     }
 }
+
+/// Interface used to read the RAPL counters.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Backend {
+    /// Prefer perf_events, and fall back to powercap if perf_events is not available.
+    #[default]
+    Auto,
+    /// Only use perf_events.
+    PerfEvents,
+    /// Only use the powercap sysfs.
+    Powercap,
+}
+
+fn default_total_excluded_domains() -> Vec<String> {
+    vec![
+        RaplDomainType::Platform.to_string(),
+        RaplDomainType::PP0.to_string(),
+        RaplDomainType::PP1.to_string(),
+    ]
+}
+
+fn default_counter_state_max_age() -> Duration {
+    Duration::from_secs(60)
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_thermal_path() -> PathBuf {
+    PathBuf::from(thermal::THERMAL_PATH)
+}
+
+fn default_power_supply_path() -> PathBuf {
+    PathBuf::from(power_supply::POWER_SUPPLY_PATH)
+}
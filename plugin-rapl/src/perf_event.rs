@@ -1,9 +1,5 @@
-use alumet::{
-    measurement::{MeasurementAccumulator, MeasurementPoint, Timestamp},
-    metrics::TypedMetricId,
-    plugin::util::{CounterDiff, CounterDiffUpdate},
-    resources::{Resource, ResourceConsumer},
-};
+use alumet::measurement::{MeasurementAccumulator, Timestamp};
+use alumet::metrics::RawMetricId;
 use anyhow::{Context, Result};
 use perf_event_open_sys as sys;
 use std::{
@@ -15,6 +11,8 @@ use std::{
 
 use super::cpus::CpuId;
 use super::domains::RaplDomainType;
+use crate::energy::{Calibration, EnergyCounter, EnergyMeasurements, NegativeDeltaPolicy, PsysOverlapCheck};
+use crate::Metrics;
 
 // See https://github.com/torvalds/linux/commit/4788e5b4b2338f85fa42a712a182d8afd65d7c58
 // for an explanation of the RAPL PMU driver.
@@ -163,22 +161,26 @@ fn read_perf_event(fd: &mut File) -> io::Result<u64> {
 
 /// Energy probe based on perf_event for intel RAPL.
 pub struct PerfEventProbe {
-    /// Id of the metric to push.
-    metric: TypedMetricId<f64>,
+    /// Ids of the metrics to push.
+    metrics: Metrics,
     /// Ready-to-use power events with additional metadata.
     events: Vec<OpenedPowerEvent>,
+    /// Check of the overlap between psys and the packages, until it is over.
+    psys_check: Option<PsysOverlapCheck>,
 }
 
 struct OpenedPowerEvent {
     fd: File,
-    scale: f64,
-    domain: RaplDomainType,
-    resource: Resource,
-    counter: CounterDiff,
+    /// Energy counter of the event's domain.
+    counter: EnergyCounter,
 }
 
 impl PerfEventProbe {
-    pub fn new(metric: TypedMetricId<f64>, events_on_cpus: &[(&PowerEvent, &CpuId)]) -> anyhow::Result<PerfEventProbe> {
+    pub fn new(
+        metrics: Metrics,
+        events_on_cpus: &[(&PowerEvent, &CpuId)],
+        total_excluded_domains: &[RaplDomainType],
+    ) -> anyhow::Result<PerfEventProbe> {
         const ADVICE: &str = "Try to set kernel.perf_event_paranoid to 0 or -1, or to give CAP_PERFMON to the application's binary (CAP_SYS_ADMIN before Linux 5.8).";
 
         let pmu_type = pmu_type()?;
@@ -189,17 +191,37 @@ impl PerfEventProbe {
                 .with_context(|| format!("perf_event_open failed. {ADVICE}"))?;
             let fd = unsafe { File::from_raw_fd(raw_fd) };
             let scale = event.scale as f64;
-            let counter = CounterDiff::with_max_value(PERF_MAX_ENERGY);
-            let opened_event = OpenedPowerEvent {
-                fd,
-                scale,
-                domain: event.domain,
-                resource: event.domain.to_resource(*socket),
-                counter,
-            };
+            let counter = EnergyCounter::new(event.domain, *socket, PERF_MAX_ENERGY, scale, total_excluded_domains);
+            let opened_event = OpenedPowerEvent { fd, counter };
             opened.push(opened_event)
         }
-        Ok(PerfEventProbe { metric, events: opened })
+        Ok(PerfEventProbe {
+            metrics,
+            events: opened,
+            psys_check: None,
+        })
+    }
+
+    /// Sets how to report the small decreases of the counters.
+    pub fn with_negative_delta(mut self, policy: NegativeDeltaPolicy) -> Self {
+        for evt in &mut self.events {
+            evt.counter.negative_delta = policy;
+        }
+        self
+    }
+
+    /// Sets the calibration factors of the domains.
+    pub fn with_calibration(mut self, calibration: &Calibration) -> Self {
+        for evt in &mut self.events {
+            evt.counter.calibration = calibration.factor(evt.counter.domain);
+        }
+        self
+    }
+
+    /// Checks, during the first polls, whether psys overlaps with the packages (see [`PsysOverlapCheck`]).
+    pub fn with_psys_overlap_check(mut self) -> Self {
+        self.psys_check = Some(PsysOverlapCheck::new());
+        self
     }
 }
 
@@ -209,29 +231,19 @@ impl alumet::pipeline::Source for PerfEventProbe {
         measurements: &mut MeasurementAccumulator,
         timestamp: Timestamp,
     ) -> Result<(), alumet::pipeline::PollError> {
+        let mut energy = EnergyMeasurements::new(&self.metrics, timestamp, measurements);
         for evt in &mut self.events {
             // read the new value of the perf-events counter
-            let counter_value = read_perf_event(&mut evt.fd)
-                .with_context(|| format!("failed to read perf_event {:?} for domain {:?}", evt.fd, evt.domain))?;
-
-            // correct any overflows
-            let diff = match evt.counter.update(counter_value) {
-                CounterDiffUpdate::FirstTime => None,
-                CounterDiffUpdate::Difference(diff) => Some(diff),
-                CounterDiffUpdate::CorrectedDifference(diff) => {
-                    log::debug!("Overflow on perf_event counter for RAPL domain {}", evt.domain);
-                    Some(diff)
-                }
-            };
-            if let Some(value) = diff {
-                // convert to joules and push
-                let joules = (value as f64) * evt.scale;
-                let consumer = ResourceConsumer::LocalMachine;
-                measurements.push(
-                    MeasurementPoint::new(timestamp, self.metric, evt.resource.clone(), consumer, joules)
-                        .with_attr("domain", evt.domain.as_str()),
-                );
-            }
+            let counter_value = read_perf_event(&mut evt.fd).with_context(|| {
+                format!(
+                    "failed to read perf_event {:?} for domain {:?}",
+                    evt.fd, evt.counter.domain
+                )
+            })?;
+
+            // correct any overflows, convert to joules and push
+            energy.update(&mut evt.counter, counter_value);
+
             // NOTE: the energy can be a floating-point number in Joules,
             // without any loss of precision. Why? Because multiplying any number
             // by a float that is a power of two will only change the "exponent" part,
@@ -241,6 +253,16 @@ impl alumet::pipeline::Source for PerfEventProbe {
             // up to approximately 2^24, which is not enough for the RAPL counter values,
             // so we use a f64 here.
         }
+        let psys_sample = energy.finish();
+        if let Some(check) = &mut self.psys_check {
+            if check.record(psys_sample) {
+                self.psys_check = None;
+            }
+        }
         Ok(())
     }
+
+    fn declared_metrics(&self) -> Vec<RawMetricId> {
+        self.metrics.energy_metrics()
+    }
 }
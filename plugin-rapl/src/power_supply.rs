@@ -0,0 +1,229 @@
+//! Power of the batteries and state of the AC adapters, read from `/sys/class/power_supply`.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use alumet::{
+    measurement::{MeasurementAccumulator, MeasurementPoint, Timestamp},
+    metrics::TypedMetricId,
+    pipeline::{PollError, Source},
+    resources::{Resource, ResourceConsumer},
+};
+use anyhow::Context;
+
+pub const POWER_SUPPLY_PATH: &str = "/sys/class/power_supply";
+
+/// `power_now` is in microwatts, `current_now` in microamperes and `voltage_now` in microvolts.
+const MICRO: f64 = 1e-6;
+
+/// Kind of power supply, from its `type` file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SupplyKind {
+    Battery,
+    /// An AC adapter.
+    Mains,
+}
+
+/// A power supply of the machine, as exposed by the Linux kernel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PowerSupply {
+    /// Name of the supply, for instance `BAT0` or `AC`.
+    pub name: String,
+    pub kind: SupplyKind,
+    /// Directory of the supply in sysfs.
+    pub dir: PathBuf,
+}
+
+pub struct PowerSupplyMetrics {
+    pub discharge_power: TypedMetricId<f64>,
+    pub ac_online: TypedMetricId<u64>,
+}
+
+/// Measures the discharge power of the batteries, and whether the AC adapters are plugged.
+///
+/// The supplies are discovered again at each poll, because a battery can be removed or inserted.
+/// On a machine without any battery or AC adapter, such as most desktops and servers, nothing is measured.
+pub struct PowerSupplyProbe {
+    metrics: PowerSupplyMetrics,
+    /// Directory that contains the power supplies.
+    path: PathBuf,
+}
+
+impl PowerSupplyProbe {
+    pub fn new(metrics: PowerSupplyMetrics, path: PathBuf) -> Self {
+        Self { metrics, path }
+    }
+}
+
+impl Source for PowerSupplyProbe {
+    fn poll(&mut self, measurements: &mut MeasurementAccumulator, timestamp: Timestamp) -> Result<(), PollError> {
+        let supplies = match power_supplies_at(&self.path) {
+            Ok(supplies) => supplies,
+            Err(e) => {
+                // no power_supply class at all: nothing to measure
+                log::debug!("No power supply found: {e:#}");
+                return Ok(());
+            }
+        };
+        for supply in supplies {
+            let resource = Resource::custom("power_supply", supply.name.clone());
+            match supply.kind {
+                SupplyKind::Battery => {
+                    let status = read_status(&supply.dir);
+                    let Some(watts) = read_battery_power(&supply.dir) else {
+                        log::debug!("The power of battery {} is not available.", supply.name);
+                        continue;
+                    };
+                    // the power is the charging power while the battery is charging
+                    let discharge = if status == "Charging" { 0.0 } else { watts };
+                    let point = MeasurementPoint::new(
+                        timestamp,
+                        self.metrics.discharge_power,
+                        resource,
+                        ResourceConsumer::LocalMachine,
+                        discharge,
+                    )
+                    .with_attr("status", status);
+                    measurements.push(point);
+                }
+                SupplyKind::Mains => match read_integer(&supply.dir.join("online")) {
+                    Ok(online) => {
+                        let point = MeasurementPoint::new(
+                            timestamp,
+                            self.metrics.ac_online,
+                            resource,
+                            ResourceConsumer::LocalMachine,
+                            (online != 0) as u64,
+                        );
+                        measurements.push(point);
+                    }
+                    Err(e) => log::debug!("Failed to read the state of AC adapter {}: {e:#}", supply.name),
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the batteries and AC adapters that exist in the given directory, sorted by name.
+///
+/// The other supplies (USB ports, wireless chargers...) and the batteries of peripheral devices,
+/// such as wireless mice, are ignored.
+pub fn power_supplies_at(path: &Path) -> anyhow::Result<Vec<PowerSupply>> {
+    let mut supplies = Vec::new();
+    let entries = fs::read_dir(path).with_context(|| format!("Could not list {}", path.display()))?;
+    for entry in entries {
+        let dir = entry?.path();
+        let Some(name) = dir.file_name().and_then(|n| n.to_str()).map(|n| n.to_owned()) else {
+            continue;
+        };
+        let kind = match fs::read_to_string(dir.join("type")).as_deref().map(str::trim_end) {
+            Ok("Battery") => SupplyKind::Battery,
+            Ok("Mains") => SupplyKind::Mains,
+            _ => continue,
+        };
+        // the batteries of the devices have scope "Device", those of the machine have no scope or "System"
+        if fs::read_to_string(dir.join("scope")).is_ok_and(|scope| scope.trim_end() == "Device") {
+            continue;
+        }
+        supplies.push(PowerSupply { name, kind, dir });
+    }
+    supplies.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(supplies)
+}
+
+/// Reads the power of a battery, in watts, from `power_now`, or from `current_now` and `voltage_now`.
+///
+/// Some drivers report a negative power or current while discharging: only the magnitude is kept.
+fn read_battery_power(dir: &Path) -> Option<f64> {
+    if let Ok(micro_watts) = read_integer(&dir.join("power_now")) {
+        return Some(micro_watts.unsigned_abs() as f64 * MICRO);
+    }
+    let micro_amps = read_integer(&dir.join("current_now")).ok()?;
+    let micro_volts = read_integer(&dir.join("voltage_now")).ok()?;
+    Some((micro_amps.unsigned_abs() as f64 * MICRO) * (micro_volts.unsigned_abs() as f64 * MICRO))
+}
+
+/// Reads the status of a battery, for instance `Discharging` or `Charging`.
+fn read_status(dir: &Path) -> String {
+    fs::read_to_string(dir.join("status"))
+        .map(|s| s.trim_end().to_owned())
+        .unwrap_or_else(|_| String::from("Unknown"))
+}
+
+fn read_integer(path: &Path) -> anyhow::Result<i64> {
+    let content = fs::read_to_string(path).with_context(|| format!("Could not read {}", path.display()))?;
+    content
+        .trim_end()
+        .parse()
+        .with_context(|| format!("Could not parse {}: '{content}'", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use super::{power_supplies_at, read_battery_power, read_integer, read_status, SupplyKind};
+
+    fn create_supply(root: &Path, name: &str, files: &[(&str, &str)]) {
+        let dir = root.join(name);
+        fs::create_dir_all(&dir).unwrap();
+        for (file, content) in files {
+            fs::write(dir.join(file), format!("{content}\n")).unwrap();
+        }
+    }
+
+    #[test]
+    fn power_supplies() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().to_path_buf();
+        create_supply(
+            &root,
+            "BAT1",
+            &[
+                ("type", "Battery"),
+                ("current_now", "-1500000"),
+                ("voltage_now", "12000000"),
+            ],
+        );
+        create_supply(
+            &root,
+            "BAT0",
+            &[("type", "Battery"), ("status", "Discharging"), ("power_now", "8500000")],
+        );
+        create_supply(&root, "AC", &[("type", "Mains"), ("online", "1")]);
+        create_supply(
+            &root,
+            "ucsi-source-psy-USBC000:001",
+            &[("type", "USB"), ("online", "0")],
+        );
+        create_supply(&root, "hidpp_battery_0", &[("type", "Battery"), ("scope", "Device")]);
+        create_supply(&root, "BAT2", &[("type", "Battery")]);
+
+        let supplies = power_supplies_at(&root).unwrap();
+        let names: Vec<(&str, SupplyKind)> = supplies.iter().map(|s| (s.name.as_str(), s.kind)).collect();
+        assert_eq!(
+            names,
+            vec![
+                ("AC", SupplyKind::Mains),
+                ("BAT0", SupplyKind::Battery),
+                ("BAT1", SupplyKind::Battery),
+                ("BAT2", SupplyKind::Battery),
+            ]
+        );
+        assert_eq!(read_integer(&supplies[0].dir.join("online")).unwrap(), 1);
+        assert_eq!(read_battery_power(&supplies[1].dir), Some(8.5));
+        assert_eq!(read_status(&supplies[1].dir), "Discharging");
+        assert_eq!(read_battery_power(&supplies[2].dir), Some(18.0));
+        assert_eq!(read_status(&supplies[2].dir), "Unknown");
+        assert_eq!(read_battery_power(&supplies[3].dir), None);
+
+        // a desktop without power supplies
+        let empty = root.join("empty");
+        fs::create_dir_all(&empty).unwrap();
+        assert!(power_supplies_at(&empty).unwrap().is_empty());
+        assert!(power_supplies_at(&root.join("missing")).is_err());
+    }
+}
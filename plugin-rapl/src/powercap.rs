@@ -4,25 +4,34 @@
 use std::{
     fmt::Display,
     fs::{self, File},
-    io::{Read, Seek},
+    io::{self, Read, Seek},
+    os::unix::fs::FileExt,
     path::{Path, PathBuf},
+    time::{Duration, Instant},
 };
 
-use alumet::metrics::TypedMetricId;
-use alumet::plugin::util::{CounterDiff, CounterDiffUpdate};
-use alumet::resources::Resource;
 use alumet::{
-    measurement::{AttributeValue, MeasurementAccumulator, MeasurementPoint, Timestamp},
+    measurement::{MeasurementAccumulator, MeasurementPoint, Timestamp},
+    metrics::{MetricId, RawMetricId},
     resources::ResourceConsumer,
 };
 use anyhow::{anyhow, Context};
 
 use super::domains::RaplDomainType;
+use crate::counter_state::{CounterStore, SavedCounter};
+use crate::cpus::{self, CpuVendor};
+use crate::energy::{Calibration, EnergyCounter, EnergyMeasurements, NegativeDeltaPolicy, PsysOverlapCheck};
+use crate::Metrics;
 
-const POWERCAP_RAPL_PATH: &str = "/sys/devices/virtual/powercap/intel-rapl";
+pub(crate) const POWERCAP_PATH: &str = "/sys/devices/virtual/powercap";
+pub(crate) const POWERCAP_RAPL_PATH: &str = "/sys/devices/virtual/powercap/intel-rapl";
 const POWER_ZONE_PREFIX: &str = "intel-rapl";
 const POWERCAP_ENERGY_UNIT: f64 = 0.000_001; // 1 microJoules
 
+/// Size of the buffer used to read `energy_uj` with a single positional read.
+/// The counter is an u64, that is at most 20 digits, plus a newline.
+const ENERGY_READ_BUF_SIZE: usize = 32;
+
 const PERMISSION_ADVICE: &str = "Try to adjust file permissions.";
 
 /// Hierarchy of power zones
@@ -55,6 +64,23 @@ pub struct PowerZone {
 
     /// The id of the socket that "contains" this zone, if applicable (psys has no socket)
     pub socket_id: Option<u32>,
+
+    /// The name of the powercap control type of the zone, for instance `intel-rapl` or `intel-rapl-mmio`.
+    /// Only recorded if the control types come from the config (see [`ControlTypes::record_control_type`]).
+    pub control_type: Option<String>,
+}
+
+/// A power limit of a zone, described by its `constraint_N_*` files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PowerConstraint {
+    /// The number `N` of the constraint.
+    pub index: u32,
+    /// The name of the constraint, for instance `long_term` or `short_term`, if provided.
+    pub name: Option<String>,
+    /// The power limit, in micro-watts.
+    pub power_limit_uw: u64,
+    /// The time window over which the power is averaged, in micro-seconds, if provided.
+    pub time_window_us: Option<u64>,
 }
 
 impl PowerZone {
@@ -66,6 +92,83 @@ impl PowerZone {
         self.path.join("max_energy_range_uj")
     }
 
+    pub fn enabled_path(&self) -> PathBuf {
+        self.path.join("enabled")
+    }
+
+    /// Returns true if the zone is enabled, as reported by its `enabled` file.
+    ///
+    /// The file is optional: a zone without it is considered enabled.
+    pub fn is_enabled(&self) -> anyhow::Result<bool> {
+        let path = self.enabled_path();
+        match read_optional(&path)? {
+            Some(content) => Ok(parse_u64(&content, &path)? != 0),
+            None => Ok(true),
+        }
+    }
+
+    /// Returns the numbers of the constraints of the zone, in ascending order.
+    ///
+    /// The number of constraints depends on the zone and on the hardware,
+    /// and there can be gaps in the numbering.
+    pub fn constraint_indices(&self) -> io::Result<Vec<u32>> {
+        let mut indices = Vec::new();
+        for e in fs::read_dir(&self.path)? {
+            let file_name = e?.file_name();
+            let index = file_name
+                .to_str()
+                .and_then(|f| f.strip_prefix("constraint_"))
+                .and_then(|f| f.strip_suffix("_power_limit_uw"))
+                .and_then(|n| n.parse().ok());
+            if let Some(i) = index {
+                indices.push(i);
+            }
+        }
+        indices.sort_unstable();
+        Ok(indices)
+    }
+
+    /// Reads the constraint number `index`.
+    ///
+    /// Returns `None` if the zone has no such constraint. The name and the time window
+    /// are optional, because some zones do not provide them.
+    pub fn constraint(&self, index: u32) -> anyhow::Result<Option<PowerConstraint>> {
+        let file = |suffix: &str| self.path.join(format!("constraint_{index}_{suffix}"));
+        let Some(power_limit_uw) = read_optional(&file("power_limit_uw"))? else {
+            return Ok(None);
+        };
+        let power_limit_uw = parse_u64(&power_limit_uw, &file("power_limit_uw"))?;
+        let name = read_optional(&file("name"))?.map(|n| n.trim().to_owned());
+        let time_window_us = match read_optional(&file("time_window_us"))? {
+            Some(content) => Some(parse_u64(&content, &file("time_window_us"))?),
+            None => None,
+        };
+        Ok(Some(PowerConstraint {
+            index,
+            name,
+            power_limit_uw,
+            time_window_us,
+        }))
+    }
+
+    /// Reads all the constraints of the zone.
+    ///
+    /// The constraints that cannot be read are skipped, with a warning.
+    pub fn constraints(&self) -> anyhow::Result<Vec<PowerConstraint>> {
+        let indices = self
+            .constraint_indices()
+            .with_context(|| format!("Could not list the constraints of {}", self.path.display()))?;
+        let mut constraints = Vec::with_capacity(indices.len());
+        for i in indices {
+            match self.constraint(i) {
+                Ok(Some(c)) => constraints.push(c),
+                Ok(None) => (), // removed in the meantime
+                Err(e) => log::warn!("Skipping constraint {i} of {}: {e:#}", self.path.display()),
+            }
+        }
+        Ok(constraints)
+    }
+
     fn fmt_rec(&self, f: &mut std::fmt::Formatter<'_>, level: i8) -> std::fmt::Result {
         let mut indent = "  ".repeat(level as _);
         if level > 0 {
@@ -90,22 +193,129 @@ impl Display for PowerZone {
     }
 }
 
-/// Discovers all the RAPL power zones in the powercap sysfs.
-pub fn all_power_zones() -> anyhow::Result<PowerZoneHierarchy> {
-    fn parse_zone_name(name: &str) -> Option<RaplDomainType> {
-        match name {
-            "psys" => Some(RaplDomainType::Platform),
-            "core" => Some(RaplDomainType::PP0),
-            "uncore" => Some(RaplDomainType::PP1),
-            "dram" => Some(RaplDomainType::Dram),
-            _ if name.starts_with("package-") => Some(RaplDomainType::Package),
-            _ => None,
+/// Reads the content of a file, or returns `None` if it does not exist.
+fn read_optional(path: &Path) -> anyhow::Result<Option<String>> {
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(Some(content)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("Could not read {}. {PERMISSION_ADVICE}", path.display())),
+    }
+}
+
+fn parse_u64(content: &str, path: &Path) -> anyhow::Result<u64> {
+    content
+        .trim_end()
+        .parse()
+        .with_context(|| format!("Could not parse {}: '{content}'", path.display()))
+}
+
+/// Parses the name of a power zone, as found in its `name` file, into a RAPL domain type.
+///
+/// Returns `None` if the name is unknown.
+pub fn parse_zone_name(name: &str) -> Option<RaplDomainType> {
+    match name {
+        "psys" => Some(RaplDomainType::Platform),
+        "core" => Some(RaplDomainType::PP0),
+        "uncore" => Some(RaplDomainType::PP1),
+        "dram" => Some(RaplDomainType::Dram),
+        _ if name.starts_with("package-") => Some(RaplDomainType::Package),
+        _ => None,
+    }
+}
+
+/// Returns the powercap control types that provide the RAPL zones of the given vendor, by order of preference.
+///
+/// On AMD, the kernel registers the RAPL zones in the `intel-rapl` control type, like on Intel.
+fn vendor_control_types(vendor: CpuVendor) -> &'static [&'static str] {
+    match vendor {
+        CpuVendor::Intel => &["intel-rapl", "intel-rapl-mmio"],
+        CpuVendor::Amd => &["intel-rapl"],
+    }
+}
+
+/// Finds the directory of the powercap control type that contains the RAPL zones, in `powercap_root`
+/// (usually [`POWERCAP_PATH`]).
+///
+/// The control types of the vendor are tried first. If none of them exists, or if the vendor is unknown,
+/// all the control types are scanned, and the first one that contains an energy counter is returned.
+pub fn find_control_type(powercap_root: &Path, vendor: Option<CpuVendor>) -> anyhow::Result<PathBuf> {
+    if let Some(vendor) = vendor {
+        for control_type in vendor_control_types(vendor) {
+            let path = powercap_root.join(control_type);
+            if path.is_dir() {
+                return Ok(path);
+            }
         }
+        log::debug!(
+            "no powercap control type of {vendor:?} found in {}, scanning all the control types",
+            powercap_root.display()
+        );
     }
 
+    let mut control_types: Vec<PathBuf> = fs::read_dir(powercap_root)
+        .with_context(|| format!("Could not explore {}. {PERMISSION_ADVICE}", powercap_root.display()))?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|path| path.is_dir())
+        .collect();
+    control_types.sort();
+    control_types
+        .into_iter()
+        .find(|path| has_energy_zone(path))
+        .with_context(|| {
+            format!(
+                "no powercap control type with energy counters in {}",
+                powercap_root.display()
+            )
+        })
+}
+
+/// Returns true if the control type contains at least one power zone with an energy counter.
+fn has_energy_zone(control_type: &Path) -> bool {
+    let prefix = zone_prefix(control_type);
+    let Ok(entries) = fs::read_dir(control_type) else {
+        return false;
+    };
+    entries.filter_map(Result::ok).any(|e| {
+        let path = e.path();
+        path.file_name().unwrap().to_string_lossy().starts_with(&prefix) && path.join("energy_uj").is_file()
+    })
+}
+
+/// Returns the name of a control type, that is, the name of its directory.
+fn control_type_name(control_type: &Path) -> String {
+    control_type
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or(POWER_ZONE_PREFIX.to_owned())
+}
+
+/// Returns the prefix of the power zones of a control type, for instance `intel-rapl:` for `intel-rapl:0`.
+fn zone_prefix(control_type: &Path) -> String {
+    format!("{}:", control_type_name(control_type))
+}
+
+/// Discovers all the RAPL power zones in the powercap sysfs, in the control type of the CPU vendor.
+pub fn all_power_zones() -> anyhow::Result<PowerZoneHierarchy> {
+    let vendor = cpus::cpu_vendor().ok();
+    let root = find_control_type(Path::new(POWERCAP_PATH), vendor).unwrap_or_else(|_| POWERCAP_RAPL_PATH.into());
+    all_power_zones_at(&root)
+}
+
+/// Discovers all the RAPL power zones in the given directory.
+///
+/// `root` is the directory of the powercap control type, usually `intel-rapl` (see [`POWERCAP_RAPL_PATH`]),
+/// but it can be elsewhere, for instance when sysfs is mounted at a different place in a container.
+pub fn all_power_zones_at(root: &Path) -> anyhow::Result<PowerZoneHierarchy> {
+    power_zones_at(root, false)
+}
+
+/// Discovers all the RAPL power zones in the given directory, and records their control type if asked to.
+fn power_zones_at(root: &Path, record_control_type: bool) -> anyhow::Result<PowerZoneHierarchy> {
     /// Recursively explore a power zone
     fn explore_rec(
         dir: &Path,
+        prefix: &str,
+        control_type: Option<&str>,
         parent_socket: Option<u32>,
         flat: &mut Vec<PowerZone>,
     ) -> anyhow::Result<Vec<PowerZone>> {
@@ -115,7 +325,7 @@ pub fn all_power_zones() -> anyhow::Result<PowerZoneHierarchy> {
             let path = entry.path();
             let file_name = path.file_name().unwrap().to_string_lossy();
 
-            if path.is_dir() && file_name.starts_with(POWER_ZONE_PREFIX) {
+            if path.is_dir() && file_name.starts_with(prefix) {
                 let name_path = path.join("name");
                 let name = fs::read_to_string(&name_path)?.trim().to_owned();
                 let socket_id = {
@@ -131,13 +341,14 @@ pub fn all_power_zones() -> anyhow::Result<PowerZoneHierarchy> {
                     }
                 };
                 let domain = parse_zone_name(&name).with_context(|| format!("Unknown RAPL powercap zone {name}"))?;
-                let children = explore_rec(&path, socket_id, flat)?; // recursively explore
+                let children = explore_rec(&path, prefix, control_type, socket_id, flat)?; // recursively explore
                 let zone = PowerZone {
                     name,
                     domain,
                     path,
                     children,
                     socket_id,
+                    control_type: control_type.map(str::to_owned),
                 };
                 zones.push(zone.clone());
                 flat.push(zone);
@@ -147,123 +358,939 @@ pub fn all_power_zones() -> anyhow::Result<PowerZoneHierarchy> {
         Ok(zones)
     }
     let mut flat = Vec::new();
-    let top = explore_rec(Path::new(POWERCAP_RAPL_PATH), None, &mut flat)
-        .with_context(|| format!("Could not explore {POWERCAP_RAPL_PATH}. {PERMISSION_ADVICE}"))?;
+    let control_type = record_control_type.then(|| control_type_name(root));
+    let top = explore_rec(root, &zone_prefix(root), control_type.as_deref(), None, &mut flat)
+        .with_context(|| format!("Could not explore {}. {PERMISSION_ADVICE}", root.display()))?;
     Ok(PowerZoneHierarchy { flat, top })
 }
 
+/// The powercap control types in which the RAPL power zones are discovered.
+///
+/// Most machines provide the RAPL zones in a single control type, but some Intel CPUs provide them in
+/// `intel-rapl` (read through the MSRs) and in `intel-rapl-mmio` (read through memory-mapped registers).
+/// The two control types can overlap: on these CPUs, the package zone is usually available in both,
+/// and both zones measure the same energy. See [`ControlTypes::power_zones`] for how to avoid double-counting.
+#[derive(Debug, Clone)]
+pub struct ControlTypes {
+    /// Directories of the control types, by order of preference.
+    pub paths: Vec<PathBuf>,
+    /// Whether to ignore the zones that overlap with a zone of a previous control type.
+    pub deduplicate: bool,
+    /// Whether the zones record their control type, which their measurements report in the `control_type` attribute.
+    pub record_control_type: bool,
+}
+
+impl ControlTypes {
+    /// Discovers the zones in a single control type.
+    pub fn single(path: PathBuf) -> Self {
+        Self {
+            paths: vec![path],
+            deduplicate: true,
+            record_control_type: false,
+        }
+    }
+
+    /// Discovers the power zones of all the control types, and merges them in one hierarchy.
+    ///
+    /// A zone overlaps with another if they have the same domain and socket. If `deduplicate` is true,
+    /// the overlapping zones are ignored, with their sub-zones, so that only the zone of the first control type
+    /// is measured. Otherwise, they are kept with a warning, because their energy is counted twice in the total.
+    ///
+    /// The control types that do not exist are skipped, but at least one of them must exist.
+    pub fn power_zones(&self) -> anyhow::Result<PowerZoneHierarchy> {
+        if let [path] = self.paths.as_slice() {
+            return power_zones_at(path, self.record_control_type);
+        }
+        let mut merged: Option<PowerZoneHierarchy> = None;
+        for path in &self.paths {
+            if !path.is_dir() {
+                log::debug!("Skipping {}, the control type does not exist.", path.display());
+                continue;
+            }
+            let zones = power_zones_at(path, self.record_control_type)?;
+            merged = Some(match merged {
+                Some(merged) => merge_zones(merged, zones, self.deduplicate),
+                None => zones,
+            });
+        }
+        merged.with_context(|| format!("none of the powercap control types exists: {self}"))
+    }
+}
+
+impl Display for ControlTypes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let paths: Vec<String> = self.paths.iter().map(|p| p.display().to_string()).collect();
+        write!(f, "{}", paths.join(" "))
+    }
+}
+
+/// Adds the power zones of another control type to `merged`, see [`ControlTypes::power_zones`].
+fn merge_zones(mut merged: PowerZoneHierarchy, mut zones: PowerZoneHierarchy, deduplicate: bool) -> PowerZoneHierarchy {
+    let overlapping: Vec<PathBuf> = zones
+        .flat
+        .iter()
+        .filter(|z| {
+            let same_zone = |m: &PowerZone| m.domain == z.domain && m.socket_id == z.socket_id;
+            merged.flat.iter().any(same_zone)
+        })
+        .map(|z| z.path.clone())
+        .collect();
+    for path in &overlapping {
+        if deduplicate {
+            log::info!(
+                "RAPL power zone {} overlaps with a zone of another control type, it will be ignored.",
+                path.display()
+            );
+        } else {
+            log::warn!(
+                "RAPL power zone {} overlaps with a zone of another control type, its energy is counted twice in the total. Set deduplicate_zones = true to ignore it.",
+                path.display()
+            );
+        }
+    }
+    if deduplicate && !overlapping.is_empty() {
+        remove_zones(&mut zones.flat, &overlapping);
+        remove_zones(&mut zones.top, &overlapping);
+    }
+    merged.flat.extend(zones.flat);
+    merged.top.extend(zones.top);
+    merged
+}
+
+/// Returns the paths of the disabled zones.
+///
+/// A zone whose state cannot be read is considered enabled, because it can still be measured.
+fn disabled_zones(zones: &[PowerZone]) -> Vec<PathBuf> {
+    let mut disabled = Vec::new();
+    for zone in zones {
+        match zone.is_enabled() {
+            Ok(true) => (),
+            Ok(false) => disabled.push(zone.path.clone()),
+            Err(e) => log::debug!("Could not check whether {} is enabled: {e:#}", zone.path.display()),
+        }
+    }
+    disabled
+}
+
+/// Removes the zones whose path is in `removed`, and their sub-zones, from `zones`.
+fn remove_zones(zones: &mut Vec<PowerZone>, removed: &[PathBuf]) {
+    zones.retain(|z| !removed.iter().any(|path| z.path.starts_with(path)));
+    for zone in zones {
+        remove_zones(&mut zone.children, removed);
+    }
+}
+
+/// Checks that the zones are enabled, because the energy counter of a disabled zone may be stale.
+///
+/// If `skip_disabled` is true, the disabled zones and their sub-zones are removed from the hierarchy.
+/// Otherwise, they are kept, with a warning.
+pub fn check_enabled_zones(mut zones: PowerZoneHierarchy, skip_disabled: bool) -> PowerZoneHierarchy {
+    let disabled = disabled_zones(&zones.flat);
+    for path in &disabled {
+        if skip_disabled {
+            log::warn!("RAPL power zone {} is disabled, it will be ignored.", path.display());
+        } else {
+            log::warn!(
+                "RAPL power zone {} is disabled, its energy counter may be stale. Set skip_disabled_zones = true to ignore it.",
+                path.display()
+            );
+        }
+    }
+    if skip_disabled && !disabled.is_empty() {
+        remove_zones(&mut zones.flat, &disabled);
+        remove_zones(&mut zones.top, &disabled);
+    }
+    zones
+}
+
 /// Powercap probe
 pub struct PowercapProbe {
-    metric: TypedMetricId<f64>,
+    metrics: Metrics,
 
     /// Ready-to-use powercap zones with additional metadata
     zones: Vec<OpenedZone>,
+
+    /// Whether to read the zones with [`read_positional`], or only with [`read_sequential`].
+    positional_reads: bool,
+
+    /// Domains that are not counted in the total (kept to open new zones).
+    total_excluded_domains: Vec<RaplDomainType>,
+
+    /// How to report the small decreases of the counters (kept to open new zones).
+    negative_delta: NegativeDeltaPolicy,
+
+    /// Calibration factors of the domains (kept to open new zones).
+    calibration: Calibration,
+
+    /// Periodic discovery of the power zones, if enabled.
+    rescan: Option<ZoneRescan>,
+
+    /// Check of the overlap between psys and the packages, until it is over.
+    psys_check: Option<PsysOverlapCheck>,
+
+    /// Where to save the state of the counters when the probe stops, if enabled.
+    counter_store: Option<CounterStore>,
+}
+
+/// Settings and state of the periodic re-discovery of the power zones.
+struct ZoneRescan {
+    /// Control types in which the zones are discovered.
+    control_types: ControlTypes,
+    /// Only the zones of these domains are opened, so that the rescan does not bypass
+    /// the consistency checks that have been made before creating the probe.
+    domains: Vec<RaplDomainType>,
+    /// Whether the disabled zones are ignored, see [`check_enabled_zones`].
+    skip_disabled: bool,
+    interval: Duration,
+    last_scan: Instant,
 }
 
 struct OpenedZone {
+    /// Path of the zone in sysfs, which identifies it.
+    path: PathBuf,
     file: File,
-    domain: RaplDomainType,
-    /// The corresponding ResourceId
-    resource: Resource,
-    /// Overflow-correcting counter, to compute the energy consumption difference.
-    counter: CounterDiff,
+    /// Content of `energy_uj`, as read by the last poll.
+    buf: Vec<u8>,
+    /// Energy counter of the zone's domain.
+    counter: EnergyCounter,
 }
 
 impl PowercapProbe {
-    pub fn new(metric: TypedMetricId<f64>, zones: &[PowerZone]) -> anyhow::Result<PowercapProbe> {
+    pub fn new(
+        metrics: Metrics,
+        zones: &[PowerZone],
+        total_excluded_domains: &[RaplDomainType],
+    ) -> anyhow::Result<PowercapProbe> {
         if zones.is_empty() {
             return Err(anyhow!("At least one power zone is required for PowercapProbe"))?;
         }
 
         let mut opened = Vec::with_capacity(zones.len());
         for zone in zones {
-            let file = File::open(zone.energy_path()).with_context(|| {
-                format!(
-                    "Could not open {}. {PERMISSION_ADVICE}",
-                    zone.energy_path().to_string_lossy()
-                )
-            })?;
-
-            let str_max_energy_uj = fs::read_to_string(zone.max_energy_path()).with_context(|| {
-                format!(
-                    "Could not read {}. {PERMISSION_ADVICE}",
-                    zone.max_energy_path().to_string_lossy()
-                )
-            })?;
-
-            let max_energy_uj = str_max_energy_uj
-                .trim_end()
-                .parse()
-                .with_context(|| format!("parse max_energy_uj: '{str_max_energy_uj}'"))?;
+            opened.push(OpenedZone::open(zone, total_excluded_domains)?);
+        }
 
-            let socket = zone.socket_id.unwrap_or(0); // put psys in socket 0
+        Ok(PowercapProbe {
+            metrics,
+            zones: opened,
+            positional_reads: true,
+            total_excluded_domains: total_excluded_domains.to_vec(),
+            negative_delta: NegativeDeltaPolicy::default(),
+            calibration: Calibration::default(),
+            rescan: None,
+            psys_check: None,
+            counter_store: None,
+        })
+    }
 
-            let counter = CounterDiff::with_max_value(max_energy_uj);
-            let opened_zone = OpenedZone {
-                file,
-                domain: zone.domain,
-                resource: zone.domain.to_resource(socket),
-                counter,
-            };
-            opened.push(opened_zone);
+    /// Sets how to report the small decreases of the counters.
+    pub fn with_negative_delta(mut self, policy: NegativeDeltaPolicy) -> Self {
+        for zone in &mut self.zones {
+            zone.counter.negative_delta = policy;
+        }
+        self.negative_delta = policy;
+        self
+    }
+
+    /// Sets the calibration factors of the domains.
+    pub fn with_calibration(mut self, calibration: &Calibration) -> Self {
+        for zone in &mut self.zones {
+            zone.counter.calibration = calibration.factor(zone.counter.domain);
+        }
+        self.calibration = calibration.clone();
+        self
+    }
+
+    /// Checks, during the first polls, whether psys overlaps with the packages (see [`PsysOverlapCheck`]).
+    pub fn with_psys_overlap_check(mut self) -> Self {
+        self.psys_check = Some(PsysOverlapCheck::new());
+        self
+    }
+
+    /// Restores the counters saved in the store by a previous run, and saves them again when the probe stops.
+    ///
+    /// A counter is identified by the path of its zone. See [`crate::counter_state`] for the conditions
+    /// under which a saved state is restored.
+    pub fn with_counter_store(mut self, store: CounterStore) -> Self {
+        let saved = store.load();
+        let mut restored = 0;
+        for zone in &mut self.zones {
+            if let Some(state) = saved.get(&zone.path.display().to_string()) {
+                if zone.counter.restore(*state) {
+                    restored += 1;
+                }
+            }
+        }
+        if !saved.is_empty() {
+            log::info!("Restored the state of {restored}/{} RAPL counter(s).", self.zones.len());
+        }
+        self.counter_store = Some(store);
+        self
+    }
+
+    /// Enables the periodic discovery of the power zones in `control_types`.
+    ///
+    /// Every `interval`, the zones are listed again: the zones that have disappeared
+    /// (for instance because the kernel module has been unloaded) are closed, and the new zones are opened.
+    /// If `skip_disabled` is true, the zones that are disabled are closed as well.
+    pub fn with_rescan(mut self, control_types: ControlTypes, interval: Duration, skip_disabled: bool) -> Self {
+        let mut domains: Vec<RaplDomainType> = self.zones.iter().map(|z| z.counter.domain).collect();
+        domains.sort_unstable();
+        domains.dedup();
+        self.rescan = Some(ZoneRescan {
+            control_types,
+            domains,
+            skip_disabled,
+            interval,
+            last_scan: Instant::now(),
+        });
+        self
+    }
+
+    /// Lists the power zones again and updates the opened zones accordingly.
+    fn rescan_zones(&mut self) {
+        let Some(rescan) = &mut self.rescan else {
+            return;
+        };
+        if rescan.last_scan.elapsed() < rescan.interval {
+            return;
+        }
+        rescan.last_scan = Instant::now();
+
+        let mut discovered: Vec<PowerZone> = match rescan.control_types.power_zones() {
+            Ok(zones) => zones.flat.into_iter().filter(|z| rescan.domains.contains(&z.domain)).collect(),
+            Err(e) => {
+                log::warn!("Could not rescan the RAPL power zones, keeping the current ones: {e:#}");
+                return;
+            }
+        };
+        if rescan.skip_disabled {
+            let disabled = disabled_zones(&discovered);
+            remove_zones(&mut discovered, &disabled);
+        }
+        let opened_paths: Vec<&Path> = self.zones.iter().map(|z| z.path.as_path()).collect();
+        let (removed, added) = diff_zones(&opened_paths, &discovered);
+
+        for i in removed.into_iter().rev() {
+            // dropping the zone closes the file and forgets its counter
+            let zone = self.zones.remove(i);
+            log::info!("RAPL power zone {} has disappeared, it will no longer be measured.", zone.path.display());
+        }
+        for zone in added {
+            match OpenedZone::open(zone, &self.total_excluded_domains) {
+                Ok(mut opened) => {
+                    opened.counter.negative_delta = self.negative_delta;
+                    opened.counter.calibration = self.calibration.factor(opened.counter.domain);
+                    log::info!("New RAPL power zone found: {} ({})", zone.name, zone.path.display());
+                    self.zones.push(opened);
+                }
+                Err(e) => log::warn!("Could not open the new RAPL power zone {}: {e:#}", zone.path.display()),
+            }
         }
+    }
+}
+
+impl OpenedZone {
+    fn open(zone: &PowerZone, total_excluded_domains: &[RaplDomainType]) -> anyhow::Result<OpenedZone> {
+        let file = File::open(zone.energy_path()).with_context(|| {
+            format!(
+                "Could not open {}. {PERMISSION_ADVICE}",
+                zone.energy_path().to_string_lossy()
+            )
+        })?;
+
+        let str_max_energy_uj = fs::read_to_string(zone.max_energy_path()).with_context(|| {
+            format!(
+                "Could not read {}. {PERMISSION_ADVICE}",
+                zone.max_energy_path().to_string_lossy()
+            )
+        })?;
+
+        let max_energy_uj = str_max_energy_uj
+            .trim_end()
+            .parse()
+            .with_context(|| format!("parse max_energy_uj: '{str_max_energy_uj}'"))?;
 
-        Ok(PowercapProbe { metric, zones: opened })
+        // The resource of a zone depends on its socket, except for psys, which covers the whole machine.
+        // The sub-zones of a package inherit its socket: two `core` zones in different packages
+        // are different resources. A sub-zone without a socket would be mistaken for a zone of socket 0.
+        let socket = match (zone.socket_id, zone.domain) {
+            (Some(id), _) => id,
+            (None, RaplDomainType::Platform) => 0,
+            (None, domain) => {
+                return Err(anyhow!(
+                    "The RAPL power zone {} ({domain}) does not belong to any package",
+                    zone.path.display()
+                ))
+            }
+        };
+
+        let mut counter = EnergyCounter::new(
+            zone.domain,
+            socket,
+            max_energy_uj,
+            POWERCAP_ENERGY_UNIT,
+            total_excluded_domains,
+        );
+        counter.control_type = zone.control_type.clone();
+        Ok(OpenedZone {
+            path: zone.path.clone(),
+            file,
+            // The size of the content of the file `energy_uj` should never exceed those of `max_energy_uj`,
+            // which is 16 bytes on all our test machines (if it does exceed 16 bytes it's fine, but less optimal).
+            buf: Vec::with_capacity(16),
+            counter,
+        })
+    }
+
+    /// Parses the counter value that has been read by [`read_zones`].
+    fn parse_counter(&self) -> anyhow::Result<u64> {
+        let content = std::str::from_utf8(&self.buf)?;
+        content
+            .trim_end()
+            .parse()
+            .with_context(|| format!("failed to parse {:?}: '{content}'", self.file))
     }
 }
 
+/// Compares the opened zones with the discovered ones.
+///
+/// Returns the indices of the opened zones that have not been discovered (in ascending order),
+/// and the discovered zones that are not opened.
+fn diff_zones<'a>(opened: &[&Path], discovered: &'a [PowerZone]) -> (Vec<usize>, Vec<&'a PowerZone>) {
+    let removed = (0..opened.len())
+        .filter(|i| !discovered.iter().any(|z| z.path == opened[*i]))
+        .collect();
+    let added = discovered
+        .iter()
+        .filter(|z| !opened.contains(&z.path.as_path()))
+        .collect();
+    (removed, added)
+}
+
+/// Reads the content of `file` with a single positional read (`pread`) at offset 0.
+///
+/// Compared to [`read_sequential`], this saves a `lseek` and the final `read` that detects
+/// the end of the file: one syscall per zone instead of three.
+///
+/// Returns `false` if the content may have been truncated, in which case the caller should
+/// use [`read_sequential`] instead.
+fn read_positional(file: &File, buf: &mut Vec<u8>) -> io::Result<bool> {
+    buf.resize(ENERGY_READ_BUF_SIZE, 0);
+    let n = file.read_at(buf, 0)?;
+    buf.truncate(n);
+    Ok(n < ENERGY_READ_BUF_SIZE)
+}
+
+/// Reads the `energy_uj` file of every zone, in a tight loop.
+///
+/// Parsing is done afterwards, so that the zones are read as close together as possible:
+/// this reduces the skew between sibling zones (e.g. a package and its subzones), whose
+/// values are summed or compared with each other.
+///
+/// If a positional read fails, `positional_reads` is set to false and sequential reads are used instead.
+fn read_zones(zones: &mut [OpenedZone], positional_reads: &mut bool) -> anyhow::Result<()> {
+    for zone in zones {
+        // read the file, with one syscall if possible
+        let complete = *positional_reads
+            && match read_positional(&zone.file, &mut zone.buf) {
+                Ok(complete) => complete,
+                Err(e) => {
+                    log::warn!(
+                        "Positional read of {:?} failed, falling back to sequential reads: {e}",
+                        zone.file
+                    );
+                    *positional_reads = false;
+                    false
+                }
+            };
+        if !complete {
+            read_sequential(&mut zone.file, &mut zone.buf).with_context(|| format!("failed to read {:?}", zone.file))?;
+        }
+    }
+    Ok(())
+}
+
+/// Reads the entire content of `file`, from the beginning.
+fn read_sequential(file: &mut File, buf: &mut Vec<u8>) -> io::Result<()> {
+    buf.clear();
+    file.rewind()?;
+    file.read_to_end(buf)?;
+    Ok(())
+}
+
 impl alumet::pipeline::Source for PowercapProbe {
     fn poll(
         &mut self,
         measurements: &mut MeasurementAccumulator,
         timestamp: Timestamp,
     ) -> Result<(), alumet::pipeline::PollError> {
-        // Reuse the same buffer for all the zones.
-        // The size of the content of the file `energy_uj` should never exceed those of `max_energy_uj`,
-        // which is 16 bytes on all our test machines (if it does exceed 16 bytes it's fine, but less optimal).
-        let mut zone_reading_buf = Vec::with_capacity(16);
+        self.rescan_zones();
+
+        // read all the zones first, then parse them, to minimize the skew between the zones
+        read_zones(&mut self.zones, &mut self.positional_reads)?;
 
+        let mut energy = EnergyMeasurements::new(&self.metrics, timestamp, measurements);
         for zone in &mut self.zones {
-            // read the file from the beginning
-            zone.file
-                .rewind()
-                .with_context(|| format!("failed to rewind {:?}", zone.file))?;
-            zone.file
-                .read_to_end(&mut zone_reading_buf)
-                .with_context(|| format!("failed to read {:?}", zone.file))?;
-
-            // parse the content of the file
-            let content = std::str::from_utf8(&zone_reading_buf)?;
-            let counter_value: u64 = content
-                .trim_end()
-                .parse()
-                .with_context(|| format!("failed to parse {:?}: '{content}'", zone.file))?;
+            let counter_value = zone.parse_counter()?;
 
             // store the value, handle the overflow if there is one
-            let diff = match zone.counter.update(counter_value) {
-                CounterDiffUpdate::FirstTime => None,
-                CounterDiffUpdate::Difference(diff) => Some(diff),
-                CounterDiffUpdate::CorrectedDifference(diff) => Some(diff),
-            };
-            if let Some(value) = diff {
-                let joules = (value as f64) * POWERCAP_ENERGY_UNIT;
-                let consumer = ResourceConsumer::LocalMachine;
-                measurements.push(
-                    MeasurementPoint::new(timestamp, self.metric, zone.resource.clone(), consumer, joules)
-                        .with_attr("domain", AttributeValue::String(zone.domain.to_string())),
-                )
-            };
+            energy.update(&mut zone.counter, counter_value);
+        }
+        let psys_sample = energy.finish();
+        if let Some(check) = &mut self.psys_check {
+            if check.record(psys_sample) {
+                self.psys_check = None;
+            }
+        }
 
-            // clear the buffer, so that we can fill it again
-            zone_reading_buf.clear();
+        // expose the number of corrections, to detect misbehaving counters or a too slow polling
+        for zone in &self.zones {
+            let mut point = MeasurementPoint::new(
+                timestamp,
+                self.metrics.overflow_corrections,
+                zone.counter.resource.clone(),
+                ResourceConsumer::LocalMachine,
+                zone.counter.overflow_corrections,
+            )
+            .with_attr("domain", zone.counter.domain.as_str());
+            if let Some(control_type) = &zone.counter.control_type {
+                point = point.with_attr("control_type", control_type.clone());
+            }
+            measurements.push(point);
+        }
+        Ok(())
+    }
+
+    fn stop(&mut self) -> anyhow::Result<()> {
+        // close the sysfs files now, instead of waiting for the probe to be dropped
+        log::debug!("Closing {} powercap zone(s)", self.zones.len());
+        if let Some(store) = self.counter_store.take() {
+            let counters = self
+                .zones
+                .iter()
+                .map(|zone| SavedCounter {
+                    id: zone.path.display().to_string(),
+                    state: zone.counter.counter_state(),
+                })
+                .collect();
+            if let Err(e) = store.save(counters) {
+                log::warn!("Could not save the state of the RAPL counters: {e:#}");
+            }
         }
+        self.zones.clear();
+        self.rescan = None;
         Ok(())
     }
+
+    fn declared_metrics(&self) -> Vec<RawMetricId> {
+        let mut metrics = self.metrics.energy_metrics();
+        metrics.push(self.metrics.overflow_corrections.untyped_id());
+        metrics
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::all_power_zones;
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+    };
+
+    use alumet::resources::Resource;
+
+    use crate::{cpus::CpuVendor, domains::RaplDomainType};
+
+    use super::{
+        all_power_zones, all_power_zones_at, check_enabled_zones, diff_zones, find_control_type, parse_zone_name,
+        read_positional, read_sequential, read_zones, ControlTypes, OpenedZone, PowerConstraint, PowerZone,
+        ENERGY_READ_BUF_SIZE,
+    };
+
+    /// Fixture of a machine with two sockets, each with a `core` and `dram` subzone, and a `psys` zone.
+    fn fixture_2sockets() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("test-data/powercap-2sockets/intel-rapl")
+    }
+
+    #[test]
+    fn test_parse_zone_name() {
+        assert_eq!(parse_zone_name("package-0"), Some(RaplDomainType::Package));
+        assert_eq!(parse_zone_name("package-12"), Some(RaplDomainType::Package));
+        assert_eq!(parse_zone_name("core"), Some(RaplDomainType::PP0));
+        assert_eq!(parse_zone_name("uncore"), Some(RaplDomainType::PP1));
+        assert_eq!(parse_zone_name("dram"), Some(RaplDomainType::Dram));
+        assert_eq!(parse_zone_name("psys"), Some(RaplDomainType::Platform));
+        assert_eq!(parse_zone_name("package"), None);
+        assert_eq!(parse_zone_name("gpu"), None);
+    }
+
+    #[test]
+    fn test_fixture_2sockets() {
+        let zones = all_power_zones_at(&fixture_2sockets()).expect("failed to explore the fixture");
+
+        // tree
+        let top: Vec<(&str, Option<u32>)> = zones.top.iter().map(|z| (z.name.as_str(), z.socket_id)).collect();
+        assert_eq!(top, vec![("package-0", Some(0)), ("package-1", Some(1)), ("psys", None)]);
+        for (socket, pkg) in zones.top[..2].iter().enumerate() {
+            let children: Vec<(RaplDomainType, Option<u32>)> =
+                pkg.children.iter().map(|z| (z.domain, z.socket_id)).collect();
+            let socket = Some(socket as u32);
+            assert_eq!(children, vec![(RaplDomainType::PP0, socket), (RaplDomainType::Dram, socket)]);
+        }
+        assert!(zones.top[2].children.is_empty());
+
+        // the flat list must contain exactly the zones of the tree
+        fn collect_paths(zones: &[PowerZone], res: &mut Vec<PathBuf>) {
+            for z in zones {
+                res.push(z.path.clone());
+                collect_paths(&z.children, res);
+            }
+        }
+        let mut tree_paths = Vec::new();
+        collect_paths(&zones.top, &mut tree_paths);
+        tree_paths.sort();
+        let mut flat_paths: Vec<PathBuf> = zones.flat.iter().map(|z| z.path.clone()).collect();
+        flat_paths.sort();
+        assert_eq!(flat_paths.len(), 7);
+        assert_eq!(tree_paths, flat_paths);
+    }
+
+    #[test]
+    fn test_subzones_of_each_socket_are_distinct() {
+        let zones = all_power_zones_at(&fixture_2sockets()).unwrap();
+        let opened: Vec<OpenedZone> = zones.flat.iter().map(|z| OpenedZone::open(z, &[]).unwrap()).collect();
+
+        // both sockets have a zone named "core", each one is measured as a different resource
+        let cores: Vec<Resource> = opened
+            .iter()
+            .filter(|z| z.counter.domain == RaplDomainType::PP0)
+            .map(|z| z.counter.resource.clone())
+            .collect();
+        assert_eq!(cores.len(), 2);
+        assert!(cores.contains(&Resource::CpuPackage { id: 0 }));
+        assert!(cores.contains(&Resource::CpuPackage { id: 1 }));
+
+        // the (resource, domain) pair identifies each zone
+        for (i, a) in opened.iter().enumerate() {
+            for b in &opened[i + 1..] {
+                assert!(
+                    a.counter.resource != b.counter.resource || a.counter.domain != b.counter.domain,
+                    "{} and {} have the same identity",
+                    a.path.display(),
+                    b.path.display()
+                );
+            }
+        }
+
+        // a sub-zone must belong to a package
+        let mut orphan = zones.flat.iter().find(|z| z.domain == RaplDomainType::PP0).unwrap().clone();
+        orphan.socket_id = None;
+        assert!(OpenedZone::open(&orphan, &[]).is_err());
+    }
+
+    #[test]
+    fn test_positional_reads_match_sequential() {
+        let zones = all_power_zones_at(&fixture_2sockets()).unwrap();
+        let mut positional = Vec::new();
+        let mut sequential = Vec::new();
+        for zone in &zones.flat {
+            let mut file = fs::File::open(zone.energy_path()).unwrap();
+            // read twice, to check that the file offset does not matter
+            for _ in 0..2 {
+                assert!(read_positional(&file, &mut positional).unwrap());
+                read_sequential(&mut file, &mut sequential).unwrap();
+                assert_eq!(positional, sequential, "different content for {}", zone.path.display());
+            }
+        }
+
+        // content that does not fit in the buffer: the positional read is incomplete
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("energy_uj");
+        let content = "1".repeat(ENERGY_READ_BUF_SIZE + 10);
+        fs::write(&path, &content).unwrap();
+        let mut file = fs::File::open(&path).unwrap();
+        assert!(!read_positional(&file, &mut positional).unwrap());
+        read_sequential(&mut file, &mut sequential).unwrap();
+        assert_eq!(sequential, content.as_bytes());
+    }
+
+    #[test]
+    fn test_read_all_then_parse() {
+        let zones = all_power_zones_at(&fixture_2sockets()).unwrap();
+        let mut opened: Vec<OpenedZone> = zones.flat.iter().map(|z| OpenedZone::open(z, &[]).unwrap()).collect();
+
+        // same values as reading and parsing each zone one after another
+        let mut positional = true;
+        read_zones(&mut opened, &mut positional).unwrap();
+        assert!(positional);
+        for zone in &mut opened {
+            let expected: u64 = fs::read_to_string(zone.path.join("energy_uj"))
+                .unwrap()
+                .trim_end()
+                .parse()
+                .unwrap();
+            assert_eq!(zone.parse_counter().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_read_zones_before_parsing() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("intel-rapl");
+        create_zone(&root.join("intel-rapl:0"), "package-0");
+        create_zone(&root.join("intel-rapl:0/intel-rapl:0:0"), "core");
+        create_zone(&root.join("intel-rapl:1"), "package-1");
+        let zones = all_power_zones_at(&root).unwrap();
+        let mut opened: Vec<OpenedZone> = zones.flat.iter().map(|z| OpenedZone::open(z, &[]).unwrap()).collect();
+        let mut positional = true;
+
+        // All the zones are read before any of them is parsed: the counters that are modified
+        // after read_zones (that is, while the values are being parsed) have no effect on the values.
+        read_zones(&mut opened, &mut positional).unwrap();
+        for zone in &opened {
+            fs::write(zone.path.join("energy_uj"), "654321\n").unwrap();
+        }
+        for zone in &opened {
+            assert_eq!(zone.parse_counter().unwrap(), 123456);
+        }
+
+        // the next read sees the new values
+        read_zones(&mut opened, &mut positional).unwrap();
+        for zone in &opened {
+            assert_eq!(zone.parse_counter().unwrap(), 654321);
+        }
+    }
+
+    /// Creates a power zone directory with a `name` and energy files.
+    fn create_zone(dir: &Path, name: &str) {
+        fs::create_dir_all(dir).unwrap();
+        fs::write(dir.join("name"), format!("{name}\n")).unwrap();
+        fs::write(dir.join("energy_uj"), "123456\n").unwrap();
+        fs::write(dir.join("max_energy_range_uj"), "262143328850\n").unwrap();
+    }
+
+    #[test]
+    fn test_synthetic_powercap() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("intel-rapl");
+        create_zone(&root.join("intel-rapl:0"), "package-0");
+        create_zone(&root.join("intel-rapl:0/intel-rapl:0:0"), "core");
+        create_zone(&root.join("intel-rapl:1"), "psys");
+        // other files in the control type must be ignored
+        fs::write(root.join("enabled"), "1\n").unwrap();
+
+        let zones = all_power_zones_at(&root).expect("failed to explore the synthetic power zones");
+        assert_eq!(zones.top.len(), 2);
+        assert_eq!(zones.flat.len(), 3);
+
+        let pkg = &zones.top[0];
+        assert_eq!(pkg.name, "package-0");
+        assert_eq!(pkg.domain, RaplDomainType::Package);
+        assert_eq!(pkg.socket_id, Some(0));
+        assert_eq!(pkg.children.len(), 1);
+        assert_eq!(pkg.children[0].domain, RaplDomainType::PP0);
+        assert_eq!(pkg.children[0].socket_id, Some(0));
+
+        let psys = &zones.top[1];
+        assert_eq!(psys.domain, RaplDomainType::Platform);
+        assert_eq!(psys.socket_id, None);
+    }
+
+    #[test]
+    fn test_find_control_type() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().to_path_buf();
+        // a control type without energy counter, and one with RAPL zones but an unusual name
+        fs::create_dir_all(root.join("dtpm")).unwrap();
+        create_zone(&root.join("other-rapl/other-rapl:0"), "package-0");
+
+        // the control types of the vendor do not exist: scan all of them
+        assert_eq!(
+            find_control_type(&root, Some(CpuVendor::Amd)).unwrap(),
+            root.join("other-rapl")
+        );
+        assert_eq!(find_control_type(&root, None).unwrap(), root.join("other-rapl"));
+        let zones = all_power_zones_at(&root.join("other-rapl")).unwrap();
+        assert_eq!(zones.flat.len(), 1);
+
+        // the control type of the vendor is preferred
+        create_zone(&root.join("intel-rapl/intel-rapl:0"), "package-0");
+        assert_eq!(
+            find_control_type(&root, Some(CpuVendor::Intel)).unwrap(),
+            root.join("intel-rapl")
+        );
+
+        assert!(find_control_type(&root.join("dtpm"), None).is_err());
+    }
+
+    #[test]
+    fn test_multiple_control_types() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().to_path_buf();
+        create_zone(&root.join("intel-rapl/intel-rapl:0"), "package-0");
+        create_zone(&root.join("intel-rapl/intel-rapl:0/intel-rapl:0:0"), "core");
+        create_zone(&root.join("intel-rapl/intel-rapl:1"), "psys");
+        // the package is also available through mmio, with a sub-zone that msr does not provide
+        create_zone(&root.join("intel-rapl-mmio/intel-rapl-mmio:0"), "package-0");
+        let mmio = root.join("intel-rapl-mmio");
+        create_zone(&mmio.join("intel-rapl-mmio:0/intel-rapl-mmio:0:0"), "dram");
+        create_zone(&mmio.join("intel-rapl-mmio:1"), "package-1");
+        let paths = vec![root.join("intel-rapl"), mmio.clone(), root.join("missing")];
+
+        // the zone of the first control type is kept, the overlapping one is removed with its sub-zone
+        let mut control_types = ControlTypes::single(root.join("intel-rapl"));
+        control_types.paths = paths;
+        control_types.record_control_type = true;
+        let zones = control_types.power_zones().unwrap();
+        let zone_and_type = |z: &PowerZone| (z.name.clone(), z.control_type.clone());
+        let flat: Vec<(String, Option<String>)> = zones.flat.iter().map(zone_and_type).collect();
+        assert_eq!(flat.len(), 4);
+        let has = |name: &str, control_type: &str| flat.contains(&(name.to_owned(), Some(control_type.to_owned())));
+        assert!(has("package-0", "intel-rapl"));
+        assert!(has("core", "intel-rapl"));
+        assert!(has("psys", "intel-rapl"));
+        assert!(has("package-1", "intel-rapl-mmio"));
+        let top: Vec<&str> = zones.top.iter().map(|z| z.name.as_str()).collect();
+        assert_eq!(top, vec!["package-0", "psys", "package-1"]);
+
+        // without deduplication, both packages are kept
+        control_types.deduplicate = false;
+        let zones = control_types.power_zones().unwrap();
+        assert_eq!(zones.flat.len(), 6);
+        assert_eq!(zones.top.len(), 4);
+        let mmio_package = zones.top.iter().find(|z| z.path == mmio.join("intel-rapl-mmio:0"));
+        assert_eq!(mmio_package.unwrap().control_type.as_deref(), Some("intel-rapl-mmio"));
+
+        // the counters report their control type
+        let opened = OpenedZone::open(&zones.top[0], &[]).unwrap();
+        assert_eq!(opened.counter.control_type.as_deref(), Some("intel-rapl"));
+
+        // the control type is not recorded when the zones are discovered in a single, detected control type
+        let zones = ControlTypes::single(mmio.clone()).power_zones().unwrap();
+        assert!(zones.flat.iter().all(|z| z.control_type.is_none()));
+        let opened = OpenedZone::open(&zones.top[0], &[]).unwrap();
+        assert_eq!(opened.counter.control_type, None);
+
+        // at least one control type must exist
+        let control_types = ControlTypes {
+            paths: vec![root.join("missing"), root.join("other")],
+            deduplicate: true,
+            record_control_type: true,
+        };
+        assert!(control_types.power_zones().is_err());
+        assert!(ControlTypes::single(root.join("missing")).power_zones().is_err());
+    }
+
+    #[test]
+    fn test_constraints() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("intel-rapl");
+        let dir = root.join("intel-rapl:0");
+        create_zone(&dir, "package-0");
+        let write = |file: &str, content: &str| fs::write(dir.join(file), content).unwrap();
+        write("constraint_0_name", "long_term\n");
+        write("constraint_0_power_limit_uw", "65000000\n");
+        write("constraint_0_time_window_us", "27983872\n");
+        write("constraint_1_name", "short_term\n");
+        write("constraint_1_power_limit_uw", "90000000\n");
+        // constraint 2 has no power limit, and constraint 3 has no name and no time window
+        write("constraint_2_name", "peak_power\n");
+        write("constraint_3_power_limit_uw", "100000000\n");
+        write("constraint_4_power_limit_uw", "not a number\n");
+
+        let zones = all_power_zones_at(&root).unwrap();
+        let zone = &zones.top[0];
+        assert_eq!(zone.constraint_indices().unwrap(), vec![0, 1, 3, 4]);
+        assert_eq!(zone.constraint(2).unwrap(), None);
+        assert!(zone.constraint(4).is_err());
+        assert_eq!(
+            zone.constraints().unwrap(),
+            vec![
+                PowerConstraint {
+                    index: 0,
+                    name: Some(String::from("long_term")),
+                    power_limit_uw: 65_000_000,
+                    time_window_us: Some(27_983_872),
+                },
+                PowerConstraint {
+                    index: 1,
+                    name: Some(String::from("short_term")),
+                    power_limit_uw: 90_000_000,
+                    time_window_us: None,
+                },
+                PowerConstraint {
+                    index: 3,
+                    name: None,
+                    power_limit_uw: 100_000_000,
+                    time_window_us: None,
+                },
+            ]
+        );
+
+        // a zone without constraints
+        let fixture = all_power_zones_at(&fixture_2sockets()).unwrap();
+        assert!(fixture.top[0].constraints().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_enabled_zones() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("intel-rapl");
+        create_zone(&root.join("intel-rapl:0"), "package-0");
+        create_zone(&root.join("intel-rapl:0/intel-rapl:0:0"), "core");
+        create_zone(&root.join("intel-rapl:1"), "package-1");
+        create_zone(&root.join("intel-rapl:1/intel-rapl:1:0"), "core");
+        create_zone(&root.join("intel-rapl:2"), "psys");
+        fs::write(root.join("intel-rapl:0/enabled"), "1\n").unwrap();
+        fs::write(root.join("intel-rapl:1/enabled"), "0\n").unwrap();
+        // psys has no enabled file
+
+        let zones = all_power_zones_at(&root).unwrap();
+        let enabled: Vec<bool> = zones.top.iter().map(|z| z.is_enabled().unwrap()).collect();
+        assert_eq!(enabled, vec![true, false, true]);
+        assert_eq!(zones.top[1].enabled_path(), root.join("intel-rapl:1/enabled"));
+
+        // by default, the disabled zones are kept
+        let kept = check_enabled_zones(all_power_zones_at(&root).unwrap(), false);
+        assert_eq!(kept.flat.len(), 5);
+        assert_eq!(kept.top.len(), 3);
+
+        // the disabled package is skipped, with its sub-zone
+        let skipped = check_enabled_zones(zones, true);
+        let top: Vec<&str> = skipped.top.iter().map(|z| z.name.as_str()).collect();
+        assert_eq!(top, vec!["package-0", "psys"]);
+        assert_eq!(skipped.top[0].children.len(), 1);
+        assert_eq!(skipped.flat.len(), 3);
+        let disabled = root.join("intel-rapl:1");
+        assert!(skipped.flat.iter().all(|z| !z.path.starts_with(&disabled)));
+
+        // invalid content
+        fs::write(root.join("intel-rapl:0/enabled"), "yes\n").unwrap();
+        assert!(all_power_zones_at(&root).unwrap().top[0].is_enabled().is_err());
+    }
+
+    #[test]
+    fn test_diff_zones() {
+        let zones = all_power_zones_at(&fixture_2sockets()).unwrap().flat;
+        let all: Vec<&Path> = zones.iter().map(|z| z.path.as_path()).collect();
+
+        // nothing changed
+        let (removed, added) = diff_zones(&all, &zones);
+        assert!(removed.is_empty());
+        assert!(added.is_empty());
+
+        // the first and last zones disappear, and come back
+        let remaining = &zones[1..zones.len() - 1];
+        let (removed, added) = diff_zones(&all, remaining);
+        assert_eq!(removed, vec![0, zones.len() - 1]);
+        assert!(added.is_empty());
+
+        let opened: Vec<&Path> = remaining.iter().map(|z| z.path.as_path()).collect();
+        let (removed, added) = diff_zones(&opened, &zones);
+        assert!(removed.is_empty());
+        let added: Vec<&Path> = added.iter().map(|z| z.path.as_path()).collect();
+        assert_eq!(added, vec![all[0], all[all.len() - 1]]);
+    }
 
     #[test]
     fn test_powercap() {
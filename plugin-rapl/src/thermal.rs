@@ -0,0 +1,187 @@
+//! Temperature of the thermal zones, read from `/sys/class/thermal/thermal_zone*`.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use alumet::{
+    measurement::{MeasurementAccumulator, MeasurementPoint, Timestamp},
+    metrics::TypedMetricId,
+    pipeline::{PollError, Source},
+    resources::{Resource, ResourceConsumer},
+};
+use anyhow::Context;
+
+pub const THERMAL_PATH: &str = "/sys/class/thermal";
+
+const TEMPERATURE_UNIT: f64 = 0.001; // 1 milli degree Celsius
+
+/// A thermal zone, as exposed by the Linux kernel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThermalZone {
+    /// Number of the zone, `N` in `thermal_zoneN`.
+    pub id: u32,
+    /// Type of the zone, for instance `x86_pkg_temp` or `acpitz`.
+    pub zone_type: String,
+    /// Path to the `temp` file of the zone.
+    pub temp_path: PathBuf,
+}
+
+/// Measures the temperature of the thermal zones.
+///
+/// The zones are discovered again at each poll, because some of them can appear or disappear
+/// (for instance when a device is plugged or when a kernel module is loaded).
+pub struct ThermalProbe {
+    metric: TypedMetricId<f64>,
+    /// Directory that contains the `thermal_zone*` directories.
+    path: PathBuf,
+    /// Types of the zones to measure. If empty, all the zones are measured.
+    types: Vec<String>,
+    /// The known zones, and whether they match `types`.
+    zones: Vec<(ThermalZone, bool)>,
+}
+
+impl ThermalProbe {
+    pub fn new(metric: TypedMetricId<f64>, path: PathBuf, types: Vec<String>) -> Self {
+        Self {
+            metric,
+            path,
+            types,
+            zones: Vec::new(),
+        }
+    }
+
+    /// Updates the list of known zones, without reading the type of the zones that are already known.
+    fn refresh_zones(&mut self) -> anyhow::Result<()> {
+        let ids = thermal_zone_ids_at(&self.path)?;
+        self.zones.retain(|(zone, _)| {
+            let exists = ids.contains(&zone.id);
+            if !exists {
+                log::info!("Thermal zone {} ({}) has disappeared.", zone.id, zone.zone_type);
+            }
+            exists
+        });
+        for id in ids {
+            if self.zones.iter().all(|(zone, _)| zone.id != id) {
+                match thermal_zone_at(&self.path, id) {
+                    Ok(zone) => {
+                        let selected = self.types.is_empty() || self.types.contains(&zone.zone_type);
+                        log::debug!("New thermal zone {id} ({}), selected: {selected}.", zone.zone_type);
+                        self.zones.push((zone, selected));
+                    }
+                    Err(e) => log::debug!("Ignoring thermal zone {id}: {e:#}"),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Source for ThermalProbe {
+    fn poll(&mut self, measurements: &mut MeasurementAccumulator, timestamp: Timestamp) -> Result<(), PollError> {
+        self.refresh_zones()?;
+        for (zone, _) in self.zones.iter().filter(|(_, selected)| *selected) {
+            // The zone can disappear at any time, and some zones cannot be read (e.g. when the sensor is disabled).
+            let millidegrees = match read_temperature(&zone.temp_path) {
+                Ok(t) => t,
+                Err(e) => {
+                    log::debug!("Failed to read the temperature of thermal zone {}: {e:#}", zone.id);
+                    continue;
+                }
+            };
+            let point = MeasurementPoint::new(
+                timestamp,
+                self.metric,
+                Resource::custom("thermal_zone", zone.id.to_string()),
+                ResourceConsumer::LocalMachine,
+                millidegrees as f64 * TEMPERATURE_UNIT,
+            )
+            .with_attr("zone_type", zone.zone_type.clone());
+            measurements.push(point);
+        }
+        Ok(())
+    }
+}
+
+/// Returns the ids of the thermal zones that exist in the given directory, in ascending order.
+pub fn thermal_zone_ids_at(path: &Path) -> anyhow::Result<Vec<u32>> {
+    let mut ids = Vec::new();
+    let entries = fs::read_dir(path).with_context(|| format!("Could not list {}", path.display()))?;
+    for entry in entries {
+        let file_name = entry?.file_name();
+        if let Some(id) = file_name
+            .to_str()
+            .and_then(|name| name.strip_prefix("thermal_zone"))
+            .and_then(|id| id.parse().ok())
+        {
+            ids.push(id);
+        }
+    }
+    ids.sort_unstable();
+    Ok(ids)
+}
+
+/// Reads the information about the thermal zone `id`.
+pub fn thermal_zone_at(path: &Path, id: u32) -> anyhow::Result<ThermalZone> {
+    let dir = path.join(format!("thermal_zone{id}"));
+    let type_path = dir.join("type");
+    let zone_type =
+        fs::read_to_string(&type_path).with_context(|| format!("Could not read {}", type_path.display()))?;
+    Ok(ThermalZone {
+        id,
+        zone_type: zone_type.trim_end().to_owned(),
+        temp_path: dir.join("temp"),
+    })
+}
+
+/// Reads the temperature in milli degrees Celsius.
+fn read_temperature(path: &Path) -> anyhow::Result<i64> {
+    let content = fs::read_to_string(path).with_context(|| format!("Could not read {}", path.display()))?;
+    content
+        .trim_end()
+        .parse()
+        .with_context(|| format!("Could not parse {}: '{content}'", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use super::{read_temperature, thermal_zone_at, thermal_zone_ids_at, ThermalZone};
+
+    fn create_zone(root: &Path, id: u32, zone_type: &str, temp: &str) {
+        let dir = root.join(format!("thermal_zone{id}"));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("type"), format!("{zone_type}\n")).unwrap();
+        fs::write(dir.join("temp"), format!("{temp}\n")).unwrap();
+    }
+
+    #[test]
+    fn thermal_zones() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().to_path_buf();
+        create_zone(&root, 10, "x86_pkg_temp", "45000");
+        create_zone(&root, 0, "acpitz", "-1500");
+        create_zone(&root, 2, "iwlwifi_1", "not a number");
+        // cooling devices must be ignored
+        fs::create_dir_all(root.join("cooling_device0")).unwrap();
+
+        assert_eq!(thermal_zone_ids_at(&root).unwrap(), vec![0, 2, 10]);
+        let zone = thermal_zone_at(&root, 10).unwrap();
+        assert_eq!(
+            zone,
+            ThermalZone {
+                id: 10,
+                zone_type: String::from("x86_pkg_temp"),
+                temp_path: root.join("thermal_zone10/temp"),
+            }
+        );
+        assert_eq!(read_temperature(&zone.temp_path).unwrap(), 45000);
+        let acpi = thermal_zone_at(&root, 0).unwrap();
+        assert_eq!(read_temperature(&acpi.temp_path).unwrap(), -1500);
+        let wifi = thermal_zone_at(&root, 2).unwrap();
+        assert!(read_temperature(&wifi.temp_path).is_err());
+        assert!(thermal_zone_at(&root, 1).is_err());
+    }
+}
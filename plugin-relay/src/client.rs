@@ -63,6 +63,11 @@ impl AlumetPlugin for RelayClientPlugin {
         env!("CARGO_PKG_VERSION")
     }
 
+    fn log_target() -> &'static str {
+        // the client and the server are in the same crate
+        module_path!()
+    }
+
     fn default_config() -> anyhow::Result<Option<ConfigTable>> {
         Ok(Some(serialize_config(Config::default())?))
     }
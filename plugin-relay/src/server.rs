@@ -62,6 +62,11 @@ impl AlumetPlugin for RelayServerPlugin {
         env!("CARGO_PKG_VERSION")
     }
 
+    fn log_target() -> &'static str {
+        // the client and the server are in the same crate
+        module_path!()
+    }
+
     fn default_config() -> anyhow::Result<Option<ConfigTable>> {
         let config = serialize_config(Config::default())?;
         Ok(Some(config))
@@ -0,0 +1,70 @@
+use std::{io, path::Path};
+
+use alumet::plugin::command::PluginCommand;
+use anyhow::anyhow;
+
+use crate::ring;
+
+/// Prints the measurements of a ring buffer, from the oldest to the newest.
+pub struct DumpCommand;
+
+impl PluginCommand for DumpCommand {
+    fn name(&self) -> &str {
+        "dump"
+    }
+
+    fn about(&self) -> &str {
+        "Prints the measurements stored in a ring buffer, oldest first, as JSON lines"
+    }
+
+    fn run(&self, args: &[String], out: &mut dyn io::Write) -> anyhow::Result<()> {
+        let dir = match args {
+            [dir] => Path::new(dir),
+            _ => return Err(anyhow!("invalid arguments {args:?}, usage: dump <directory>")),
+        };
+        for path in ring::ring_segments(dir)? {
+            ring::scan_segment(&path, |line| {
+                writeln!(out, "{line}")?;
+                Ok(())
+            })?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use alumet::plugin::command::PluginCommand;
+
+    use super::DumpCommand;
+    use crate::ring::{RingSettings, RingWriter};
+
+    #[test]
+    fn dump() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_path_buf();
+        let mut ring = RingWriter::open(RingSettings {
+            dir: dir.clone(),
+            segments: 2,
+            max_segment_size: 1024,
+            max_segment_duration: Duration::from_nanos(10),
+            sync: false,
+        })
+        .unwrap();
+        for t in [0, 10, 20, 30] {
+            ring.append(t, format!(r#"{{"timestamp":{t}}}"#).as_bytes()).unwrap();
+            ring.end_batch().unwrap();
+        }
+        ring.close().unwrap();
+
+        let mut out = Vec::new();
+        DumpCommand.run(&[dir.display().to_string()], &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"timestamp\":20}\n{\"timestamp\":30}\n"
+        );
+        assert!(DumpCommand.run(&[], &mut Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,116 @@
+mod commands;
+mod output;
+pub mod ring;
+
+use std::{path::PathBuf, time::Duration};
+
+use alumet::plugin::{
+    command::PluginCommand,
+    rust::{deserialize_config, serialize_config, AlumetPlugin, InvalidConfig},
+    util::{AttributeAllowlist, MetricFilter},
+    ConfigTable,
+};
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
+
+use output::RingBufferOutput;
+use ring::{RingSettings, RingWriter};
+
+pub struct RingBufferPlugin {
+    config: Option<Config>,
+}
+
+impl AlumetPlugin for RingBufferPlugin {
+    fn name() -> &'static str {
+        "ring-buffer"
+    }
+
+    fn version() -> &'static str {
+        env!("CARGO_PKG_VERSION")
+    }
+
+    fn default_config() -> anyhow::Result<Option<ConfigTable>> {
+        Ok(Some(serialize_config(Config::default())?))
+    }
+
+    fn init(config: ConfigTable) -> anyhow::Result<Box<Self>> {
+        let config: Config = deserialize_config(config)?;
+        if config.segments < 2 {
+            return Err(anyhow!("segments must be at least 2, got {}", config.segments)).context(InvalidConfig);
+        }
+        Ok(Box::new(RingBufferPlugin { config: Some(config) }))
+    }
+
+    fn start(&mut self, alumet: &mut alumet::plugin::AlumetStart) -> anyhow::Result<()> {
+        let config = self.config.take().unwrap();
+        // each segment holds a part of the measurements, the oldest part is dropped when the ring is full
+        let settings = RingSettings {
+            dir: config.directory,
+            segments: config.segments,
+            max_segment_size: config.max_size / config.segments as u64,
+            max_segment_duration: config.max_duration / config.segments as u32,
+            sync: config.sync,
+        };
+        let ring = RingWriter::open(settings)?;
+        let output = RingBufferOutput::new(ring);
+        let output = AttributeAllowlist::wrap_opt(config.attributes, Box::new(output));
+        alumet.add_output(config.metric_filter.wrap(output));
+        Ok(())
+    }
+
+    fn stop(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn commands() -> Vec<Box<dyn PluginCommand>> {
+        vec![Box::new(commands::DumpCommand)]
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Config {
+    /// Directory of the ring buffer.
+    directory: PathBuf,
+
+    /// How long the measurements are kept, approximately.
+    #[serde(with = "humantime_serde")]
+    max_duration: Duration,
+
+    /// Maximum size of the ring buffer on disk, in bytes.
+    max_size: u64,
+
+    /// Number of files in the ring. When a file is full, the oldest one is overwritten.
+    #[serde(default = "default_segments")]
+    segments: usize,
+
+    /// Syncs the data to the disk after each write. Slower, but nothing is lost if the machine crashes.
+    #[serde(default)]
+    sync: bool,
+
+    /// Only keeps the metrics whose name matches these patterns. By default, all the metrics are kept.
+    #[serde(default, skip_serializing_if = "MetricFilter::is_empty")]
+    metric_filter: MetricFilter,
+
+    /// Only keeps the attributes whose key matches these patterns, an empty list removes all the attributes.
+    /// By default, all the attributes are kept.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    attributes: Option<AttributeAllowlist>,
+}
+
+fn default_segments() -> usize {
+    10
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            directory: PathBuf::from("alumet-ring-buffer"),
+            max_duration: Duration::from_secs(10 * 60),
+            max_size: 64 * 1024 * 1024,
+            segments: default_segments(),
+            sync: false,
+            metric_filter: MetricFilter::default(),
+            attributes: None,
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use alumet::{
+    measurement::MeasurementBuffer,
+    pipeline::{Output, OutputContext, WriteError},
+    plugin::util::JsonPoint,
+};
+use anyhow::Context;
+
+use crate::ring::RingWriter;
+
+/// Keeps the last measurements in a ring of files, see the [`ring`](crate::ring) module.
+///
+/// The index of the ring is written when the output is finalized, that is, when the pipeline stops.
+pub struct RingBufferOutput {
+    ring: RingWriter,
+    /// Whether the index has been written by [`Output::finalize`].
+    finalized: bool,
+}
+
+impl RingBufferOutput {
+    pub fn new(ring: RingWriter) -> Self {
+        Self { ring, finalized: false }
+    }
+}
+
+impl Output for RingBufferOutput {
+    fn write(&mut self, measurements: &MeasurementBuffer, ctx: &OutputContext) -> Result<(), WriteError> {
+        for m in measurements {
+            let metric = ctx
+                .metrics
+                .with_id(&m.metric)
+                .with_context(|| format!("unknown metric {:?}", m.metric))?;
+            // the unit is stored with each point, so that the measurements can be replayed
+            let unit = metric.unit.unique_name();
+            let point = JsonPoint::new(m, &metric.name, ctx.metric_plugin(&m.metric))?.with_unit(unit);
+            let line = serde_json::to_vec(&point)?;
+            self.ring.append(point.timestamp, &line)?;
+        }
+        self.ring.end_batch()?;
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> anyhow::Result<()> {
+        self.ring
+            .close()
+            .context("failed to write the index of the ring buffer")?;
+        self.finalized = true;
+        Ok(())
+    }
+}
+
+impl Drop for RingBufferOutput {
+    fn drop(&mut self) {
+        // The output has not been finalized if it has stopped because of an error:
+        // write the index anyway.
+        if !self.finalized {
+            if let Err(e) = self.ring.close() {
+                log::error!("Failed to write the index of the ring buffer: {e:#}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use alumet::{
+        measurement::{AttributeValue, MeasurementPoint, Timestamp, WrappedMeasurementValue},
+        metrics::RawMetricId,
+        plugin::util::JsonPoint,
+        resources::{Resource, ResourceConsumer},
+    };
+
+    #[test]
+    fn point_to_json() {
+        let point = MeasurementPoint::new_untyped(
+            Timestamp::from(UNIX_EPOCH + Duration::from_secs(2)),
+            RawMetricId::from_u64(0),
+            Resource::CpuPackage { id: 1 },
+            ResourceConsumer::LocalMachine,
+            WrappedMeasurementValue::F64(12.5),
+        )
+        .with_attr("domain", AttributeValue::Str("package"));
+        let json = JsonPoint::new(&point, "rapl_consumed_energy", Some("rapl"))
+            .unwrap()
+            .with_unit(String::from("J"));
+        assert_eq!(
+            serde_json::to_string(&json).unwrap(),
+            r#"{"metric":"rapl_consumed_energy","unit":"J","plugin":"rapl","timestamp":2000000000,"value":12.5,"resource_kind":"cpu_package","resource_id":"1","consumer_kind":"local_machine","consumer_id":null,"attributes":{"domain":"package"}}"#
+        );
+    }
+}
@@ -0,0 +1,441 @@
+//! The on-disk ring: a fixed number of segment files, reused in turn.
+//!
+//! Each segment is a JSON Lines file named `segment-NNN.jsonl`. Its first line is a header that contains
+//! the sequence number of the segment, which increases each time a segment is started, even across restarts.
+//! The other lines are the measurement points, one JSON object per line, with a `timestamp` field
+//! (nanoseconds since the UNIX epoch).
+//!
+//! When a segment is full, the writer starts the next one, which truncates the oldest segment of the ring.
+//! On clean shutdown, the writer adds an index file, `index.json`, that lists the segments from the oldest
+//! to the newest. The index is deleted when the writer starts, hence its presence proves that the segments
+//! have not been modified since it was written.
+//!
+//! ## Recovery
+//!
+//! If the index exists, the segments are read in the order of the index. Otherwise (the agent has crashed
+//! or has been killed), the segments are ordered by the sequence number of their header, and the last line
+//! of each segment is ignored if it is incomplete. See [`ring_segments`] and [`scan_segment`].
+
+use std::{
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
+
+pub const INDEX_FILE: &str = "index.json";
+
+/// Name of the format, written in the header of each segment.
+const FORMAT: &str = "alumet-ring-buffer";
+const FORMAT_VERSION: u32 = 1;
+
+/// Settings of the ring.
+#[derive(Debug, Clone)]
+pub struct RingSettings {
+    /// Directory that contains the segment files.
+    pub dir: PathBuf,
+    /// Number of segment files.
+    pub segments: usize,
+    /// Size after which a segment is full, in bytes.
+    pub max_segment_size: u64,
+    /// Time span of the measurements after which a segment is full.
+    pub max_segment_duration: Duration,
+    /// Whether to sync the data to the disk after each write, instead of only flushing it to the OS.
+    pub sync: bool,
+}
+
+/// First line of a segment file.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct SegmentHeader {
+    format: String,
+    version: u32,
+    sequence: u64,
+}
+
+/// Description of a segment, as written in the index.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SegmentInfo {
+    /// Name of the segment file, relative to the directory of the ring.
+    pub file: String,
+    pub sequence: u64,
+    /// Number of measurement points in the segment.
+    pub points: u64,
+    /// Timestamp of the first point, in nanoseconds since the UNIX epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_timestamp: Option<u64>,
+    /// Timestamp of the last point, in nanoseconds since the UNIX epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_timestamp: Option<u64>,
+}
+
+/// Content of the index file.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Index {
+    /// The segments, from the oldest to the newest.
+    pub segments: Vec<SegmentInfo>,
+}
+
+/// The only field of a point that the ring needs to read.
+#[derive(Deserialize)]
+struct PointTimestamp {
+    timestamp: u64,
+}
+
+/// Writes lines to the ring, overwriting the oldest segment when the current one is full.
+pub struct RingWriter {
+    settings: RingSettings,
+    /// Description of each segment of the ring, by position. `None` if the segment does not exist yet.
+    segments: Vec<Option<SegmentInfo>>,
+    /// Position of the segment being written.
+    current: usize,
+    writer: BufWriter<File>,
+    /// Size of the current segment, in bytes.
+    size: u64,
+}
+
+impl RingWriter {
+    /// Opens the ring, keeping the segments written by the previous runs until they are overwritten.
+    pub fn open(settings: RingSettings) -> anyhow::Result<Self> {
+        if settings.segments == 0 {
+            return Err(anyhow!("the ring buffer needs at least one segment"));
+        }
+        let dir = &settings.dir;
+        fs::create_dir_all(dir).with_context(|| format!("could not create {}", dir.display()))?;
+
+        // the index describes a closed ring, it is written again on shutdown
+        remove_index(dir)?;
+
+        let segments: Vec<Option<SegmentInfo>> = (0..settings.segments)
+            .map(|i| {
+                let path = dir.join(segment_file(i));
+                if !path.exists() {
+                    return None;
+                }
+                match scan_segment(&path, |_| Ok(())) {
+                    Ok(info) => Some(info),
+                    Err(e) => {
+                        log::warn!("Ignoring invalid segment {}: {e:#}", path.display());
+                        None
+                    }
+                }
+            })
+            .collect();
+
+        // continue after the newest segment
+        let newest = segments
+            .iter()
+            .enumerate()
+            .filter_map(|(i, s)| s.as_ref().map(|s| (i, s.sequence)))
+            .max_by_key(|(_, sequence)| *sequence);
+        let (current, sequence) = match newest {
+            Some((i, sequence)) => ((i + 1) % settings.segments, sequence + 1),
+            None => (0, 0),
+        };
+        let (writer, info, size) = create_segment(dir, current, sequence)?;
+        let mut ring = Self {
+            settings,
+            segments,
+            current,
+            writer,
+            size,
+        };
+        ring.segments[current] = Some(info);
+        Ok(ring)
+    }
+
+    /// Appends a line to the current segment. `line` must not contain any line break.
+    ///
+    /// The data is buffered until [`end_batch`](Self::end_batch) is called.
+    pub fn append(&mut self, timestamp: u64, line: &[u8]) -> io::Result<()> {
+        self.writer.write_all(line)?;
+        self.writer.write_all(b"\n")?;
+        self.size += line.len() as u64 + 1;
+        let info = self.current_info();
+        info.points += 1;
+        info.first_timestamp.get_or_insert(timestamp);
+        info.last_timestamp = Some(timestamp);
+        Ok(())
+    }
+
+    /// Writes the buffered lines to the file, then starts a new segment if the current one is full.
+    ///
+    /// A segment can exceed its maximum size by the size of one batch.
+    pub fn end_batch(&mut self) -> io::Result<()> {
+        self.flush()?;
+        let info = self.current_info();
+        let span = match (info.first_timestamp, info.last_timestamp) {
+            (Some(first), Some(last)) => Duration::from_nanos(last.saturating_sub(first)),
+            _ => Duration::ZERO,
+        };
+        if self.size >= self.settings.max_segment_size || span >= self.settings.max_segment_duration {
+            self.rotate()?;
+        }
+        Ok(())
+    }
+
+    /// Flushes the data and writes the index of the ring.
+    pub fn close(&mut self) -> anyhow::Result<()> {
+        self.flush()?;
+        let mut segments: Vec<SegmentInfo> = self.segments.iter().flatten().cloned().collect();
+        segments.sort_by_key(|s| s.sequence);
+        let content = serde_json::to_vec_pretty(&Index { segments })?;
+        // write the whole file at once, so that an interrupted shutdown does not leave a truncated index
+        let path = self.settings.dir.join(INDEX_FILE);
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, content).with_context(|| format!("failed to write {}", tmp_path.display()))?;
+        fs::rename(&tmp_path, &path).with_context(|| format!("failed to replace {}", path.display()))?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        if self.settings.sync {
+            self.writer.get_ref().sync_data()?;
+        }
+        Ok(())
+    }
+
+    /// Starts the next segment, which replaces the oldest one.
+    fn rotate(&mut self) -> io::Result<()> {
+        let sequence = self.current_info().sequence + 1;
+        self.current = (self.current + 1) % self.settings.segments;
+        let (writer, info, size) = create_segment(&self.settings.dir, self.current, sequence)?;
+        log::debug!(
+            "Starting segment {} of the ring buffer (sequence {sequence})",
+            info.file
+        );
+        self.writer = writer;
+        self.size = size;
+        self.segments[self.current] = Some(info);
+        Ok(())
+    }
+
+    fn current_info(&mut self) -> &mut SegmentInfo {
+        self.segments[self.current]
+            .as_mut()
+            .expect("the current segment should exist")
+    }
+}
+
+/// Returns the name of the segment file at the given position in the ring.
+fn segment_file(position: usize) -> String {
+    format!("segment-{position:03}.jsonl")
+}
+
+/// Creates (or truncates) a segment file and writes its header.
+fn create_segment(dir: &Path, position: usize, sequence: u64) -> io::Result<(BufWriter<File>, SegmentInfo, u64)> {
+    let file_name = segment_file(position);
+    let mut writer = BufWriter::new(File::create(dir.join(&file_name))?);
+    let header = SegmentHeader {
+        format: String::from(FORMAT),
+        version: FORMAT_VERSION,
+        sequence,
+    };
+    let mut line = serde_json::to_vec(&header)?;
+    line.push(b'\n');
+    writer.write_all(&line)?;
+    writer.flush()?;
+    let info = SegmentInfo {
+        file: file_name,
+        sequence,
+        points: 0,
+        first_timestamp: None,
+        last_timestamp: None,
+    };
+    Ok((writer, info, line.len() as u64))
+}
+
+fn remove_index(dir: &Path) -> anyhow::Result<()> {
+    let path = dir.join(INDEX_FILE);
+    match fs::remove_file(&path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+            Err(e).with_context(|| format!("could not remove {}", path.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Reads a segment file, calls `on_point` with each complete point, and returns the description of the segment.
+///
+/// Reading stops at the first invalid line, which is usually a line that was being written when
+/// the agent stopped abruptly.
+pub fn scan_segment(path: &Path, mut on_point: impl FnMut(&str) -> anyhow::Result<()>) -> anyhow::Result<SegmentInfo> {
+    let file = File::open(path).with_context(|| format!("could not open {}", path.display()))?;
+    let mut lines = BufReader::new(file).lines();
+    let header = lines.next().ok_or_else(|| anyhow!("empty segment"))??;
+    let header: SegmentHeader = serde_json::from_str(&header).context("invalid segment header")?;
+    if header.format != FORMAT || header.version != FORMAT_VERSION {
+        return Err(anyhow!(
+            "unsupported format {} version {}",
+            header.format,
+            header.version
+        ));
+    }
+    let mut info = SegmentInfo {
+        file: path.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+        sequence: header.sequence,
+        points: 0,
+        first_timestamp: None,
+        last_timestamp: None,
+    };
+    for line in lines {
+        let Ok(line) = line else { break };
+        let Ok(point) = serde_json::from_str::<PointTimestamp>(&line) else {
+            log::debug!("Ignoring the incomplete end of segment {}", path.display());
+            break;
+        };
+        on_point(&line)?;
+        info.points += 1;
+        info.first_timestamp.get_or_insert(point.timestamp);
+        info.last_timestamp = Some(point.timestamp);
+    }
+    Ok(info)
+}
+
+/// Returns the paths of the segments of the ring, from the oldest to the newest.
+///
+/// The order is given by the index, if it exists, or by the sequence numbers of the segments.
+pub fn ring_segments(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let index_path = dir.join(INDEX_FILE);
+    if index_path.exists() {
+        let content =
+            fs::read_to_string(&index_path).with_context(|| format!("could not read {}", index_path.display()))?;
+        let index: Index = serde_json::from_str(&content).context("invalid index")?;
+        return Ok(index.segments.into_iter().map(|s| dir.join(s.file)).collect());
+    }
+    log::info!(
+        "No index in {}, the segments will be ordered by sequence number.",
+        dir.display()
+    );
+    let mut segments = Vec::new();
+    for entry in fs::read_dir(dir).with_context(|| format!("could not list {}", dir.display()))? {
+        let path = entry?.path();
+        let is_segment = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .is_some_and(|n| n.starts_with("segment-") && n.ends_with(".jsonl"));
+        if !is_segment {
+            continue;
+        }
+        let header = File::open(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|f| read_header(BufReader::new(f)));
+        match header {
+            Ok(header) => segments.push((header.sequence, path)),
+            Err(e) => log::warn!("Ignoring invalid segment {}: {e:#}", path.display()),
+        }
+    }
+    segments.sort_by_key(|(sequence, _)| *sequence);
+    Ok(segments.into_iter().map(|(_, path)| path).collect())
+}
+
+fn read_header(mut reader: impl BufRead) -> anyhow::Result<SegmentHeader> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    Ok(serde_json::from_str(&line)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path, time::Duration};
+
+    use super::{ring_segments, scan_segment, Index, RingSettings, RingWriter, INDEX_FILE};
+
+    fn settings(dir: &Path) -> RingSettings {
+        RingSettings {
+            dir: dir.to_owned(),
+            segments: 3,
+            max_segment_size: 1024 * 1024,
+            max_segment_duration: Duration::from_nanos(100),
+            sync: false,
+        }
+    }
+
+    fn write_batch(ring: &mut RingWriter, timestamps: &[u64]) {
+        for t in timestamps {
+            ring.append(*t, format!(r#"{{"timestamp":{t}}}"#).as_bytes()).unwrap();
+        }
+        ring.end_batch().unwrap();
+    }
+
+    /// Reads all the timestamps of the ring, in order.
+    fn read_all(dir: &Path) -> Vec<u64> {
+        let mut timestamps = Vec::new();
+        for path in ring_segments(dir).unwrap() {
+            scan_segment(&path, |line| {
+                let point: serde_json::Value = serde_json::from_str(line)?;
+                timestamps.push(point["timestamp"].as_u64().unwrap());
+                Ok(())
+            })
+            .unwrap();
+        }
+        timestamps
+    }
+
+    #[test]
+    fn rotation_and_recovery() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_path_buf();
+
+        let mut ring = RingWriter::open(settings(&dir)).unwrap();
+        // each segment spans 100ns: 5 segments are started, the first two are overwritten
+        for t in (0..600).step_by(50) {
+            write_batch(&mut ring, &[t, t + 10]);
+        }
+        let expected: Vec<u64> = (300..600).step_by(50).flat_map(|t| [t, t + 10]).collect();
+        assert_eq!(read_all(&dir), expected);
+
+        // clean shutdown: the index lists the segments in order
+        ring.close().unwrap();
+        let index: Index = serde_json::from_str(&fs::read_to_string(dir.join(INDEX_FILE)).unwrap()).unwrap();
+        let sequences: Vec<u64> = index.segments.iter().map(|s| s.sequence).collect();
+        assert_eq!(sequences, vec![2, 3, 4]);
+        assert_eq!(index.segments[0].first_timestamp, Some(300));
+        assert_eq!(index.segments[1].last_timestamp, Some(560));
+        assert_eq!(index.segments[2].points, 0);
+        drop(ring);
+
+        // restart: the index is removed and the new points go after the old ones
+        let mut ring = RingWriter::open(settings(&dir)).unwrap();
+        assert!(!dir.join(INDEX_FILE).exists());
+        write_batch(&mut ring, &[1000]);
+        let mut expected: Vec<u64> = (450..600).step_by(50).flat_map(|t| [t, t + 10]).collect();
+        expected.push(1000);
+        assert_eq!(read_all(&dir), expected);
+
+        // crash in the middle of a line: the incomplete line is ignored
+        drop(ring);
+        let newest = ring_segments(&dir).unwrap().pop().unwrap();
+        let mut content = fs::read(&newest).unwrap();
+        content.extend_from_slice(br#"{"timestamp":10"#);
+        fs::write(&newest, content).unwrap();
+        assert_eq!(read_all(&dir), expected);
+    }
+
+    #[test]
+    fn rotation_by_size() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_path_buf();
+        let settings = RingSettings {
+            max_segment_size: 100,
+            max_segment_duration: Duration::from_secs(3600),
+            ..settings(&dir)
+        };
+        let mut ring = RingWriter::open(settings).unwrap();
+        for t in 0..20 {
+            write_batch(&mut ring, &[t]);
+        }
+        // the ring never grows beyond its segments
+        let total: u64 = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().metadata().unwrap().len())
+            .sum();
+        assert!(total <= 3 * 120, "{total}");
+        let timestamps = read_all(&dir);
+        assert_eq!(timestamps.last(), Some(&19));
+        assert!(timestamps.windows(2).all(|w| w[0] + 1 == w[1]), "{timestamps:?}");
+    }
+}
@@ -0,0 +1,252 @@
+use std::{fmt::Write, time::SystemTime};
+
+use alumet::measurement::{MeasurementPoint, WrappedMeasurementValue};
+use anyhow::anyhow;
+use time::{format_description::well_known::Rfc3339, macros::format_description, OffsetDateTime};
+
+use crate::SyslogFormat;
+
+pub const DEFAULT_TEMPLATE: &str =
+    "{metric}={value}{unit} resource={resource_kind}:{resource_id} consumer={consumer_kind}:{consumer_id} {attributes}";
+
+/// A field of a measurement that can be written in a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Metric,
+    Unit,
+    Plugin,
+    Value,
+    Timestamp,
+    ResourceKind,
+    ResourceId,
+    ConsumerKind,
+    ConsumerId,
+    /// All the attributes, as `key=value` separated by spaces.
+    Attributes,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Field(Field),
+}
+
+/// The content of a message, made of text and placeholders such as `{metric}`.
+///
+/// Literal braces are written `{{` and `}}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    pub fn parse(template: &str) -> anyhow::Result<Self> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = template.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest
+                        .find('}')
+                        .ok_or_else(|| anyhow!("unterminated placeholder in {template:?}"))?;
+                    let field = match &rest[..end] {
+                        "metric" => Field::Metric,
+                        "unit" => Field::Unit,
+                        "plugin" => Field::Plugin,
+                        "value" => Field::Value,
+                        "timestamp" => Field::Timestamp,
+                        "resource_kind" => Field::ResourceKind,
+                        "resource_id" => Field::ResourceId,
+                        "consumer_kind" => Field::ConsumerKind,
+                        "consumer_id" => Field::ConsumerId,
+                        "attributes" => Field::Attributes,
+                        other => return Err(anyhow!("unknown placeholder {{{other}}} in {template:?}")),
+                    };
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(Part::Field(field));
+                    chars = rest[end + 1..].chars();
+                }
+                '}' => {
+                    return Err(anyhow!(
+                        "unexpected '}}' in {template:?}, write '}}}}' for a literal brace"
+                    ))
+                }
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        Ok(Self { parts })
+    }
+}
+
+/// Information about the metric of a measurement, obtained from the [`OutputContext`](alumet::pipeline::OutputContext).
+pub struct MetricInfo<'a> {
+    pub name: &'a str,
+    pub unit: String,
+    pub plugin: Option<&'a str>,
+}
+
+/// Formats the measurements as syslog messages.
+pub struct MessageFormatter {
+    pub format: SyslogFormat,
+    /// Facility * 8 + severity.
+    pub priority: u8,
+    pub hostname: String,
+    pub app_name: String,
+    pub template: Template,
+}
+
+impl MessageFormatter {
+    /// Returns the complete syslog message (header and content) of a measurement.
+    pub fn message(&self, m: &MeasurementPoint, metric: &MetricInfo) -> anyhow::Result<String> {
+        let datetime = OffsetDateTime::from(SystemTime::from(m.timestamp));
+        let pid = std::process::id();
+        let mut res = match self.format {
+            SyslogFormat::Rfc3164 => {
+                let timestamp = datetime.format(format_description!(
+                    "[month repr:short] [day padding:space] [hour]:[minute]:[second]"
+                ))?;
+                format!(
+                    "<{}>{timestamp} {} {}[{pid}]: ",
+                    self.priority, self.hostname, self.app_name
+                )
+            }
+            SyslogFormat::Rfc5424 => {
+                let timestamp = datetime.format(&Rfc3339)?;
+                format!(
+                    "<{}>1 {timestamp} {} {} {pid} - - ",
+                    self.priority, self.hostname, self.app_name
+                )
+            }
+        };
+        for part in &self.template.parts {
+            match part {
+                Part::Text(text) => res.push_str(text),
+                Part::Field(field) => write_field(&mut res, *field, m, metric, &datetime)?,
+            }
+        }
+        // the attributes are often last, don't leave a trailing space when there is none
+        res.truncate(res.trim_end().len());
+        Ok(res)
+    }
+}
+
+fn write_field(
+    out: &mut String,
+    field: Field,
+    m: &MeasurementPoint,
+    metric: &MetricInfo,
+    datetime: &OffsetDateTime,
+) -> anyhow::Result<()> {
+    match field {
+        Field::Metric => out.push_str(metric.name),
+        Field::Unit => out.push_str(&metric.unit),
+        Field::Plugin => out.push_str(metric.plugin.unwrap_or("-")),
+        Field::Value => match m.value {
+            WrappedMeasurementValue::F64(v) => write!(out, "{v}")?,
+            WrappedMeasurementValue::U64(v) => write!(out, "{v}")?,
+        },
+        Field::Timestamp => out.push_str(&datetime.format(&Rfc3339)?),
+        Field::ResourceKind => out.push_str(m.resource.kind()),
+        Field::ResourceId => write!(out, "{}", m.resource.id_display())?,
+        Field::ConsumerKind => out.push_str(m.consumer.kind()),
+        Field::ConsumerId => write!(out, "{}", m.consumer.id_display())?,
+        Field::Attributes => {
+            for (i, (key, value)) in m.attributes().enumerate() {
+                if i > 0 {
+                    out.push(' ');
+                }
+                write!(out, "{key}={value}")?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use alumet::{
+        measurement::{AttributeValue, MeasurementPoint, Timestamp, WrappedMeasurementValue},
+        metrics::RawMetricId,
+        resources::{Resource, ResourceConsumer},
+    };
+
+    use super::{Field, MessageFormatter, MetricInfo, Part, Template, DEFAULT_TEMPLATE};
+    use crate::SyslogFormat;
+
+    fn point() -> MeasurementPoint {
+        MeasurementPoint::new_untyped(
+            Timestamp::from(UNIX_EPOCH + Duration::from_secs(86400 * 365)),
+            RawMetricId::from_u64(0),
+            Resource::CpuPackage { id: 1 },
+            ResourceConsumer::LocalMachine,
+            WrappedMeasurementValue::F64(12.5),
+        )
+        .with_attr("domain", AttributeValue::Str("package"))
+    }
+
+    #[test]
+    fn parse_template() {
+        let template = Template::parse("{{{metric}}} = {value}").unwrap();
+        assert_eq!(
+            template.parts,
+            vec![
+                Part::Text(String::from("{")),
+                Part::Field(Field::Metric),
+                Part::Text(String::from("} = ")),
+                Part::Field(Field::Value),
+            ]
+        );
+        Template::parse(DEFAULT_TEMPLATE).unwrap();
+        assert!(Template::parse("{metric").is_err());
+        assert!(Template::parse("{name}").is_err());
+        assert!(Template::parse("a } b").is_err());
+    }
+
+    #[test]
+    fn messages() {
+        let metric = MetricInfo {
+            name: "rapl_consumed_energy",
+            unit: String::from("J"),
+            plugin: Some("rapl"),
+        };
+        let mut formatter = MessageFormatter {
+            format: SyslogFormat::Rfc5424,
+            priority: 3 * 8 + 6,
+            hostname: String::from("node-a"),
+            app_name: String::from("alumet"),
+            template: Template::parse(DEFAULT_TEMPLATE).unwrap(),
+        };
+        let pid = std::process::id();
+        assert_eq!(
+            formatter.message(&point(), &metric).unwrap(),
+            format!(
+                "<30>1 1971-01-01T00:00:00Z node-a alumet {pid} - - \
+                 rapl_consumed_energy=12.5J resource=cpu_package:1 consumer=local_machine: domain=package"
+            )
+        );
+
+        formatter.format = SyslogFormat::Rfc3164;
+        formatter.template = Template::parse("{plugin}/{metric} {value}").unwrap();
+        assert_eq!(
+            formatter.message(&point(), &metric).unwrap(),
+            format!("<30>Jan  1 00:00:00 node-a alumet[{pid}]: rapl/rapl_consumed_energy 12.5")
+        );
+    }
+}
@@ -0,0 +1,211 @@
+mod format;
+mod output;
+
+use alumet::plugin::{
+    rust::{deserialize_config, serialize_config, AlumetPlugin},
+    util::{system_hostname, AttributeAllowlist, MetricFilter},
+    ConfigTable,
+};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use format::{MessageFormatter, Template};
+use output::SyslogOutput;
+
+pub struct SyslogPlugin {
+    config: Option<Config>,
+}
+
+impl AlumetPlugin for SyslogPlugin {
+    fn name() -> &'static str {
+        "syslog"
+    }
+
+    fn version() -> &'static str {
+        env!("CARGO_PKG_VERSION")
+    }
+
+    fn default_config() -> anyhow::Result<Option<ConfigTable>> {
+        Ok(Some(serialize_config(Config::default())?))
+    }
+
+    fn init(config: ConfigTable) -> anyhow::Result<Box<Self>> {
+        let config: Config = deserialize_config(config)?;
+        // check the template now, to report the error as soon as possible
+        Template::parse(&config.template).context("invalid message template")?;
+        Ok(Box::new(SyslogPlugin { config: Some(config) }))
+    }
+
+    fn start(&mut self, alumet: &mut alumet::plugin::AlumetStart) -> anyhow::Result<()> {
+        let config = self.config.take().unwrap();
+        let formatter = MessageFormatter {
+            format: config.format,
+            priority: config.facility.code() * 8 + config.severity.code(),
+            hostname: config
+                .hostname
+                .or_else(|| system_hostname().ok())
+                .unwrap_or_else(|| String::from("-")),
+            app_name: config.app_name,
+            template: Template::parse(&config.template)?,
+        };
+        let output = SyslogOutput::new(config.transport, config.address, formatter);
+        let output = AttributeAllowlist::wrap_opt(config.attributes, Box::new(output));
+        alumet.add_output(config.metric_filter.wrap(output));
+        Ok(())
+    }
+
+    fn stop(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Config {
+    /// How the messages are sent to the syslog server.
+    transport: Transport,
+
+    /// Address of the syslog server, `host:port` for UDP and TCP, or the path of the socket for Unix.
+    address: String,
+
+    /// Syslog protocol: "rfc3164" (BSD syslog) or "rfc5424".
+    #[serde(default)]
+    format: SyslogFormat,
+
+    /// Facility of the messages, for instance "daemon" or "local0".
+    #[serde(default)]
+    facility: Facility,
+
+    /// Severity of the messages, for instance "info" or "notice".
+    #[serde(default)]
+    severity: Severity,
+
+    /// Hostname written in the messages. Defaults to the hostname of the machine.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hostname: Option<String>,
+
+    /// Name of the application (or tag) written in the messages.
+    app_name: String,
+
+    /// Content of each message. The placeholders between braces are replaced by the fields of the measurement,
+    /// see the README for the list.
+    template: String,
+
+    /// Only sends the metrics whose name matches these patterns. By default, all the metrics are sent.
+    #[serde(default, skip_serializing_if = "MetricFilter::is_empty")]
+    metric_filter: MetricFilter,
+
+    /// Only sends the attributes whose key matches these patterns, an empty list removes all the attributes.
+    /// By default, all the attributes are sent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    attributes: Option<AttributeAllowlist>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            transport: Transport::Udp,
+            address: String::from("127.0.0.1:514"),
+            format: SyslogFormat::default(),
+            facility: Facility::default(),
+            severity: Severity::default(),
+            hostname: None,
+            app_name: String::from("alumet"),
+            template: String::from(format::DEFAULT_TEMPLATE),
+            metric_filter: MetricFilter::default(),
+            attributes: None,
+        }
+    }
+}
+
+/// Transport protocol of the messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Transport {
+    /// One datagram per message (RFC 5426).
+    Udp,
+    /// A stream of messages framed with their length (RFC 6587), sent in batches.
+    Tcp,
+    /// One datagram per message, on a local socket such as `/dev/log`.
+    Unix,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum SyslogFormat {
+    Rfc3164,
+    #[default]
+    Rfc5424,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Facility {
+    Kern,
+    #[default]
+    User,
+    Mail,
+    Daemon,
+    Auth,
+    Syslog,
+    Lpr,
+    News,
+    Uucp,
+    Cron,
+    Authpriv,
+    Ftp,
+    Local0,
+    Local1,
+    Local2,
+    Local3,
+    Local4,
+    Local5,
+    Local6,
+    Local7,
+}
+
+impl Facility {
+    fn code(self) -> u8 {
+        match self {
+            Facility::Kern => 0,
+            Facility::User => 1,
+            Facility::Mail => 2,
+            Facility::Daemon => 3,
+            Facility::Auth => 4,
+            Facility::Syslog => 5,
+            Facility::Lpr => 6,
+            Facility::News => 7,
+            Facility::Uucp => 8,
+            Facility::Cron => 9,
+            Facility::Authpriv => 10,
+            Facility::Ftp => 11,
+            Facility::Local0 => 16,
+            Facility::Local1 => 17,
+            Facility::Local2 => 18,
+            Facility::Local3 => 19,
+            Facility::Local4 => 20,
+            Facility::Local5 => 21,
+            Facility::Local6 => 22,
+            Facility::Local7 => 23,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Severity {
+    Emerg,
+    Alert,
+    Crit,
+    Err,
+    Warning,
+    Notice,
+    #[default]
+    Info,
+    Debug,
+}
+
+impl Severity {
+    fn code(self) -> u8 {
+        self as u8
+    }
+}
@@ -0,0 +1,214 @@
+use std::{
+    io::{self, Write},
+    net::{TcpStream, ToSocketAddrs, UdpSocket},
+    time::Duration,
+};
+
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+
+use alumet::{
+    measurement::MeasurementBuffer,
+    pipeline::{Output, OutputContext, WriteError},
+};
+use anyhow::Context;
+
+use crate::{
+    format::{MessageFormatter, MetricInfo},
+    Transport,
+};
+
+/// Maximum duration of a write, to avoid blocking the pipeline when the server is stuck.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Sends the measurements to a syslog server, one message per measurement.
+///
+/// The connection is opened lazily. When sending fails, the connection is closed and reopened on the next write.
+pub struct SyslogOutput {
+    transport: Transport,
+    address: String,
+    formatter: MessageFormatter,
+    connection: Option<Connection>,
+}
+
+enum Connection {
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixDatagram),
+}
+
+impl SyslogOutput {
+    pub fn new(transport: Transport, address: String, formatter: MessageFormatter) -> Self {
+        Self {
+            transport,
+            address,
+            formatter,
+            connection: None,
+        }
+    }
+
+    fn connect(&self) -> io::Result<Connection> {
+        match self.transport {
+            Transport::Udp => {
+                let target = self
+                    .address
+                    .to_socket_addrs()?
+                    .next()
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address found"))?;
+                let local = if target.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+                let socket = UdpSocket::bind(local)?;
+                socket.connect(target)?;
+                Ok(Connection::Udp(socket))
+            }
+            Transport::Tcp => {
+                let stream = TcpStream::connect(&self.address)?;
+                stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+                Ok(Connection::Tcp(stream))
+            }
+            #[cfg(unix)]
+            Transport::Unix => {
+                let socket = UnixDatagram::unbound()?;
+                socket.connect(&self.address)?;
+                socket.set_write_timeout(Some(WRITE_TIMEOUT))?;
+                Ok(Connection::Unix(socket))
+            }
+            #[cfg(not(unix))]
+            Transport::Unix => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Unix sockets are not supported here",
+            )),
+        }
+    }
+
+    /// Sends the messages, connecting first if needed. On error, the connection is dropped.
+    fn send(&mut self, messages: &[String]) -> io::Result<()> {
+        if self.connection.is_none() {
+            self.connection = Some(self.connect()?);
+            log::info!("Connected to the syslog server at {}", self.address);
+        }
+        let res = match self.connection.as_mut().unwrap() {
+            // datagrams: one message per datagram, no batching
+            Connection::Udp(socket) => messages
+                .iter()
+                .try_for_each(|msg| socket.send(msg.as_bytes()).map(|_| ())),
+            #[cfg(unix)]
+            Connection::Unix(socket) => messages
+                .iter()
+                .try_for_each(|msg| socket.send(msg.as_bytes()).map(|_| ())),
+            // stream: the whole batch in one write
+            Connection::Tcp(stream) => stream.write_all(&frame_octet_counting(messages)),
+        };
+        if res.is_err() {
+            self.connection = None;
+        }
+        res
+    }
+}
+
+/// Frames the messages for a stream transport, with the octet counting method of RFC 6587:
+/// each message is preceded by its length in bytes and a space.
+fn frame_octet_counting(messages: &[String]) -> Vec<u8> {
+    let mut res = Vec::new();
+    for msg in messages {
+        res.extend_from_slice(format!("{} ", msg.len()).as_bytes());
+        res.extend_from_slice(msg.as_bytes());
+    }
+    res
+}
+
+impl Output for SyslogOutput {
+    fn write(&mut self, measurements: &MeasurementBuffer, ctx: &OutputContext) -> Result<(), WriteError> {
+        if measurements.is_empty() {
+            return Ok(());
+        }
+        let mut messages = Vec::with_capacity(measurements.len());
+        for m in measurements {
+            let metric = ctx
+                .metrics
+                .with_id(&m.metric)
+                .with_context(|| format!("unknown metric {:?}", m.metric))?;
+            let info = MetricInfo {
+                name: &metric.name,
+                unit: metric.unit.display_name(),
+                plugin: ctx.metric_plugin(&m.metric),
+            };
+            messages.push(self.formatter.message(m, &info)?);
+        }
+        self.send(&messages)
+            .with_context(|| format!("failed to send the messages to {}", self.address))
+            .map_err(WriteError::CanRetry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Read,
+        net::{TcpListener, UdpSocket},
+    };
+
+    use super::{frame_octet_counting, SyslogOutput};
+    use crate::{
+        format::{MessageFormatter, Template},
+        SyslogFormat, Transport,
+    };
+
+    fn output(transport: Transport, address: String) -> SyslogOutput {
+        let formatter = MessageFormatter {
+            format: SyslogFormat::Rfc5424,
+            priority: 14,
+            hostname: String::from("-"),
+            app_name: String::from("alumet"),
+            template: Template::parse("{value}").unwrap(),
+        };
+        SyslogOutput::new(transport, address, formatter)
+    }
+
+    #[test]
+    fn octet_counting() {
+        let framed = frame_octet_counting(&[String::from("abc"), String::from("hello")]);
+        assert_eq!(framed, b"3 abc5 hello");
+    }
+
+    #[test]
+    fn udp_datagrams() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut output = output(Transport::Udp, server.local_addr().unwrap().to_string());
+        output.send(&[String::from("first"), String::from("second")]).unwrap();
+        let mut buf = [0u8; 64];
+        let n = server.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"first");
+        let n = server.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"second");
+    }
+
+    #[test]
+    fn tcp_reconnect() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut output = output(Transport::Tcp, server.local_addr().unwrap().to_string());
+        output.send(&[String::from("a"), String::from("bc")]).unwrap();
+        let (mut peer, _) = server.accept().unwrap();
+        let mut buf = [0u8; 7];
+        peer.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"1 a2 bc");
+
+        // the server closes the connection: sending fails (maybe not immediately), then reconnects
+        drop(peer);
+        let mut failed = false;
+        for _ in 0..100 {
+            if output.send(&[String::from("x")]).is_err() {
+                failed = true;
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+        assert!(failed, "sending to a closed connection should fail");
+        assert!(output.connection.is_none());
+        output.send(&[String::from("again")]).unwrap();
+        let (mut peer, _) = server.accept().unwrap();
+        let mut buf = [0u8; 7];
+        peer.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"5 again");
+    }
+}
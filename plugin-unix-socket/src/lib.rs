@@ -0,0 +1,104 @@
+#[cfg(unix)]
+mod output;
+
+use std::{path::PathBuf, time::Duration};
+
+use alumet::plugin::{
+    rust::{deserialize_config, serialize_config, AlumetPlugin},
+    util::{AttributeAllowlist, MetricFilter},
+    ConfigTable,
+};
+use serde::{Deserialize, Serialize};
+
+pub struct UnixSocketPlugin {
+    config: Option<Config>,
+}
+
+impl AlumetPlugin for UnixSocketPlugin {
+    fn name() -> &'static str {
+        "unix-socket"
+    }
+
+    fn version() -> &'static str {
+        env!("CARGO_PKG_VERSION")
+    }
+
+    fn default_config() -> anyhow::Result<Option<ConfigTable>> {
+        Ok(Some(serialize_config(Config::default())?))
+    }
+
+    fn init(config: ConfigTable) -> anyhow::Result<Box<Self>> {
+        let config = deserialize_config(config)?;
+        Ok(Box::new(UnixSocketPlugin { config: Some(config) }))
+    }
+
+    #[cfg(unix)]
+    fn start(&mut self, alumet: &mut alumet::plugin::AlumetStart) -> anyhow::Result<()> {
+        let config = self.config.take().unwrap();
+        let output = output::UnixSocketOutput::new(config.path, config.format, config.buffer_duration);
+        let output = AttributeAllowlist::wrap_opt(config.attributes, Box::new(output));
+        alumet.add_output(config.metric_filter.wrap(output));
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn start(&mut self, _alumet: &mut alumet::plugin::AlumetStart) -> anyhow::Result<()> {
+        Err(anyhow::anyhow!("Unix domain sockets are not supported here"))
+    }
+
+    fn stop(&mut self) -> anyhow::Result<()> {
+        // The output closes its connection when it is dropped by the pipeline.
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Config {
+    /// Path of the socket to connect to. The peer (for instance a sidecar process) must listen on it.
+    path: PathBuf,
+
+    /// How the measurements are framed on the socket.
+    #[serde(default)]
+    format: FrameFormat,
+
+    /// How long to keep the measurements when the peer is not listening, before dropping them.
+    #[serde(default = "default_buffer_duration", with = "humantime_serde")]
+    buffer_duration: Duration,
+
+    /// Only sends the metrics whose name matches these patterns. By default, all the metrics are sent.
+    #[serde(default, skip_serializing_if = "MetricFilter::is_empty")]
+    metric_filter: MetricFilter,
+
+    /// Only sends the attributes whose key matches these patterns, an empty list removes all the attributes.
+    /// By default, all the attributes are sent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    attributes: Option<AttributeAllowlist>,
+}
+
+/// Format of the data sent on the socket.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum FrameFormat {
+    /// One JSON object per measurement, followed by a newline.
+    #[default]
+    JsonLines,
+    /// One frame per measurement buffer: the length of the payload (4 bytes, big endian),
+    /// followed by the payload, which is a JSON array of measurements.
+    LengthPrefixed,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("alumet-measurements.sock"),
+            format: FrameFormat::default(),
+            buffer_duration: default_buffer_duration(),
+            metric_filter: MetricFilter::default(),
+            attributes: None,
+        }
+    }
+}
+
+fn default_buffer_duration() -> Duration {
+    Duration::from_secs(10)
+}
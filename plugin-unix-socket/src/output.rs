@@ -0,0 +1,281 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Write},
+    net::Shutdown,
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use alumet::{
+    measurement::MeasurementBuffer,
+    pipeline::{Output, OutputContext, WriteError},
+    plugin::util::JsonPoint,
+};
+use anyhow::Context;
+
+use crate::FrameFormat;
+
+/// Maximum duration of a write on the socket, to avoid blocking the pipeline when the peer is stuck.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Streams the measurements to a Unix domain socket.
+///
+/// The output connects to the socket lazily, and reconnects when the peer disconnects.
+/// While the peer is not listening, the frames are kept for `buffer_duration`, then dropped.
+pub struct UnixSocketOutput {
+    path: PathBuf,
+    format: FrameFormat,
+    buffer_duration: Duration,
+    stream: Option<UnixStream>,
+    /// Frames that have not been sent yet, with the time at which they were created.
+    pending: VecDeque<(Instant, Vec<u8>)>,
+}
+
+impl UnixSocketOutput {
+    pub fn new(path: PathBuf, format: FrameFormat, buffer_duration: Duration) -> Self {
+        Self {
+            path,
+            format,
+            buffer_duration,
+            stream: None,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Queues the frames and sends as many pending frames as possible.
+    ///
+    /// It is not an error for the peer to be absent: the frames stay in the queue until they expire.
+    fn send(&mut self, frames: Vec<Vec<u8>>) -> io::Result<()> {
+        let now = Instant::now();
+        self.pending.extend(frames.into_iter().map(|f| (now, f)));
+        self.drop_expired(now);
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+
+        if self.stream.is_none() {
+            match UnixStream::connect(&self.path) {
+                Ok(stream) => {
+                    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+                    log::info!("Connected to {}", self.path.display());
+                    self.stream = Some(stream);
+                }
+                Err(e) => {
+                    log::debug!(
+                        "Could not connect to {}, {} frame(s) pending: {e}",
+                        self.path.display(),
+                        self.pending.len()
+                    );
+                    return Ok(());
+                }
+            }
+        }
+
+        let stream = self.stream.as_mut().unwrap();
+        let mut disconnected = false;
+        while let Some((_, frame)) = self.pending.front() {
+            if let Err(e) = stream.write_all(frame) {
+                // The frame is sent again, entirely, on the next connection.
+                log::warn!("Lost the connection to {}: {e}", self.path.display());
+                disconnected = true;
+                break;
+            }
+            self.pending.pop_front();
+        }
+        if disconnected {
+            self.stream = None;
+        }
+        Ok(())
+    }
+
+    /// Closes the connection, and reports the frames that have never been sent.
+    fn close(&mut self) -> io::Result<()> {
+        if !self.pending.is_empty() {
+            log::warn!(
+                "{} frame(s) were never sent to {}",
+                self.pending.len(),
+                self.path.display()
+            );
+            self.pending.clear();
+        }
+        if let Some(stream) = self.stream.take() {
+            // Tells the peer that there is nothing more to read.
+            match stream.shutdown(Shutdown::Both) {
+                // the peer has already closed the connection
+                Err(e) if e.kind() == io::ErrorKind::NotConnected => (),
+                res => res?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Drops the frames that have been waiting for longer than `buffer_duration`.
+    fn drop_expired(&mut self, now: Instant) {
+        let before = self.pending.len();
+        self.pending
+            .retain(|(created, _)| now.duration_since(*created) <= self.buffer_duration);
+        let dropped = before - self.pending.len();
+        if dropped > 0 {
+            log::warn!(
+                "Dropped {dropped} frame(s): nobody listened on {} for {:?}",
+                self.path.display(),
+                self.buffer_duration
+            );
+        }
+    }
+}
+
+impl Output for UnixSocketOutput {
+    fn write(&mut self, measurements: &MeasurementBuffer, ctx: &OutputContext) -> Result<(), WriteError> {
+        if measurements.is_empty() {
+            return Ok(());
+        }
+        let mut points = Vec::with_capacity(measurements.len());
+        for m in measurements {
+            let metric = ctx
+                .metrics
+                .with_id(&m.metric)
+                .with_context(|| format!("unknown metric {:?}", m.metric))?;
+            points.push(JsonPoint::new(m, &metric.name, ctx.metric_plugin(&m.metric))?);
+        }
+        let frames = encode(&points, self.format)?;
+        self.send(frames)
+            .with_context(|| format!("failed to write to {}", self.path.display()))
+            .map_err(WriteError::CanRetry)
+    }
+
+    fn finalize(&mut self) -> anyhow::Result<()> {
+        // last chance to deliver the pending frames
+        self.send(Vec::new())
+            .with_context(|| format!("failed to write to {}", self.path.display()))
+    }
+
+    fn stop(&mut self) -> anyhow::Result<()> {
+        self.close()
+            .with_context(|| format!("failed to close the connection to {}", self.path.display()))
+    }
+}
+
+impl Drop for UnixSocketOutput {
+    fn drop(&mut self) {
+        // The output has not been stopped if it has not been run by the pipeline: close it anyway.
+        if let Err(e) = self.close() {
+            log::error!("Failed to close the connection to {}: {e}", self.path.display());
+        }
+    }
+}
+
+/// Encodes the points into frames, according to the format.
+fn encode(points: &[JsonPoint], format: FrameFormat) -> anyhow::Result<Vec<Vec<u8>>> {
+    match format {
+        FrameFormat::JsonLines => {
+            let mut frame = Vec::new();
+            for p in points {
+                serde_json::to_writer(&mut frame, p)?;
+                frame.push(b'\n');
+            }
+            Ok(vec![frame])
+        }
+        FrameFormat::LengthPrefixed => {
+            let payload = serde_json::to_vec(points)?;
+            let len = u32::try_from(payload.len()).context("frame too large")?;
+            let mut frame = Vec::with_capacity(4 + payload.len());
+            frame.extend_from_slice(&len.to_be_bytes());
+            frame.extend_from_slice(&payload);
+            Ok(vec![frame])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        io::Read,
+        os::unix::net::UnixListener,
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use alumet::{
+        measurement::{MeasurementPoint, Timestamp, WrappedMeasurementValue},
+        metrics::RawMetricId,
+        pipeline::Output,
+        resources::{Resource, ResourceConsumer},
+    };
+
+    use alumet::plugin::util::JsonPoint;
+
+    use super::{encode, UnixSocketOutput};
+    use crate::FrameFormat;
+
+    fn point(value: u64) -> MeasurementPoint {
+        MeasurementPoint::new_untyped(
+            Timestamp::from(UNIX_EPOCH + Duration::from_secs(1)),
+            RawMetricId::from_u64(0),
+            Resource::LocalMachine,
+            ResourceConsumer::LocalMachine,
+            WrappedMeasurementValue::U64(value),
+        )
+    }
+
+    #[test]
+    fn frame_formats() {
+        let (a, b) = (point(1), point(2));
+        let points = vec![
+            JsonPoint::new(&a, "m", None).unwrap(),
+            JsonPoint::new(&b, "m", None).unwrap(),
+        ];
+
+        let lines = encode(&points, FrameFormat::JsonLines).unwrap();
+        assert_eq!(lines.len(), 1);
+        let lines = String::from_utf8(lines[0].clone()).unwrap();
+        assert_eq!(lines.lines().count(), 2);
+        assert!(lines.ends_with('\n'));
+
+        let frames = encode(&points, FrameFormat::LengthPrefixed).unwrap();
+        let frame = &frames[0];
+        let len = u32::from_be_bytes(frame[..4].try_into().unwrap()) as usize;
+        assert_eq!(len, frame.len() - 4);
+        let array: Vec<serde_json::Value> = serde_json::from_slice(&frame[4..]).unwrap();
+        assert_eq!(array.len(), 2);
+        assert_eq!(array[1]["value"], 2);
+    }
+
+    #[test]
+    fn buffer_until_peer_listens() {
+        let dir = std::env::temp_dir().join("alumet-test-unix-socket");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("out.sock");
+
+        let mut output = UnixSocketOutput::new(path.clone(), FrameFormat::JsonLines, Duration::from_secs(60));
+        // nobody is listening: the frame is kept
+        output.send(vec![b"first\n".to_vec()]).unwrap();
+        assert_eq!(output.pending.len(), 1);
+        assert!(output.stream.is_none());
+
+        // the peer appears: everything is delivered, in order
+        let listener = UnixListener::bind(&path).unwrap();
+        output.send(vec![b"second\n".to_vec()]).unwrap();
+        assert!(output.pending.is_empty());
+
+        // the connection is closed when the output is stopped
+        output.stop().unwrap();
+        let (mut peer, _) = listener.accept().unwrap();
+        let mut received = String::new();
+        peer.read_to_string(&mut received).unwrap();
+        assert_eq!(received, "first\nsecond\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn drop_expired_frames() {
+        let path = std::env::temp_dir().join("alumet-test-unix-socket-missing.sock");
+        let mut output = UnixSocketOutput::new(path, FrameFormat::LengthPrefixed, Duration::ZERO);
+        output.send(vec![b"frame".to_vec()]).unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+        output.send(Vec::new()).unwrap();
+        assert!(output.pending.is_empty());
+    }
+}
@@ -0,0 +1,105 @@
+mod output;
+
+use std::{collections::BTreeMap, time::Duration};
+
+use alumet::plugin::{
+    rust::{deserialize_config, serialize_config, AlumetPlugin},
+    util::{AttributeAllowlist, MetricFilter},
+    ConfigTable,
+};
+use anyhow::Context;
+use output::WebhookOutput;
+use serde::{Deserialize, Serialize};
+
+pub struct WebhookPlugin {
+    config: Option<Config>,
+}
+
+impl AlumetPlugin for WebhookPlugin {
+    fn name() -> &'static str {
+        "webhook"
+    }
+
+    fn version() -> &'static str {
+        env!("CARGO_PKG_VERSION")
+    }
+
+    fn default_config() -> anyhow::Result<Option<ConfigTable>> {
+        Ok(Some(serialize_config(Config::default())?))
+    }
+
+    fn init(config: ConfigTable) -> anyhow::Result<Box<Self>> {
+        let config = deserialize_config(config)?;
+        Ok(Box::new(WebhookPlugin { config: Some(config) }))
+    }
+
+    fn start(&mut self, alumet: &mut alumet::plugin::AlumetStart) -> anyhow::Result<()> {
+        let config = self.config.take().unwrap();
+        let output = WebhookOutput::new(
+            config.url,
+            config.headers,
+            config.timeout,
+            config.max_retries,
+            config.retry_delay,
+        )
+        .context("invalid webhook configuration")?;
+        let output = AttributeAllowlist::wrap_opt(config.attributes, Box::new(output));
+        alumet.add_output(config.metric_filter.wrap(output));
+        Ok(())
+    }
+
+    fn stop(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Config {
+    /// URL to POST the measurements to.
+    url: String,
+
+    /// Additional HTTP headers, for instance `Authorization = "Bearer <token>"`.
+    /// The values of the authentication headers are redacted in the logs.
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+
+    /// Maximum duration of a request.
+    #[serde(with = "humantime_serde")]
+    timeout: Duration,
+
+    /// How many times to send a batch again when the server does not accept it.
+    /// The batch is dropped afterwards. The client errors (4xx) are not retried, except 408 and 429.
+    #[serde(default)]
+    max_retries: u32,
+
+    /// Delay before the first retry, doubled after each failed attempt.
+    #[serde(with = "humantime_serde", default = "default_retry_delay")]
+    retry_delay: Duration,
+
+    /// Only sends the metrics whose name matches these patterns. By default, all the metrics are sent.
+    #[serde(default, skip_serializing_if = "MetricFilter::is_empty")]
+    metric_filter: MetricFilter,
+
+    /// Only sends the attributes whose key matches these patterns, an empty list removes all the attributes.
+    /// By default, all the attributes are sent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    attributes: Option<AttributeAllowlist>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            url: String::from("http://localhost:8080/alumet"),
+            headers: BTreeMap::new(),
+            timeout: Duration::from_secs(5),
+            max_retries: 0,
+            retry_delay: default_retry_delay(),
+            metric_filter: MetricFilter::default(),
+            attributes: None,
+        }
+    }
+}
+
+fn default_retry_delay() -> Duration {
+    Duration::from_millis(500)
+}
@@ -0,0 +1,177 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use alumet::{
+    measurement::MeasurementBuffer,
+    pipeline::{Output, OutputContext, WriteError},
+    plugin::util::JsonPoint,
+};
+use anyhow::{anyhow, Context};
+use reqwest::{
+    header::{self, HeaderMap, HeaderName, HeaderValue},
+    StatusCode,
+};
+
+/// Headers whose value is secret, and must not appear in the logs.
+const SENSITIVE_HEADERS: [&str; 4] = ["authorization", "proxy-authorization", "x-api-key", "x-auth-token"];
+
+/// Sends each measurement buffer to an HTTP endpoint, as a JSON array.
+pub struct WebhookOutput {
+    client: reqwest::Client,
+    url: reqwest::Url,
+    headers: HeaderMap,
+    max_retries: u32,
+    /// Delay before the first retry, doubled after each attempt.
+    retry_delay: Duration,
+}
+
+impl WebhookOutput {
+    pub fn new(
+        url: String,
+        headers: BTreeMap<String, String>,
+        timeout: Duration,
+        max_retries: u32,
+        retry_delay: Duration,
+    ) -> anyhow::Result<Self> {
+        let url = reqwest::Url::parse(&url).with_context(|| format!("invalid url: {url}"))?;
+        let mut header_map = HeaderMap::with_capacity(headers.len() + 1);
+        header_map.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        for (key, value) in headers {
+            let name = HeaderName::try_from(key.as_str()).with_context(|| format!("invalid header name: {key}"))?;
+            let mut value = HeaderValue::try_from(value).with_context(|| format!("invalid value for header {key}"))?;
+            if is_sensitive(&name) {
+                value.set_sensitive(true);
+            }
+            header_map.insert(name, value);
+        }
+        let client = reqwest::Client::builder().timeout(timeout).build()?;
+        Ok(Self {
+            client,
+            url,
+            headers: header_map,
+            max_retries,
+            retry_delay,
+        })
+    }
+
+    async fn post(&self, body: Vec<u8>) -> anyhow::Result<()> {
+        let mut attempt = 0;
+        loop {
+            let res = self
+                .client
+                .post(self.url.clone())
+                .headers(self.headers.clone())
+                .body(body.clone())
+                .send()
+                .await;
+            let (error, retryable) = match res {
+                Ok(res) if res.status().is_success() => return Ok(()),
+                Ok(res) => (
+                    anyhow!("the server responded with {}", res.status()),
+                    is_retryable(res.status()),
+                ),
+                Err(e) => (anyhow!(e), true),
+            };
+            log::warn!(
+                "POST {} (headers: {:?}) failed on attempt {}: {error:#}",
+                self.url,
+                redacted(&self.headers),
+                attempt + 1
+            );
+            if !retryable || attempt >= self.max_retries {
+                return Err(error.context(format!("failed to POST measurements to {}", self.url)));
+            }
+            tokio::time::sleep(self.retry_delay.saturating_mul(2u32.saturating_pow(attempt))).await;
+            attempt += 1;
+        }
+    }
+}
+
+impl Output for WebhookOutput {
+    fn write(&mut self, measurements: &MeasurementBuffer, ctx: &OutputContext) -> Result<(), WriteError> {
+        if measurements.is_empty() {
+            return Ok(());
+        }
+        let mut points = Vec::with_capacity(measurements.len());
+        for m in measurements {
+            let metric = ctx
+                .metrics
+                .with_id(&m.metric)
+                .with_context(|| format!("unknown metric {:?}", m.metric))?;
+            points.push(JsonPoint::new(m, &metric.name, ctx.metric_plugin(&m.metric))?);
+        }
+        let body = serde_json::to_vec(&points)?;
+
+        // Do the request on the tokio Runtime.
+        // A failed request does not break the output: the next buffer can be sent.
+        let handle = tokio::runtime::Handle::current();
+        handle.block_on(self.post(body)).map_err(WriteError::CanRetry)
+    }
+}
+
+/// Returns true if sending the same request again can succeed: the client errors (4xx) are not retried,
+/// except for timeouts and rate limiting.
+fn is_retryable(status: StatusCode) -> bool {
+    !status.is_client_error() || matches!(status, StatusCode::REQUEST_TIMEOUT | StatusCode::TOO_MANY_REQUESTS)
+}
+
+fn is_sensitive(name: &HeaderName) -> bool {
+    SENSITIVE_HEADERS.contains(&name.as_str())
+}
+
+/// Returns the headers with the value of the sensitive ones replaced by `<redacted>`, for logging purposes.
+fn redacted(headers: &HeaderMap) -> BTreeMap<&str, &str> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if value.is_sensitive() || is_sensitive(name) {
+                "<redacted>"
+            } else {
+                value.to_str().unwrap_or("<binary>")
+            };
+            (name.as_str(), value)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, time::Duration};
+
+    use reqwest::StatusCode;
+
+    use super::{is_retryable, redacted, WebhookOutput};
+
+    #[test]
+    fn redact_auth_headers() {
+        let headers = BTreeMap::from([
+            (String::from("Authorization"), String::from("Bearer secret")),
+            (String::from("X-Api-Key"), String::from("secret")),
+            (String::from("X-Source"), String::from("alumet")),
+        ]);
+        let output = WebhookOutput::new(
+            String::from("http://localhost:8080/alumet"),
+            headers,
+            Duration::from_secs(1),
+            0,
+            Duration::from_millis(500),
+        )
+        .unwrap();
+        let logged = format!("{:?}", redacted(&output.headers));
+        assert!(!logged.contains("secret"), "{logged}");
+        assert_eq!(redacted(&output.headers)["authorization"], "<redacted>");
+        assert_eq!(redacted(&output.headers)["x-api-key"], "<redacted>");
+        assert_eq!(redacted(&output.headers)["x-source"], "alumet");
+        assert_eq!(redacted(&output.headers)["content-type"], "application/json");
+    }
+
+    #[test]
+    fn retryable_statuses() {
+        assert!(is_retryable(StatusCode::INTERNAL_SERVER_ERROR));
+        assert!(is_retryable(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(is_retryable(StatusCode::TOO_MANY_REQUESTS));
+        assert!(is_retryable(StatusCode::REQUEST_TIMEOUT));
+        assert!(!is_retryable(StatusCode::BAD_REQUEST));
+        assert!(!is_retryable(StatusCode::UNAUTHORIZED));
+        assert!(!is_retryable(StatusCode::NOT_FOUND));
+    }
+}